@@ -0,0 +1,474 @@
+//! Headless mode: `pdf-resizer --cli <command> [options]` runs the same processing as the
+//! window's commands, prints the result as JSON on stdout and exits without opening a window.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::error::CommandError;
+use crate::ghostscript::{
+    resolve_ghostscript_runtime, GhostscriptRuntime, GhostscriptWarning, JobContext,
+};
+use crate::logging::init_logging;
+use crate::operations::{
+    flatten_file, paper_size, run_operation, BatchItemResult, CompressPreset, ContentMode,
+    FlattenOptions, Operation, PageDimensions, QueuedOperation, ResizeAnchor,
+};
+use crate::output::OutputOptions;
+use crate::settings::{apply_settings, PresetStore, Settings, SettingsStore};
+
+const COMMANDS: &[&str] = &["flatten", "resize", "compress", "run", "batch"];
+
+const USAGE: &str = "\
+Usage: pdf-resizer --cli <command> [options]
+
+Commands:
+  flatten   --in <pdf> --out <pdf> [--keep-text] [--compatibility-level <1.4..2.0>]
+            [--linearize] [--repair]
+  resize    --in <pdf> --out <pdf> (--preset <name> | --paper <a4|letter|...> |
+            --width-pt <pt> --height-pt <pt>) [--content-mode <scale|keep-size>]
+            [--anchor <center|top-left|...>] [--linearize]
+  compress  --in <pdf> --out <pdf> (--level <screen|ebook|printer|prepress> |
+            --preset <name>)
+  run       --in <pdf> --out <pdf> --preset <name>
+  batch     --jobs <file.json>   a JSON array of { input_path, output_path, operation,
+                                 output_options }
+
+Output options, for all commands but batch:
+  --on-conflict <error|skip|overwrite|rename>  --allow-in-place  --preserve-mtime
+  --timeout <seconds>
+
+Results are printed as JSON. The exit code is 0 on success, 1 if processing failed and
+2 for invalid arguments.";
+
+/// Exit code for arguments that don't make a valid command; failed jobs exit with 1.
+const USAGE_EXIT_CODE: i32 = 2;
+
+/// The arguments after the program name, if they ask for headless mode: `--cli`, or one of
+/// `COMMANDS` that isn't also the name of a file to open.
+pub(super) fn cli_arguments() -> Option<Vec<String>> {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("--cli") => {
+            args.remove(0);
+            Some(args)
+        }
+        Some(first) if COMMANDS.contains(&first) && !Path::new(first).exists() => Some(args),
+        _ => None,
+    }
+}
+
+/// Run the command in `args` and return the process exit code.
+pub(super) fn run<A: tauri::Assets>(args: Vec<String>, context: &tauri::Context<A>) -> i32 {
+    attach_parent_console();
+    let Some((command, rest)) = args.split_first() else {
+        eprintln!("{}", USAGE);
+        return USAGE_EXIT_CODE;
+    };
+    if command == "--help" || command == "-h" || command == "help" {
+        println!("{}", USAGE);
+        return 0;
+    }
+    let arguments = match Arguments::parse(rest) {
+        Ok(arguments) => arguments,
+        Err(error) => return usage_error(&error),
+    };
+
+    let config_dir = tauri::api::path::app_config_dir(context.config());
+    let settings = SettingsStore::load(config_dir.clone()).get();
+    init_logging(
+        tauri::api::path::app_log_dir(context.config()),
+        settings.log_level,
+    );
+    apply_settings(&settings);
+    let runtime = resolve_ghostscript_runtime(
+        tauri::api::path::resource_dir(context.package_info(), &tauri::Env::default()),
+        config_dir.clone(),
+    );
+    let headless = Headless {
+        settings,
+        presets: PresetStore::load(config_dir),
+        runtime,
+    };
+
+    let outcome = match command.as_str() {
+        "flatten" => headless.flatten(arguments),
+        "resize" => headless.resize(arguments),
+        "compress" => headless.compress(arguments),
+        "run" => headless.run_preset(arguments),
+        "batch" => headless.batch(arguments),
+        _ => Err(Failure::Usage(format!("Unknown command '{}'.", command))),
+    };
+    match outcome {
+        Ok((result, exit_code)) => {
+            println!("{}", result);
+            exit_code
+        }
+        Err(Failure::Usage(error)) => usage_error(&error),
+        Err(Failure::Command(error)) => {
+            println!("{}", to_json(ErrorOutput { error }));
+            1
+        }
+    }
+}
+
+fn usage_error(error: &str) -> i32 {
+    eprintln!("{}\n\n{}", error, USAGE);
+    USAGE_EXIT_CODE
+}
+
+/// Release builds on Windows are GUI programs without a console of their own; print to
+/// the one the command was started from, if any.
+fn attach_parent_console() {
+    #[cfg(windows)]
+    {
+        const ATTACH_PARENT_PROCESS: u32 = u32::MAX;
+        #[link(name = "kernel32")]
+        extern "system" {
+            fn AttachConsole(process_id: u32) -> i32;
+        }
+        // SAFETY: AttachConsole takes no pointers; it fails harmlessly when there is no
+        // parent console or the process already has one.
+        unsafe {
+            AttachConsole(ATTACH_PARENT_PROCESS);
+        }
+    }
+}
+
+enum Failure {
+    /// Bad arguments: reported on stderr with the usage text.
+    Usage(String),
+    /// The processing itself failed: reported as JSON on stdout.
+    Command(CommandError),
+}
+
+impl From<CommandError> for Failure {
+    fn from(error: CommandError) -> Self {
+        Failure::Command(error)
+    }
+}
+
+/// `--name value`, `--name=value` and bare `--flag` arguments, each given at most once.
+struct Arguments(HashMap<String, Option<String>>);
+
+/// Options without a value.
+const FLAGS: &[&str] = &[
+    "keep-text",
+    "linearize",
+    "repair",
+    "allow-in-place",
+    "preserve-mtime",
+];
+
+impl Arguments {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut parsed = HashMap::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let Some(option) = arg.strip_prefix("--") else {
+                return Err(format!("Unexpected argument '{}'.", arg));
+            };
+            let (name, value) = match option.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None if FLAGS.contains(&option) => (option, None),
+                None => match args.next() {
+                    Some(value) => (option, Some(value.clone())),
+                    None => return Err(format!("--{} needs a value.", option)),
+                },
+            };
+            if parsed.insert(name.to_string(), value).is_some() {
+                return Err(format!("--{} is given more than once.", name));
+            }
+        }
+        Ok(Self(parsed))
+    }
+
+    /// Remove and return the value of `--name`.
+    fn value(&mut self, name: &str) -> Result<Option<String>, String> {
+        match self.0.remove(name) {
+            Some(Some(value)) => Ok(Some(value)),
+            Some(None) => Err(format!("--{} needs a value.", name)),
+            None => Ok(None),
+        }
+    }
+
+    fn required(&mut self, name: &str) -> Result<String, String> {
+        self.value(name)?
+            .ok_or_else(|| format!("--{} is required.", name))
+    }
+
+    /// Remove `--name` and return whether it was given. `--name=false` turns it off.
+    fn flag(&mut self, name: &str) -> Result<bool, String> {
+        match self.0.remove(name) {
+            None => Ok(false),
+            Some(None) => Ok(true),
+            Some(Some(value)) => value
+                .parse()
+                .map_err(|_| format!("--{} takes true or false, not '{}'.", name, value)),
+        }
+    }
+
+    fn number<T: std::str::FromStr>(&mut self, name: &str) -> Result<Option<T>, String> {
+        self.value(name)?
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|_| format!("--{} takes a number, not '{}'.", name, value))
+            })
+            .transpose()
+    }
+
+    /// A value of one of the enums the commands take, spelled as in their JSON.
+    fn choice<T: serde::de::DeserializeOwned>(&mut self, name: &str) -> Result<Option<T>, String> {
+        self.value(name)?
+            .map(|value| {
+                serde_json::from_value(serde_json::Value::String(value.clone()))
+                    .map_err(|_| format!("'{}' is not a valid --{}.", value, name))
+            })
+            .transpose()
+    }
+
+    fn output_options(&mut self, settings: &Settings) -> Result<OutputOptions, String> {
+        Ok(OutputOptions {
+            on_conflict: self.choice("on-conflict")?.unwrap_or(settings.on_conflict),
+            allow_in_place: self.flag("allow-in-place")?,
+            preserve_mtime: self.flag("preserve-mtime")? || settings.preserve_mtime,
+            timeout_secs: self.number("timeout")?,
+        })
+    }
+
+    /// Fail on options the command didn't take.
+    fn finish(self) -> Result<(), String> {
+        let mut unknown: Vec<_> = self
+            .0
+            .into_keys()
+            .map(|name| format!("--{}", name))
+            .collect();
+        if unknown.is_empty() {
+            return Ok(());
+        }
+        unknown.sort();
+        Err(format!("Unknown option(s) {}.", unknown.join(", ")))
+    }
+}
+
+/// State the window's commands get from Tauri, loaded the same way.
+struct Headless {
+    settings: Settings,
+    presets: PresetStore,
+    runtime: GhostscriptRuntime,
+}
+
+/// Output of a command and the code to exit with.
+type Outcome = Result<(String, i32), Failure>;
+
+/// What a single-file command prints.
+#[derive(serde::Serialize)]
+struct OperationOutput {
+    input_path: String,
+    output_path: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<GhostscriptWarning>,
+}
+
+#[derive(serde::Serialize)]
+struct ErrorOutput {
+    error: CommandError,
+}
+
+fn to_json(value: impl serde::Serialize) -> String {
+    serde_json::to_string(&value).expect("CLI results serialize to JSON")
+}
+
+impl Headless {
+    fn flatten(&self, mut arguments: Arguments) -> Outcome {
+        let input = PathBuf::from(arguments.required("in").map_err(Failure::Usage)?);
+        let output = PathBuf::from(arguments.required("out").map_err(Failure::Usage)?);
+        let mut options = FlattenOptions::default();
+        let parsed = (|| {
+            options.outline_fonts = !arguments.flag("keep-text")?;
+            if let Some(level) = arguments.value("compatibility-level")? {
+                options.compatibility_level = level;
+            }
+            options.linearize = arguments.flag("linearize")?;
+            options.try_repair = arguments.flag("repair")?;
+            let output_options = arguments.output_options(&self.settings)?;
+            options.timeout_secs = output_options.timeout_secs;
+            Ok(output_options)
+        })();
+        let output_options = parsed.map_err(Failure::Usage)?;
+        arguments.finish().map_err(Failure::Usage)?;
+
+        let written = flatten_file(
+            &input,
+            &output,
+            &options,
+            output_options,
+            self.settings.pipe_threshold_bytes,
+            &self.runtime,
+            JobContext::default(),
+        )?;
+        Ok((to_json(written), 0))
+    }
+
+    fn resize(&self, mut arguments: Arguments) -> Outcome {
+        let operation = (|| {
+            if let Some(name) = arguments.value("preset")? {
+                return self.preset_operation(&name, |operation| {
+                    matches!(
+                        operation,
+                        Operation::Resize { .. } | Operation::FitToPage { .. }
+                    )
+                });
+            }
+            let size = match arguments.value("paper")? {
+                Some(name) => {
+                    paper_size(&name).ok_or_else(|| format!("Unknown paper size '{}'.", name))?
+                }
+                None => match (
+                    arguments.number("width-pt")?,
+                    arguments.number("height-pt")?,
+                ) {
+                    (Some(width_pt), Some(height_pt)) => PageDimensions {
+                        width_pt,
+                        height_pt,
+                    },
+                    _ => {
+                        return Err(String::from(
+                            "Pass --preset, --paper or both --width-pt and --height-pt.",
+                        ))
+                    }
+                },
+            };
+            Ok(Operation::Resize {
+                width_pt: size.width_pt,
+                height_pt: size.height_pt,
+                content_mode: arguments
+                    .choice::<ContentMode>("content-mode")?
+                    .unwrap_or(ContentMode::Scale),
+                anchor: arguments.choice::<ResizeAnchor>("anchor")?,
+                linearize: arguments.flag("linearize")?,
+            })
+        })();
+        self.run_single(operation, arguments)
+    }
+
+    fn compress(&self, mut arguments: Arguments) -> Outcome {
+        let operation = (|| match (
+            arguments.choice::<CompressPreset>("level")?,
+            arguments.value("preset")?,
+        ) {
+            (Some(preset), None) => Ok(Operation::Compress { preset }),
+            (None, Some(name)) => self.preset_operation(&name, |operation| {
+                matches!(
+                    operation,
+                    Operation::Compress { .. } | Operation::DownsampleImages { .. }
+                )
+            }),
+            _ => Err(String::from("Pass either --level or --preset.")),
+        })();
+        self.run_single(operation, arguments)
+    }
+
+    fn run_preset(&self, mut arguments: Arguments) -> Outcome {
+        let operation = arguments
+            .required("preset")
+            .and_then(|name| self.preset_operation(&name, |_| true));
+        self.run_single(operation, arguments)
+    }
+
+    /// The operation of the preset called `name`, which `accepts` has to allow.
+    fn preset_operation(
+        &self,
+        name: &str,
+        accepts: impl Fn(&Operation) -> bool,
+    ) -> Result<Operation, String> {
+        let preset = self
+            .presets
+            .find(name)
+            .ok_or_else(|| format!("There is no preset named '{}'.", name))?;
+        if !accepts(&preset.operation) {
+            return Err(format!(
+                "Preset '{}' does something else; use `run --preset {}` for it.",
+                name, name
+            ));
+        }
+        Ok(preset.operation)
+    }
+
+    /// Run `operation` from `--in` to `--out`, once the rest of `arguments` checks out.
+    fn run_single(
+        &self,
+        operation: Result<Operation, String>,
+        mut arguments: Arguments,
+    ) -> Outcome {
+        let operation = operation.map_err(Failure::Usage)?;
+        let input = arguments.required("in").map_err(Failure::Usage)?;
+        let output = arguments.required("out").map_err(Failure::Usage)?;
+        let options = arguments
+            .output_options(&self.settings)
+            .map_err(Failure::Usage)?;
+        arguments.finish().map_err(Failure::Usage)?;
+
+        let (output_path, warnings) = run_operation(
+            &operation,
+            Path::new(&input),
+            Path::new(&output),
+            options,
+            &self.runtime,
+            JobContext::default(),
+        )?;
+        Ok((
+            to_json(OperationOutput {
+                input_path: input,
+                output_path,
+                warnings,
+            }),
+            0,
+        ))
+    }
+
+    /// Run every job in the `--jobs` file; like `process_batch`, a failed job is reported
+    /// and the rest still run. Exits with 1 if any of them failed.
+    fn batch(&self, mut arguments: Arguments) -> Outcome {
+        let jobs_path = arguments.required("jobs").map_err(Failure::Usage)?;
+        arguments.finish().map_err(Failure::Usage)?;
+        let contents = std::fs::read_to_string(&jobs_path).map_err(|e| CommandError::Io {
+            action: "read_jobs_file",
+            error: format!("'{}': {}", jobs_path, e),
+        })?;
+        let jobs: Vec<QueuedOperation> = serde_json::from_str(&contents).map_err(|e| {
+            Failure::Usage(format!("'{}' is not a valid job file: {}", jobs_path, e))
+        })?;
+
+        let mut results = Vec::with_capacity(jobs.len());
+        let mut exit_code = 0;
+        for job in jobs {
+            let written = run_operation(
+                &job.operation,
+                Path::new(&job.input_path),
+                Path::new(&job.output_path),
+                job.output_options,
+                &self.runtime,
+                JobContext::default(),
+            );
+            match written {
+                Ok((output_path, warnings)) => results.push(BatchItemResult {
+                    input_path: job.input_path,
+                    output_path: Some(output_path),
+                    error: None,
+                    warnings,
+                }),
+                Err(error) => {
+                    exit_code = 1;
+                    results.push(BatchItemResult {
+                        input_path: job.input_path,
+                        output_path: None,
+                        error: Some(error),
+                        warnings: Vec::new(),
+                    });
+                }
+            }
+        }
+        Ok((to_json(results), exit_code))
+    }
+}
@@ -0,0 +1,823 @@
+//! The error every command returns, with the stable code the frontend localizes by.
+
+use crate::ghostscript::GhostscriptFailure;
+
+/// Error of commands whose failures the frontend branches on. Serializes as
+/// `{ code, params, message, suggestion, details }`: the frontend localizes by `code`
+/// interpolating `params`, `message` and `suggestion` are English fallbacks and `details`
+/// carries the raw text behind it.
+#[derive(Debug, Clone)]
+pub(crate) enum CommandError {
+    /// No Ghostscript binary could be started; holds why.
+    GsNotFound(String),
+    GsFailed {
+        /// What `diagnose_ghostscript_output` recognized in the output, if anything.
+        failure: Option<GhostscriptFailure>,
+        exit_code: Option<i32>,
+        /// Ghostscript's stderr followed by its stdout, where it prints most errors.
+        stderr: String,
+        /// The stderr bytes as captured, when they weren't UTF-8 and `stderr` is decoded.
+        stderr_bytes: Option<Vec<u8>>,
+    },
+    /// The Ghostscript build has no such output device.
+    GsMissingDevice {
+        device: String,
+    },
+    /// The command needs a newer Ghostscript; both are version strings.
+    GsTooOld {
+        found: String,
+        required: String,
+    },
+    /// `value` is not an accepted value of the argument `field`.
+    InvalidInput {
+        field: &'static str,
+        value: String,
+        expected: String,
+    },
+    PasswordRequired,
+    WrongPassword,
+    DiskFull {
+        volume: String,
+        needed_bytes: u64,
+        available_bytes: u64,
+    },
+    /// Aborted via `cancel_job`.
+    Cancelled,
+    /// The output exists and `ConflictPolicy::Skip` left it alone.
+    OutputSkipped {
+        path: String,
+    },
+    /// Ghostscript ran longer than its timeout and was killed.
+    Timeout {
+        after_secs: u64,
+    },
+    /// `action` is snake_case, e.g. `write_temp_input`.
+    Io {
+        action: &'static str,
+        error: String,
+    },
+    /// A file or folder the command was given does not exist.
+    NotFound {
+        path: String,
+    },
+    NotAFile {
+        path: String,
+    },
+    NotAFolder {
+        path: String,
+    },
+    /// The output folder can't be written to; `error` is the system's reason.
+    NotWritable {
+        path: String,
+        error: String,
+    },
+    /// The output would replace its own input.
+    SameInputAndOutput {
+        path: String,
+    },
+    /// lopdf could not read `path` as a PDF.
+    InvalidPdf {
+        path: String,
+        error: String,
+    },
+    /// A page number past the end of the document.
+    PageOutOfRange {
+        page: u32,
+        page_count: u32,
+    },
+    /// A number outside what `field` accepts; `expected` describes the range.
+    OutOfRange {
+        field: &'static str,
+        value: String,
+        expected: String,
+    },
+    /// `field` is required but was empty or not given.
+    MissingInput {
+        field: &'static str,
+    },
+    /// An output name template that can't be used; `reason` is English.
+    InvalidTemplate {
+        template: String,
+        reason: String,
+    },
+    PresetNotFound {
+        name: String,
+    },
+    PresetExists {
+        name: String,
+    },
+    /// Built-in presets can only be duplicated, not changed.
+    PresetBuiltIn {
+        name: String,
+    },
+    /// `path` is not a file written by `export_presets`.
+    NotAPresetFile {
+        path: String,
+        error: String,
+    },
+    /// No app opened `path`, or the file manager could not show it.
+    OpenFailed {
+        path: String,
+    },
+    JobAlreadyRunning {
+        job_id: String,
+    },
+    /// `update_settings` got values that don't fit the settings.
+    InvalidSettings {
+        error: String,
+    },
+    /// `start_watch` was asked to write into the folder it watches.
+    OutputIsWatchedFolder {
+        path: String,
+    },
+    /// The document has no pages to work on.
+    NoPages {
+        path: String,
+    },
+    /// Encryption was given the same owner and user password, so the permissions would
+    /// have no effect.
+    PasswordsMustDiffer,
+    /// `embed_fonts` without substitution found no file for `fonts`, or only a substitute.
+    FontsNotEmbedded {
+        fonts: Vec<String>,
+    },
+    /// `path` is an image the command can't read; `reason` is snake_case, e.g. `interlaced`.
+    InvalidImage {
+        path: String,
+        reason: &'static str,
+    },
+    /// The page of `path` the command would place has an empty visible box.
+    NoVisibleArea {
+        path: String,
+        page: u32,
+    },
+    /// `path` is not a TrueType font with a Unicode character map.
+    UnsupportedFont {
+        path: String,
+    },
+    /// The font at `path` has no glyphs for `characters`, each listed once.
+    MissingGlyphs {
+        path: String,
+        characters: String,
+    },
+    /// No installed font covers the text to draw and none was chosen.
+    NoOverlayFont,
+    /// Skipping `skipped` pages leaves nothing of the `page_count` to work on.
+    AllPagesSkipped {
+        skipped: u32,
+        page_count: u32,
+    },
+    /// EPS holds a single page, but `selected` pages were selected.
+    EpsNeedsOnePage {
+        selected: u32,
+    },
+    /// Ghostscript's `inkcov` device reported fewer pages than were selected.
+    InkCoverageIncomplete {
+        reported: u32,
+        page_count: u32,
+    },
+    /// The document at `path` has no attachment called `name`.
+    AttachmentNotFound {
+        path: String,
+        name: String,
+    },
+    /// A file the app ships with, e.g. the `srgb.icc` profile, is missing from the install.
+    MissingBundledFile {
+        name: String,
+    },
+    /// The `field` box (`crop`, `trim` or `bleed`) reaches past the MediaBox of `page`.
+    BoxExceedsPage {
+        field: &'static str,
+        page: u32,
+        width_pt: f64,
+        height_pt: f64,
+    },
+    /// The bleed box of `page` does not contain its trim box.
+    BleedMissesTrim {
+        page: u32,
+    },
+    /// Deleting the selected pages would leave a document with no pages.
+    NoPagesLeft,
+    /// `path` is not an ICC profile, or one for `found` data where `expected` was asked for;
+    /// both are header color space signatures such as `CMYK`.
+    InvalidIccProfile {
+        path: String,
+        found: Option<String>,
+        expected: String,
+    },
+    /// Neither lopdf nor Ghostscript could tell how many pages `path` has.
+    PageCountUnknown {
+        path: String,
+    },
+    /// The output is taken and `ConflictPolicy::Error` leaves it alone.
+    OutputExists {
+        path: String,
+    },
+    /// The finished output for `path` failed its check before replacing anything; `reason` is
+    /// `empty` or `not_pdf`.
+    OutputInvalid {
+        path: String,
+        reason: &'static str,
+    },
+    /// Another program kept `path` locked through every retry; `error` is the system's reason.
+    FileLocked {
+        path: String,
+        error: String,
+    },
+    /// `path` is not a PDF file; `reason` is `empty` or `not_pdf`.
+    NotAPdf {
+        path: String,
+        reason: &'static str,
+    },
+    /// The blocking task running a command panicked or was cancelled by the runtime.
+    TaskFailed {
+        error: String,
+    },
+}
+
+#[derive(serde::Serialize)]
+struct CommandErrorBody {
+    pub(crate) code: &'static str,
+    #[serde(skip_serializing_if = "serde_json::Map::is_empty")]
+    pub(crate) params: serde_json::Map<String, serde_json::Value>,
+    pub(crate) message: String,
+    /// What the user can do about it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) suggestion: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    details: Option<CommandErrorDetails>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(untagged)]
+enum CommandErrorDetails {
+    Text(String),
+    Exit {
+        exit_code: Option<i32>,
+        stderr: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        stderr_bytes: Option<Vec<u8>>,
+    },
+}
+
+impl CommandError {
+    pub(crate) fn code(&self) -> &'static str {
+        match self {
+            CommandError::GsNotFound(_) => "gs_not_found",
+            CommandError::GsFailed {
+                failure: Some(failure),
+                ..
+            } => failure.code(),
+            CommandError::GsFailed { .. } => "gs_failed",
+            CommandError::GsMissingDevice { .. } => "gs_missing_device",
+            CommandError::GsTooOld { .. } => "gs_too_old",
+            CommandError::InvalidInput { .. } => "invalid_input",
+            CommandError::PasswordRequired => "password_required",
+            CommandError::WrongPassword => "wrong_password",
+            CommandError::DiskFull { .. } => "disk_full",
+            CommandError::Cancelled => "cancelled",
+            CommandError::OutputSkipped { .. } => "output_skipped",
+            CommandError::Timeout { .. } => "timeout",
+            CommandError::Io { .. } => "io",
+            CommandError::NotFound { .. } => "not_found",
+            CommandError::NotAFile { .. } => "not_a_file",
+            CommandError::NotAFolder { .. } => "not_a_folder",
+            CommandError::NotWritable { .. } => "not_writable",
+            CommandError::SameInputAndOutput { .. } => "same_input_and_output",
+            CommandError::InvalidPdf { .. } => "invalid_pdf",
+            CommandError::PageOutOfRange { .. } => "page_out_of_range",
+            CommandError::OutOfRange { .. } => "out_of_range",
+            CommandError::MissingInput { .. } => "missing_input",
+            CommandError::InvalidTemplate { .. } => "invalid_template",
+            CommandError::PresetNotFound { .. } => "preset_not_found",
+            CommandError::PresetExists { .. } => "preset_exists",
+            CommandError::PresetBuiltIn { .. } => "preset_built_in",
+            CommandError::NotAPresetFile { .. } => "not_a_preset_file",
+            CommandError::OpenFailed { .. } => "open_failed",
+            CommandError::JobAlreadyRunning { .. } => "job_already_running",
+            CommandError::InvalidSettings { .. } => "invalid_settings",
+            CommandError::OutputIsWatchedFolder { .. } => "output_is_watched_folder",
+            CommandError::NoPages { .. } => "no_pages",
+            CommandError::PasswordsMustDiffer => "passwords_must_differ",
+            CommandError::FontsNotEmbedded { .. } => "fonts_not_embedded",
+            CommandError::InvalidImage { .. } => "invalid_image",
+            CommandError::NoVisibleArea { .. } => "no_visible_area",
+            CommandError::UnsupportedFont { .. } => "unsupported_font",
+            CommandError::MissingGlyphs { .. } => "missing_glyphs",
+            CommandError::NoOverlayFont => "no_overlay_font",
+            CommandError::AllPagesSkipped { .. } => "all_pages_skipped",
+            CommandError::EpsNeedsOnePage { .. } => "eps_needs_one_page",
+            CommandError::InkCoverageIncomplete { .. } => "ink_coverage_incomplete",
+            CommandError::AttachmentNotFound { .. } => "attachment_not_found",
+            CommandError::MissingBundledFile { .. } => "missing_bundled_file",
+            CommandError::BoxExceedsPage { .. } => "box_exceeds_page",
+            CommandError::BleedMissesTrim { .. } => "bleed_misses_trim",
+            CommandError::NoPagesLeft => "no_pages_left",
+            CommandError::InvalidIccProfile { .. } => "invalid_icc_profile",
+            CommandError::PageCountUnknown { .. } => "page_count_unknown",
+            CommandError::OutputExists { .. } => "output_exists",
+            CommandError::OutputInvalid { .. } => "output_invalid",
+            CommandError::FileLocked { .. } => "file_locked",
+            CommandError::NotAPdf { .. } => "not_a_pdf",
+            CommandError::TaskFailed { .. } => "task_failed",
+        }
+    }
+
+    pub(crate) fn message(&self) -> String {
+        match self {
+            CommandError::GsNotFound(_) => {
+                String::from("Ghostscript is not installed or could not be started.")
+            }
+            CommandError::GsFailed {
+                failure: Some(failure),
+                ..
+            } => failure.message().to_string(),
+            CommandError::GsFailed {
+                exit_code: Some(code),
+                ..
+            } => format!("Ghostscript failed with exit code {}.", code),
+            CommandError::GsFailed { .. } => String::from("Ghostscript was terminated."),
+            CommandError::GsMissingDevice { device } => {
+                format!("Your Ghostscript build lacks the {} device.", device)
+            }
+            CommandError::GsTooOld { found, required } => format!(
+                "Ghostscript is too old ({} < {} required).",
+                found, required
+            ),
+            CommandError::PasswordRequired => {
+                GhostscriptFailure::PasswordRequired.message().to_string()
+            }
+            CommandError::WrongPassword => String::from("The password is incorrect."),
+            CommandError::Cancelled => String::from("The job was cancelled."),
+            CommandError::OutputSkipped { path } => {
+                format!("'{}' already exists and was left alone.", path)
+            }
+            CommandError::Timeout { after_secs } => format!(
+                "Ghostscript was stopped after running for {} seconds.",
+                after_secs
+            ),
+            CommandError::InvalidInput {
+                field,
+                value,
+                expected,
+            } => format!(
+                "Unsupported {} '{}'; expected one of {}.",
+                field.replace('_', " "),
+                value,
+                expected
+            ),
+            CommandError::DiskFull {
+                volume,
+                needed_bytes,
+                available_bytes,
+            } => format!(
+                "{} needs {} more free space ({} needed, {} available).",
+                volume,
+                format_bytes(needed_bytes.saturating_sub(*available_bytes)),
+                format_bytes(*needed_bytes),
+                format_bytes(*available_bytes)
+            ),
+            CommandError::Io { action, error } => {
+                format!("Failed to {}: {}", action.replace('_', " "), error)
+            }
+            CommandError::NotFound { path } => format!("'{}' does not exist.", path),
+            CommandError::NotAFile { path } => format!("'{}' is not a file.", path),
+            CommandError::NotAFolder { path } => format!("'{}' is not a folder.", path),
+            CommandError::NotWritable { path, error } => {
+                format!("The folder '{}' is not writable: {}", path, error)
+            }
+            CommandError::SameInputAndOutput { path } => format!(
+                "'{}' is the input file; choose another output or allow in-place writes.",
+                path
+            ),
+            CommandError::InvalidPdf { path, error } => {
+                format!("Failed to parse PDF '{}': {}", path, error)
+            }
+            CommandError::PageOutOfRange { page, page_count } => format!(
+                "Page {} is out of range; the document has {} page(s).",
+                page, page_count
+            ),
+            CommandError::OutOfRange {
+                field,
+                value,
+                expected,
+            } => format!(
+                "The {} must be {}, got {}.",
+                field.replace('_', " "),
+                expected,
+                value
+            ),
+            CommandError::MissingInput { field } => {
+                format!("No {} was given.", field.replace('_', " "))
+            }
+            CommandError::InvalidTemplate { template, reason } => {
+                format!("The name template '{}' can't be used: {}", template, reason)
+            }
+            CommandError::PresetNotFound { name } => {
+                format!("There is no preset named '{}'.", name)
+            }
+            CommandError::PresetExists { name } => {
+                format!("A preset named '{}' already exists.", name)
+            }
+            CommandError::PresetBuiltIn { name } => format!(
+                "'{}' is a built-in preset; duplicate it under another name to change it.",
+                name
+            ),
+            CommandError::NotAPresetFile { path, error } => {
+                format!("'{}' is not a preset file: {}", path, error)
+            }
+            CommandError::OpenFailed { path } => format!("Could not open '{}'.", path),
+            CommandError::JobAlreadyRunning { job_id } => {
+                format!("Job '{}' is already running.", job_id)
+            }
+            CommandError::InvalidSettings { error } => format!("Invalid settings: {}", error),
+            CommandError::OutputIsWatchedFolder { path } => format!(
+                "The output folder '{}' must not be the watched folder.",
+                path
+            ),
+            CommandError::NoPages { path } => format!("'{}' has no pages.", path),
+            CommandError::PasswordsMustDiffer => String::from(
+                "The owner password must differ from the user password, \
+                 or the permissions have no effect.",
+            ),
+            CommandError::FontsNotEmbedded { fonts } => {
+                format!("Fonts could not be embedded: {}.", fonts.join(", "))
+            }
+            CommandError::InvalidImage { path, reason } => match *reason {
+                "interlaced" => format!(
+                    "'{}' is an interlaced PNG, which is not supported; \
+                     save it without interlacing.",
+                    path
+                ),
+                _ => format!(
+                    "'{}' is not a PNG file the app can read ({}).",
+                    path,
+                    reason.replace('_', " ")
+                ),
+            },
+            CommandError::NoVisibleArea { path, page } => {
+                format!("Page {} of '{}' has no visible area.", page, path)
+            }
+            CommandError::UnsupportedFont { path } => format!(
+                "'{}' is not a TrueType font with a Unicode character map.",
+                path
+            ),
+            CommandError::MissingGlyphs { path, characters } => {
+                format!("The font '{}' has no glyphs for: {}", path, characters)
+            }
+            CommandError::NoOverlayFont => String::from(
+                "No installed font covers the text; choose a TrueType font explicitly.",
+            ),
+            CommandError::AllPagesSkipped { page_count, .. } => {
+                format!("All {} page(s) are skipped; nothing to number.", page_count)
+            }
+            CommandError::EpsNeedsOnePage { selected } => {
+                format!(
+                    "EPS holds a single page, but {} pages were selected.",
+                    selected
+                )
+            }
+            CommandError::InkCoverageIncomplete {
+                reported,
+                page_count,
+            } => format!(
+                "Ghostscript reported ink coverage for {} of {} pages.",
+                reported, page_count
+            ),
+            CommandError::AttachmentNotFound { path, name } => {
+                format!("'{}' has no attachment named '{}'.", path, name)
+            }
+            CommandError::MissingBundledFile { name } => {
+                format!("The bundled file '{}' is missing; reinstall the app.", name)
+            }
+            CommandError::BoxExceedsPage {
+                field,
+                page,
+                width_pt,
+                height_pt,
+            } => format!(
+                "The {} box exceeds page {} ({:.1} x {:.1} pt).",
+                field, page, width_pt, height_pt
+            ),
+            CommandError::BleedMissesTrim { page } => {
+                format!(
+                    "The bleed box of page {} does not contain its trim box.",
+                    page
+                )
+            }
+            CommandError::NoPagesLeft => {
+                String::from("Cannot delete every page; the output would be empty.")
+            }
+            CommandError::InvalidIccProfile {
+                path,
+                found,
+                expected,
+            } => match found {
+                Some(found) => format!(
+                    "ICC profile '{}' is for {} data, expected {}.",
+                    path, found, expected
+                ),
+                None => format!("'{}' is not an ICC profile.", path),
+            },
+            CommandError::PageCountUnknown { path } => {
+                format!("Could not read page count for '{}'.", path)
+            }
+            CommandError::OutputExists { path } => format!("'{}' already exists.", path),
+            CommandError::OutputInvalid { path, reason } => match *reason {
+                "empty" => format!("The output for '{}' came out empty.", path),
+                _ => format!("The output for '{}' is not a valid PDF.", path),
+            },
+            CommandError::FileLocked { path, error } => {
+                format!("'{}' is in use by another program: {}", path, error)
+            }
+            CommandError::NotAPdf { path, reason } => match *reason {
+                "empty" => format!("'{}' is empty.", path),
+                _ => format!("'{}' is not a PDF.", path),
+            },
+            CommandError::TaskFailed { error } => format!("Processing task failed: {}", error),
+        }
+    }
+
+    /// Values the localized message interpolates, named like the variant's fields.
+    pub(crate) fn params(&self) -> serde_json::Map<String, serde_json::Value> {
+        let mut params = serde_json::Map::new();
+        match self {
+            CommandError::GsFailed {
+                exit_code: Some(code),
+                ..
+            } => {
+                params.insert(String::from("exit_code"), (*code).into());
+            }
+            CommandError::GsMissingDevice { device } => {
+                params.insert(String::from("device"), device.clone().into());
+            }
+            CommandError::GsTooOld { found, required } => {
+                params.insert(String::from("found"), found.clone().into());
+                params.insert(String::from("required"), required.clone().into());
+            }
+            CommandError::InvalidInput {
+                field,
+                value,
+                expected,
+            } => {
+                params.insert(String::from("field"), (*field).into());
+                params.insert(String::from("value"), value.clone().into());
+                params.insert(String::from("expected"), expected.clone().into());
+            }
+            CommandError::DiskFull {
+                volume,
+                needed_bytes,
+                available_bytes,
+            } => {
+                params.insert(String::from("volume"), volume.clone().into());
+                params.insert(String::from("needed_bytes"), (*needed_bytes).into());
+                params.insert(String::from("available_bytes"), (*available_bytes).into());
+            }
+            CommandError::OutputSkipped { path } => {
+                params.insert(String::from("path"), path.clone().into());
+            }
+            CommandError::Timeout { after_secs } => {
+                params.insert(String::from("after_secs"), (*after_secs).into());
+            }
+            CommandError::Io { action, error } => {
+                params.insert(String::from("action"), (*action).into());
+                params.insert(String::from("error"), error.clone().into());
+            }
+            CommandError::NotFound { path }
+            | CommandError::NotAFile { path }
+            | CommandError::NotAFolder { path }
+            | CommandError::SameInputAndOutput { path }
+            | CommandError::OpenFailed { path }
+            | CommandError::OutputIsWatchedFolder { path } => {
+                params.insert(String::from("path"), path.clone().into());
+            }
+            CommandError::NotWritable { path, error }
+            | CommandError::InvalidPdf { path, error }
+            | CommandError::NotAPresetFile { path, error } => {
+                params.insert(String::from("path"), path.clone().into());
+                params.insert(String::from("error"), error.clone().into());
+            }
+            CommandError::PageOutOfRange { page, page_count } => {
+                params.insert(String::from("page"), (*page).into());
+                params.insert(String::from("page_count"), (*page_count).into());
+            }
+            CommandError::OutOfRange {
+                field,
+                value,
+                expected,
+            } => {
+                params.insert(String::from("field"), (*field).into());
+                params.insert(String::from("value"), value.clone().into());
+                params.insert(String::from("expected"), expected.clone().into());
+            }
+            CommandError::MissingInput { field } => {
+                params.insert(String::from("field"), (*field).into());
+            }
+            CommandError::InvalidTemplate { template, reason } => {
+                params.insert(String::from("template"), template.clone().into());
+                params.insert(String::from("reason"), reason.clone().into());
+            }
+            CommandError::PresetNotFound { name }
+            | CommandError::PresetExists { name }
+            | CommandError::PresetBuiltIn { name } => {
+                params.insert(String::from("name"), name.clone().into());
+            }
+            CommandError::JobAlreadyRunning { job_id } => {
+                params.insert(String::from("job_id"), job_id.clone().into());
+            }
+            CommandError::InvalidSettings { error } => {
+                params.insert(String::from("error"), error.clone().into());
+            }
+            CommandError::NoPages { path } => {
+                params.insert(String::from("path"), path.clone().into());
+            }
+            CommandError::FontsNotEmbedded { fonts } => {
+                params.insert(String::from("fonts"), fonts.clone().into());
+            }
+            CommandError::InvalidImage { path, reason } => {
+                params.insert(String::from("path"), path.clone().into());
+                params.insert(String::from("reason"), (*reason).into());
+            }
+            CommandError::NoVisibleArea { path, page } => {
+                params.insert(String::from("path"), path.clone().into());
+                params.insert(String::from("page"), (*page).into());
+            }
+            CommandError::UnsupportedFont { path } => {
+                params.insert(String::from("path"), path.clone().into());
+            }
+            CommandError::MissingGlyphs { path, characters } => {
+                params.insert(String::from("path"), path.clone().into());
+                params.insert(String::from("characters"), characters.clone().into());
+            }
+            CommandError::AllPagesSkipped {
+                skipped,
+                page_count,
+            } => {
+                params.insert(String::from("skipped"), (*skipped).into());
+                params.insert(String::from("page_count"), (*page_count).into());
+            }
+            CommandError::EpsNeedsOnePage { selected } => {
+                params.insert(String::from("selected"), (*selected).into());
+            }
+            CommandError::InkCoverageIncomplete {
+                reported,
+                page_count,
+            } => {
+                params.insert(String::from("reported"), (*reported).into());
+                params.insert(String::from("page_count"), (*page_count).into());
+            }
+            CommandError::AttachmentNotFound { path, name } => {
+                params.insert(String::from("path"), path.clone().into());
+                params.insert(String::from("name"), name.clone().into());
+            }
+            CommandError::MissingBundledFile { name } => {
+                params.insert(String::from("name"), name.clone().into());
+            }
+            CommandError::BoxExceedsPage {
+                field,
+                page,
+                width_pt,
+                height_pt,
+            } => {
+                params.insert(String::from("field"), (*field).into());
+                params.insert(String::from("page"), (*page).into());
+                params.insert(String::from("width_pt"), (*width_pt).into());
+                params.insert(String::from("height_pt"), (*height_pt).into());
+            }
+            CommandError::BleedMissesTrim { page } => {
+                params.insert(String::from("page"), (*page).into());
+            }
+            CommandError::InvalidIccProfile {
+                path,
+                found,
+                expected,
+            } => {
+                params.insert(String::from("path"), path.clone().into());
+                params.insert(String::from("found"), found.clone().into());
+                params.insert(String::from("expected"), expected.clone().into());
+            }
+            CommandError::PageCountUnknown { path } => {
+                params.insert(String::from("path"), path.clone().into());
+            }
+            CommandError::OutputExists { path } => {
+                params.insert(String::from("path"), path.clone().into());
+            }
+            CommandError::OutputInvalid { path, reason } => {
+                params.insert(String::from("path"), path.clone().into());
+                params.insert(String::from("reason"), (*reason).into());
+            }
+            CommandError::FileLocked { path, error } => {
+                params.insert(String::from("path"), path.clone().into());
+                params.insert(String::from("error"), error.clone().into());
+            }
+            CommandError::NotAPdf { path, reason } => {
+                params.insert(String::from("path"), path.clone().into());
+                params.insert(String::from("reason"), (*reason).into());
+            }
+            CommandError::TaskFailed { error } => {
+                params.insert(String::from("error"), error.clone().into());
+            }
+            CommandError::GsNotFound(_)
+            | CommandError::GsFailed { .. }
+            | CommandError::PasswordRequired
+            | CommandError::WrongPassword
+            | CommandError::Cancelled
+            | CommandError::PasswordsMustDiffer
+            | CommandError::NoOverlayFont
+            | CommandError::NoPagesLeft => {}
+        }
+        params
+    }
+
+    pub(crate) fn suggestion(&self) -> Option<&'static str> {
+        match self {
+            CommandError::GsNotFound(_) => {
+                Some("Install Ghostscript or choose its executable in the settings.")
+            }
+            CommandError::GsTooOld { .. } => {
+                Some("Install a newer Ghostscript or choose it in the settings.")
+            }
+            CommandError::GsMissingDevice { .. } => {
+                Some(GhostscriptFailure::UnknownDevice.suggestion())
+            }
+            CommandError::GsFailed {
+                failure: Some(failure),
+                ..
+            } => Some(failure.suggestion()),
+            CommandError::PasswordRequired => {
+                Some(GhostscriptFailure::PasswordRequired.suggestion())
+            }
+            CommandError::WrongPassword => Some("Check the password and try again."),
+            CommandError::DiskFull { .. } => Some("Free up space or choose another output folder."),
+            CommandError::Timeout { .. } => Some(
+                "The PDF may be damaged; try repairing it, or raise the timeout in the settings.",
+            ),
+            _ => None,
+        }
+    }
+
+    /// `message` followed by the text it was built from, such as Ghostscript's output, for
+    /// reports that show failures as lines of text.
+    pub(crate) fn detailed_message(&self) -> String {
+        match self {
+            CommandError::GsNotFound(reason) => reason.clone(),
+            CommandError::GsFailed { stderr, .. } if !stderr.trim().is_empty() => {
+                format!("{} {}", self.message(), stderr.trim())
+            }
+            _ => self.message(),
+        }
+    }
+
+    fn details(&self) -> Option<CommandErrorDetails> {
+        match self {
+            CommandError::GsNotFound(reason) => Some(CommandErrorDetails::Text(reason.clone())),
+            CommandError::GsFailed {
+                exit_code,
+                stderr,
+                stderr_bytes,
+                ..
+            } => Some(CommandErrorDetails::Exit {
+                exit_code: *exit_code,
+                stderr: stderr.clone(),
+                stderr_bytes: stderr_bytes.clone(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for CommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message())
+    }
+}
+
+impl serde::Serialize for CommandError {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        CommandErrorBody {
+            code: self.code(),
+            params: self.params(),
+            message: self.message(),
+            suggestion: self.suggestion(),
+            details: self.details(),
+        }
+        .serialize(serializer)
+    }
+}
+
+/// Errors of helpers that only have an English message.
+/// Byte counts for messages, e.g. `3.2 MB`.
+pub(crate) fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["bytes", "KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1000.0 && unit + 1 < UNITS.len() {
+        value /= 1000.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
@@ -0,0 +1,1809 @@
+//! Finding and running Ghostscript: candidates, the resolved runtime, timeouts and
+//! cancellation, and what its output says went wrong.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::error::CommandError;
+use crate::output::check_free_space;
+
+/// Default of `Settings::ghostscript_timeout_secs`.
+pub(crate) const DEFAULT_GHOSTSCRIPT_TIMEOUT_SECS: u64 = 10 * 60;
+
+/// How long a timed-out Ghostscript gets to exit after SIGTERM before it is killed.
+#[cfg(unix)]
+pub(crate) const GHOSTSCRIPT_TERMINATE_GRACE: std::time::Duration =
+    std::time::Duration::from_secs(2);
+
+/// Ghostscript arguments whose values are replaced by `***` in the log.
+const SECRET_GHOSTSCRIPT_ARGS: [&str; 3] =
+    ["-sPDFPassword=", "-sOwnerPassword=", "-sUserPassword="];
+
+/// `Settings::ghostscript_timeout_secs` in effect; 0 disables the timeout.
+pub(crate) static GHOSTSCRIPT_TIMEOUT_SECS: AtomicU64 =
+    AtomicU64::new(DEFAULT_GHOSTSCRIPT_TIMEOUT_SECS);
+
+/// Frequent causes of a failed Ghostscript run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum GhostscriptFailure {
+    PasswordRequired,
+    DamagedFile,
+    UnknownDevice,
+    OutOfMemory,
+    InvalidFont,
+    FileNotFound,
+    FileAccessDenied,
+}
+
+/// Lowercase fragments of Ghostscript's output and what they mean, checked in order.
+const GHOSTSCRIPT_FAILURE_PATTERNS: &[(&str, GhostscriptFailure)] = &[
+    ("requires a password", GhostscriptFailure::PasswordRequired),
+    ("/invalidfileaccess", GhostscriptFailure::FileAccessDenied),
+    ("/undefinedfilename", GhostscriptFailure::FileNotFound),
+    ("unknown device", GhostscriptFailure::UnknownDevice),
+    ("/vmerror", GhostscriptFailure::OutOfMemory),
+    ("out of memory", GhostscriptFailure::OutOfMemory),
+    ("/invalidfont", GhostscriptFailure::InvalidFont),
+    ("xref", GhostscriptFailure::DamagedFile),
+    ("couldn't find trailer", GhostscriptFailure::DamagedFile),
+    ("/syntaxerror", GhostscriptFailure::DamagedFile),
+];
+
+/// Ghostscript message lines that flag a problem in a run that still succeeded, with the
+/// `GhostscriptWarning::code` each gets; the first pattern found in a line wins. Lines
+/// that continue a message, like the producer after `This file had errors`, don't match.
+const GHOSTSCRIPT_WARNING_PATTERNS: [(&str, &str); 5] = [
+    (
+        "this file had errors that were repaired",
+        "gs_file_repaired",
+    ),
+    ("substituting font", "gs_font_substituted"),
+    ("can't find (or can't open) font", "gs_font_missing"),
+    ("**** error:", "gs_error_recovered"),
+    ("**** warning:", "gs_warning"),
+];
+
+/// A problem Ghostscript reported while still producing output.
+#[derive(Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub(crate) struct GhostscriptWarning {
+    /// From `GHOSTSCRIPT_WARNING_PATTERNS`, or the failure code when the line names one.
+    pub(crate) code: String,
+    /// The line as Ghostscript printed it, without the `****` marker.
+    pub(crate) message: String,
+    /// How often the line appeared; many repeat once per page.
+    pub(crate) count: u32,
+}
+
+/// Cancellation handle for one processing job; holds the Ghostscript child while it runs.
+#[derive(Default)]
+pub(crate) struct JobControl {
+    pub(crate) cancelled: AtomicBool,
+    pub(crate) child: Mutex<Option<std::process::Child>>,
+    /// Overrides `Settings::ghostscript_timeout_secs` for this job's runs; 0 disables it.
+    pub(crate) timeout_secs: Option<u64>,
+}
+
+/// Job plumbing handed to processing functions: cancellation plus a progress callback
+/// receiving `(current_page, total_pages)`.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct JobContext<'a> {
+    pub(crate) control: Option<&'a JobControl>,
+    pub(crate) on_progress: Option<&'a (dyn Fn(u32, Option<u32>) + Sync)>,
+    /// Where the job's Ghostscript runs leave their warnings; `write_output` hands each
+    /// attempt a fresh one.
+    pub(crate) warnings: Option<&'a WarningLog>,
+}
+
+/// Warnings gathered from the Ghostscript runs of one attempt at writing an output.
+#[derive(Default)]
+pub(crate) struct WarningLog(pub(crate) Mutex<Vec<GhostscriptWarning>>);
+
+#[derive(Clone, Default)]
+pub(crate) struct GhostscriptRuntime {
+    pub(crate) roots: Vec<PathBuf>,
+    /// User-selected executable, tried before every other candidate.
+    pub(crate) custom_command: Arc<Mutex<Option<PathBuf>>>,
+    pub(crate) config_dir: Option<PathBuf>,
+    /// Directories holding the ICC profiles shipped with the app, in lookup order.
+    pub(crate) icc_profile_dirs: Vec<PathBuf>,
+    /// The candidate that last ran, so later runs skip the search.
+    pub(crate) resolved: Arc<Mutex<Option<ResolvedGhostscript>>>,
+    /// Output devices of the resolved binary, from `gs -h`; cleared with `resolved`.
+    pub(crate) devices: Arc<Mutex<Option<Vec<String>>>>,
+}
+
+#[derive(Clone)]
+pub(crate) struct ResolvedGhostscript {
+    pub(crate) candidate: GhostscriptCandidate,
+    /// `GS_LIB` and friends for a bundled runtime.
+    pub(crate) envs: HashMap<&'static str, String>,
+}
+
+const GHOSTSCRIPT_CONFIG_FILE: &str = "ghostscript.json";
+
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+struct GhostscriptConfig {
+    #[serde(default)]
+    pub(crate) custom_path: Option<String>,
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct GhostscriptProbeLog {
+    pub(crate) attempted: Vec<String>,
+    pub(crate) selected: Option<String>,
+    pub(crate) selected_source: Option<GhostscriptSource>,
+    pub(crate) last_error: Option<String>,
+}
+
+/// A Ghostscript release number; ordering compares releases.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+pub(crate) struct GhostscriptVersion {
+    pub(crate) major: u32,
+    pub(crate) minor: u32,
+    pub(crate) patch: u32,
+}
+
+/// Oldest release whose output the app trusts; older ones write broken PDF/A and PDF/X.
+pub(crate) const MIN_GHOSTSCRIPT_VERSION: GhostscriptVersion = GhostscriptVersion {
+    major: 9,
+    minor: 53,
+    patch: 0,
+};
+
+#[derive(Clone)]
+pub(crate) struct GhostscriptCandidate {
+    pub(crate) command: PathBuf,
+    pub(crate) gs_root: Option<PathBuf>,
+    pub(crate) source: GhostscriptSource,
+}
+
+/// Where a candidate came from; determines whether it must exist on disk to be tried.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum GhostscriptSource {
+    /// Named by one of `GHOSTSCRIPT_ENV_VARS`.
+    Environment(&'static str),
+    Custom,
+    Bundled,
+    /// Registered by the Ghostscript installer on Windows.
+    #[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+    Registry,
+    System,
+}
+
+pub(crate) struct GhostscriptExecOutput {
+    /// What `parse_ghostscript_warnings` found in stderr and stdout.
+    pub(crate) warnings: Vec<GhostscriptWarning>,
+    /// Empty for piped runs, whose stdout is binary.
+    pub(crate) stdout: String,
+    pub(crate) stderr: String,
+    /// Stdout of a piped run (see `GhostscriptHooks::stdin`), byte for byte.
+    stdout_bytes: Vec<u8>,
+}
+
+impl GhostscriptRuntime {
+    pub(crate) fn bundled_icc_profile(&self, name: &str) -> Option<PathBuf> {
+        self.icc_profile_dirs
+            .iter()
+            .map(|dir| dir.join(name))
+            .find(|path| path.is_file())
+    }
+
+    pub(crate) fn custom_command(&self) -> Option<PathBuf> {
+        self.custom_command
+            .lock()
+            .expect("custom ghostscript mutex poisoned")
+            .clone()
+    }
+
+    pub(crate) fn set_custom_command(&self, command: Option<PathBuf>) -> Result<(), CommandError> {
+        if let Some(config_dir) = self.config_dir.as_deref() {
+            let config = GhostscriptConfig {
+                custom_path: command.as_ref().map(|p| p.to_string_lossy().to_string()),
+            };
+            save_ghostscript_config(config_dir, &config)?;
+        }
+        *self
+            .custom_command
+            .lock()
+            .expect("custom ghostscript mutex poisoned") = command;
+        self.invalidate_resolved();
+        Ok(())
+    }
+
+    /// The cached candidate, unless its executable has since disappeared.
+    pub(crate) fn resolved(&self) -> Option<ResolvedGhostscript> {
+        let mut resolved = self
+            .resolved
+            .lock()
+            .expect("resolved ghostscript mutex poisoned");
+        let command = &resolved.as_ref()?.candidate.command;
+        // Bare command names are resolved through PATH by the OS.
+        if command.is_absolute() && !command.is_file() {
+            log_info!(
+                "Cached Ghostscript '{}' is gone; searching again",
+                command.display()
+            );
+            *resolved = None;
+            self.clear_devices();
+        }
+        resolved.clone()
+    }
+
+    pub(crate) fn set_resolved(&self, resolved: ResolvedGhostscript) {
+        *self
+            .resolved
+            .lock()
+            .expect("resolved ghostscript mutex poisoned") = Some(resolved);
+    }
+
+    pub(crate) fn invalidate_resolved(&self) {
+        *self
+            .resolved
+            .lock()
+            .expect("resolved ghostscript mutex poisoned") = None;
+        self.clear_devices();
+    }
+
+    fn clear_devices(&self) {
+        *self
+            .devices
+            .lock()
+            .expect("ghostscript devices mutex poisoned") = None;
+    }
+
+    /// The output devices `gs -h` lists, queried once per resolved binary.
+    pub(crate) fn devices(&self, hooks: GhostscriptHooks<'_>) -> Result<Vec<String>, CommandError> {
+        if let Some(devices) = self
+            .devices
+            .lock()
+            .expect("ghostscript devices mutex poisoned")
+            .clone()
+        {
+            return Ok(devices);
+        }
+        let output = run_ghostscript(&["-h"], self, None, hooks)?;
+        let devices = parse_ghostscript_devices(&output.stdout);
+        *self
+            .devices
+            .lock()
+            .expect("ghostscript devices mutex poisoned") = Some(devices.clone());
+        Ok(devices)
+    }
+
+    /// Whether the Ghostscript in effect has `device`. `true` when its devices can't be
+    /// listed, leaving the verdict to the run itself.
+    fn has_device(&self, device: &str, hooks: GhostscriptHooks<'_>) -> bool {
+        self.devices(hooks)
+            .map_or(true, |devices| devices.iter().any(|name| name == device))
+    }
+
+    pub(crate) fn mac_root_string(&self) -> Option<String> {
+        if !cfg!(target_os = "macos") {
+            return None;
+        }
+        self.roots.first().map(|p| p.to_string_lossy().to_string())
+    }
+
+    pub(crate) fn windows_root_string(&self) -> Option<String> {
+        if !cfg!(target_os = "windows") {
+            return None;
+        }
+        self.roots.first().map(|p| p.to_string_lossy().to_string())
+    }
+}
+
+impl JobControl {
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        let mut guard = self.child.lock().expect("job child mutex poisoned");
+        if let Some(child) = guard.as_mut() {
+            let _ = child.kill();
+        }
+    }
+}
+
+impl<'a> JobContext<'a> {
+    pub(crate) fn collecting(self, warnings: &'a WarningLog) -> Self {
+        JobContext {
+            warnings: Some(warnings),
+            ..self
+        }
+    }
+
+    pub(crate) fn hooks(self) -> GhostscriptHooks<'a> {
+        GhostscriptHooks {
+            job: self.control,
+            on_stdout_line: None,
+            stdin: None,
+        }
+    }
+}
+
+#[cfg(target_os = "windows")]
+const GHOSTSCRIPT_FALLBACK_COMMANDS: [&str; 3] = ["gswin64c", "gswin32c", "gs"];
+
+#[cfg(target_os = "macos")]
+const GHOSTSCRIPT_FALLBACK_COMMANDS: [&str; 4] = [
+    "gs",
+    "/opt/homebrew/bin/gs",
+    "/usr/local/bin/gs",
+    "/opt/local/bin/gs",
+];
+
+#[cfg(all(not(target_os = "windows"), not(target_os = "macos")))]
+const GHOSTSCRIPT_FALLBACK_COMMANDS: [&str; 1] = ["gs"];
+
+/// Library and font directories of an app-local Ghostscript tree.
+#[derive(Default)]
+struct GhostscriptResourceDirs {
+    lib: Vec<PathBuf>,
+    pub(crate) fonts: Vec<PathBuf>,
+}
+
+/// Finds the resource directories of a bundled runtime. Unix builds keep them under
+/// `share/ghostscript`, flattened (e.g. Homebrew) or in a versioned subdirectory, while
+/// the Windows distribution places `lib`, `Resource` and `fonts` next to `bin`, either
+/// directly under the root or in a versioned `gs*` folder.
+fn ghostscript_resource_dirs(gs_root: &Path) -> GhostscriptResourceDirs {
+    let mut bases = Vec::new();
+    for base in [
+        gs_root.join("share").join("ghostscript"),
+        gs_root.to_path_buf(),
+    ] {
+        if !base.is_dir() {
+            continue;
+        }
+        if let Ok(dirs) = std::fs::read_dir(&base) {
+            bases.extend(
+                dirs.flatten()
+                    .map(|entry| entry.path())
+                    .filter(|p| p.is_dir()),
+            );
+        }
+        bases.push(base);
+    }
+
+    let mut dirs = GhostscriptResourceDirs::default();
+    for base in &bases {
+        for lib in [
+            base.join("lib"),
+            base.join("Resource"),
+            base.join("Resource").join("Init"),
+            base.join("iccprofiles"),
+        ] {
+            if lib.is_dir() {
+                dirs.lib.push(lib);
+            }
+        }
+        for fonts in [base.join("Resource").join("Font"), base.join("fonts")] {
+            if fonts.is_dir() {
+                dirs.fonts.push(fonts);
+            }
+        }
+    }
+    for list in [&mut dirs.lib, &mut dirs.fonts] {
+        list.sort();
+        list.dedup();
+    }
+    dirs
+}
+
+/// Joins directories with the platform's search-path separator (`;` on Windows, `:`
+/// elsewhere).
+fn join_search_path(dirs: &[PathBuf]) -> Option<String> {
+    if dirs.is_empty() {
+        return None;
+    }
+    std::env::join_paths(dirs)
+        .ok()
+        .map(|joined| joined.to_string_lossy().to_string())
+}
+
+pub(crate) fn collect_ghostscript_env(gs_root: &Path) -> HashMap<&'static str, String> {
+    let dirs = ghostscript_resource_dirs(gs_root);
+    let mut envs = HashMap::new();
+    // Font directories go on GS_LIB too, so `Fontmap` entries that name files resolve.
+    let lib_path = [dirs.lib.as_slice(), dirs.fonts.as_slice()].concat();
+    if let Some(gs_lib) = join_search_path(&lib_path) {
+        envs.insert("GS_LIB", gs_lib);
+    }
+    if let Some(font_path) = join_search_path(&dirs.fonts) {
+        envs.insert("GS_FONTPATH", font_path);
+    }
+    envs
+}
+
+/// Per-invocation hooks for `run_ghostscript`: an optional cancellation handle and an
+/// optional callback that sees each stdout line as Ghostscript prints it.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct GhostscriptHooks<'a> {
+    pub(crate) job: Option<&'a JobControl>,
+    /// Sees Ghostscript's messages. With `stdin` set they arrive on stderr instead, since
+    /// stdout then carries the output file.
+    on_stdout_line: Option<&'a (dyn Fn(&str) + Sync)>,
+    /// Input fed through stdin; stdout is then kept as raw bytes in `stdout_bytes`.
+    stdin: Option<&'a [u8]>,
+}
+
+impl GhostscriptHooks<'_> {
+    fn is_cancelled(&self) -> bool {
+        self.job.is_some_and(JobControl::is_cancelled)
+    }
+
+    /// The job's own timeout, else the one from the settings.
+    fn timeout(&self) -> Option<std::time::Duration> {
+        let secs = self
+            .job
+            .and_then(|job| job.timeout_secs)
+            .unwrap_or_else(|| GHOSTSCRIPT_TIMEOUT_SECS.load(Ordering::Relaxed));
+        (secs > 0).then(|| std::time::Duration::from_secs(secs))
+    }
+}
+
+fn read_pipe<R: std::io::Read>(pipe: R, on_line: Option<&(dyn Fn(&str) + Sync)>) -> Vec<u8> {
+    use std::io::BufRead;
+
+    let mut buffer = Vec::new();
+    match on_line {
+        None => {
+            let mut pipe = pipe;
+            let _ = pipe.read_to_end(&mut buffer);
+        }
+        Some(on_line) => {
+            let mut reader = std::io::BufReader::new(pipe);
+            let mut line = Vec::new();
+            loop {
+                line.clear();
+                match reader.read_until(b'\n', &mut line) {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => {
+                        on_line(decode_process_output(&line).trim_end());
+                        buffer.extend_from_slice(&line);
+                    }
+                }
+            }
+        }
+    }
+    buffer
+}
+
+fn wait_for_child(
+    child: std::process::Child,
+    job: Option<&JobControl>,
+    timeout: Option<std::time::Duration>,
+) -> std::io::Result<std::process::ExitStatus> {
+    let local_job;
+    let job = match job {
+        Some(job) => job,
+        None if timeout.is_none() => {
+            let mut child = child;
+            return child.wait();
+        }
+        None => {
+            local_job = JobControl::default();
+            &local_job
+        }
+    };
+    let deadline = timeout.map(|timeout| std::time::Instant::now() + timeout);
+
+    *job.child.lock().expect("job child mutex poisoned") = Some(child);
+    if job.is_cancelled() {
+        job.cancel();
+    }
+    loop {
+        let mut guard = job.child.lock().expect("job child mutex poisoned");
+        let waited = guard
+            .as_mut()
+            .expect("job child taken while waiting")
+            .try_wait();
+        match waited {
+            Ok(Some(status)) => {
+                guard.take();
+                return Ok(status);
+            }
+            Ok(None) if deadline.is_some_and(|deadline| std::time::Instant::now() >= deadline) => {
+                if let Some(mut child) = guard.take() {
+                    drop(guard);
+                    terminate_child(&mut child);
+                }
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    "Ghostscript timed out",
+                ));
+            }
+            Ok(None) => {}
+            Err(e) => {
+                if let Some(mut child) = guard.take() {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                }
+                return Err(e);
+            }
+        }
+        drop(guard);
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+}
+
+/// Stop a runaway child. On Unix it gets SIGTERM and `GHOSTSCRIPT_TERMINATE_GRACE` to
+/// exit before SIGKILL; on Windows `Child::kill` is TerminateProcess, which can't be
+/// ignored.
+fn terminate_child(child: &mut std::process::Child) {
+    #[cfg(unix)]
+    {
+        extern "C" {
+            fn kill(pid: i32, sig: i32) -> i32;
+        }
+        const SIGTERM: i32 = 15;
+
+        if let Ok(pid) = i32::try_from(child.id()) {
+            // SAFETY: `pid` is our own child, which has not been reaped yet, so the ID
+            // can't have been reused by another process.
+            unsafe {
+                kill(pid, SIGTERM);
+            }
+            let deadline = std::time::Instant::now() + GHOSTSCRIPT_TERMINATE_GRACE;
+            while std::time::Instant::now() < deadline {
+                if let Ok(Some(_)) = child.try_wait() {
+                    return;
+                }
+                std::thread::sleep(std::time::Duration::from_millis(50));
+            }
+        }
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+/// Lists a child in `RUNNING_CHILDREN` until dropped, which happens once it was waited for.
+struct ChildRegistration(u32);
+
+impl ChildRegistration {
+    pub(crate) fn new(pid: u32) -> Self {
+        RUNNING_CHILDREN
+            .lock()
+            .expect("child registry mutex poisoned")
+            .push(pid);
+        ChildRegistration(pid)
+    }
+}
+
+impl Drop for ChildRegistration {
+    fn drop(&mut self) {
+        RUNNING_CHILDREN
+            .lock()
+            .expect("child registry mutex poisoned")
+            .retain(|&pid| pid != self.0);
+    }
+}
+
+/// Send `signal` to each PID on Unix; Windows has no gentle stop for console children,
+/// so any signal there is TerminateProcess.
+pub(crate) fn signal_children(
+    pids: &[u32],
+    #[cfg_attr(windows, allow(unused_variables))] signal: i32,
+) {
+    #[cfg(unix)]
+    {
+        extern "C" {
+            fn kill(pid: i32, sig: i32) -> i32;
+        }
+        for &pid in pids {
+            if let Ok(pid) = i32::try_from(pid) {
+                // SAFETY: the caller holds the `RUNNING_CHILDREN` lock, and children leave
+                // it only after being reaped, so each PID is still one of ours.
+                unsafe {
+                    kill(pid, signal);
+                }
+            }
+        }
+    }
+    #[cfg(windows)]
+    {
+        #[link(name = "kernel32")]
+        extern "system" {
+            fn OpenProcess(access: u32, inherit: i32, pid: u32) -> *mut std::ffi::c_void;
+            fn TerminateProcess(process: *mut std::ffi::c_void, exit_code: u32) -> i32;
+            fn CloseHandle(handle: *mut std::ffi::c_void) -> i32;
+        }
+        const PROCESS_TERMINATE: u32 = 0x0001;
+
+        for &pid in pids {
+            // SAFETY: the handle is checked before use and closed right after; a PID that
+            // already exited just fails to open.
+            unsafe {
+                let process = OpenProcess(PROCESS_TERMINATE, 0, pid);
+                if !process.is_null() {
+                    TerminateProcess(process, 1);
+                    CloseHandle(process);
+                }
+            }
+        }
+    }
+}
+
+/// Wait up to `timeout` for `done`, polling.
+pub(crate) fn wait_until(timeout: std::time::Duration, done: impl Fn() -> bool) -> bool {
+    let deadline = std::time::Instant::now() + timeout;
+    loop {
+        if done() {
+            return true;
+        }
+        if std::time::Instant::now() >= deadline {
+            return false;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+}
+
+/// Spawn the command and wait for it while draining both pipes. When a job is given,
+/// the child is parked in it so `cancel_job` can kill it from another thread.
+fn spawn_and_wait(
+    cmd: &mut std::process::Command,
+    hooks: GhostscriptHooks<'_>,
+) -> std::io::Result<std::process::Output> {
+    use std::process::Stdio;
+
+    if SHUTTING_DOWN.load(Ordering::SeqCst) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Interrupted,
+            "the app is closing",
+        ));
+    }
+    let mut child = cmd
+        .stdin(match hooks.stdin {
+            Some(_) => Stdio::piped(),
+            None => Stdio::null(),
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let _registration = ChildRegistration::new(child.id());
+    let stdin_pipe = child.stdin.take();
+    let stdout_pipe = child.stdout.take();
+    let stderr_pipe = child.stderr.take();
+    let (stdout_line, stderr_line) = match hooks.stdin {
+        Some(_) => (None, hooks.on_stdout_line),
+        None => (hooks.on_stdout_line, None),
+    };
+
+    std::thread::scope(|scope| {
+        // Written from its own thread so a child blocked on a full stdout pipe can't
+        // deadlock the write; dropping the pipe afterwards signals end of input.
+        if let (Some(mut pipe), Some(input)) = (stdin_pipe, hooks.stdin) {
+            scope.spawn(move || {
+                use std::io::Write;
+                let _ = pipe.write_all(input);
+            });
+        }
+        let stdout_reader =
+            stdout_pipe.map(|pipe| scope.spawn(move || read_pipe(pipe, stdout_line)));
+        let stderr_reader =
+            stderr_pipe.map(|pipe| scope.spawn(move || read_pipe(pipe, stderr_line)));
+
+        let status = wait_for_child(child, hooks.job, hooks.timeout())?;
+
+        let stdout = stdout_reader
+            .and_then(|reader| reader.join().ok())
+            .unwrap_or_default();
+        let stderr = stderr_reader
+            .and_then(|reader| reader.join().ok())
+            .unwrap_or_default();
+        Ok(std::process::Output {
+            status,
+            stdout,
+            stderr,
+        })
+    })
+}
+
+/// The first failure in `GHOSTSCRIPT_FAILURE_PATTERNS` that `output` shows.
+pub(crate) fn diagnose_ghostscript_output(output: &str) -> Option<GhostscriptFailure> {
+    let output = output.to_lowercase();
+    GHOSTSCRIPT_FAILURE_PATTERNS
+        .iter()
+        .find(|(pattern, _)| output.contains(pattern))
+        .map(|&(_, failure)| failure)
+}
+
+impl GhostscriptFailure {
+    pub(crate) fn code(self) -> &'static str {
+        match self {
+            GhostscriptFailure::PasswordRequired => "password_required",
+            GhostscriptFailure::DamagedFile => "gs_damaged_file",
+            GhostscriptFailure::UnknownDevice => "gs_unknown_device",
+            GhostscriptFailure::OutOfMemory => "gs_out_of_memory",
+            GhostscriptFailure::InvalidFont => "gs_invalid_font",
+            GhostscriptFailure::FileNotFound => "gs_file_not_found",
+            GhostscriptFailure::FileAccessDenied => "gs_file_access_denied",
+        }
+    }
+
+    pub(crate) fn message(self) -> &'static str {
+        match self {
+            GhostscriptFailure::PasswordRequired => "This PDF is password protected.",
+            GhostscriptFailure::DamagedFile => "The PDF is damaged and could not be read.",
+            GhostscriptFailure::UnknownDevice => {
+                "This Ghostscript build lacks the output device the job needs."
+            }
+            GhostscriptFailure::OutOfMemory => "Ghostscript ran out of memory.",
+            GhostscriptFailure::InvalidFont => "A font in the PDF is broken or unsupported.",
+            GhostscriptFailure::FileNotFound => "Ghostscript could not find a file it needed.",
+            GhostscriptFailure::FileAccessDenied => "Ghostscript was not allowed to open a file.",
+        }
+    }
+
+    pub(crate) fn suggestion(self) -> &'static str {
+        match self {
+            GhostscriptFailure::PasswordRequired => "Supply the password and try again.",
+            GhostscriptFailure::DamagedFile => {
+                "Try repairing it first, or export it again from the app that created it."
+            }
+            GhostscriptFailure::UnknownDevice => {
+                "Install a complete Ghostscript or choose another one in the settings."
+            }
+            GhostscriptFailure::OutOfMemory => {
+                "Close other apps, or process fewer pages or a lower resolution at a time."
+            }
+            GhostscriptFailure::InvalidFont => {
+                "Export the PDF again with its fonts embedded, or flatten it first."
+            }
+            GhostscriptFailure::FileNotFound => {
+                "Check that the file still exists and that its drive is connected."
+            }
+            GhostscriptFailure::FileAccessDenied => {
+                "Move the file to a folder you can write to, or give it a plainer name."
+            }
+        }
+    }
+}
+
+/// `args` as written to the log, with password values hidden.
+fn redacted_ghostscript_args(args: &[&str]) -> Vec<String> {
+    args.iter()
+        .map(|arg| {
+            match SECRET_GHOSTSCRIPT_ARGS
+                .iter()
+                .find(|prefix| arg.starts_with(*prefix))
+            {
+                Some(prefix) => format!("{}***", prefix),
+                None => arg.to_string(),
+            }
+        })
+        .collect()
+}
+
+impl WarningLog {
+    /// Add `warnings`, counting repeats instead of listing them again.
+    pub(crate) fn record(&self, warnings: &[GhostscriptWarning]) {
+        let mut recorded = self.0.lock().expect("warning log mutex poisoned");
+        for warning in warnings {
+            match recorded
+                .iter_mut()
+                .find(|seen| seen.code == warning.code && seen.message == warning.message)
+            {
+                Some(seen) => seen.count += warning.count,
+                None => recorded.push(warning.clone()),
+            }
+        }
+    }
+
+    pub(crate) fn into_warnings(self) -> Vec<GhostscriptWarning> {
+        self.0.into_inner().expect("warning log mutex poisoned")
+    }
+}
+
+/// The lines of `output` that match `GHOSTSCRIPT_WARNING_PATTERNS`, each listed once. A
+/// line that also shows a `GHOSTSCRIPT_FAILURE_PATTERNS` failure gets that failure's code.
+fn parse_ghostscript_warnings(output: &str) -> Vec<GhostscriptWarning> {
+    let mut warnings: Vec<GhostscriptWarning> = Vec::new();
+    for line in output.lines() {
+        let lower = line.to_lowercase();
+        let Some(&(_, code)) = GHOSTSCRIPT_WARNING_PATTERNS
+            .iter()
+            .find(|(pattern, _)| lower.contains(pattern))
+        else {
+            continue;
+        };
+        let code = diagnose_ghostscript_output(line).map_or(code, GhostscriptFailure::code);
+        let message = line.trim().trim_start_matches('*').trim();
+        match warnings.iter_mut().find(|seen| seen.message == message) {
+            Some(seen) => seen.count += 1,
+            None => warnings.push(GhostscriptWarning {
+                code: code.to_string(),
+                message: message.to_string(),
+                count: 1,
+            }),
+        }
+    }
+    warnings
+}
+
+/// Text a child printed. Ghostscript writes UTF-8 for its own messages but passes
+/// through system error text, which on Windows is in the ANSI code page (e.g. CP1251
+/// on Russian systems); bytes that aren't UTF-8 are decoded from that code page there,
+/// and lossily as UTF-8 elsewhere.
+pub(crate) fn decode_process_output(bytes: &[u8]) -> String {
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        return text.to_string();
+    }
+    #[cfg(windows)]
+    {
+        #[link(name = "kernel32")]
+        extern "system" {
+            fn MultiByteToWideChar(
+                code_page: u32,
+                flags: u32,
+                multi_byte: *const u8,
+                multi_byte_len: i32,
+                wide: *mut u16,
+                wide_len: i32,
+            ) -> i32;
+        }
+        const CP_ACP: u32 = 0;
+
+        if let Ok(len) = i32::try_from(bytes.len()) {
+            // SAFETY: the first call only measures; the second writes at most `needed`
+            // UTF-16 units into a buffer of exactly that size.
+            let mut wide = Vec::new();
+            let needed = unsafe {
+                MultiByteToWideChar(CP_ACP, 0, bytes.as_ptr(), len, std::ptr::null_mut(), 0)
+            };
+            if needed > 0 {
+                wide.resize(needed as usize, 0);
+                let written = unsafe {
+                    MultiByteToWideChar(CP_ACP, 0, bytes.as_ptr(), len, wide.as_mut_ptr(), needed)
+                };
+                if written > 0 {
+                    wide.truncate(written as usize);
+                    return String::from_utf16_lossy(&wide);
+                }
+            }
+        }
+    }
+    String::from_utf8_lossy(bytes).to_string()
+}
+
+fn record_attempt(log: &mut GhostscriptProbeLog, command: &Path) {
+    log.attempted.push(command.to_string_lossy().to_string());
+}
+
+pub(crate) fn run_candidate(
+    candidate: &GhostscriptCandidate,
+    envs: &HashMap<&'static str, String>,
+    args: &[&str],
+    log: &mut GhostscriptProbeLog,
+    hooks: GhostscriptHooks<'_>,
+) -> Result<GhostscriptExecOutput, CommandError> {
+    record_attempt(log, &candidate.command);
+
+    let mut cmd = std::process::Command::new(&candidate.command);
+    cmd.args(args);
+    cmd.envs(envs);
+
+    let started = std::time::Instant::now();
+    let result = spawn_and_wait(&mut cmd, hooks);
+    let elapsed_ms = started.elapsed().as_millis();
+    match &result {
+        Ok(output) => log_debug!(
+            "Ran {} {:?} in {} ms: {}",
+            candidate.command.display(),
+            redacted_ghostscript_args(args),
+            elapsed_ms,
+            output.status
+        ),
+        Err(e) => log_debug!(
+            "Could not run {} {:?}: {}",
+            candidate.command.display(),
+            redacted_ghostscript_args(args),
+            e
+        ),
+    }
+    match result {
+        Ok(output) if output.status.success() => {
+            let stderr = decode_process_output(&output.stderr);
+            log.selected = Some(candidate.command.to_string_lossy().to_string());
+            log.selected_source = Some(candidate.source);
+            let (stdout, stdout_bytes) = match hooks.stdin {
+                Some(_) => (String::new(), output.stdout),
+                None => (decode_process_output(&output.stdout), Vec::new()),
+            };
+            Ok(GhostscriptExecOutput {
+                warnings: parse_ghostscript_warnings(&[stderr.as_str(), &stdout].join("\n")),
+                stdout,
+                stderr,
+                stdout_bytes,
+            })
+        }
+        Ok(output) => {
+            let stderr = decode_process_output(&output.stderr);
+            let stdout = match hooks.stdin {
+                Some(_) => String::new(),
+                None => decode_process_output(&output.stdout),
+            };
+            let text = [stderr.trim(), stdout.trim()]
+                .iter()
+                .filter(|text| !text.is_empty())
+                .copied()
+                .collect::<Vec<_>>()
+                .join("\n");
+            let error = format!(
+                "Ghostscript command '{}' failed with exit code {}: {}",
+                candidate.command.display(),
+                output
+                    .status
+                    .code()
+                    .map_or_else(|| String::from("none"), |code| code.to_string()),
+                text
+            );
+            log_warn!("{}", error);
+            log.last_error = Some(error);
+            Err(ghostscript_failure(
+                output.status.code(),
+                text,
+                &output.stderr,
+            ))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {
+            let after_secs = hooks.timeout().map_or(0, |timeout| timeout.as_secs());
+            log_warn!(
+                "Killed {} {:?} after {} s",
+                candidate.command.display(),
+                redacted_ghostscript_args(args),
+                after_secs
+            );
+            Err(CommandError::Timeout { after_secs })
+        }
+        // `spawn_and_wait` refuses to start anything once the app is closing.
+        Err(e) if e.kind() == std::io::ErrorKind::Interrupted => Err(CommandError::Cancelled),
+        Err(e) => {
+            let error = format!(
+                "Failed to execute Ghostscript command '{}': {}",
+                candidate.command.display(),
+                e
+            );
+            log.last_error = Some(error.clone());
+            Err(CommandError::GsNotFound(error))
+        }
+    }
+}
+
+/// The error of a run that exited unsuccessfully; `output` is its stderr followed by its
+/// stdout. `stderr_bytes` are kept when they weren't UTF-8, since `output` is decoded.
+fn ghostscript_failure(
+    exit_code: Option<i32>,
+    output: String,
+    stderr_bytes: &[u8],
+) -> CommandError {
+    let failure = diagnose_ghostscript_output(&output);
+    if failure == Some(GhostscriptFailure::PasswordRequired) {
+        return CommandError::PasswordRequired;
+    }
+    CommandError::GsFailed {
+        failure,
+        exit_code,
+        stderr: output,
+        stderr_bytes: std::str::from_utf8(stderr_bytes)
+            .is_err()
+            .then(|| stderr_bytes.to_vec()),
+    }
+}
+
+/// Environment variables that point at a Ghostscript executable, checked in order.
+const GHOSTSCRIPT_ENV_VARS: [&str; 2] = ["PDFRESIZER_GS", "GS_EXECUTABLE"];
+
+pub(crate) fn ghostscript_env_override() -> Option<(&'static str, PathBuf)> {
+    GHOSTSCRIPT_ENV_VARS.iter().find_map(|name| {
+        std::env::var_os(name)
+            .filter(|value| !value.is_empty())
+            .map(|value| (*name, PathBuf::from(value)))
+    })
+}
+
+fn is_executable_file(path: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        path.metadata()
+            .map(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+            .unwrap_or(false)
+    }
+    #[cfg(not(unix))]
+    {
+        path.is_file()
+    }
+}
+
+impl GhostscriptSource {
+    pub(crate) fn label(self) -> &'static str {
+        match self {
+            GhostscriptSource::Environment(_) => "environment",
+            GhostscriptSource::Custom => "custom",
+            GhostscriptSource::Bundled => "bundled",
+            GhostscriptSource::Registry => "registry",
+            GhostscriptSource::System => "path",
+        }
+    }
+}
+
+impl std::fmt::Display for GhostscriptVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{:02}", self.major, self.minor)?;
+        if self.patch > 0 {
+            write!(f, ".{}", self.patch)?;
+        }
+        Ok(())
+    }
+}
+
+/// The names under `Available devices:` in `gs -h` output. The list is wrapped over
+/// indented lines and ends at the next unindented heading.
+fn parse_ghostscript_devices(help: &str) -> Vec<String> {
+    let mut lines = help.lines();
+    if !lines
+        .by_ref()
+        .any(|line| line.trim().eq_ignore_ascii_case("available devices:"))
+    {
+        return Vec::new();
+    }
+    lines
+        .take_while(|line| line.starts_with(char::is_whitespace) || line.trim().is_empty())
+        .flat_map(str::split_whitespace)
+        .map(String::from)
+        .collect()
+}
+
+/// Parse `--version` output such as `10.02.1` or `9.21`; a missing patch is 0.
+pub(crate) fn parse_ghostscript_version(text: &str) -> Option<GhostscriptVersion> {
+    let mut parts = text.trim().split('.');
+    let mut next = || -> Option<u32> {
+        let digits: String = parts
+            .next()?
+            .chars()
+            .take_while(char::is_ascii_digit)
+            .collect();
+        digits.parse().ok()
+    };
+    let major = next()?;
+    let minor = next()?;
+    Some(GhostscriptVersion {
+        major,
+        minor,
+        patch: next().unwrap_or(0),
+    })
+}
+
+/// Refuse with `CommandError::GsTooOld` when the Ghostscript in effect predates
+/// `MIN_GHOSTSCRIPT_VERSION`. An unparseable version passes.
+pub(crate) fn require_minimum_ghostscript(
+    runtime: &GhostscriptRuntime,
+    hooks: GhostscriptHooks<'_>,
+) -> Result<(), CommandError> {
+    require_ghostscript_version(MIN_GHOSTSCRIPT_VERSION, runtime, hooks)
+}
+
+/// Refuse with `CommandError::GsTooOld` when the Ghostscript in effect predates
+/// `required`, for features newer than the app-wide minimum. An unparseable version passes.
+pub(crate) fn require_ghostscript_version(
+    required: GhostscriptVersion,
+    runtime: &GhostscriptRuntime,
+    hooks: GhostscriptHooks<'_>,
+) -> Result<(), CommandError> {
+    let output = run_ghostscript(&["--version"], runtime, None, hooks)?;
+    match parse_ghostscript_version(&output.stdout) {
+        Some(version) if version < required => Err(CommandError::GsTooOld {
+            found: version.to_string(),
+            required: required.to_string(),
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Every Ghostscript candidate in the order it should be tried: environment override,
+/// user-configured path, app-bundled runtimes, then system installs.
+pub(crate) fn collect_candidates(
+    runtime: &GhostscriptRuntime,
+    env_override: Option<(&'static str, PathBuf)>,
+) -> Vec<GhostscriptCandidate> {
+    let mut candidates = Vec::new();
+
+    if let Some((name, command)) = env_override {
+        candidates.push(GhostscriptCandidate {
+            command,
+            gs_root: None,
+            source: GhostscriptSource::Environment(name),
+        });
+    }
+
+    if let Some(command) = runtime.custom_command() {
+        candidates.push(GhostscriptCandidate {
+            command,
+            gs_root: None,
+            source: GhostscriptSource::Custom,
+        });
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    {
+        for root in &runtime.roots {
+            candidates.push(GhostscriptCandidate {
+                command: root.join("bin").join("gs"),
+                gs_root: Some(root.clone()),
+                source: GhostscriptSource::Bundled,
+            });
+            candidates.push(GhostscriptCandidate {
+                command: root.join("gs"),
+                gs_root: Some(root.clone()),
+                source: GhostscriptSource::Bundled,
+            });
+        }
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        for root in &runtime.roots {
+            candidates.push(GhostscriptCandidate {
+                command: root.join("bin").join("gswin64c.exe"),
+                gs_root: Some(root.clone()),
+                source: GhostscriptSource::Bundled,
+            });
+            candidates.push(GhostscriptCandidate {
+                command: root.join("bin").join("gswin32c.exe"),
+                gs_root: Some(root.clone()),
+                source: GhostscriptSource::Bundled,
+            });
+        }
+    }
+
+    #[cfg(target_os = "windows")]
+    for bin in registry_ghostscript_bins() {
+        let console = bin.join("gswin64c.exe");
+        candidates.push(GhostscriptCandidate {
+            command: if console.is_file() {
+                console
+            } else {
+                bin.join("gswin32c.exe")
+            },
+            gs_root: None,
+            source: GhostscriptSource::Registry,
+        });
+    }
+
+    // Last fallback to system Ghostscript on PATH.
+    for command in GHOSTSCRIPT_FALLBACK_COMMANDS {
+        candidates.push(GhostscriptCandidate {
+            command: PathBuf::from(command),
+            gs_root: None,
+            source: GhostscriptSource::System,
+        });
+    }
+
+    candidates
+}
+
+/// `bin` folders of the installs registered under `HKLM\SOFTWARE\GPL Ghostscript` and
+/// its Artifex and 32-bit counterparts, newest version first.
+#[cfg(target_os = "windows")]
+fn registry_ghostscript_bins() -> Vec<PathBuf> {
+    use std::os::windows::ffi::{OsStrExt, OsStringExt};
+
+    #[link(name = "advapi32")]
+    extern "system" {
+        fn RegOpenKeyExW(
+            key: isize,
+            sub_key: *const u16,
+            options: u32,
+            desired: u32,
+            result: *mut isize,
+        ) -> i32;
+        fn RegEnumKeyExW(
+            key: isize,
+            index: u32,
+            name: *mut u16,
+            name_length: *mut u32,
+            reserved: *mut u32,
+            class: *mut u16,
+            class_length: *mut u32,
+            last_write: *mut std::ffi::c_void,
+        ) -> i32;
+        fn RegGetValueW(
+            key: isize,
+            sub_key: *const u16,
+            value: *const u16,
+            flags: u32,
+            kind: *mut u32,
+            data: *mut std::ffi::c_void,
+            data_length: *mut u32,
+        ) -> i32;
+        fn RegCloseKey(key: isize) -> i32;
+    }
+    // The predefined handle is the sign-extended 0x80000002.
+    const HKEY_LOCAL_MACHINE: isize = 0x8000_0002u32 as i32 as isize;
+    const KEY_READ: u32 = 0x2_0019;
+    const RRF_RT_REG_SZ: u32 = 0x2;
+    const KEYS: [&str; 3] = [
+        "SOFTWARE\\GPL Ghostscript",
+        "SOFTWARE\\Artifex Ghostscript",
+        "SOFTWARE\\WOW6432Node\\GPL Ghostscript",
+    ];
+
+    let wide = |text: &str| -> Vec<u16> {
+        std::ffi::OsStr::new(text)
+            .encode_wide()
+            .chain(std::iter::once(0))
+            .collect()
+    };
+    let value_name = wide("GS_DLL");
+    let mut installs: Vec<(String, PathBuf)> = Vec::new();
+    for key_path in KEYS {
+        let mut key = 0isize;
+        // SAFETY: the key path is NUL-terminated and `key` is a valid out pointer.
+        let status = unsafe {
+            RegOpenKeyExW(
+                HKEY_LOCAL_MACHINE,
+                wide(key_path).as_ptr(),
+                0,
+                KEY_READ,
+                &mut key,
+            )
+        };
+        if status != 0 {
+            continue;
+        }
+        for index in 0.. {
+            let mut name = [0u16; 256];
+            let mut name_length = name.len() as u32;
+            // SAFETY: `name` has room for `name_length` units; unused outputs are null.
+            let status = unsafe {
+                RegEnumKeyExW(
+                    key,
+                    index,
+                    name.as_mut_ptr(),
+                    &mut name_length,
+                    std::ptr::null_mut(),
+                    std::ptr::null_mut(),
+                    std::ptr::null_mut(),
+                    std::ptr::null_mut(),
+                )
+            };
+            if status != 0 {
+                break;
+            }
+            let mut data = [0u16; 1024];
+            let mut data_length = (data.len() * 2) as u32;
+            // SAFETY: `name` is NUL-terminated after enumeration and `data` has room for
+            // `data_length` bytes.
+            let status = unsafe {
+                RegGetValueW(
+                    key,
+                    name.as_ptr(),
+                    value_name.as_ptr(),
+                    RRF_RT_REG_SZ,
+                    std::ptr::null_mut(),
+                    data.as_mut_ptr().cast(),
+                    &mut data_length,
+                )
+            };
+            if status != 0 {
+                continue;
+            }
+            let length = data
+                .iter()
+                .position(|&unit| unit == 0)
+                .unwrap_or(data.len());
+            let dll = PathBuf::from(std::ffi::OsString::from_wide(&data[..length]));
+            if let Some(bin) = dll.parent() {
+                let version = String::from_utf16_lossy(&name[..name_length as usize]);
+                installs.push((version, bin.to_path_buf()));
+            }
+        }
+        // SAFETY: `key` was opened above.
+        unsafe { RegCloseKey(key) };
+    }
+    installs.sort_by(|a, b| natural_cmp(&b.0, &a.0));
+    installs.dedup_by(|a, b| a.1 == b.1);
+    installs.into_iter().map(|(_, bin)| bin).collect()
+}
+
+/// Every `dir/command` on `PATH` that is a file, in `PATH` order.
+pub(crate) fn find_on_path(command: &str) -> Vec<PathBuf> {
+    let Some(path) = std::env::var_os("PATH") else {
+        return Vec::new();
+    };
+    let file_name = if cfg!(target_os = "windows") {
+        format!("{}.exe", command)
+    } else {
+        command.to_string()
+    };
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(&file_name))
+        .filter(|candidate| candidate.is_file())
+        .collect()
+}
+
+pub(crate) fn run_ghostscript(
+    args: &[&str],
+    runtime: &GhostscriptRuntime,
+    probe: Option<&mut GhostscriptProbeLog>,
+    hooks: GhostscriptHooks<'_>,
+) -> Result<GhostscriptExecOutput, CommandError> {
+    let mut log = GhostscriptProbeLog::default();
+    let mut override_error = None;
+    // The last candidate that started but failed on the job.
+    let mut failed_run = None;
+
+    if let Some(resolved) = runtime.resolved() {
+        if hooks.is_cancelled() {
+            return Err(CommandError::Cancelled);
+        }
+        match run_candidate(&resolved.candidate, &resolved.envs, args, &mut log, hooks) {
+            Err(_) if hooks.is_cancelled() => return Err(CommandError::Cancelled),
+            // It no longer starts; search again below.
+            Err(CommandError::GsNotFound(_)) => runtime.invalidate_resolved(),
+            // It started, so a failure is the job's, not the binary's.
+            result => {
+                if let Some(probe_log) = probe {
+                    *probe_log = log;
+                }
+                return result;
+            }
+        }
+    }
+
+    for candidate in collect_candidates(runtime, ghostscript_env_override()) {
+        match candidate.source {
+            GhostscriptSource::Environment(name) => {
+                if !is_executable_file(&candidate.command) {
+                    override_error = Some(format!(
+                        "{} is set to '{}', which is not an executable file.",
+                        name,
+                        candidate.command.display()
+                    ));
+                    continue;
+                }
+            }
+            // Bare command names are resolved through PATH by the OS.
+            GhostscriptSource::System if !candidate.command.is_absolute() => {}
+            _ => {
+                if !candidate.command.exists() {
+                    continue;
+                }
+            }
+        }
+        if hooks.is_cancelled() {
+            return Err(CommandError::Cancelled);
+        }
+        let envs = candidate
+            .gs_root
+            .as_deref()
+            .map(collect_ghostscript_env)
+            .unwrap_or_default();
+        match run_candidate(&candidate, &envs, args, &mut log, hooks) {
+            Ok(output) => {
+                runtime.set_resolved(ResolvedGhostscript { candidate, envs });
+                if let Some(probe_log) = probe {
+                    *probe_log = log;
+                }
+                return Ok(output);
+            }
+            // Another candidate would only hang on the same input.
+            Err(error @ (CommandError::Timeout { .. } | CommandError::Cancelled)) => {
+                return Err(error)
+            }
+            Err(error) => {
+                if let GhostscriptSource::Environment(name) = candidate.source {
+                    override_error = Some(format!(
+                        "{} override failed: {}",
+                        name,
+                        log.last_error.as_deref().unwrap_or_default()
+                    ));
+                }
+                if !matches!(error, CommandError::GsNotFound(_)) {
+                    failed_run = Some(error);
+                }
+            }
+        }
+    }
+
+    if hooks.is_cancelled() {
+        return Err(CommandError::Cancelled);
+    }
+
+    let mut error = log
+        .last_error
+        .clone()
+        .unwrap_or_else(|| String::from("Ghostscript is not available."));
+    if let Some(override_error) = override_error {
+        if override_error != error {
+            error = format!("{}\n{}", override_error, error);
+        }
+    }
+    log_error!("No Ghostscript candidate worked: {}", error);
+    if let Some(probe_log) = probe {
+        *probe_log = log;
+    }
+    Err(failed_run.unwrap_or(CommandError::GsNotFound(error)))
+}
+
+/// PIDs of the Ghostscript children not reaped yet; see `ChildRegistration`.
+pub(crate) static RUNNING_CHILDREN: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+/// Set once the app starts exiting; no Ghostscript is spawned after that.
+pub(crate) static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
+/// How long exiting waits for killed jobs to wind down and remove their own temp files.
+pub(crate) const SHUTDOWN_WAIT: std::time::Duration = std::time::Duration::from_secs(3);
+
+/// Quote a string as a PostScript string literal, escaping delimiters.
+pub(crate) fn ps_string_literal(value: &str) -> String {
+    let mut literal = String::with_capacity(value.len() + 2);
+    literal.push('(');
+    for c in value.chars() {
+        if matches!(c, '(' | ')' | '\\') {
+            literal.push('\\');
+        }
+        literal.push(c);
+    }
+    literal.push(')');
+    literal
+}
+
+/// Ask Ghostscript for the page count without rendering anything.
+pub(crate) fn ghostscript_page_count(
+    path: &Path,
+    runtime: &GhostscriptRuntime,
+    hooks: GhostscriptHooks<'_>,
+) -> Result<u32, CommandError> {
+    let path_str = path.to_string_lossy().to_string();
+    let permit_arg = format!("--permit-file-read={}", path_str);
+    let program = format!(
+        "{} (r) file runpdfbegin pdfpagecount = quit",
+        ps_string_literal(&path_str)
+    );
+    let args = [
+        "-q",
+        "-dNODISPLAY",
+        "-dSAFER",
+        permit_arg.as_str(),
+        "-c",
+        program.as_str(),
+    ];
+    let output = run_ghostscript(&args, runtime, None, hooks)?;
+    output
+        .stdout
+        .lines()
+        .rev()
+        .find_map(|line| line.trim().parse::<u32>().ok())
+        .ok_or(CommandError::PageCountUnknown { path: path_str })
+}
+
+/// Parse the `Page N` lines Ghostscript prints while processing without `-dQUIET`.
+pub(crate) fn parse_page_line(line: &str) -> Option<u32> {
+    line.trim().strip_prefix("Page ")?.trim().parse().ok()
+}
+
+/// `-sOutputFile=` argument for a literal path; Ghostscript treats `%` as a format spec.
+pub(crate) fn gs_output_file_arg(path: &Path) -> String {
+    format!("-sOutputFile={}", path.to_string_lossy().replace('%', "%%"))
+}
+
+/// Run one pdfwrite pass from `inputs` (concatenated in order) to `output`, forwarding
+/// `Page N` lines as progress. `device_args` go between the fixed switches and the inputs,
+/// so they may end in a `-c <PostScript> -f` sequence.
+pub(crate) fn run_pdfwrite(
+    inputs: &[&Path],
+    output: &Path,
+    device_args: &[String],
+    total_pages: Option<u32>,
+    runtime: &GhostscriptRuntime,
+    ctx: JobContext<'_>,
+) -> Result<GhostscriptExecOutput, CommandError> {
+    // The output is estimated at the size of the inputs.
+    let input_bytes = inputs
+        .iter()
+        .filter_map(|input| input.metadata().ok())
+        .map(|metadata| metadata.len())
+        .sum();
+    let output_dir = output
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    check_free_space(output_dir, input_bytes)?;
+    run_pdfwrite_to(
+        inputs,
+        &gs_output_file_arg(output),
+        device_args,
+        total_pages,
+        runtime,
+        ctx,
+    )
+}
+
+/// `run_pdfwrite` with a prebuilt `-sOutputFile=` argument, for `%d` page patterns.
+pub(crate) fn run_pdfwrite_to(
+    inputs: &[&Path],
+    output_file_arg: &str,
+    device_args: &[String],
+    total_pages: Option<u32>,
+    runtime: &GhostscriptRuntime,
+    ctx: JobContext<'_>,
+) -> Result<GhostscriptExecOutput, CommandError> {
+    run_device_to(
+        "pdfwrite",
+        inputs,
+        output_file_arg,
+        device_args,
+        total_pages,
+        runtime,
+        ctx,
+    )
+}
+
+/// `-sPageList=` argument selecting `pages`, which are rendered in ascending order.
+pub(crate) fn page_list_arg(pages: &[u32]) -> String {
+    let pages: Vec<String> = pages.iter().map(u32::to_string).collect();
+    format!("-sPageList={}", pages.join(","))
+}
+
+/// `run_pdfwrite_to` for any output device, e.g. the PNG rasterizers.
+pub(crate) fn run_device_to(
+    device: &str,
+    inputs: &[&Path],
+    output_file_arg: &str,
+    device_args: &[String],
+    total_pages: Option<u32>,
+    runtime: &GhostscriptRuntime,
+    ctx: JobContext<'_>,
+) -> Result<GhostscriptExecOutput, CommandError> {
+    let on_stdout_line = |line: &str| {
+        if let (Some(on_progress), Some(page)) = (ctx.on_progress, parse_page_line(line)) {
+            on_progress(page, total_pages);
+        }
+    };
+    let hooks = GhostscriptHooks {
+        job: ctx.control,
+        on_stdout_line: Some(&on_stdout_line),
+        stdin: None,
+    };
+
+    let input_file_args: Vec<String> = inputs
+        .iter()
+        .map(|input| input.to_string_lossy().to_string())
+        .collect();
+    // `-dQUIET` is left off so Ghostscript reports each `Page N` for progress events.
+    // The output file is set before `device_args` because `-c` code opens the device.
+    // Every build has pdfwrite; for the others fail before Ghostscript does, vaguely.
+    if device != "pdfwrite" && !runtime.has_device(device, hooks) {
+        return Err(CommandError::GsMissingDevice {
+            device: device.to_string(),
+        });
+    }
+    let device_arg = format!("-sDEVICE={}", device);
+    let mut args = vec![
+        "-dBATCH",
+        "-dNOPAUSE",
+        "-dSAFER",
+        device_arg.as_str(),
+        output_file_arg,
+    ];
+    args.extend(device_args.iter().map(String::as_str));
+    args.extend(input_file_args.iter().map(String::as_str));
+    let result = run_ghostscript(&args, runtime, None, hooks)?;
+    if let Some(warnings) = ctx.warnings {
+        warnings.record(&result.warnings);
+    }
+    Ok(result)
+}
+
+/// `run_pdfwrite` for an input held in memory: the PDF goes in through stdin and the
+/// output comes back from stdout, so neither touches the temp directory.
+pub(crate) fn run_pdfwrite_piped(
+    input: &[u8],
+    device_args: &[String],
+    total_pages: Option<u32>,
+    runtime: &GhostscriptRuntime,
+    ctx: JobContext<'_>,
+) -> Result<Vec<u8>, CommandError> {
+    let on_stdout_line = |line: &str| {
+        if let (Some(on_progress), Some(page)) = (ctx.on_progress, parse_page_line(line)) {
+            on_progress(page, total_pages);
+        }
+    };
+    let hooks = GhostscriptHooks {
+        job: ctx.control,
+        on_stdout_line: Some(&on_stdout_line),
+        stdin: Some(input),
+    };
+
+    // `-sstdout=%stderr` keeps Ghostscript's messages out of the PDF on stdout.
+    let mut args = vec![
+        "-dBATCH",
+        "-dNOPAUSE",
+        "-dSAFER",
+        "-sDEVICE=pdfwrite",
+        "-sstdout=%stderr",
+        "-sOutputFile=%stdout",
+    ];
+    args.extend(device_args.iter().map(String::as_str));
+    args.push("-");
+    let result = run_ghostscript(&args, runtime, None, hooks)?;
+    if let Some(warnings) = ctx.warnings {
+        warnings.record(&result.warnings);
+    }
+
+    // A run that exits cleanly without output failed all the same.
+    if !result.stdout_bytes.starts_with(b"%PDF") {
+        return Err(ghostscript_failure(Some(0), result.stderr, &[]));
+    }
+    Ok(result.stdout_bytes)
+}
+
+fn load_ghostscript_config(config_dir: &Path) -> GhostscriptConfig {
+    std::fs::read_to_string(config_dir.join(GHOSTSCRIPT_CONFIG_FILE))
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+fn save_ghostscript_config(
+    config_dir: &Path,
+    config: &GhostscriptConfig,
+) -> Result<(), CommandError> {
+    let contents = serde_json::to_string_pretty(config).map_err(|e| CommandError::Io {
+        action: "serialize_ghostscript_config",
+        error: e.to_string(),
+    })?;
+    std::fs::create_dir_all(config_dir)
+        .and_then(|_| std::fs::write(config_dir.join(GHOSTSCRIPT_CONFIG_FILE), contents))
+        .map_err(|e| CommandError::Io {
+            action: "save_ghostscript_config",
+            error: e.to_string(),
+        })
+}
+
+pub(crate) fn resolve_ghostscript_runtime(
+    resource_dir: Option<PathBuf>,
+    config_dir: Option<PathBuf>,
+) -> GhostscriptRuntime {
+    let manifest_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let exe_dir = std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(|p| p.to_path_buf()));
+
+    let mut runtime = GhostscriptRuntime {
+        config_dir,
+        ..GhostscriptRuntime::default()
+    };
+    let mut icc_profile_dirs = Vec::new();
+    if let Some(base) = resource_dir.as_ref() {
+        push_root_if_exists(&mut icc_profile_dirs, base.join("resources").join("icc"));
+    }
+    push_root_if_exists(
+        &mut icc_profile_dirs,
+        manifest_dir.join("resources").join("icc"),
+    );
+    runtime.icc_profile_dirs = icc_profile_dirs;
+    if let Some(config_dir) = runtime.config_dir.as_deref() {
+        let custom_path = load_ghostscript_config(config_dir).custom_path;
+        *runtime
+            .custom_command
+            .lock()
+            .expect("custom ghostscript mutex poisoned") = custom_path.map(PathBuf::from);
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        let mut roots = Vec::new();
+        if let Some(base) = resource_dir.as_ref() {
+            push_root_if_exists(&mut roots, base.join("bin").join("ghostscript"));
+        }
+        if let Some(base) = exe_dir.as_ref() {
+            if let Some(contents_dir) = base.parent() {
+                if let Some(app_dir) = contents_dir.parent() {
+                    push_root_if_exists(
+                        &mut roots,
+                        app_dir.join("Resources").join("bin").join("ghostscript"),
+                    );
+                }
+            }
+        }
+        push_root_if_exists(&mut roots, manifest_dir.join("bin").join("ghostscript"));
+        runtime.roots = roots;
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        let mut roots = Vec::new();
+        if let Some(base) = resource_dir.as_ref() {
+            push_root_if_exists(&mut roots, base.join("bin").join("ghostscript-win"));
+            push_root_if_exists(&mut roots, base.join("ghostscript-win"));
+            push_root_if_exists(
+                &mut roots,
+                base.join("resources").join("bin").join("ghostscript-win"),
+            );
+        }
+        if let Some(base) = exe_dir.as_ref() {
+            push_root_if_exists(&mut roots, base.join("bin").join("ghostscript-win"));
+            push_root_if_exists(&mut roots, base.join("ghostscript-win"));
+            push_root_if_exists(
+                &mut roots,
+                base.join("resources").join("bin").join("ghostscript-win"),
+            );
+        }
+        push_root_if_exists(&mut roots, manifest_dir.join("bin").join("ghostscript-win"));
+        runtime.roots = roots;
+    }
+
+    #[cfg(all(not(target_os = "macos"), not(target_os = "windows")))]
+    {
+        let mut roots = Vec::new();
+        for base in [resource_dir.as_ref(), exe_dir.as_ref()]
+            .into_iter()
+            .flatten()
+        {
+            push_root_if_exists(&mut roots, base.join("bin").join("ghostscript-linux"));
+            push_root_if_exists(&mut roots, base.join("bin").join("ghostscript"));
+        }
+        push_root_if_exists(
+            &mut roots,
+            manifest_dir.join("bin").join("ghostscript-linux"),
+        );
+        runtime.roots = roots;
+    }
+
+    runtime
+}
+
+pub(crate) fn take_digits(chars: &mut std::iter::Peekable<std::str::Chars<'_>>) -> String {
+    let mut run = String::new();
+    while let Some(c) = chars.next_if(|c| c.is_ascii_digit()) {
+        run.push(c);
+    }
+    run
+}
+
+/// Compare names the way a person would: case-insensitively, with runs of digits ordered
+/// by value, so `file2` sorts before `file10`.
+pub(crate) fn natural_cmp(a: &str, b: &str) -> std::cmp::Ordering {
+    use std::cmp::Ordering;
+
+    let mut a_chars = a.chars().peekable();
+    let mut b_chars = b.chars().peekable();
+    loop {
+        let ordering = match (a_chars.peek().copied(), b_chars.peek().copied()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let x_run = take_digits(&mut a_chars);
+                let y_run = take_digits(&mut b_chars);
+                let x_value = x_run.trim_start_matches('0');
+                let y_value = y_run.trim_start_matches('0');
+                x_value
+                    .len()
+                    .cmp(&y_value.len())
+                    .then_with(|| x_value.cmp(y_value))
+            }
+            (Some(x), Some(y)) => {
+                a_chars.next();
+                b_chars.next();
+                x.to_lowercase().cmp(y.to_lowercase())
+            }
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+pub(crate) fn push_root_if_exists(roots: &mut Vec<PathBuf>, root: PathBuf) {
+    if root.exists() && root.is_dir() && !roots.iter().any(|existing| existing == &root) {
+        roots.push(root);
+    }
+}
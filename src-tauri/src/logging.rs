@@ -0,0 +1,171 @@
+//! The app log: a size-rotated file in the app log dir, written through the `log_*!` macros.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Mutex;
+
+/// Most verbose level written to the log file; more verbose messages are dropped.
+#[derive(Debug, Default, Clone, Copy, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum LogLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+}
+
+const LOG_FILE: &str = "pdf-resizer.log";
+
+/// Size at which the log file is rotated to `.1`.
+const MAX_LOG_FILE_BYTES: u64 = 1_000_000;
+
+/// Rotated log files kept besides the current one.
+const ROTATED_LOG_FILES: usize = 3;
+
+static LOG_LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+
+static LOG_SINK: Mutex<Option<LogSink>> = Mutex::new(None);
+
+/// The open log file in the app log dir.
+struct LogSink {
+    pub(crate) path: PathBuf,
+    pub(crate) file: std::fs::File,
+    pub(crate) size: u64,
+}
+
+/// Start writing the log to `log_dir`. Without a dir messages only reach stderr, and
+/// only in debug builds.
+pub(crate) fn init_logging(log_dir: Option<PathBuf>, level: LogLevel) {
+    apply_log_level(level);
+    let Some(log_dir) = log_dir else {
+        return;
+    };
+    let path = log_dir.join(LOG_FILE);
+    let opened = std::fs::create_dir_all(&log_dir).and_then(|_| {
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+    });
+    match opened {
+        Ok(file) => {
+            let size = file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+            *LOG_SINK.lock().expect("log mutex poisoned") = Some(LogSink { path, file, size });
+        }
+        Err(e) => eprintln!("Failed to open log file '{}': {}", path.display(), e),
+    }
+}
+
+pub(crate) fn apply_log_level(level: LogLevel) {
+    LOG_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub(crate) fn log_file_path() -> Option<PathBuf> {
+    LOG_SINK
+        .lock()
+        .expect("log mutex poisoned")
+        .as_ref()
+        .map(|sink| sink.path.clone())
+}
+
+/// Append one line to the log file, rotating it first when it is full. Use the
+/// `log_*!` macros rather than calling this directly.
+pub(crate) fn write_log(level: LogLevel, message: std::fmt::Arguments<'_>) {
+    use std::io::Write;
+
+    if level as u8 > LOG_LEVEL.load(Ordering::Relaxed) {
+        return;
+    }
+    let now_ms = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as i64)
+        .unwrap_or(0);
+    let (year, month, day) = civil_from_days(now_ms.div_euclid(86_400_000));
+    let day_ms = now_ms.rem_euclid(86_400_000);
+    let line = format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:03}Z {:5} {}\n",
+        year,
+        month,
+        day,
+        day_ms / 3_600_000,
+        day_ms / 60_000 % 60,
+        day_ms / 1000 % 60,
+        day_ms % 1000,
+        format!("{:?}", level).to_uppercase(),
+        message
+    );
+    if cfg!(debug_assertions) {
+        eprint!("{}", line);
+    }
+
+    let mut guard = LOG_SINK.lock().expect("log mutex poisoned");
+    let Some(sink) = guard.as_mut() else {
+        return;
+    };
+    if sink.size + line.len() as u64 > MAX_LOG_FILE_BYTES {
+        let rotated = |index: usize| PathBuf::from(format!("{}.{}", sink.path.display(), index));
+        for index in (1..ROTATED_LOG_FILES).rev() {
+            let _ = std::fs::rename(rotated(index), rotated(index + 1));
+        }
+        let reopened = std::fs::rename(&sink.path, rotated(1)).and_then(|_| {
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&sink.path)
+        });
+        // On failure keep appending to the full file rather than losing messages.
+        if let Ok(file) = reopened {
+            sink.file = file;
+            sink.size = 0;
+        }
+    }
+    if sink.file.write_all(line.as_bytes()).is_ok() {
+        sink.size += line.len() as u64;
+    }
+}
+
+/// The last `max_bytes` of the log, starting at a line boundary.
+pub(crate) fn log_tail(max_bytes: u64) -> Vec<u8> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    let Some(path) = log_file_path() else {
+        return Vec::new();
+    };
+    let mut tail = Vec::new();
+    let Ok(mut file) = std::fs::File::open(path) else {
+        return tail;
+    };
+    let length = file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+    let start = length.saturating_sub(max_bytes);
+    if file.seek(SeekFrom::Start(start)).is_err() || file.read_to_end(&mut tail).is_err() {
+        return Vec::new();
+    }
+    if start > 0 {
+        let line_start = tail
+            .iter()
+            .position(|&byte| byte == b'\n')
+            .map_or(0, |i| i + 1);
+        tail.drain(..line_start);
+    }
+    tail
+}
+
+/// Year, month and day of a day count since 1970-01-01 (proleptic Gregorian).
+pub(crate) fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
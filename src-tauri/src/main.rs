@@ -3,508 +3,11708 @@
     windows_subsystem = "windows"
 )]
 
-use std::collections::HashMap;
+use notify::RecursiveMode;
+use notify_debouncer_mini::{new_debouncer, DebounceEventResult};
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use tauri::{Manager, State};
 
+use crate::error::CommandError;
+#[cfg(unix)]
+use crate::ghostscript::GHOSTSCRIPT_TERMINATE_GRACE;
+use crate::ghostscript::{
+    collect_candidates, collect_ghostscript_env, find_on_path, ghostscript_env_override,
+    ghostscript_page_count, gs_output_file_arg, natural_cmp, page_list_arg,
+    parse_ghostscript_version, parse_page_line, ps_string_literal, require_ghostscript_version,
+    require_minimum_ghostscript, resolve_ghostscript_runtime, run_candidate, run_device_to,
+    run_ghostscript, run_pdfwrite, run_pdfwrite_to, signal_children, wait_until,
+    GhostscriptCandidate, GhostscriptExecOutput, GhostscriptHooks, GhostscriptProbeLog,
+    GhostscriptRuntime, GhostscriptSource, GhostscriptVersion, GhostscriptWarning, JobContext,
+    JobControl, MIN_GHOSTSCRIPT_VERSION, RUNNING_CHILDREN, SHUTDOWN_WAIT, SHUTTING_DOWN,
+};
+use crate::logging::{
+    apply_log_level, civil_from_days, init_logging, log_file_path, log_tail, LogLevel,
+};
+use crate::operations::{
+    compress_with_ghostscript, document_page_count, downsample_with_ghostscript, file_size,
+    fit_to_page_with_ghostscript, flatten_with_ghostscript, ghostscript_messages,
+    grayscale_with_ghostscript, linearize_with_ghostscript, paper_size, repair_with_ghostscript,
+    resize_with_ghostscript, run_operation, BatchItemResult, CompressPreset, CompressResult,
+    ContentMode, DownsampleResult, FitOrientation, FitResult, FlattenOptions, GrayscaleResult,
+    LinearizeResult, Operation, PageDimensions, QueuedOperation, RepairReport, ResizeAnchor,
+    ResizeResult, PAPER_SIZE_NAMES,
+};
+use crate::output::{
+    check_output_location, claim_output_path, claim_output_paths, commit_output,
+    ensure_distinct_paths, ensure_output_writable, extended_length_path, is_locked,
+    sibling_temp_path, unique_temp_path, unique_temp_stem, write_file_atomically, write_output,
+    ConflictPolicy, OutputOptions, OutputResult, TempFileGuard, WritableCheck, Written,
+    LIVE_TEMP_FILES, MAX_PATH_LENGTH,
+};
+use crate::pdf::{
+    concat_matrix, document_fonts, inherited_page_attribute, load_pdf_document, normalize_rotation,
+    page_content_scopes, pdf_dict, pdf_header, pdf_number, pdf_rect, pdf_rect_object,
+    read_page_geometry, resource_category, save_pdf_document, stream_content, transform_rect,
+    without_subset_tag, FontInfo, PageGeometry, PdfMatrix, PDF_HEADER_WINDOW,
+};
+use crate::settings::{
+    apply_settings, built_in_presets, import_preset_entries, user_preset_index,
+    validate_preset_name, Preset, PresetExport, PresetImportReport, PresetStore, Settings,
+    SettingsStore, DEFAULT_PIPE_THRESHOLD_BYTES, PRESET_EXPORT_VERSION,
+};
+
+macro_rules! log_error {
+    ($($arg:tt)*) => {
+        $crate::logging::write_log($crate::logging::LogLevel::Error, format_args!($($arg)*))
+    };
+}
+
+macro_rules! log_warn {
+    ($($arg:tt)*) => {
+        $crate::logging::write_log($crate::logging::LogLevel::Warn, format_args!($($arg)*))
+    };
+}
+
+macro_rules! log_info {
+    ($($arg:tt)*) => {
+        $crate::logging::write_log($crate::logging::LogLevel::Info, format_args!($($arg)*))
+    };
+}
+
+macro_rules! log_debug {
+    ($($arg:tt)*) => {
+        $crate::logging::write_log($crate::logging::LogLevel::Debug, format_args!($($arg)*))
+    };
+}
+
+// Declared after the `log_*!` macros so they can use them.
+mod cli;
+mod error;
+mod ghostscript;
+mod logging;
+mod operations;
+mod output;
+mod pdf;
+mod settings;
+#[cfg(test)]
+mod tests;
+
+/// What was opened from outside the app, held until the frontend is listening.
+#[derive(Default)]
+struct PendingOpenPaths(Mutex<PendingOpen>);
+
+#[derive(Default)]
+struct PendingOpen {
+    /// Set by `frontend_ready`; from then on everything is emitted as it arrives.
+    frontend_ready: bool,
+    paths: Vec<String>,
+    preset_requests: Vec<PresetRequest>,
+    unsupported: Vec<String>,
+    truncated_folders: Vec<String>,
+}
+
+#[derive(Default)]
+struct RunningJobs(Mutex<HashMap<String, Arc<JobControl>>>);
+
+/// Keeps a job registered in `RunningJobs` for as long as the command is running.
+struct JobRegistration<'a> {
+    jobs: &'a RunningJobs,
+    job_id: String,
+    control: Arc<JobControl>,
+}
+
+/// Jobs waiting for or holding one of the queue's `max_concurrency` slots, in the order
+/// they were enqueued. Finished jobs leave the list.
+struct JobQueue(Mutex<QueueState>);
+
+struct QueueState {
+    max_concurrency: usize,
+    jobs: Vec<QueueEntry>,
+}
+
+struct QueueEntry {
+    job_id: String,
+    request: QueuedOperation,
+    state: JobState,
+    /// `cancel_job` came while the job was starting, before it was in `RunningJobs`.
+    cancel_requested: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum JobState {
+    Queued,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+/// Payload of `job-state-changed`.
+#[derive(Clone, serde::Serialize)]
+struct JobStateChanged {
+    job_id: String,
+    state: JobState,
+    input_path: String,
+    /// The path written, once done.
+    output_path: Option<String>,
+    error: Option<CommandError>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<GhostscriptWarning>,
+}
+
+/// How long `start_watch` gathers a folder's change events before it scans the folder.
+const WATCH_DEBOUNCE: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// How often a watched folder is scanned again while files in it are settling; otherwise
+/// it is only scanned on change events.
+const WATCH_RESCAN_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// How long a new file's size and modification time must stay unchanged for before it is
+/// processed, so files still being copied in aren't picked up half-written.
+const WATCH_SETTLE_TIME: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Hot folders started by `start_watch`, by watcher ID.
+#[derive(Default)]
+struct FolderWatchers(Mutex<HashMap<String, FolderWatcher>>);
+
+struct FolderWatcher {
+    control: Arc<WatcherControl>,
+    /// Wakes the thread waiting for change events, so it sees that it was stopped.
+    wake: std::sync::mpsc::Sender<DebounceEventResult>,
+    thread: std::thread::JoinHandle<()>,
+}
+
 #[derive(Default)]
-struct PendingOpenPaths(Mutex<Vec<String>>);
+struct WatcherControl {
+    stopped: AtomicBool,
+    /// The job of the file being processed, cancelled when the watcher stops.
+    current_job: Mutex<Option<Arc<JobControl>>>,
+}
+
+/// What a watcher knows about one file in its folder.
+struct WatchedFile {
+    signature: FileSignature,
+    /// When the file was first seen at `signature`.
+    seen: std::time::Instant,
+    /// Processed (or there before the watcher started) at this `signature`; only a
+    /// changed file is picked up again.
+    handled: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct FileSignature {
+    len: u64,
+    modified: Option<std::time::SystemTime>,
+}
+
+/// Payload of `watch-file-changed`: a file in a watched folder was queued once seen,
+/// started once its size settled, or finished.
+#[derive(Clone, serde::Serialize)]
+struct WatchFileChanged {
+    watcher_id: String,
+    input_path: String,
+    state: JobState,
+    output_path: Option<String>,
+    error: Option<CommandError>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<GhostscriptWarning>,
+}
+
+#[derive(serde::Serialize)]
+struct QueuedJobInfo {
+    job_id: String,
+    input_path: String,
+    state: JobState,
+}
+
+#[derive(serde::Serialize)]
+struct QueueStatus {
+    max_concurrency: usize,
+    running: usize,
+    queued: usize,
+    jobs: Vec<QueuedJobInfo>,
+}
+
+const RECENT_FILES_FILE: &str = "recent-files.json";
+
+/// Entries kept in the recent-files list.
+const MAX_RECENT_FILES: usize = 50;
+
+/// Recently used files, most recent first, persisted in the app config dir.
+struct RecentFiles {
+    config_dir: Option<PathBuf>,
+    entries: Mutex<Vec<RecentFileEntry>>,
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+struct RecentFileEntry {
+    path: String,
+    last_used_ms: u64,
+}
+
+#[derive(serde::Serialize)]
+struct RecentFile {
+    path: String,
+    last_used_ms: u64,
+    /// The file is gone, e.g. moved or on an unmounted volume; kept so the UI can show it
+    /// grayed out.
+    missing: bool,
+}
+
+const JOB_HISTORY_FILE: &str = "job-history.json";
+
+/// Entries kept in the job history.
+const MAX_JOB_HISTORY: usize = 500;
+
+/// Finished queue jobs, most recent first, persisted in the app data dir.
+struct JobHistory {
+    data_dir: Option<PathBuf>,
+    entries: Mutex<Vec<JobHistoryEntry>>,
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+struct JobHistoryEntry {
+    job_id: String,
+    /// Done, failed or cancelled.
+    state: JobState,
+    input_path: String,
+    /// The path written; absent unless the job is done.
+    output_path: Option<String>,
+    /// What was run, with all its parameters, so the job can be enqueued again.
+    operation: Operation,
+    input_size: Option<u64>,
+    output_size: Option<u64>,
+    finished_ms: u64,
+    duration_ms: u64,
+    /// Binary that answered `--version` when the job finished.
+    ghostscript_path: Option<String>,
+    error: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<GhostscriptWarning>,
+}
+
+/// Which history entries `get_job_history` returns; unset fields match everything.
+#[derive(Default, serde::Deserialize)]
+#[serde(default)]
+struct JobHistoryFilter {
+    /// Case-insensitive substring of the input or output path.
+    path: Option<String>,
+    state: Option<JobState>,
+    /// Operation kind, e.g. `compress`.
+    kind: Option<String>,
+    since_ms: Option<u64>,
+    until_ms: Option<u64>,
+}
+
+#[derive(serde::Serialize)]
+struct GhostscriptPathInfo {
+    /// Binary that answered `--version`, i.e. the one processing commands will use.
+    path: Option<String>,
+    version: Option<String>,
+    custom_path: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct GhostscriptInfo {
+    found: bool,
+    path: Option<String>,
+    /// `--version` as printed.
+    version_string: Option<String>,
+    version: Option<GhostscriptVersion>,
+    /// `environment`, `custom`, `bundled` or `path`.
+    source: Option<&'static str>,
+    minimum_version: GhostscriptVersion,
+    meets_minimum: bool,
+    /// Why it wasn't found.
+    error: Option<CommandError>,
+}
+
+#[derive(serde::Serialize)]
+struct GhostscriptProbeResult {
+    attempted: Vec<String>,
+    selected: Option<String>,
+    last_error: Option<String>,
+    mac_root: Option<String>,
+    windows_root: Option<String>,
+}
+
+/// One entry of `list_ghostscript_candidates`.
+#[derive(serde::Serialize)]
+struct GhostscriptCandidateInfo {
+    path: String,
+    /// See `GhostscriptInfo::source`; `registry` for Windows installs.
+    source: &'static str,
+    exists: bool,
+    /// `--version` succeeded.
+    executed: bool,
+    version: Option<String>,
+    error: Option<String>,
+    /// The one processing commands run.
+    in_use: bool,
+}
+
+#[derive(Clone, serde::Serialize)]
+struct PdfProgress {
+    job_id: String,
+    current_page: u32,
+    total_pages: Option<u32>,
+}
+
+#[derive(serde::Serialize)]
+struct CroppedPage {
+    page: u32,
+    crop_box: [f64; 4],
+    width_pt: f64,
+    height_pt: f64,
+}
+
+#[derive(serde::Serialize)]
+struct PageBoxes {
+    page: u32,
+    /// Absolute coordinates in the page's user space, like the boxes in the file.
+    media_box: [f64; 4],
+    crop_box: Option<[f64; 4]>,
+    trim_box: Option<[f64; 4]>,
+    bleed_box: Option<[f64; 4]>,
+}
+
+#[derive(serde::Serialize)]
+struct PageBoxesResult {
+    output_path: String,
+    pages: Vec<PageBoxes>,
+}
+
+#[derive(serde::Serialize)]
+struct CropResult {
+    output_path: String,
+    pages: Vec<CroppedPage>,
+}
+
+#[derive(serde::Serialize)]
+struct PageRotation {
+    page: u32,
+    rotation: i32,
+}
+
+#[derive(serde::Serialize)]
+struct RotateResult {
+    output_path: String,
+    pages: Vec<PageRotation>,
+}
+
+#[derive(serde::Serialize)]
+struct PageCountResult {
+    output_path: String,
+    page_count: u32,
+}
+
+#[derive(serde::Serialize)]
+struct NupResult {
+    output_path: String,
+    sheet: PageDimensions,
+    pages_per_sheet: u32,
+    sheet_count: u32,
+}
+
+#[derive(serde::Serialize)]
+struct BookletResult {
+    output_path: String,
+    sheet: PageDimensions,
+    /// Blank pages appended to reach a multiple of four.
+    blank_pages: u32,
+    /// Sheets of paper, each printed on both sides.
+    sheet_count: u32,
+}
+
+/// ICC rendering intents, in Ghostscript's `-dRenderIntent` numbering.
+#[derive(Clone, Copy, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum RenderingIntent {
+    Perceptual,
+    /// Relative colorimetric.
+    Relative,
+    Saturation,
+    /// Absolute colorimetric.
+    Absolute,
+}
+
+#[derive(serde::Serialize)]
+struct CmykResult {
+    output_path: String,
+    /// `None` when Ghostscript's default CMYK profile was used.
+    icc_profile: Option<String>,
+    rendering_intent: RenderingIntent,
+}
+
+#[derive(Clone, Copy, serde::Deserialize)]
+enum PdfaLevel {
+    #[serde(rename = "1b")]
+    Pdfa1b,
+    #[serde(rename = "2b")]
+    Pdfa2b,
+    #[serde(rename = "3b")]
+    Pdfa3b,
+}
+
+#[derive(serde::Serialize)]
+struct PdfaResult {
+    output_path: String,
+    /// The output carries a PDF/A identification and output intent, and Ghostscript did
+    /// not report abandoning PDF/A.
+    conformant: bool,
+    /// Ghostscript's PDF/A messages, e.g. why it fell back to plain PDF.
+    warnings: Vec<String>,
+    /// Transparency was flattened first because PDF/A-1 forbids it.
+    transparency_flattened: bool,
+}
+
+#[derive(serde::Serialize)]
+struct PdfxResult {
+    output_path: String,
+    /// The output carries a PDF/X version and output intent, and Ghostscript did not
+    /// report abandoning PDF/X.
+    conformant: bool,
+    /// Ghostscript's PDF/X messages, e.g. why it fell back to plain PDF.
+    warnings: Vec<String>,
+    /// Pages whose RGB content was converted to CMYK.
+    rgb_pages: Vec<u32>,
+    /// Fonts the source did not embed; Ghostscript embedded them from the system or
+    /// substituted them, see `font_substitutions`.
+    unembedded_fonts: Vec<String>,
+    font_substitutions: Vec<String>,
+    transparency_flattened: bool,
+}
+
+#[derive(serde::Serialize)]
+struct EncryptResult {
+    output_path: String,
+    /// Cipher actually applied, e.g. `RC4-128`.
+    algorithm: String,
+    key_length_bits: u32,
+    /// Standard security handler revision (`/R`).
+    revision: u32,
+    warnings: Vec<String>,
+}
+
+/// Document information, from the Info dictionary with XMP metadata as the fallback.
+/// Dates are RFC 3339 when they parse, otherwise returned as stored.
+#[derive(serde::Serialize)]
+struct PdfMetadata {
+    title: Option<String>,
+    author: Option<String>,
+    subject: Option<String>,
+    keywords: Option<String>,
+    creator: Option<String>,
+    producer: Option<String>,
+    creation_date: Option<String>,
+    modification_date: Option<String>,
+    page_count: u32,
+    encrypted: bool,
+}
+
+/// Fields to change with `set_pdf_metadata`. Omitted fields are left alone; an empty string
+/// removes the entry. Dates are RFC 3339.
+#[derive(serde::Deserialize)]
+struct MetadataPatch {
+    title: Option<String>,
+    author: Option<String>,
+    subject: Option<String>,
+    keywords: Option<String>,
+    creator: Option<String>,
+    producer: Option<String>,
+    creation_date: Option<String>,
+    modification_date: Option<String>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+enum PageOrientation {
+    Portrait,
+    Landscape,
+}
+
+/// One page's boxes as stored in the file, plus the sizes a viewer displays.
+#[derive(serde::Serialize)]
+struct PageSize {
+    page: u32,
+    /// Raw box coordinates in user space, before UserUnit scaling.
+    media_box: [f64; 4],
+    crop_box: Option<[f64; 4]>,
+    rotate: i32,
+    user_unit: f64,
+    /// MediaBox in points, rotation and UserUnit applied.
+    media_size: PageDimensions,
+    /// The visible (cropped) area in points, rotation and UserUnit applied.
+    visible_size: PageDimensions,
+    /// Of the visible area; square pages count as portrait.
+    orientation: PageOrientation,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ImageFormat {
+    Png,
+    Jpeg,
+    Tiff,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum PostScriptDevice {
+    Ps2write,
+    /// Encapsulated PostScript; one page only.
+    Eps2write,
+}
+
+#[derive(serde::Serialize)]
+struct PostScriptResult {
+    output_path: String,
+    /// Pages with transparency, which the PostScript devices rasterize.
+    transparent_pages: Vec<u32>,
+    /// Ghostscript's messages about transparency and rasterization.
+    warnings: Vec<String>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum WatermarkPosition {
+    Center,
+    /// Repeated across the whole page.
+    Tile,
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+/// Options for `add_watermark`; omitted fields keep the defaults (48pt grey text at 30%
+/// opacity, rotated 45 degrees across the center of every page).
+#[derive(serde::Deserialize)]
+#[serde(default)]
+struct WatermarkOptions {
+    font_size: f64,
+    /// 0.0 (invisible) to 1.0 (opaque).
+    opacity: f64,
+    /// Counter-clockwise, in degrees.
+    rotation: f64,
+    /// `#rrggbb`.
+    color: String,
+    position: WatermarkPosition,
+    /// 1-based pages to mark; every page when omitted.
+    pages: Option<Vec<u32>>,
+    /// TrueType font to draw with; a system font covering the text is picked when omitted.
+    font_path: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct WatermarkResult {
+    output_path: String,
+    /// The font the text was drawn with.
+    font_path: String,
+    pages_watermarked: u32,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum MirrorAxis {
+    /// Left and right swap.
+    Horizontal,
+    /// Top and bottom swap.
+    Vertical,
+}
+
+#[derive(serde::Serialize)]
+struct MirrorResult {
+    output_path: String,
+    mirrored_pages: u32,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+enum FontEmbeddingStatus {
+    /// Already embedded in the input; pdfwrite carried it over.
+    Unchanged,
+    /// Missing from the input and embedded from a matching system font.
+    Embedded,
+    /// Missing from the input and replaced with a different font.
+    Substituted,
+    /// Still not embedded in the output.
+    Missing,
+}
+
+#[derive(serde::Serialize)]
+struct FontEmbedding {
+    name: String,
+    status: FontEmbeddingStatus,
+    /// The font Ghostscript used instead, for `substituted`.
+    substitute: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct EmbedFontsResult {
+    output_path: String,
+    fonts: Vec<FontEmbedding>,
+}
+
+/// Which printer marks `add_printer_marks` draws; all off when omitted.
+#[derive(Clone, Copy, Default, serde::Deserialize)]
+#[serde(default)]
+struct PrinterMarks {
+    crop: bool,
+    registration: bool,
+    color_bars: bool,
+}
+
+#[derive(serde::Serialize)]
+struct MarkedPage {
+    page: u32,
+    media_box: [f64; 4],
+    bleed_box: [f64; 4],
+    trim_box: [f64; 4],
+}
+
+#[derive(serde::Serialize)]
+struct PrinterMarksResult {
+    output_path: String,
+    /// Marks use the all-separations registration color instead of black.
+    registration_color: bool,
+    pages: Vec<MarkedPage>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum PageNumberPosition {
+    HeaderLeft,
+    HeaderCenter,
+    HeaderRight,
+    FooterLeft,
+    FooterCenter,
+    FooterRight,
+}
+
+/// Options for `add_page_numbers`; omitted fields give a plain 10pt number centered in
+/// the footer of every page.
+#[derive(serde::Deserialize)]
+#[serde(default)]
+struct PageNumberOptions {
+    /// `{n}` is the page's number, `{total}` the last number used.
+    format: String,
+    /// TrueType font to draw with; a system font covering the text is picked when omitted.
+    font_path: Option<String>,
+    font_size: f64,
+    /// `#rrggbb`.
+    color: String,
+    position: PageNumberPosition,
+    /// Distance from the CropBox edges, in points.
+    margin_pt: f64,
+    /// Number given to the first numbered page.
+    start_number: u32,
+    /// Leading pages (e.g. a cover) left without a number.
+    skip_first: u32,
+}
+
+#[derive(serde::Serialize)]
+struct PageNumberResult {
+    output_path: String,
+    font_path: String,
+    pages_numbered: u32,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum StampLayer {
+    /// On top of the page content.
+    Over,
+    /// Behind the page content, e.g. letterhead.
+    Under,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum StampScale {
+    /// PDF stamps at their page size, PNGs at their pHYs resolution (72 dpi if unset).
+    Actual,
+    /// As large as fits the visible page, keeping the aspect ratio.
+    Fit,
+    /// `width_pt` wide, keeping the aspect ratio.
+    Width,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum StampAnchor {
+    Center,
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+/// Options for `stamp_pdf`; omitted fields stamp the first page of the stamp file at
+/// actual size over the center of every page.
+#[derive(serde::Deserialize)]
+#[serde(default)]
+struct StampPlacement {
+    layer: StampLayer,
+    scale: StampScale,
+    /// Required for `StampScale::Width`.
+    width_pt: Option<f64>,
+    anchor: StampAnchor,
+    /// Distance from the anchor edges towards the page center; for `Center`, a shift
+    /// right and up.
+    offset_x_pt: f64,
+    offset_y_pt: f64,
+    /// 1-based pages to stamp; every page when omitted.
+    pages: Option<Vec<u32>>,
+    /// Page of a PDF stamp file to use.
+    stamp_page: u32,
+}
+
+#[derive(serde::Serialize)]
+struct StampResult {
+    output_path: String,
+    pages_stamped: u32,
+}
+
+/// Which overlay page goes on base pages past the end of a shorter overlay document.
+#[derive(Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum OverlayRepeat {
+    /// Overlay page N on base page N only; later base pages are left alone.
+    Once,
+    /// Start again from the first overlay page.
+    Cycle,
+    /// Keep using the last overlay page.
+    StretchLast,
+}
+
+#[derive(serde::Serialize)]
+struct OverlayResult {
+    output_path: String,
+    pages_overlaid: u32,
+}
+
+/// The pieces of a PNG file needed to rebuild it as a PDF image.
+struct PngImage {
+    /// Where the image was read from, for errors.
+    path: String,
+    width: u32,
+    height: u32,
+    bit_depth: u8,
+    color_type: u8,
+    palette: Vec<u8>,
+    /// The `tRNS` chunk: palette alphas, or the transparent gray/RGB sample values.
+    transparency: Option<Vec<u8>>,
+    /// Concatenated `IDAT` data: zlib-compressed, filtered scanlines.
+    data: Vec<u8>,
+    /// Horizontal resolution from `pHYs`, when given in pixels per meter.
+    dpi: Option<f64>,
+}
+
+#[derive(Default, serde::Serialize)]
+struct PageTransparency {
+    page: u32,
+    /// The page or one of its forms is a transparency group.
+    groups: bool,
+    /// Soft masks in graphics states or on images.
+    soft_masks: bool,
+    /// Constant opacity (`/CA` or `/ca`) below 1.
+    constant_alpha: bool,
+    /// Blend modes other than Normal, by name.
+    blend_modes: Vec<String>,
+}
+
+#[derive(serde::Serialize)]
+struct TransparencyReport {
+    /// Pages with any of the features below, for e.g. "pages 3 and 7 contain transparency".
+    transparent_pages: Vec<u32>,
+    pages: Vec<PageTransparency>,
+}
+
+#[derive(serde::Serialize)]
+struct PageColors {
+    page: u32,
+    /// Color space families of text, paths and shadings: `DeviceRGB`, `DeviceCMYK`,
+    /// `DeviceGray`, `ICCBased`, `CalRGB`, `CalGray`, `Lab`, `Separation`, `DeviceN` or
+    /// `Pattern`. Indexed spaces are listed as their base.
+    vector: Vec<String>,
+    /// The same for images.
+    images: Vec<String>,
+    /// Colorant names of Separation and DeviceN spaces, e.g. `PANTONE 185 C`.
+    spot_colors: Vec<String>,
+}
+
+#[derive(serde::Serialize)]
+struct ColorReport {
+    /// Every spot color in the document, in order of first use.
+    spot_colors: Vec<String>,
+    pages: Vec<PageColors>,
+}
+
+#[derive(serde::Serialize)]
+struct InkCoverage {
+    page: u32,
+    /// Percent of the page area each ink covers, 0 to 100.
+    cyan: f64,
+    magenta: f64,
+    yellow: f64,
+    black: f64,
+    /// Sum of the four, to compare against a printer's total area coverage limit (e.g. 300).
+    total: f64,
+}
+
+#[derive(serde::Serialize)]
+struct AnnotationCount {
+    /// The annotation's `/Subtype`, e.g. `Text`, `Highlight` or `Widget`.
+    subtype: String,
+    count: u32,
+}
+
+#[derive(serde::Serialize)]
+struct RemoveAnnotationsResult {
+    output_path: String,
+    /// Removed annotations by subtype, sorted by subtype.
+    removed: Vec<AnnotationCount>,
+    kept_links: u32,
+}
+
+#[derive(serde::Serialize)]
+struct FlattenFormsResult {
+    output_path: String,
+    fields_flattened: u32,
+    /// Fully qualified names of widgets that had no appearance for their current state and
+    /// were removed without leaving anything on the page.
+    missing_appearances: Vec<String>,
+    /// The form set `/NeedAppearances`, asking viewers to redraw fields, so the stored
+    /// appearances that were baked in may not show the current values.
+    stale_appearances: bool,
+}
+
+/// Options for `sanitize_pdf`; by default everything it knows about is removed.
+#[derive(Default, serde::Deserialize)]
+#[serde(default)]
+struct SanitizeOptions {
+    /// Leave `/URI` actions, so web links keep working.
+    keep_uri_links: bool,
+}
+
+/// What `sanitize_pdf` removed, counted per entry.
+#[derive(Default, serde::Serialize)]
+struct SanitizeReport {
+    output_path: String,
+    /// Document-level scripts (`/Names /JavaScript`) and JavaScript actions.
+    javascript: u32,
+    /// The catalog's `/OpenAction`, whatever it does.
+    open_actions: u32,
+    /// `/AA` dictionaries: actions triggered by opening pages, focusing fields and so on.
+    additional_actions: u32,
+    launch_actions: u32,
+    uri_actions: u32,
+    /// Entries of `/Names /EmbeddedFiles` and file attachment annotations.
+    embedded_files: u32,
+}
+
+#[derive(serde::Serialize)]
+struct ExtractedImage {
+    page: u32,
+    /// Object number of the image XObject; images used on several pages are listed once.
+    object: u32,
+    width: u32,
+    height: u32,
+    /// The color space family, e.g. `DeviceRGB`, `ICCBased` or `Indexed`.
+    color_space: String,
+    bits_per_component: u32,
+    /// `None` when the image was skipped.
+    path: Option<String>,
+    /// Why the image could not be written, e.g. a CMYK image without JPEG compression.
+    skipped: Option<ImageSkip>,
+    /// The image has a soft mask that could not be kept as the output's alpha channel.
+    soft_mask_dropped: bool,
+}
+
+/// Why `extract_images` left an image out. Serializes as `{ code, params }` like
+/// `CommandError`, for the frontend to localize.
+#[derive(serde::Serialize)]
+#[serde(tag = "code", content = "params", rename_all = "snake_case")]
+enum ImageSkip {
+    /// A stencil mask (`/ImageMask`), which has no colors of its own.
+    StencilMask,
+    /// JPEG data with a `/Decode` array, usually inverted CMYK, which would not look the same.
+    DecodedJpeg,
+    /// A non-default `/Decode` array on decoded samples.
+    CustomDecode,
+    /// A color space PNG can't hold, e.g. CMYK or spot colors.
+    UnsupportedColorSpace,
+    UnsupportedBitDepth {
+        bits: i64,
+    },
+    /// lopdf can't decode the stream's filters.
+    UnsupportedFilter {
+        filters: Vec<String>,
+    },
+    /// The stream has fewer samples than its size asks for.
+    Truncated,
+    /// Writing the PNG failed; `error` is the encoder's reason.
+    EncodeFailed {
+        error: String,
+    },
+    /// The output file exists and the conflict policy kept it.
+    OutputExists {
+        path: String,
+    },
+}
+
+#[derive(serde::Serialize)]
+struct Attachment {
+    /// The file name (`/UF`, else `/F`), which `extract_attachment` takes.
+    name: String,
+    /// Uncompressed size in bytes.
+    size: Option<u64>,
+    /// The embedded file's `/Subtype`, e.g. `text/xml`.
+    mime_type: Option<String>,
+    description: Option<String>,
+    /// PDF/A-3 `/AFRelationship`, e.g. `Alternative` for Factur-X invoice data.
+    relationship: Option<String>,
+    /// The page of a file attachment annotation; `None` for document-level attachments.
+    page: Option<u32>,
+}
+
+#[derive(serde::Serialize)]
+struct OutlineNode {
+    title: String,
+    /// 1-based target page; `None` when the destination is missing or points at a page
+    /// that isn't in the document.
+    page: Option<u32>,
+    /// 0 for top-level bookmarks.
+    depth: u32,
+    children: Vec<OutlineNode>,
+}
+
+#[derive(serde::Serialize)]
+struct ValidationIssue {
+    /// 1-based page the problem was found on; `None` for document-level problems.
+    page: Option<u32>,
+    message: String,
+}
+
+#[derive(serde::Serialize)]
+struct ValidationReport {
+    path: String,
+    /// No errors were found; warnings alone don't fail a file.
+    valid: bool,
+    /// From the `%PDF-x.y` header.
+    version: Option<String>,
+    encrypted: bool,
+    /// Ghostscript could not open the file without a user password, so its pages were
+    /// not checked.
+    password_required: bool,
+    page_count: Option<u32>,
+    errors: Vec<ValidationIssue>,
+    warnings: Vec<ValidationIssue>,
+}
+
+#[derive(serde::Serialize)]
+struct PdfCheck {
+    path: String,
+    exists: bool,
+    /// A `%PDF-` header was found near the start of the file.
+    is_pdf: bool,
+    /// From the header, e.g. `1.7`.
+    version: Option<String>,
+    /// Why the file was rejected: missing, empty, unreadable or not a PDF.
+    error: Option<CommandError>,
+}
+
+#[derive(serde::Serialize)]
+struct FileInfo {
+    path: String,
+    /// `false` when the path is gone; the other fields are then empty.
+    exists: bool,
+    size: Option<u64>,
+    /// Milliseconds since the Unix epoch, ready for `new Date(...)`.
+    modified_ms: Option<u64>,
+    read_only: bool,
+    /// The path itself is a symbolic link; size and times are those of its target.
+    is_symlink: bool,
+}
+
+#[derive(serde::Serialize)]
+struct ExtractedAttachment {
+    output_path: String,
+}
 
-#[derive(Clone, Default)]
-struct GhostscriptRuntime {
-    #[cfg(any(target_os = "macos", target_os = "windows"))]
-    roots: Vec<PathBuf>,
+#[derive(serde::Serialize)]
+struct MetadataResult {
+    output_path: String,
+    #[serde(flatten)]
+    metadata: PdfMetadata,
+}
+
+/// Values `render_output_name` fills into a template.
+struct OutputNameContext {
+    /// File stem of the input.
+    name: String,
+    /// Extension of the input, without the dot.
+    ext: String,
+    pages: Option<u32>,
+    /// Visible size of the first page as displayed.
+    size: Option<PageDimensions>,
+    preset: Option<String>,
+    /// 1-based page, when every page gets its own file.
+    page: Option<u32>,
+    /// Local wall-clock time as seconds since the Unix epoch, for `{date}` and `{time}`.
+    local_time: i64,
+}
+
+/// A literal run or a `{key}` / `{key:03}` placeholder of a file name template.
+enum TemplatePart<'a> {
+    Text(&'a str),
+    Placeholder { key: &'a str, width: usize },
+}
+
+/// Bounds on the PDFs gathered from a folder opened from outside the app.
+struct FolderScanLimits {
+    /// Levels of subfolders to descend into; 0 takes only the folder's own files.
+    max_depth: usize,
+    max_files: usize,
+}
+
+struct FolderScan {
+    files: Vec<PathBuf>,
+    /// Whether `max_files` cut the scan short.
+    truncated: bool,
+}
+
+/// Payload of `external-folders-truncated`: opened folders holding more PDFs than were
+/// taken from them.
+#[derive(Clone, serde::Serialize)]
+struct TruncatedFolders {
+    folders: Vec<String>,
+    max_files: usize,
+}
+
+/// A parsed `pdfresizer://open` link.
+struct OpenLink {
+    paths: Vec<String>,
+    preset: Option<String>,
+}
+
+/// Payload of `external-preset-requested`: files a link asked to have a preset applied
+/// to. The files themselves arrive through `external-files-opened` first.
+#[derive(Clone, serde::Serialize)]
+struct PresetRequest {
+    paths: Vec<String>,
+    preset: String,
+}
+
+/// What opened paths resolve to once folders are expanded and non-PDFs set aside.
+#[derive(Default)]
+struct ResolvedOpenPaths {
+    files: Vec<String>,
+    truncated_folders: Vec<String>,
+    unsupported: Vec<String>,
+}
+
+/// `report.json` of a diagnostics bundle.
+#[derive(serde::Serialize)]
+struct DiagnosticsReport {
+    app_version: String,
+    os: &'static str,
+    os_version: Option<String>,
+    arch: &'static str,
+    ghostscript: GhostscriptPathInfo,
+    /// Every binary tried while finding `ghostscript.path`.
+    ghostscript_attempted: Vec<String>,
+    self_test: SelfTestResult,
+}
+
+/// Outcome of flattening `SELF_TEST_PDF`.
+#[derive(serde::Serialize)]
+struct SelfTestResult {
+    passed: bool,
+    duration_ms: u64,
+    output_bytes: Option<u64>,
+    error: Option<CommandError>,
+}
+
+/// One-page PDF flattened by the diagnostics self-test, so no user document is needed.
+const SELF_TEST_PDF: &[u8] = include_bytes!("../resources/self-test.pdf");
+
+/// How much of the end of the log a diagnostics bundle includes.
+const DIAGNOSTICS_LOG_TAIL_BYTES: u64 = 256 * 1024;
+
+/// Settings whose names contain one of these are replaced by `***` in diagnostics.
+const SECRET_SETTING_FRAGMENTS: [&str; 3] = ["password", "secret", "token"];
+
+#[derive(serde::Serialize)]
+struct Thumbnail {
+    page: u32,
+    path: String,
+}
+
+/// A source page wrapped as a Form XObject inside the document being rewritten.
+struct PageForm {
+    id: lopdf::ObjectId,
+    geometry: PageGeometry,
+}
+
+/// An output page composed from placed page forms.
+struct Sheet {
+    size: PageDimensions,
+    content: String,
+    xobjects: lopdf::Dictionary,
+}
+
+/// How `nup_pdf` lays out its sheets.
+#[derive(Clone, Copy)]
+struct NupLayout<'a> {
+    cols: u32,
+    rows: u32,
+    /// A paper size name, e.g. `A4`.
+    sheet_size: &'a str,
+    gap_pt: f64,
+    border: bool,
+}
+
+/// Equal cells of an N-up sheet, separated from each other and the sheet edge by `gap`.
+struct NupGrid {
+    cols: usize,
+    rows: usize,
+    gap: f64,
+    sheet: PageDimensions,
+    cell: PageDimensions,
+}
+
+impl RunningJobs {
+    fn register(
+        &self,
+        job_id: Option<String>,
+        timeout_secs: Option<u64>,
+    ) -> Result<JobRegistration<'_>, CommandError> {
+        let job_id = job_id.unwrap_or_else(next_job_id);
+        let control = Arc::new(JobControl {
+            timeout_secs,
+            ..JobControl::default()
+        });
+        let mut guard = self.0.lock().expect("running jobs mutex poisoned");
+        if guard.contains_key(&job_id) {
+            return Err(CommandError::JobAlreadyRunning { job_id });
+        }
+        guard.insert(job_id.clone(), control.clone());
+        Ok(JobRegistration {
+            jobs: self,
+            job_id,
+            control,
+        })
+    }
+
+    fn cancel(&self, job_id: &str) -> bool {
+        let control = {
+            let guard = self.0.lock().expect("running jobs mutex poisoned");
+            guard.get(job_id).cloned()
+        };
+        match control {
+            Some(control) => {
+                control.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn cancel_all(&self) {
+        let controls: Vec<_> = {
+            let guard = self.0.lock().expect("running jobs mutex poisoned");
+            guard.values().cloned().collect()
+        };
+        for control in controls {
+            control.cancel();
+        }
+    }
+}
+
+impl JobRegistration<'_> {
+    fn context<'s>(&'s self, on_progress: &'s (dyn Fn(u32, Option<u32>) + Sync)) -> JobContext<'s> {
+        JobContext {
+            control: Some(&self.control),
+            on_progress: Some(on_progress),
+            warnings: None,
+        }
+    }
+}
+
+impl Drop for JobRegistration<'_> {
+    fn drop(&mut self) {
+        let mut guard = self.jobs.0.lock().expect("running jobs mutex poisoned");
+        guard.remove(&self.job_id);
+    }
+}
+
+/// Physical CPU cores where the platform reports them, otherwise half the logical ones,
+/// which is what simultaneous multithreading gives.
+fn physical_core_count() -> usize {
+    #[cfg(target_os = "macos")]
+    if let Some(cores) = std::process::Command::new("sysctl")
+        .args(["-n", "hw.physicalcpu"])
+        .output()
+        .ok()
+        .and_then(|output| String::from_utf8_lossy(&output.stdout).trim().parse().ok())
+    {
+        return cores;
+    }
+    #[cfg(target_os = "linux")]
+    if let Ok(cpuinfo) = std::fs::read_to_string("/proc/cpuinfo") {
+        // Cores are unique by package and core ID; SMT siblings share both.
+        let mut cores = HashSet::new();
+        let mut package = "";
+        for (key, value) in cpuinfo.lines().filter_map(|line| line.split_once(':')) {
+            match key.trim() {
+                "physical id" => package = value.trim(),
+                "core id" => {
+                    cores.insert((package, value.trim()));
+                }
+                _ => {}
+            }
+        }
+        if !cores.is_empty() {
+            return cores.len();
+        }
+    }
+    let logical = std::thread::available_parallelism()
+        .map(std::num::NonZeroUsize::get)
+        .unwrap_or(2);
+    (logical / 2).max(1)
+}
+
+impl Default for JobQueue {
+    fn default() -> Self {
+        JobQueue(Mutex::new(QueueState {
+            max_concurrency: (physical_core_count() / 2).max(1),
+            jobs: Vec::new(),
+        }))
+    }
+}
+
+fn emit_job_state(app: &tauri::AppHandle, change: JobStateChanged) {
+    let _ = app.emit_all("job-state-changed", change);
+}
+
+/// Start queued jobs, oldest first, while slots are free.
+fn start_queued_jobs(app: &tauri::AppHandle) {
+    let queue: State<'_, JobQueue> = app.state();
+    let mut guard = queue.0.lock().expect("job queue mutex poisoned");
+    loop {
+        let running = guard
+            .jobs
+            .iter()
+            .filter(|entry| entry.state == JobState::Running)
+            .count();
+        if running >= guard.max_concurrency {
+            break;
+        }
+        let Some(entry) = guard
+            .jobs
+            .iter_mut()
+            .find(|entry| entry.state == JobState::Queued)
+        else {
+            break;
+        };
+        entry.state = JobState::Running;
+        let job_id = entry.job_id.clone();
+        let request = entry.request.clone();
+        emit_job_state(
+            app,
+            JobStateChanged {
+                job_id: job_id.clone(),
+                state: JobState::Running,
+                input_path: request.input_path.clone(),
+                output_path: None,
+                error: None,
+                warnings: Vec::new(),
+            },
+        );
+        let app = app.clone();
+        std::thread::spawn(move || run_queued_job(app, job_id, request));
+    }
+}
+
+fn run_queued_job(app: tauri::AppHandle, job_id: String, request: QueuedOperation) {
+    let started = std::time::Instant::now();
+    let input_size = std::fs::metadata(&request.input_path)
+        .ok()
+        .map(|metadata| metadata.len());
+    let outcome = {
+        let runtime: State<'_, GhostscriptRuntime> = app.state();
+        let jobs: State<'_, RunningJobs> = app.state();
+        let queue: State<'_, JobQueue> = app.state();
+        let main_window = app.get_window("main");
+        jobs.register(Some(job_id.clone()), request.output_options.timeout_secs)
+            .and_then(|job| {
+                let cancel_requested = queue
+                    .0
+                    .lock()
+                    .expect("job queue mutex poisoned")
+                    .jobs
+                    .iter()
+                    .any(|entry| entry.job_id == job_id && entry.cancel_requested);
+                if cancel_requested {
+                    job.control.cancel();
+                }
+                let emit_progress = main_window
+                    .as_ref()
+                    .map(|window| progress_emitter(window, &job_id));
+                let on_progress = |current_page, total_pages| {
+                    if let Some(emit_progress) = &emit_progress {
+                        emit_progress(current_page, total_pages);
+                    }
+                };
+                run_operation(
+                    &request.operation,
+                    Path::new(&request.input_path),
+                    Path::new(&request.output_path),
+                    request.output_options,
+                    &runtime,
+                    job.context(&on_progress),
+                )
+            })
+    };
+
+    {
+        let queue: State<'_, JobQueue> = app.state();
+        let mut guard = queue.0.lock().expect("job queue mutex poisoned");
+        guard.jobs.retain(|entry| entry.job_id != job_id);
+    }
+    let (state, output_path, error, warnings) = match outcome {
+        Ok((output_path, warnings)) => (JobState::Done, Some(output_path), None, warnings),
+        Err(CommandError::Cancelled) => (JobState::Cancelled, None, None, Vec::new()),
+        Err(error) => (JobState::Failed, None, Some(error), Vec::new()),
+    };
+    let history: State<'_, JobHistory> = app.state();
+    let entry = JobHistoryEntry {
+        job_id: job_id.clone(),
+        state,
+        input_path: request.input_path.clone(),
+        output_path: output_path.clone(),
+        operation: request.operation.clone(),
+        input_size,
+        output_size: output_path
+            .as_ref()
+            .and_then(|path| std::fs::metadata(path).ok())
+            .map(|metadata| metadata.len()),
+        finished_ms: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|duration| duration.as_millis() as u64)
+            .unwrap_or(0),
+        duration_ms: started.elapsed().as_millis() as u64,
+        ghostscript_path: active_ghostscript_path(&app.state::<GhostscriptRuntime>()),
+        error: error.as_ref().map(CommandError::detailed_message),
+        warnings: warnings.clone(),
+    };
+    if let Err(error) = history.update(|entries| {
+        entries.insert(0, entry);
+        entries.truncate(MAX_JOB_HISTORY);
+    }) {
+        log_warn!("{}", error);
+    }
+    emit_job_state(
+        &app,
+        JobStateChanged {
+            job_id,
+            state,
+            input_path: request.input_path,
+            output_path,
+            error,
+            warnings,
+        },
+    );
+    start_queued_jobs(&app);
+}
+
+static JOB_ID_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+fn next_job_id() -> String {
+    format!(
+        "job-{}-{}",
+        std::process::id(),
+        JOB_ID_COUNTER.fetch_add(1, Ordering::Relaxed) + 1
+    )
+}
+
+#[tauri::command]
+fn check_file_existence(file_paths: Vec<String>) -> Vec<bool> {
+    file_paths
+        .into_iter()
+        .map(|path| path_exists(Path::new(&path)))
+        .collect()
+}
+
+fn path_exists(path: &Path) -> bool {
+    extended_length_path(path).exists()
+}
+
+#[tauri::command]
+fn greet(name: &str) -> String {
+    format!("Hello, {}! You've been greeted from Rust!", name)
+}
+
+#[tauri::command]
+fn log_path(path: String) {
+    log_debug!("Received path from frontend: {}", path);
+}
+
+#[tauri::command]
+fn take_pending_open_paths(state: State<'_, PendingOpenPaths>) -> Vec<String> {
+    let mut guard = state.0.lock().expect("pending paths mutex poisoned");
+    std::mem::take(&mut guard.paths)
+}
+
+#[tauri::command]
+fn take_pending_preset_requests(state: State<'_, PendingOpenPaths>) -> Vec<PresetRequest> {
+    let mut guard = state.0.lock().expect("pending paths mutex poisoned");
+    std::mem::take(&mut guard.preset_requests)
+}
+
+/// Called once the frontend's listeners for opened files are registered. Emits what was
+/// opened before then; anything opened later is emitted as it arrives.
+#[tauri::command]
+fn frontend_ready(window: tauri::Window, state: State<'_, PendingOpenPaths>) {
+    let mut guard = state.0.lock().expect("pending paths mutex poisoned");
+    guard.set_frontend_ready(window_emitter(&window));
+}
+
+/// Stop all processing before the app exits: stop the folder watchers, cancel every job
+/// and then `stop_ghostscript_work`. Safe to call more than once; only the first call
+/// does anything.
+fn shut_down_processing(app: &tauri::AppHandle) {
+    if SHUTTING_DOWN.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    let _ = app.emit_all("app-shutting-down", ());
+    let watcher_threads = app.state::<FolderWatchers>().stop_all();
+    app.state::<RunningJobs>().cancel_all();
+    stop_ghostscript_work();
+
+    if !wait_until(SHUTDOWN_WAIT, || {
+        watcher_threads.iter().all(|thread| thread.is_finished())
+    }) {
+        log_warn!("Folder watchers were still running at exit");
+    }
+}
+
+/// Terminate the Ghostscript children still running (SIGTERM, then SIGKILL after
+/// `GHOSTSCRIPT_TERMINATE_GRACE` on Unix), and delete the temp files their threads
+/// didn't clean up in time. `SHUTTING_DOWN` must be set, so no new children start.
+fn stop_ghostscript_work() {
+    let children_left = || {
+        RUNNING_CHILDREN
+            .lock()
+            .expect("child registry mutex poisoned")
+            .is_empty()
+    };
+    #[cfg(unix)]
+    {
+        const SIGTERM: i32 = 15;
+        signal_children(
+            &RUNNING_CHILDREN
+                .lock()
+                .expect("child registry mutex poisoned"),
+            SIGTERM,
+        );
+        wait_until(GHOSTSCRIPT_TERMINATE_GRACE, children_left);
+    }
+    const SIGKILL: i32 = 9;
+    let survivors = {
+        let pids = RUNNING_CHILDREN
+            .lock()
+            .expect("child registry mutex poisoned");
+        signal_children(&pids, SIGKILL);
+        pids.len()
+    };
+    if survivors > 0 {
+        log_warn!("Killed {} Ghostscript process(es) at exit", survivors);
+    }
+
+    // Killed runs fail, and their threads' guards delete the temp files on the way out.
+    wait_until(SHUTDOWN_WAIT, || {
+        children_left()
+            && LIVE_TEMP_FILES
+                .lock()
+                .expect("temp file registry mutex poisoned")
+                .is_empty()
+    });
+    let leftovers = std::mem::take(
+        &mut *LIVE_TEMP_FILES
+            .lock()
+            .expect("temp file registry mutex poisoned"),
+    );
+    for path in leftovers {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+/// Last-resort page count for files neither lopdf nor Ghostscript can open: counts
+/// `/Type /Page` dictionaries in the raw bytes. Pages inside compressed object streams are
+/// missed and pages rewritten by incremental updates are counted twice.
+fn scan_page_objects(path: &Path) -> Option<u32> {
+    let data = std::fs::read(path).ok()?;
+    let mut count = 0;
+    let mut rest = data.as_slice();
+    while let Some(index) = rest.windows(5).position(|window| window == b"/Type") {
+        rest = &rest[index + 5..];
+        let value_start = rest
+            .iter()
+            .position(|byte| !byte.is_ascii_whitespace())
+            .unwrap_or(rest.len());
+        let value = &rest[value_start..];
+        let is_page = value.starts_with(b"/Page")
+            && !value
+                .get(5)
+                .is_some_and(|byte| byte.is_ascii_alphanumeric());
+        if is_page {
+            count += 1;
+        }
+    }
+    (count > 0).then_some(count)
+}
+
+/// Page count for the file list: the page tree via lopdf, then Ghostscript (which repairs
+/// broken cross-reference tables), then a raw scan for page objects.
+fn quick_page_count(path: &Path, runtime: &GhostscriptRuntime) -> Result<u32, CommandError> {
+    if !path.is_file() {
+        return Err(CommandError::NotFound {
+            path: path.to_string_lossy().to_string(),
+        });
+    }
+    document_page_count(path, runtime, GhostscriptHooks::default())
+        .or_else(|e| scan_page_objects(path).ok_or(e))
+}
+
+fn file_info(path: &Path) -> FileInfo {
+    let extended = extended_length_path(path);
+    let is_symlink = std::fs::symlink_metadata(&extended)
+        .map(|metadata| metadata.file_type().is_symlink())
+        .unwrap_or(false);
+    let metadata = std::fs::metadata(&extended).ok();
+    FileInfo {
+        path: path.to_string_lossy().to_string(),
+        exists: metadata.is_some(),
+        size: metadata.as_ref().map(std::fs::Metadata::len),
+        modified_ms: metadata
+            .as_ref()
+            .and_then(|metadata| metadata.modified().ok())
+            .and_then(|modified| modified.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|duration| duration.as_millis() as u64),
+        read_only: metadata
+            .as_ref()
+            .is_some_and(|metadata| metadata.permissions().readonly()),
+        is_symlink,
+    }
+}
+
+/// Show `path` in Finder or Explorer with it selected. Linux file managers have no common
+/// way to select a file, so its folder is opened instead.
+fn reveal_path(path: &Path) -> Result<(), CommandError> {
+    if !extended_length_path(path).exists() {
+        return Err(CommandError::NotFound {
+            path: path.to_string_lossy().to_string(),
+        });
+    }
+    // File managers want the plain form of the path, without `..` or links.
+    let path = PathBuf::from(canonical_open_path(&path.to_string_lossy()));
+
+    #[cfg(target_os = "macos")]
+    {
+        let status = std::process::Command::new("open")
+            .arg("-R")
+            .arg(&path)
+            .status()
+            .map_err(|e| CommandError::Io {
+                action: "run_finder",
+                error: e.to_string(),
+            })?;
+        if !status.success() {
+            return Err(CommandError::OpenFailed {
+                path: path.to_string_lossy().to_string(),
+            });
+        }
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::process::CommandExt;
+
+        // Explorer parses its own command line and needs the quotes after the comma,
+        // which the standard argument quoting would put around the whole switch. Paths
+        // can't contain quotes, so this is safe. Explorer's exit code means nothing.
+        std::process::Command::new("explorer")
+            .raw_arg(format!("/select,\"{}\"", path.display()))
+            .spawn()
+            .map_err(|e| CommandError::Io {
+                action: "run_explorer",
+                error: e.to_string(),
+            })?;
+    }
+
+    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+    {
+        let folder = if path.is_dir() {
+            path.as_path()
+        } else {
+            path.parent().unwrap_or_else(|| Path::new("/"))
+        };
+        let status = std::process::Command::new("xdg-open")
+            .arg(folder)
+            .status()
+            .map_err(|e| CommandError::Io {
+                action: "run_xdg_open",
+                error: e.to_string(),
+            })?;
+        if !status.success() {
+            return Err(CommandError::OpenFailed {
+                path: folder.to_string_lossy().to_string(),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Extensions of the documents the app reads or writes, the only files it will hand to
+/// their default app; anything else could be a program.
+const OPENABLE_EXTENSIONS: [&str; 9] = [
+    "pdf", "png", "jpg", "jpeg", "tif", "tiff", "ps", "eps", "txt",
+];
+
+/// Open the document at `path` in the app the system associates with its type. Links are
+/// resolved first, so only the type of the file actually opened counts.
+fn open_document(path: &Path) -> Result<(), CommandError> {
+    let canonical = PathBuf::from(canonical_open_path(&path.to_string_lossy()));
+    if !extended_length_path(&canonical).is_file() {
+        return Err(CommandError::NotAFile {
+            path: path.to_string_lossy().to_string(),
+        });
+    }
+    let openable = canonical
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| {
+            OPENABLE_EXTENSIONS
+                .iter()
+                .any(|openable| ext.eq_ignore_ascii_case(openable))
+        })
+        .unwrap_or(false);
+    if !openable {
+        return Err(CommandError::InvalidInput {
+            field: "document_type",
+            value: path.to_string_lossy().to_string(),
+            expected: OPENABLE_EXTENSIONS.join(", "),
+        });
+    }
+    let path = canonical;
+
+    #[cfg(target_os = "macos")]
+    {
+        let output = std::process::Command::new("open")
+            .arg(&path)
+            .output()
+            .map_err(|e| CommandError::Io {
+                action: "run_open",
+                error: e.to_string(),
+            })?;
+        if !output.status.success() {
+            log_warn!(
+                "open failed for '{}': {}",
+                path.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            return Err(CommandError::OpenFailed {
+                path: path.to_string_lossy().to_string(),
+            });
+        }
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::ffi::OsStrExt;
+
+        #[link(name = "shell32")]
+        extern "system" {
+            fn ShellExecuteW(
+                window: *mut std::ffi::c_void,
+                operation: *const u16,
+                file: *const u16,
+                parameters: *const u16,
+                directory: *const u16,
+                show: i32,
+            ) -> isize;
+        }
+        const SW_SHOWNORMAL: i32 = 1;
+
+        let wide = |text: &std::ffi::OsStr| -> Vec<u16> {
+            text.encode_wide().chain(std::iter::once(0)).collect()
+        };
+        let operation = wide(std::ffi::OsStr::new("open"));
+        let file = wide(path.as_os_str());
+        // SAFETY: the strings are NUL-terminated and outlive the call.
+        let code = unsafe {
+            ShellExecuteW(
+                std::ptr::null_mut(),
+                operation.as_ptr(),
+                file.as_ptr(),
+                std::ptr::null(),
+                std::ptr::null(),
+                SW_SHOWNORMAL,
+            )
+        };
+        // Values above 32 mean success; 31 means no app handles the type.
+        if code <= 32 {
+            log_warn!("ShellExecuteW failed for '{}': {}", path.display(), code);
+            return Err(CommandError::OpenFailed {
+                path: path.to_string_lossy().to_string(),
+            });
+        }
+    }
+
+    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+    {
+        let status = std::process::Command::new("xdg-open")
+            .arg(&path)
+            .status()
+            .map_err(|e| CommandError::Io {
+                action: "run_xdg_open",
+                error: e.to_string(),
+            })?;
+        if !status.success() {
+            return Err(CommandError::OpenFailed {
+                path: path.to_string_lossy().to_string(),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Read just enough of `path` to tell whether it is a PDF and which version it claims.
+fn check_pdf_file(path: &Path) -> PdfCheck {
+    use std::io::Read;
+
+    let extended = extended_length_path(path);
+    let mut check = PdfCheck {
+        path: path.to_string_lossy().to_string(),
+        exists: extended.exists(),
+        is_pdf: false,
+        version: None,
+        error: None,
+    };
+    if !check.exists {
+        check.error = Some(CommandError::NotFound {
+            path: check.path.clone(),
+        });
+        return check;
+    }
+    if extended.is_dir() {
+        check.error = Some(CommandError::NotAFile {
+            path: check.path.clone(),
+        });
+        return check;
+    }
+    let not_a_pdf = |reason| CommandError::NotAPdf {
+        path: path.to_string_lossy().to_string(),
+        reason,
+    };
+    let mut head = Vec::with_capacity(PDF_HEADER_WINDOW + 8);
+    let read = std::fs::File::open(&extended).and_then(|file| {
+        file.take(PDF_HEADER_WINDOW as u64 + 8)
+            .read_to_end(&mut head)
+    });
+    match read {
+        Err(e) => {
+            check.error = Some(CommandError::Io {
+                action: "read",
+                error: format!("'{}': {}", path.display(), e),
+            })
+        }
+        Ok(0) => check.error = Some(not_a_pdf("empty")),
+        Ok(_) => match pdf_header(&head) {
+            Some((_, version)) => {
+                check.is_pdf = true;
+                check.version = version;
+            }
+            None => check.error = Some(not_a_pdf("not_pdf")),
+        },
+    }
+    check
+}
+
+/// The offset after the last `startxref` in the tail of `data`.
+fn startxref_offset(data: &[u8]) -> Option<usize> {
+    let tail = &data[data.len().saturating_sub(PDF_HEADER_WINDOW)..];
+    let index = tail.windows(9).rposition(|bytes| bytes == b"startxref")?;
+    let digits: String = tail[index + 9..]
+        .iter()
+        .map(|byte| char::from(*byte))
+        .skip_while(char::is_ascii_whitespace)
+        .take_while(char::is_ascii_digit)
+        .collect();
+    digits.parse().ok()
+}
+
+/// Whether an xref table (`xref`) or xref stream (`N G obj`) starts at `offset`.
+fn is_xref_section(data: &[u8], offset: usize) -> bool {
+    let Some(rest) = data.get(offset..) else {
+        return false;
+    };
+    let rest = &rest[..rest.len().min(32)];
+    if rest.starts_with(b"xref") {
+        return true;
+    }
+    let text: String = rest.iter().map(|byte| char::from(*byte)).collect();
+    let mut tokens = text.split_ascii_whitespace();
+    matches!(
+        (tokens.next(), tokens.next(), tokens.next()),
+        (Some(number), Some(generation), Some(keyword))
+            if number.parse::<u32>().is_ok()
+                && generation.parse::<u16>().is_ok()
+                && keyword.starts_with("obj")
+    )
+}
+
+/// Add the IDs `object` references, directly or through arrays and dictionaries.
+fn collect_references(object: &lopdf::Object, references: &mut Vec<lopdf::ObjectId>) {
+    match object {
+        lopdf::Object::Reference(id) => references.push(*id),
+        lopdf::Object::Array(items) => {
+            for item in items {
+                collect_references(item, references);
+            }
+        }
+        lopdf::Object::Dictionary(dict) => {
+            for (_, value) in dict.iter() {
+                collect_references(value, references);
+            }
+        }
+        lopdf::Object::Stream(stream) => {
+            for (_, value) in stream.dict.iter() {
+                collect_references(value, references);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Catalog, page tree and MediaBox checks for a file lopdf could parse.
+fn validate_document_structure(
+    doc: &lopdf::Document,
+    errors: &mut Vec<ValidationIssue>,
+    warnings: &mut Vec<ValidationIssue>,
+) {
+    let issue = |page: Option<u32>, message: String| ValidationIssue { page, message };
+
+    let mut references = Vec::new();
+    for object in doc.objects.values() {
+        collect_references(object, &mut references);
+    }
+    references.sort_unstable();
+    references.dedup();
+    let dangling = references
+        .iter()
+        .filter(|id| !doc.objects.contains_key(id))
+        .count();
+    if dangling > 0 {
+        warnings.push(issue(
+            None,
+            format!(
+                "{} reference(s) point to objects that don't exist.",
+                dangling
+            ),
+        ));
+    }
+
+    let catalog = match doc.catalog() {
+        Ok(catalog) => catalog,
+        Err(_) => {
+            errors.push(issue(
+                None,
+                String::from("The trailer has no /Root catalog."),
+            ));
+            return;
+        }
+    };
+    if !catalog
+        .get(b"Type")
+        .and_then(lopdf::Object::as_name)
+        .is_ok_and(|name| name == b"Catalog")
+    {
+        warnings.push(issue(
+            None,
+            String::from("The catalog has no /Type /Catalog."),
+        ));
+    }
+    let Some(pages) = catalog
+        .get(b"Pages")
+        .ok()
+        .and_then(|pages| pdf_dict(doc, pages))
+    else {
+        errors.push(issue(None, String::from("The catalog has no /Pages tree.")));
+        return;
+    };
+    let page_ids = doc.get_pages();
+    if page_ids.is_empty() {
+        errors.push(issue(None, String::from("The document has no pages.")));
+    }
+    match pages.get(b"Count").and_then(lopdf::Object::as_i64) {
+        Ok(count) if count != page_ids.len() as i64 => warnings.push(issue(
+            None,
+            format!(
+                "The page tree declares {} page(s) but contains {}.",
+                count,
+                page_ids.len()
+            ),
+        )),
+        Ok(_) => {}
+        Err(_) => warnings.push(issue(None, String::from("The page tree has no /Count."))),
+    }
+
+    // 14400 points (200 inches) is the largest page size the PDF spec asks readers to handle.
+    for (page, page_id) in page_ids {
+        let Some(media_box) = inherited_page_attribute(doc, page_id, b"MediaBox") else {
+            errors.push(issue(Some(page), String::from("The page has no MediaBox.")));
+            continue;
+        };
+        let Some([left, bottom, right, top]) = pdf_rect(doc, media_box) else {
+            errors.push(issue(
+                Some(page),
+                String::from("The MediaBox is not a rectangle."),
+            ));
+            continue;
+        };
+        let (width, height) = ((right - left).abs(), (top - bottom).abs());
+        if width < 1.0 || height < 1.0 {
+            errors.push(issue(
+                Some(page),
+                format!("The MediaBox is empty ({:.1} x {:.1} pt).", width, height),
+            ));
+        } else if width > 14400.0 || height > 14400.0 {
+            warnings.push(issue(
+                Some(page),
+                format!(
+                    "The MediaBox ({:.0} x {:.0} pt) exceeds the 14400 pt limit.",
+                    width, height
+                ),
+            ));
+        }
+        if left > right || bottom > top {
+            warnings.push(issue(
+                Some(page),
+                String::from("The MediaBox corners are swapped."),
+            ));
+        }
+    }
+}
+
+/// Check a file before it is processed: the header, the cross-reference data, the catalog
+/// and page tree, every page's MediaBox, and a Ghostscript `nullpage` dry run of every
+/// page. Ghostscript messages are tied to pages through its `Page N` lines on stdout;
+/// those on stderr are reported for the document.
+fn validate_pdf_file(
+    input: &Path,
+    runtime: &GhostscriptRuntime,
+    ctx: JobContext<'_>,
+) -> Result<ValidationReport, CommandError> {
+    let data = std::fs::read(input).map_err(|e| CommandError::Io {
+        action: "read",
+        error: format!("'{}': {}", input.display(), e),
+    })?;
+    let issue = |page: Option<u32>, message: &str| ValidationIssue {
+        page,
+        message: message.to_string(),
+    };
+    let mut errors = Vec::new();
+    let mut warnings = Vec::new();
+
+    let header = pdf_header(&data);
+    match &header {
+        None => errors.push(issue(None, "The file has no %PDF- header.")),
+        Some((offset, version)) => {
+            if *offset > 0 {
+                warnings.push(issue(
+                    None,
+                    &format!("{} byte(s) of junk precede the %PDF- header.", offset),
+                ));
+            }
+            if version.is_none() {
+                warnings.push(issue(None, "The %PDF- header has no version number."));
+            }
+        }
+    }
+    let header_offset = header.as_ref().map_or(0, |(offset, _)| *offset);
+    match startxref_offset(&data) {
+        None => errors.push(issue(
+            None,
+            "The file has no startxref; it may be truncated.",
+        )),
+        // Offsets are meant to count from the file start, but readers also accept them
+        // counted from the header.
+        Some(offset)
+            if !is_xref_section(&data, offset)
+                && !is_xref_section(&data, offset + header_offset) =>
+        {
+            warnings.push(issue(
+                None,
+                "startxref does not point at the cross-reference data; readers have to rebuild it.",
+            ))
+        }
+        Some(_) => {}
+    }
+
+    let encrypted = declares_encryption(input);
+    let mut page_count = None;
+    match lopdf::Document::load_mem(&data) {
+        Ok(doc) => {
+            page_count = Some(doc.get_pages().len() as u32);
+            validate_document_structure(&doc, &mut errors, &mut warnings);
+        }
+        // Encrypted structure can't be read without the key; Ghostscript still checks it.
+        Err(_) if encrypted => warnings.push(issue(
+            None,
+            "The file is encrypted, so its structure was only checked by Ghostscript.",
+        )),
+        Err(e) => errors.push(issue(
+            None,
+            &format!("The cross-reference data or objects are damaged: {}", e),
+        )),
+    }
+
+    let mut password_required = false;
+    if header.is_some() {
+        let device_args = [String::from("-dPDFSTOPONERROR=false")];
+        match run_device_to(
+            "nullpage",
+            &[input],
+            "-sOutputFile=%stdout",
+            &device_args,
+            page_count,
+            runtime,
+            ctx,
+        ) {
+            Err(e @ CommandError::Cancelled) => return Err(e),
+            Err(CommandError::PasswordRequired) if encrypted => password_required = true,
+            Err(e) => errors.push(issue(None, &e.detailed_message())),
+            Ok(result) => {
+                let mut current_page = None;
+                let mut classify = |page: Option<u32>, line: &str| {
+                    let line = line.trim();
+                    if line.starts_with("**** Error") || line.starts_with("**** ERROR") {
+                        errors.push(issue(page, line));
+                    } else if line.starts_with("****") {
+                        warnings.push(issue(page, line));
+                    }
+                };
+                for line in result.stdout.lines() {
+                    if let Some(page) = parse_page_line(line) {
+                        current_page = Some(page);
+                    } else {
+                        classify(current_page, line);
+                    }
+                }
+                for line in result.stderr.lines() {
+                    classify(None, line);
+                }
+            }
+        }
+    }
+
+    Ok(ValidationReport {
+        path: input.to_string_lossy().to_string(),
+        valid: errors.is_empty(),
+        version: header.and_then(|(_, version)| version),
+        encrypted,
+        password_required,
+        page_count,
+        errors,
+        warnings,
+    })
+}
+
+/// Validate 1-based page numbers against the document; `None` selects every page.
+/// The result is sorted and free of duplicates.
+fn resolve_page_selection(
+    pages: Option<Vec<u32>>,
+    page_count: u32,
+) -> Result<Vec<u32>, CommandError> {
+    let mut pages = match pages {
+        Some(pages) => pages,
+        None => return Ok((1..=page_count).collect()),
+    };
+    if let Some(page) = pages.iter().find(|page| **page == 0 || **page > page_count) {
+        return Err(CommandError::PageOutOfRange {
+            page: *page,
+            page_count,
+        });
+    }
+    pages.sort_unstable();
+    pages.dedup();
+    Ok(pages)
+}
+
+macro_rules! output_results {
+    ($($result:ty),* $(,)?) => {
+        $(impl OutputResult for $result {
+            fn set_output_path(&mut self, path: &Path) {
+                self.output_path = path.to_string_lossy().to_string();
+            }
+        })*
+    };
+}
+
+output_results!(
+    ResizeResult,
+    FitResult,
+    CropResult,
+    RotateResult,
+    PageCountResult,
+    NupResult,
+    BookletResult,
+    CompressResult,
+    DownsampleResult,
+    GrayscaleResult,
+    CmykResult,
+    PdfaResult,
+    PdfxResult,
+    LinearizeResult,
+    EncryptResult,
+    MetadataResult,
+    PostScriptResult,
+    WatermarkResult,
+    StampResult,
+    PageNumberResult,
+    PrinterMarksResult,
+    MirrorResult,
+    RepairReport,
+    EmbedFontsResult,
+    RemoveAnnotationsResult,
+    FlattenFormsResult,
+    SanitizeReport,
+    PageBoxesResult,
+    OverlayResult,
+    ExtractedAttachment,
+);
+
+/// Check a `[left, bottom, right, top]` box given relative to the MediaBox origin as the
+/// argument `field`.
+fn validate_relative_box(field: &'static str, rect: [f64; 4]) -> Result<(), CommandError> {
+    let [left, bottom, right, top] = rect;
+    if rect.iter().any(|value| !value.is_finite() || *value < 0.0) {
+        return Err(CommandError::OutOfRange {
+            field,
+            value: format!("{:?}", rect),
+            expected: String::from("zero or positive"),
+        });
+    }
+    if left >= right || bottom >= top {
+        return Err(CommandError::InvalidInput {
+            field,
+            value: format!("{:?}", rect),
+            expected: String::from("left below right and bottom below top"),
+        });
+    }
+    Ok(())
+}
+
+/// Set the TrimBox and/or BleedBox of the selected pages, given in points relative to each
+/// page's MediaBox origin. The trim box must fit the MediaBox and the bleed box must lie
+/// within the MediaBox and contain the trim box, whether given here or already set.
+fn set_pdf_page_boxes(
+    input: &Path,
+    output: &Path,
+    trim: Option<[f64; 4]>,
+    bleed: Option<[f64; 4]>,
+    pages: Option<Vec<u32>>,
+) -> Result<PageBoxesResult, CommandError> {
+    if trim.is_none() && bleed.is_none() {
+        return Err(CommandError::MissingInput {
+            field: "trim_or_bleed",
+        });
+    }
+    for (field, rect) in [("trim", trim), ("bleed", bleed)] {
+        if let Some(rect) = rect {
+            validate_relative_box(field, rect)?;
+        }
+    }
+    ensure_distinct_paths(input, output)?;
+
+    let mut doc = load_pdf_document(input)?;
+    let geometry = read_page_geometry(&doc);
+    let page_ids = doc.get_pages();
+    let selected = resolve_page_selection(pages, page_ids.len() as u32)?;
+
+    // Rounding in the stored numbers shouldn't fail the containment checks.
+    const TOLERANCE: f64 = 0.01;
+    let contains = |outer: [f64; 4], inner: [f64; 4]| {
+        inner[0] >= outer[0] - TOLERANCE
+            && inner[1] >= outer[1] - TOLERANCE
+            && inner[2] <= outer[2] + TOLERANCE
+            && inner[3] <= outer[3] + TOLERANCE
+    };
+    let mut results = Vec::with_capacity(selected.len());
+    for page in selected {
+        let page_geometry = &geometry[page as usize - 1];
+        let media = page_geometry.media_box;
+        let absolute = |rect: [f64; 4]| {
+            [
+                media[0] + rect[0],
+                media[1] + rect[1],
+                media[0] + rect[2],
+                media[1] + rect[3],
+            ]
+        };
+        let trim_box = trim.map(absolute).or(page_geometry.trim_box);
+        let bleed_box = bleed.map(absolute).or(page_geometry.bleed_box);
+        for (field, rect) in [("trim", trim_box), ("bleed", bleed_box)] {
+            if rect.is_some_and(|rect| !contains(media, rect)) {
+                return Err(CommandError::BoxExceedsPage {
+                    field,
+                    page,
+                    width_pt: media[2] - media[0],
+                    height_pt: media[3] - media[1],
+                });
+            }
+        }
+        if let (Some(trim_box), Some(bleed_box)) = (trim_box, bleed_box) {
+            if !contains(bleed_box, trim_box) {
+                return Err(CommandError::BleedMissesTrim { page });
+            }
+        }
+
+        let page_dict = doc
+            .get_dictionary_mut(page_ids[&page])
+            .map_err(|e| CommandError::Io {
+                action: "read_page",
+                error: format!("page {}: {}", page, e),
+            })?;
+        if let Some(trim_box) = trim_box.filter(|_| trim.is_some()) {
+            page_dict.set("TrimBox", pdf_rect_object(trim_box));
+        }
+        if let Some(bleed_box) = bleed_box.filter(|_| bleed.is_some()) {
+            page_dict.set("BleedBox", pdf_rect_object(bleed_box));
+        }
+        results.push(PageBoxes {
+            page,
+            media_box: media,
+            crop_box: page_geometry.crop_box,
+            trim_box,
+            bleed_box,
+        });
+    }
+
+    save_pdf_document(&mut doc, output)?;
+    Ok(PageBoxesResult {
+        output_path: output.to_string_lossy().to_string(),
+        pages: results,
+    })
+}
+
+/// Set the CropBox (and optionally the MediaBox) of the selected pages. The box is given
+/// in points relative to each page's MediaBox origin.
+fn crop_pdf_pages(
+    input: &Path,
+    output: &Path,
+    crop: [f64; 4],
+    pages: Option<Vec<u32>>,
+    set_media_box: bool,
+) -> Result<CropResult, CommandError> {
+    let [left, bottom, right, top] = crop;
+    validate_relative_box("crop", crop)?;
+    ensure_distinct_paths(input, output)?;
+
+    let mut doc = load_pdf_document(input)?;
+    let geometry = read_page_geometry(&doc);
+    let page_ids = doc.get_pages();
+    let selected = resolve_page_selection(pages, page_ids.len() as u32)?;
+
+    let mut cropped = Vec::with_capacity(selected.len());
+    for page in selected {
+        let media_box = geometry[page as usize - 1].media_box;
+        let media_width = media_box[2] - media_box[0];
+        let media_height = media_box[3] - media_box[1];
+        if right > media_width || top > media_height {
+            return Err(CommandError::BoxExceedsPage {
+                field: "crop",
+                page,
+                width_pt: media_width,
+                height_pt: media_height,
+            });
+        }
+        let crop_box = [
+            media_box[0] + left,
+            media_box[1] + bottom,
+            media_box[0] + right,
+            media_box[1] + top,
+        ];
+        let page_dict = doc
+            .get_dictionary_mut(page_ids[&page])
+            .map_err(|e| CommandError::Io {
+                action: "read_page",
+                error: format!("page {}: {}", page, e),
+            })?;
+        page_dict.set("CropBox", pdf_rect_object(crop_box));
+        if set_media_box {
+            page_dict.set("MediaBox", pdf_rect_object(crop_box));
+        }
+        cropped.push(CroppedPage {
+            page,
+            crop_box,
+            width_pt: right - left,
+            height_pt: top - bottom,
+        });
+    }
+
+    save_pdf_document(&mut doc, output)?;
+    Ok(CropResult {
+        output_path: output.to_string_lossy().to_string(),
+        pages: cropped,
+    })
+}
+
+/// Add `rotation` (a multiple of 90, may be negative) to the /Rotate of the selected
+/// pages, composing with any rotation they already carry.
+fn rotate_pdf_pages(
+    input: &Path,
+    output: &Path,
+    rotation: i32,
+    pages: Option<Vec<u32>>,
+) -> Result<RotateResult, CommandError> {
+    if rotation % 90 != 0 {
+        return Err(CommandError::OutOfRange {
+            field: "rotation",
+            value: rotation.to_string(),
+            expected: String::from("a multiple of 90 degrees"),
+        });
+    }
+    ensure_distinct_paths(input, output)?;
+
+    let mut doc = load_pdf_document(input)?;
+    let geometry = read_page_geometry(&doc);
+    let page_ids = doc.get_pages();
+    let selected = resolve_page_selection(pages, page_ids.len() as u32)?;
+
+    let mut rotations: Vec<PageRotation> = geometry
+        .iter()
+        .map(|page| PageRotation {
+            page: page.page,
+            rotation: page.rotate,
+        })
+        .collect();
+    for page in selected {
+        let entry = &mut rotations[page as usize - 1];
+        entry.rotation = normalize_rotation(i64::from(entry.rotation) + i64::from(rotation));
+        doc.get_dictionary_mut(page_ids[&page])
+            .map_err(|e| CommandError::Io {
+                action: "read_page",
+                error: format!("page {}: {}", page, e),
+            })?
+            .set("Rotate", i64::from(entry.rotation));
+    }
+
+    save_pdf_document(&mut doc, output)?;
+    Ok(RotateResult {
+        output_path: output.to_string_lossy().to_string(),
+        pages: rotations,
+    })
+}
+
+/// Copy inclusive 1-based page ranges of `input`, in the given order, into `output`:
+/// one `-dFirstPage`/`-dLastPage` pdfwrite pass per range, merged when there are several.
+fn assemble_page_ranges(
+    input: &Path,
+    output: &Path,
+    ranges: &[(u32, u32)],
+    runtime: &GhostscriptRuntime,
+    ctx: JobContext<'_>,
+) -> Result<u32, CommandError> {
+    let output_pages: u32 = ranges.iter().map(|(first, last)| last - first + 1).sum();
+    let mut temp_files = TempFileGuard::default();
+    let mut pass_outputs = Vec::with_capacity(ranges.len());
+    let mut pages_done = 0;
+
+    for &(first, last) in ranges {
+        // Report progress against the output document rather than source page numbers.
+        let on_pass_progress = |page: u32, _total: Option<u32>| {
+            if let Some(on_progress) = ctx.on_progress {
+                let within = if page >= first {
+                    page - first + 1
+                } else {
+                    page
+                };
+                on_progress(
+                    pages_done + within.min(last - first + 1),
+                    Some(output_pages),
+                );
+            }
+        };
+        let pass_ctx = JobContext {
+            on_progress: Some(&on_pass_progress),
+            ..ctx
+        };
+        let pass_output = if ranges.len() == 1 {
+            output.to_path_buf()
+        } else {
+            temp_files.track(unique_temp_path("range", "pdf"))
+        };
+        let device_args = [
+            format!("-dFirstPage={}", first),
+            format!("-dLastPage={}", last),
+        ];
+        run_pdfwrite(
+            &[input],
+            &pass_output,
+            &device_args,
+            None,
+            runtime,
+            pass_ctx,
+        )?;
+        pass_outputs.push(pass_output);
+        pages_done += last - first + 1;
+    }
+
+    if ranges.len() > 1 {
+        let merge_inputs: Vec<&Path> = pass_outputs.iter().map(PathBuf::as_path).collect();
+        run_pdfwrite(
+            &merge_inputs,
+            output,
+            &[],
+            None,
+            runtime,
+            JobContext {
+                on_progress: None,
+                ..ctx
+            },
+        )?;
+    }
+    Ok(output_pages)
+}
+
+/// Extract page ranges (inclusive, 1-based, kept in the given order) into a new PDF.
+fn extract_pdf_pages(
+    input: &Path,
+    output: &Path,
+    ranges: &[(u32, u32)],
+    runtime: &GhostscriptRuntime,
+    ctx: JobContext<'_>,
+) -> Result<PageCountResult, CommandError> {
+    if ranges.is_empty() {
+        return Err(CommandError::MissingInput { field: "ranges" });
+    }
+    if !input.is_file() {
+        return Err(CommandError::NotFound {
+            path: input.to_string_lossy().to_string(),
+        });
+    }
+    ensure_distinct_paths(input, output)?;
+
+    let page_count = document_page_count(input, runtime, ctx.hooks())?;
+    for &(first, last) in ranges {
+        if first == 0 || first > last {
+            return Err(CommandError::InvalidInput {
+                field: "ranges",
+                value: format!("{}-{}", first, last),
+                expected: String::from("first page from 1 up to the last page"),
+            });
+        }
+        if last > page_count {
+            return Err(CommandError::PageOutOfRange {
+                page: last,
+                page_count,
+            });
+        }
+    }
+
+    let page_count = assemble_page_ranges(input, output, ranges, runtime, ctx)?;
+    Ok(PageCountResult {
+        output_path: output.to_string_lossy().to_string(),
+        page_count,
+    })
+}
+
+/// Ranges of the pages that remain after removing the sorted, deduplicated `deleted`.
+fn kept_page_ranges(page_count: u32, deleted: &[u32]) -> Vec<(u32, u32)> {
+    let mut ranges = Vec::new();
+    let mut next = 1;
+    for &page in deleted {
+        if page > next {
+            ranges.push((next, page - 1));
+        }
+        next = page + 1;
+    }
+    if next <= page_count {
+        ranges.push((next, page_count));
+    }
+    ranges
+}
+
+/// Copy the document without the listed pages.
+fn delete_pdf_pages(
+    input: &Path,
+    output: &Path,
+    pages: Vec<u32>,
+    runtime: &GhostscriptRuntime,
+    ctx: JobContext<'_>,
+) -> Result<PageCountResult, CommandError> {
+    if pages.is_empty() {
+        return Err(CommandError::MissingInput { field: "pages" });
+    }
+    if !input.is_file() {
+        return Err(CommandError::NotFound {
+            path: input.to_string_lossy().to_string(),
+        });
+    }
+    ensure_distinct_paths(input, output)?;
+
+    let page_count = document_page_count(input, runtime, ctx.hooks())?;
+    let deleted = resolve_page_selection(Some(pages), page_count)?;
+    let ranges = kept_page_ranges(page_count, &deleted);
+    if ranges.is_empty() {
+        return Err(CommandError::NoPagesLeft);
+    }
+
+    let page_count = assemble_page_ranges(input, output, &ranges, runtime, ctx)?;
+    Ok(PageCountResult {
+        output_path: output.to_string_lossy().to_string(),
+        page_count,
+    })
+}
+
+/// Collapse a page sequence into ranges of consecutive ascending pages, so
+/// `[1, 2, 3, 7, 8, 4]` needs three passes instead of six.
+fn sequence_to_ranges(order: &[u32]) -> Vec<(u32, u32)> {
+    let mut ranges: Vec<(u32, u32)> = Vec::new();
+    for &page in order {
+        match ranges.last_mut() {
+            Some((_, last)) if *last + 1 == page => *last = page,
+            _ => ranges.push((page, page)),
+        }
+    }
+    ranges
+}
+
+/// Rebuild the document with pages in `order` (1-based, repeats allowed).
+fn reorder_pdf_pages(
+    input: &Path,
+    output: &Path,
+    order: &[u32],
+    runtime: &GhostscriptRuntime,
+    ctx: JobContext<'_>,
+) -> Result<PageCountResult, CommandError> {
+    if order.is_empty() {
+        return Err(CommandError::MissingInput { field: "order" });
+    }
+    if !input.is_file() {
+        return Err(CommandError::NotFound {
+            path: input.to_string_lossy().to_string(),
+        });
+    }
+    ensure_distinct_paths(input, output)?;
+
+    let page_count = document_page_count(input, runtime, ctx.hooks())?;
+    if let Some(page) = order.iter().find(|page| **page == 0 || **page > page_count) {
+        return Err(CommandError::PageOutOfRange {
+            page: *page,
+            page_count,
+        });
+    }
+
+    let ranges = sequence_to_ranges(order);
+    let page_count = assemble_page_ranges(input, output, &ranges, runtime, ctx)?;
+    Ok(PageCountResult {
+        output_path: output.to_string_lossy().to_string(),
+        page_count,
+    })
+}
+
+/// Build a Form XObject holding a page's content and resources, clipped to `bbox`.
+fn page_form_stream(
+    doc: &lopdf::Document,
+    page_id: lopdf::ObjectId,
+    bbox: [f64; 4],
+) -> Result<lopdf::Stream, CommandError> {
+    let mut dict = lopdf::Dictionary::new();
+    dict.set("Type", "XObject");
+    dict.set("Subtype", "Form");
+    dict.set("BBox", pdf_rect_object(bbox));
+    if let Some(resources) = inherited_page_attribute(doc, page_id, b"Resources") {
+        dict.set("Resources", resources.clone());
+    }
+    // Keep the transparency group so blending inside the page renders the same.
+    if let Some(group) = doc
+        .get_dictionary(page_id)
+        .ok()
+        .and_then(|page| page.get(b"Group").ok())
+    {
+        dict.set("Group", group.clone());
+    }
+
+    let streams: Vec<&lopdf::Stream> = doc
+        .get_page_contents(page_id)
+        .into_iter()
+        .filter_map(|id| doc.get_object(id).and_then(lopdf::Object::as_stream).ok())
+        .collect();
+    // A lone stream in a filter lopdf can't decode is carried over still encoded.
+    if let [stream] = streams.as_slice() {
+        if stream.dict.has(b"Filter") && stream.decompressed_content().is_err() {
+            for key in [&b"Filter"[..], b"DecodeParms"] {
+                if let Ok(value) = stream.dict.get(key) {
+                    dict.set(key, value.clone());
+                }
+            }
+            return Ok(lopdf::Stream::new(dict, stream.content.clone()));
+        }
+    }
+    let mut content = Vec::new();
+    for stream in streams {
+        content.extend(stream_content(stream)?);
+        // Content streams may split anywhere between tokens, so keep them apart.
+        content.push(b'\n');
+    }
+    Ok(lopdf::Stream::new(dict, content))
+}
+
+/// Wrap every page of `doc`, in order, as a Form XObject that can be drawn onto new sheets.
+fn page_forms(doc: &mut lopdf::Document) -> Result<Vec<PageForm>, CommandError> {
+    let geometry = read_page_geometry(doc);
+    let page_ids = doc.get_pages();
+    let mut forms = Vec::with_capacity(geometry.len());
+    for page in geometry {
+        let stream = page_form_stream(doc, page_ids[&page.page], page.visible_box())?;
+        forms.push(PageForm {
+            id: doc.add_object(stream),
+            geometry: page,
+        });
+    }
+    Ok(forms)
+}
+
+fn pdf_matrix_operands(matrix: PdfMatrix) -> String {
+    matrix
+        .iter()
+        .map(|value| format!("{:.4}", value))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl Sheet {
+    fn new(size: PageDimensions) -> Self {
+        Sheet {
+            size,
+            content: String::new(),
+            xobjects: lopdf::Dictionary::new(),
+        }
+    }
+
+    fn draw_form(&mut self, form: &PageForm, matrix: PdfMatrix) {
+        let name = format!("Pg{}", form.geometry.page);
+        self.content.push_str(&format!(
+            "q {} cm /{} Do Q\n",
+            pdf_matrix_operands(matrix),
+            name
+        ));
+        self.xobjects.set(name, form.id);
+    }
+
+    /// Draw `form` upright, scaled uniformly to fit `cell` (`[x, y, width, height]`) and
+    /// centered in it. Returns the rectangle the page occupies.
+    fn place_in_cell(&mut self, form: &PageForm, cell: [f64; 4]) -> [f64; 4] {
+        let (upright, size) = form.geometry.upright_matrix();
+        let scale = (cell[2] / size.width_pt).min(cell[3] / size.height_pt);
+        if !scale.is_finite() || scale <= 0.0 {
+            return cell;
+        }
+        let width = size.width_pt * scale;
+        let height = size.height_pt * scale;
+        let x = cell[0] + (cell[2] - width) / 2.0;
+        let y = cell[1] + (cell[3] - height) / 2.0;
+        self.draw_form(form, concat_matrix(upright, [scale, 0.0, 0.0, scale, x, y]));
+        [x, y, width, height]
+    }
+
+    fn into_page(self, doc: &mut lopdf::Document) -> lopdf::Dictionary {
+        let contents = doc.add_object(lopdf::Stream::new(
+            lopdf::Dictionary::new(),
+            self.content.into_bytes(),
+        ));
+        let mut resources = lopdf::Dictionary::new();
+        resources.set("XObject", self.xobjects);
+        let mut page = lopdf::Dictionary::new();
+        page.set("Type", "Page");
+        page.set(
+            "MediaBox",
+            pdf_rect_object([0.0, 0.0, self.size.width_pt, self.size.height_pt]),
+        );
+        // Explicit values so nothing is inherited from the original page tree root.
+        page.set("Rotate", 0);
+        page.set("Resources", resources);
+        page.set("Contents", contents);
+        page
+    }
+}
+
+/// Replace the document's pages with `pages`. Outlines, page labels, form fields and
+/// structure refer to the old pages, so they are dropped along with them.
+fn replace_document_pages(
+    doc: &mut lopdf::Document,
+    pages: Vec<lopdf::Dictionary>,
+) -> Result<(), CommandError> {
+    let catalog_id = doc
+        .trailer
+        .get(b"Root")
+        .and_then(lopdf::Object::as_reference)
+        .map_err(|e| CommandError::Io {
+            action: "read_document_catalog",
+            error: e.to_string(),
+        })?;
+    let catalog = doc
+        .get_dictionary_mut(catalog_id)
+        .map_err(|e| CommandError::Io {
+            action: "read_document_catalog",
+            error: e.to_string(),
+        })?;
+    for key in [
+        &b"Outlines"[..],
+        b"PageLabels",
+        b"AcroForm",
+        b"StructTreeRoot",
+        b"OpenAction",
+    ] {
+        catalog.remove(key);
+    }
+    let pages_id = catalog
+        .get(b"Pages")
+        .and_then(lopdf::Object::as_reference)
+        .map_err(|e| CommandError::Io {
+            action: "read_page_tree",
+            error: e.to_string(),
+        })?;
+
+    let count = pages.len() as i64;
+    let kids: Vec<lopdf::Object> = pages
+        .into_iter()
+        .map(|mut page| {
+            page.set("Parent", pages_id);
+            lopdf::Object::Reference(doc.add_object(page))
+        })
+        .collect();
+    let root = doc
+        .get_dictionary_mut(pages_id)
+        .map_err(|e| CommandError::Io {
+            action: "read_page_tree",
+            error: e.to_string(),
+        })?;
+    root.set("Kids", kids);
+    root.set("Count", count);
+    doc.prune_objects();
+    Ok(())
+}
+
+impl NupGrid {
+    fn new(cols: u32, rows: u32, sheet: PageDimensions, gap: f64) -> Result<Self, CommandError> {
+        let cell = PageDimensions {
+            width_pt: (sheet.width_pt - gap * f64::from(cols + 1)) / f64::from(cols),
+            height_pt: (sheet.height_pt - gap * f64::from(rows + 1)) / f64::from(rows),
+        };
+        if cell.width_pt <= 0.0 || cell.height_pt <= 0.0 {
+            let max_gap =
+                (sheet.width_pt / f64::from(cols + 1)).min(sheet.height_pt / f64::from(rows + 1));
+            return Err(CommandError::OutOfRange {
+                field: "gap",
+                value: gap.to_string(),
+                expected: format!("less than {:.1}", max_gap),
+            });
+        }
+        Ok(NupGrid {
+            cols: cols as usize,
+            rows: rows as usize,
+            gap,
+            sheet,
+            cell,
+        })
+    }
+
+    fn per_sheet(&self) -> usize {
+        self.cols * self.rows
+    }
+
+    /// Scale at which `page` fits a cell.
+    fn fit_scale(&self, page: PageDimensions) -> f64 {
+        (self.cell.width_pt / page.width_pt).min(self.cell.height_pt / page.height_pt)
+    }
+
+    /// Cell `[x, y, width, height]` for `slot` (reading order) on a sheet holding `count`
+    /// pages. On a short sheet the occupied rows, and the partial last row, are centered.
+    fn cell_rect(&self, slot: usize, count: usize) -> [f64; 4] {
+        let col = slot % self.cols;
+        let row = slot / self.cols;
+        let rows_used = (count + self.cols - 1) / self.cols;
+        let in_row = if row + 1 == rows_used {
+            count - row * self.cols
+        } else {
+            self.cols
+        };
+        let step_x = self.cell.width_pt + self.gap;
+        let step_y = self.cell.height_pt + self.gap;
+        let x = self.gap + col as f64 * step_x + (self.cols - in_row) as f64 * step_x / 2.0;
+        let top = self.sheet.height_pt
+            - self.gap
+            - row as f64 * step_y
+            - (self.rows - rows_used) as f64 * step_y / 2.0;
+        [
+            x,
+            top - self.cell.height_pt,
+            self.cell.width_pt,
+            self.cell.height_pt,
+        ]
+    }
+}
+
+/// Where a page of an N-up job lands on its sheet.
+struct NupPlacement {
+    scale: f64,
+    /// `[x, y, width, height]` of the scaled page on the sheet.
+    rect: [f64; 4],
+    /// The page is the last on its sheet, which is emitted after it.
+    ends_sheet: bool,
+}
+
+/// PostScript that imposes the `n`th page drawn (0-based) by `placements[n]`. `/BeginPage`
+/// moves and scales each page into its cell; `/EndPage` strokes its border and emits the
+/// sheet only after its last page, so the pages before it are drawn onto the same sheet.
+fn nup_program(placements: &[NupPlacement], border: bool) -> String {
+    let places: Vec<String> = placements
+        .iter()
+        .map(|placement| {
+            format!(
+                "[{:.6} {:.4} {:.4}]",
+                placement.scale, placement.rect[0], placement.rect[1]
+            )
+        })
+        .collect();
+    let frames: Vec<String> = placements
+        .iter()
+        .map(|placement| {
+            let [x, y, width, height] = placement.rect;
+            format!(
+                "[{:.4} {:.4} {:.4} {:.4} {}]",
+                x, y, width, height, placement.ends_sheet
+            )
+        })
+        .collect();
+    // 0.25 pt rather than a zero-width line, which some printers drop entirely.
+    let frame = if border {
+        "gsave initgraphics 0 setgray 0.25 setlinewidth rectstroke grestore"
+    } else {
+        "pop pop pop pop"
+    };
+    format!(
+        "%!\n\
+         /nup_dict 2 dict def\n\
+         nup_dict /nup_places [{places}] put\n\
+         nup_dict /nup_frames [{frames}] put\n\
+         << /BeginPage {{\n\
+         //nup_dict /nup_places get exch\n\
+         2 copy exch length lt {{ get aload pop translate dup scale }} {{ pop pop }} ifelse\n\
+         }} bind\n\
+         /EndPage {{\n\
+         2 eq {{ pop false }} {{\n\
+         //nup_dict /nup_frames get exch\n\
+         2 copy exch length lt {{ get aload pop 5 1 roll {frame} }} {{ pop pop true }} ifelse\n\
+         }} ifelse\n\
+         }} bind >> setpagedevice\n",
+        places = places.join(" "),
+        frames = frames.join(" "),
+        frame = frame,
+    )
+}
+
+/// Impose `cols` x `rows` source pages per sheet, left to right and top to bottom, with a
+/// PostScript program run ahead of the input by Ghostscript (see `nup_program`). Each
+/// page is scaled to its own cell, so mixed page sizes are fine; the pages of a short
+/// last sheet are centered. The sheet is turned whichever way shows the first page larger.
+fn nup_with_ghostscript(
+    input: &Path,
+    output: &Path,
+    layout: NupLayout<'_>,
+    runtime: &GhostscriptRuntime,
+    ctx: JobContext<'_>,
+) -> Result<NupResult, CommandError> {
+    let NupLayout {
+        cols,
+        rows,
+        sheet_size,
+        gap_pt,
+        border,
+    } = layout;
+    for (field, value) in [("cols", cols), ("rows", rows)] {
+        if value == 0 {
+            return Err(CommandError::OutOfRange {
+                field,
+                value: value.to_string(),
+                expected: String::from("at least 1"),
+            });
+        }
+    }
+    if !gap_pt.is_finite() || gap_pt < 0.0 {
+        return Err(CommandError::OutOfRange {
+            field: "gap",
+            value: gap_pt.to_string(),
+            expected: String::from("zero or positive"),
+        });
+    }
+    let sheet = paper_size(sheet_size).ok_or_else(|| CommandError::InvalidInput {
+        field: "sheet_size",
+        value: sheet_size.to_string(),
+        expected: String::from(PAPER_SIZE_NAMES),
+    })?;
+    if !input.is_file() {
+        return Err(CommandError::NotFound {
+            path: input.to_string_lossy().to_string(),
+        });
+    }
+    ensure_distinct_paths(input, output)?;
+
+    // Ghostscript draws the crop box with `-dUseCropBox`, turned by `/Rotate`.
+    let pages: Vec<PageDimensions> = read_page_geometry(&load_pdf_document(input)?)
+        .iter()
+        .map(|page| page.box_size(page.visible_box()))
+        .collect();
+    let Some(&first_page) = pages.first() else {
+        return Err(CommandError::NoPages {
+            path: input.to_string_lossy().to_string(),
+        });
+    };
+    let portrait = NupGrid::new(cols, rows, sheet.oriented(false), gap_pt);
+    let landscape = NupGrid::new(cols, rows, sheet.oriented(true), gap_pt);
+    let grid = match (portrait, landscape) {
+        (Ok(portrait), Ok(landscape)) => {
+            if landscape.fit_scale(first_page) > portrait.fit_scale(first_page) {
+                landscape
+            } else {
+                portrait
+            }
+        }
+        (Ok(grid), Err(_)) | (Err(_), Ok(grid)) => grid,
+        (Err(e), Err(_)) => return Err(e),
+    };
+
+    let mut placements = Vec::with_capacity(pages.len());
+    for chunk in pages.chunks(grid.per_sheet()) {
+        for (slot, page) in chunk.iter().enumerate() {
+            let cell = grid.cell_rect(slot, chunk.len());
+            let scale = grid.fit_scale(*page);
+            let width = page.width_pt * scale;
+            let height = page.height_pt * scale;
+            placements.push(NupPlacement {
+                scale,
+                rect: [
+                    cell[0] + (cell[2] - width) / 2.0,
+                    cell[1] + (cell[3] - height) / 2.0,
+                    width,
+                    height,
+                ],
+                ends_sheet: slot + 1 == chunk.len(),
+            });
+        }
+    }
+    let sheet_count = placements
+        .iter()
+        .filter(|placement| placement.ends_sheet)
+        .count();
+
+    let mut temp_files = TempFileGuard::default();
+    let program = temp_files.track(unique_temp_path("nup", "ps"));
+    std::fs::write(&program, nup_program(&placements, border)).map_err(|e| CommandError::Io {
+        action: "write_nup_program",
+        error: e.to_string(),
+    })?;
+    let device_args = [
+        format!("-dDEVICEWIDTHPOINTS={:.3}", grid.sheet.width_pt),
+        format!("-dDEVICEHEIGHTPOINTS={:.3}", grid.sheet.height_pt),
+        String::from("-dFIXEDMEDIA"),
+        String::from("-dUseCropBox"),
+        // Annotations are drawn into the page, so they move and scale with it.
+        String::from("-dPreserveAnnots=false"),
+    ];
+    run_pdfwrite(
+        &[program.as_path(), input],
+        output,
+        &device_args,
+        Some(pages.len() as u32),
+        runtime,
+        ctx,
+    )?;
+    Ok(NupResult {
+        output_path: output.to_string_lossy().to_string(),
+        sheet: grid.sheet,
+        pages_per_sheet: cols * rows,
+        sheet_count: sheet_count as u32,
+    })
+}
+
+/// Separation colorant that prints on every plate, for marks on CMYK output.
+fn registration_color_space() -> lopdf::Object {
+    let mut tint = lopdf::Dictionary::new();
+    tint.set("FunctionType", 2);
+    tint.set("Domain", vec![0.into(), 1.into()]);
+    tint.set("C0", vec![0.into(), 0.into(), 0.into(), 0.into()]);
+    tint.set("C1", vec![1.into(), 1.into(), 1.into(), 1.into()]);
+    tint.set("N", 1);
+    lopdf::Object::Array(vec![
+        "Separation".into(),
+        "All".into(),
+        "DeviceCMYK".into(),
+        tint.into(),
+    ])
+}
+
+/// A closed circle path from four Bézier arcs.
+fn circle_path(cx: f64, cy: f64, radius: f64) -> String {
+    // Control point distance for a quarter circle.
+    let k = radius * 0.5523;
+    format!(
+        "{:.4} {:.4} m \
+         {:.4} {:.4} {:.4} {:.4} {:.4} {:.4} c \
+         {:.4} {:.4} {:.4} {:.4} {:.4} {:.4} c \
+         {:.4} {:.4} {:.4} {:.4} {:.4} {:.4} c \
+         {:.4} {:.4} {:.4} {:.4} {:.4} {:.4} c h\n",
+        cx + radius,
+        cy,
+        cx + radius,
+        cy + k,
+        cx + k,
+        cy + radius,
+        cx,
+        cy + radius,
+        cx - k,
+        cy + radius,
+        cx - radius,
+        cy + k,
+        cx - radius,
+        cy,
+        cx - radius,
+        cy - k,
+        cx - k,
+        cy - radius,
+        cx,
+        cy - radius,
+        cx + k,
+        cy - radius,
+        cx + radius,
+        cy - k,
+        cx + radius,
+        cy
+    )
+}
+
+/// Content drawing the selected marks around a `trim` rectangle (`[x, y, width, height]`)
+/// on a sheet: marks start `gap` outside the trim edge and run for `length`. `stroke`
+/// selects the mark color; color bars always use process colors.
+fn printer_marks_content(
+    trim: [f64; 4],
+    gap: f64,
+    length: f64,
+    marks: PrinterMarks,
+    stroke: &str,
+) -> String {
+    let [x, y, width, height] = trim;
+    let (right, top) = (x + width, y + height);
+    let near = gap;
+    let far = gap + length;
+    let mut content = format!("q 0.25 w {}\n", stroke);
+    if marks.crop {
+        for (corner_x, corner_y, dx, dy) in [
+            (x, y, -1.0, -1.0),
+            (right, y, 1.0, -1.0),
+            (x, top, -1.0, 1.0),
+            (right, top, 1.0, 1.0),
+        ] {
+            content.push_str(&format!(
+                "{:.4} {:.4} m {:.4} {:.4} l S {:.4} {:.4} m {:.4} {:.4} l S\n",
+                corner_x + dx * near,
+                corner_y,
+                corner_x + dx * far,
+                corner_y,
+                corner_x,
+                corner_y + dy * near,
+                corner_x,
+                corner_y + dy * far,
+            ));
+        }
+    }
+    if marks.registration {
+        let middle = gap + length / 2.0;
+        for (cx, cy) in [
+            (x + width / 2.0, y - middle),
+            (x + width / 2.0, top + middle),
+            (x - middle, y + height / 2.0),
+            (right + middle, y + height / 2.0),
+        ] {
+            content.push_str(&circle_path(cx, cy, length / 4.0));
+            content.push_str(&format!(
+                "S {:.4} {:.4} m {:.4} {:.4} l S {:.4} {:.4} m {:.4} {:.4} l S\n",
+                cx - length / 2.0,
+                cy,
+                cx + length / 2.0,
+                cy,
+                cx,
+                cy - length / 2.0,
+                cx,
+                cy + length / 2.0,
+            ));
+        }
+    }
+    content.push_str("Q\n");
+    if marks.color_bars {
+        // Process primaries, overprints and black tints along the top mark area, clear
+        // of the registration mark in the middle.
+        const PATCHES: [[f64; 4]; 8] = [
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+            [1.0, 1.0, 0.0, 0.0],
+            [0.0, 1.0, 1.0, 0.0],
+            [1.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 0.5],
+        ];
+        let side = (length * 0.8).min((width / 2.0 - length).max(0.0) / PATCHES.len() as f64);
+        let patch_y = top + gap + (length - side) / 2.0;
+        for (index, [c, m, yellow, k]) in PATCHES.iter().enumerate() {
+            content.push_str(&format!(
+                "q {} {} {} {} k {:.4} {:.4} {:.4} {:.4} re f Q\n",
+                c,
+                m,
+                yellow,
+                k,
+                x + index as f64 * side,
+                patch_y,
+                side,
+                side
+            ));
+        }
+    }
+    content
+}
+
+/// Center each page on a larger sheet with room for bleed and printer marks. The sheet's
+/// TrimBox is the page's TrimBox (or visible area) and its BleedBox extends it by `bleed`;
+/// content already present in the bleed area is kept.
+fn printer_marks_pdf_pages(
+    input: &Path,
+    output: &Path,
+    bleed: f64,
+    mark_length: f64,
+    mark_offset: f64,
+    marks: PrinterMarks,
+) -> Result<PrinterMarksResult, CommandError> {
+    for (field, value) in [
+        ("bleed", bleed),
+        ("mark_length", mark_length),
+        ("mark_offset", mark_offset),
+    ] {
+        if !value.is_finite() || value < 0.0 {
+            return Err(CommandError::OutOfRange {
+                field,
+                value: value.to_string(),
+                expected: String::from("zero or positive"),
+            });
+        }
+    }
+    ensure_distinct_paths(input, output)?;
+
+    let mut doc = load_pdf_document(input)?;
+    let geometry = read_page_geometry(&doc);
+    let page_ids = doc.get_pages();
+    if geometry.is_empty() {
+        return Err(CommandError::NoPages {
+            path: input.to_string_lossy().to_string(),
+        });
+    }
+    let registration_color = !page_ids
+        .values()
+        .any(|page_id| page_uses_rgb(&doc, *page_id))
+        && page_ids
+            .values()
+            .any(|page_id| page_uses_cmyk(&doc, *page_id));
+    let margin = bleed + mark_offset + mark_length;
+
+    let mut sheets = Vec::with_capacity(geometry.len());
+    let mut pages = Vec::with_capacity(geometry.len());
+    for page in &geometry {
+        let page_id = page_ids[&page.page];
+        let media = page.media_box;
+        let trim = page
+            .trim_box
+            .map(|trim| {
+                [
+                    trim[0].max(media[0]),
+                    trim[1].max(media[1]),
+                    trim[2].min(media[2]),
+                    trim[3].min(media[3]),
+                ]
+            })
+            .filter(|trim| trim[0] < trim[2] && trim[1] < trim[3])
+            .unwrap_or_else(|| page.visible_box());
+        let trim_geometry = PageGeometry {
+            crop_box: Some(trim),
+            ..page.clone()
+        };
+        let unit_bleed = bleed / page.user_unit;
+        let bleed_rect = [
+            (trim[0] - unit_bleed).max(media[0]),
+            (trim[1] - unit_bleed).max(media[1]),
+            (trim[2] + unit_bleed).min(media[2]),
+            (trim[3] + unit_bleed).min(media[3]),
+        ];
+        let stream = page_form_stream(&doc, page_id, bleed_rect)?;
+        let form = PageForm {
+            id: doc.add_object(stream),
+            geometry: trim_geometry,
+        };
+
+        let (upright, trim_size) = form.geometry.upright_matrix();
+        let mut sheet = Sheet::new(PageDimensions {
+            width_pt: trim_size.width_pt + 2.0 * margin,
+            height_pt: trim_size.height_pt + 2.0 * margin,
+        });
+        sheet.draw_form(
+            &form,
+            concat_matrix(upright, [1.0, 0.0, 0.0, 1.0, margin, margin]),
+        );
+        let stroke = if registration_color {
+            "/Reg CS 1 SCN"
+        } else {
+            "0 G"
+        };
+        sheet.content.push_str(&printer_marks_content(
+            [margin, margin, trim_size.width_pt, trim_size.height_pt],
+            bleed + mark_offset,
+            mark_length,
+            marks,
+            stroke,
+        ));
+
+        let media_box = [0.0, 0.0, sheet.size.width_pt, sheet.size.height_pt];
+        let trim_box = [
+            margin,
+            margin,
+            margin + trim_size.width_pt,
+            margin + trim_size.height_pt,
+        ];
+        let bleed_box = [
+            trim_box[0] - bleed,
+            trim_box[1] - bleed,
+            trim_box[2] + bleed,
+            trim_box[3] + bleed,
+        ];
+        let mut sheet_page = sheet.into_page(&mut doc);
+        sheet_page.set("TrimBox", pdf_rect_object(trim_box));
+        sheet_page.set("BleedBox", pdf_rect_object(bleed_box));
+        if registration_color {
+            if let Ok(resources) = sheet_page
+                .get_mut(b"Resources")
+                .and_then(lopdf::Object::as_dict_mut)
+            {
+                let mut spaces = lopdf::Dictionary::new();
+                spaces.set("Reg", registration_color_space());
+                resources.set("ColorSpace", spaces);
+            }
+        }
+        sheets.push(sheet_page);
+        pages.push(MarkedPage {
+            page: page.page,
+            media_box,
+            bleed_box,
+            trim_box,
+        });
+    }
+    replace_document_pages(&mut doc, sheets)?;
+    doc.compress();
+
+    save_pdf_document(&mut doc, output)?;
+    Ok(PrinterMarksResult {
+        output_path: output.to_string_lossy().to_string(),
+        registration_color,
+        pages,
+    })
+}
+
+/// Source page indices for each printed side of a saddle-stitched booklet, `None` where a
+/// blank pads the count to a multiple of four. With `last` the final padded index, sheet
+/// `i` carries `[last - 2i, 2i]` on the front and `[2i + 1, last - 2i - 1]` on the back.
+fn booklet_sides(page_count: usize) -> Vec<[Option<usize>; 2]> {
+    let padded = (page_count + 3) / 4 * 4;
+    let page = |index: usize| (index < page_count).then_some(index);
+    (0..padded / 4)
+        .flat_map(|sheet| {
+            let outer = 2 * sheet;
+            [
+                [page(padded - 1 - outer), page(outer)],
+                [page(outer + 1), page(padded - 2 - outer)],
+            ]
+        })
+        .collect()
+}
+
+/// Impose the document as a saddle-stitched booklet: two pages side by side on landscape
+/// sheets, ordered so that folding the stacked sheets gives reading order. Each sheet
+/// further inside moves its pages `creep_pt` further from the spine; a negative value moves
+/// them toward it instead.
+fn booklet_pdf_pages(
+    input: &Path,
+    output: &Path,
+    preset: &str,
+    creep_pt: f64,
+) -> Result<BookletResult, CommandError> {
+    if !creep_pt.is_finite() {
+        return Err(CommandError::OutOfRange {
+            field: "creep",
+            value: creep_pt.to_string(),
+            expected: String::from("a finite number"),
+        });
+    }
+    let sheet_size = paper_size(preset)
+        .ok_or_else(|| CommandError::InvalidInput {
+            field: "sheet_size",
+            value: preset.to_string(),
+            expected: String::from(PAPER_SIZE_NAMES),
+        })?
+        .oriented(true);
+    ensure_distinct_paths(input, output)?;
+
+    let mut doc = load_pdf_document(input)?;
+    let forms = page_forms(&mut doc)?;
+    if forms.is_empty() {
+        return Err(CommandError::NoPages {
+            path: input.to_string_lossy().to_string(),
+        });
+    }
+    let half_width = sheet_size.width_pt / 2.0;
+
+    let sides = booklet_sides(forms.len());
+    let mut pages = Vec::with_capacity(sides.len());
+    for (side, [left, right]) in sides.iter().enumerate() {
+        let shift = (side / 2) as f64 * creep_pt;
+        let mut sheet = Sheet::new(sheet_size);
+        if let Some(index) = left {
+            sheet.place_in_cell(
+                &forms[*index],
+                [-shift, 0.0, half_width, sheet_size.height_pt],
+            );
+        }
+        if let Some(index) = right {
+            sheet.place_in_cell(
+                &forms[*index],
+                [half_width + shift, 0.0, half_width, sheet_size.height_pt],
+            );
+        }
+        pages.push(sheet.into_page(&mut doc));
+    }
+    replace_document_pages(&mut doc, pages)?;
+    doc.compress();
+
+    save_pdf_document(&mut doc, output)?;
+    Ok(BookletResult {
+        output_path: output.to_string_lossy().to_string(),
+        sheet: sheet_size,
+        blank_pages: (sides.len() * 2 - forms.len()) as u32,
+        sheet_count: (sides.len() / 2) as u32,
+    })
+}
+
+const DEFAULT_SPLIT_TEMPLATE: &str = "{name}_p{page:03}.pdf";
+
+/// Placeholders of output name templates; `{page}` is added when splitting.
+const OUTPUT_NAME_PLACEHOLDERS: [&str; 8] = [
+    "name", "ext", "date", "time", "pages", "width", "height", "preset",
+];
+
+/// Split a file name template into text and placeholders, checking every placeholder
+/// is known. Numeric placeholders take a zero-padding width, as in `{page:03}`.
+fn parse_name_template(
+    template: &str,
+    allow_page: bool,
+) -> Result<Vec<TemplatePart<'_>>, CommandError> {
+    let invalid = |reason: String| CommandError::InvalidTemplate {
+        template: template.to_string(),
+        reason,
+    };
+    let mut parts = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        if start > 0 {
+            parts.push(TemplatePart::Text(&rest[..start]));
+        }
+        let end = rest[start..]
+            .find('}')
+            .map(|offset| start + offset)
+            .ok_or_else(|| invalid(String::from("a '{' is not closed.")))?;
+        let placeholder = &rest[start + 1..end];
+        let (key, width) = match placeholder.split_once(':') {
+            Some((key, spec)) => (
+                key,
+                spec.parse::<usize>()
+                    .map_err(|_| invalid(format!("'{}' is not a padding width.", spec)))?,
+            ),
+            None => (placeholder, 0),
+        };
+        let known = OUTPUT_NAME_PLACEHOLDERS.contains(&key) || (allow_page && key == "page");
+        if !known {
+            return Err(invalid(format!("'{{{}}}' is not a placeholder.", key)));
+        }
+        if width > 0 && !matches!(key, "page" | "pages") {
+            return Err(invalid(format!("'{{{}}}' can't be padded.", key)));
+        }
+        parts.push(TemplatePart::Placeholder { key, width });
+        rest = &rest[end + 1..];
+    }
+    if !rest.is_empty() {
+        parts.push(TemplatePart::Text(rest));
+    }
+    Ok(parts)
+}
+
+/// Drop the characters the platform doesn't allow in file names, and on Windows the
+/// trailing dots and spaces it ignores and the reserved device names.
+fn sanitize_file_name(name: &str) -> String {
+    #[cfg(target_os = "windows")]
+    const INVALID: &[char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+    #[cfg(target_os = "macos")]
+    const INVALID: &[char] = &['/', ':'];
+    #[cfg(all(not(target_os = "windows"), not(target_os = "macos")))]
+    const INVALID: &[char] = &['/'];
+
+    let mut sanitized: String = name
+        .chars()
+        .filter(|c| !c.is_control() && !INVALID.contains(c))
+        .collect();
+    if cfg!(target_os = "windows") {
+        const RESERVED: [&str; 22] = [
+            "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7",
+            "COM8", "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+        ];
+        sanitized.truncate(sanitized.trim_end_matches(['.', ' ']).len());
+        let stem = sanitized.split('.').next().unwrap_or_default();
+        if RESERVED.iter().any(|name| name.eq_ignore_ascii_case(stem)) {
+            sanitized.insert(0, '_');
+        }
+    }
+    sanitized.trim().to_string()
+}
+
+/// Minutes the local time zone is ahead of UTC right now, or 0 when unknown.
+#[cfg(target_os = "windows")]
+fn local_utc_offset_minutes() -> i64 {
+    #[repr(C)]
+    struct SystemTime {
+        fields: [u16; 8],
+    }
+    #[repr(C)]
+    struct TimeZoneInformation {
+        bias: i32,
+        standard_name: [u16; 32],
+        standard_date: SystemTime,
+        standard_bias: i32,
+        daylight_name: [u16; 32],
+        daylight_date: SystemTime,
+        daylight_bias: i32,
+    }
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetTimeZoneInformation(information: *mut TimeZoneInformation) -> u32;
+    }
+    const TIME_ZONE_ID_INVALID: u32 = 0xFFFF_FFFF;
+    const TIME_ZONE_ID_DAYLIGHT: u32 = 2;
+
+    // SAFETY: the struct matches TIME_ZONE_INFORMATION and is only read after the call.
+    let mut information: TimeZoneInformation = unsafe { std::mem::zeroed() };
+    let zone = unsafe { GetTimeZoneInformation(&mut information) };
+    match zone {
+        TIME_ZONE_ID_INVALID => 0,
+        TIME_ZONE_ID_DAYLIGHT => -i64::from(information.bias + information.daylight_bias),
+        _ => -i64::from(information.bias + information.standard_bias),
+    }
+}
+
+/// Minutes the local time zone is ahead of UTC right now, or 0 when unknown.
+#[cfg(not(target_os = "windows"))]
+fn local_utc_offset_minutes() -> i64 {
+    // POSIX `date +%z` prints the offset as `+hhmm` / `-hhmm`.
+    let Ok(output) = std::process::Command::new("date").arg("+%z").output() else {
+        return 0;
+    };
+    let offset = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    let (sign, digits) = if let Some(digits) = offset.strip_prefix('+') {
+        (1, digits)
+    } else if let Some(digits) = offset.strip_prefix('-') {
+        (-1, digits)
+    } else {
+        return 0;
+    };
+    match (digits.get(..2), digits.get(2..4)) {
+        (Some(hours), Some(minutes)) => match (hours.parse::<i64>(), minutes.parse::<i64>()) {
+            (Ok(hours), Ok(minutes)) => sign * (hours * 60 + minutes),
+            _ => 0,
+        },
+        _ => 0,
+    }
+}
+
+impl OutputNameContext {
+    /// Context for `input`, with the page count and first page size when lopdf can read it.
+    fn for_input(input: &Path, preset: Option<String>) -> Self {
+        let geometry = load_pdf_document(input)
+            .map(|doc| read_page_geometry(&doc))
+            .ok();
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|duration| duration.as_secs() as i64)
+            .unwrap_or(0);
+        OutputNameContext {
+            name: input
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_else(|| String::from("document")),
+            ext: input
+                .extension()
+                .map(|extension| extension.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            pages: geometry.as_ref().map(|pages| pages.len() as u32),
+            size: geometry
+                .as_ref()
+                .and_then(|pages| pages.first())
+                .map(|page| page.upright_matrix().1),
+            preset,
+            page: None,
+            local_time: now + local_utc_offset_minutes() * 60,
+        }
+    }
+}
+
+/// Expand a file name template such as `{name}_{width}x{height}mm.pdf` or
+/// `{date}_{name}.pdf`. Sizes are in whole millimetres, `{date}` is `YYYY-MM-DD` and
+/// `{time}` is `HHMMSS`. Characters the platform forbids are dropped, and `extension` is
+/// appended when the name doesn't already end in it.
+fn render_output_name(
+    template: &str,
+    context: &OutputNameContext,
+    extension: &str,
+) -> Result<String, CommandError> {
+    let invalid = |reason: String| CommandError::InvalidTemplate {
+        template: template.to_string(),
+        reason,
+    };
+    let missing = |key: &str| invalid(format!("'{{{}}}' is not available for this file.", key));
+    let to_mm = |points: f64| format!("{:.0}", points * 25.4 / 72.0);
+    let (year, month, day) = civil_from_days(context.local_time.div_euclid(86_400));
+    let seconds = context.local_time.rem_euclid(86_400);
+
+    let mut rendered = String::with_capacity(template.len() + context.name.len());
+    for part in parse_name_template(template, context.page.is_some())? {
+        let (key, width) = match part {
+            TemplatePart::Text(text) => {
+                rendered.push_str(text);
+                continue;
+            }
+            TemplatePart::Placeholder { key, width } => (key, width),
+        };
+        let value = match key {
+            "name" => context.name.clone(),
+            "ext" => context.ext.clone(),
+            "date" => format!("{:04}-{:02}-{:02}", year, month, day),
+            "time" => format!(
+                "{:02}{:02}{:02}",
+                seconds / 3600,
+                seconds / 60 % 60,
+                seconds % 60
+            ),
+            "pages" => {
+                let pages = context.pages.ok_or_else(|| missing(key))?;
+                format!("{:0width$}", pages, width = width)
+            }
+            "page" => {
+                let page = context.page.ok_or_else(|| missing(key))?;
+                format!("{:0width$}", page, width = width)
+            }
+            "width" => to_mm(context.size.ok_or_else(|| missing(key))?.width_pt),
+            "height" => to_mm(context.size.ok_or_else(|| missing(key))?.height_pt),
+            "preset" => context.preset.clone().ok_or_else(|| missing(key))?,
+            _ => return Err(invalid(format!("'{{{}}}' is not a placeholder.", key))),
+        };
+        rendered.push_str(&value);
+    }
+
+    let mut rendered = sanitize_file_name(&rendered);
+    let suffix = format!(".{}", extension);
+    if !rendered.to_ascii_lowercase().ends_with(&suffix) {
+        rendered.push_str(&suffix);
+    }
+    if rendered == suffix {
+        return Err(invalid(String::from("it gives an empty file name.")));
+    }
+    Ok(rendered)
+}
+
+/// Write each page to its own file in `output_dir` with a single pdfwrite `%d` run, then
+/// rename the pages to the template names. Nothing is left behind if any step fails.
+fn split_pdf_pages(
+    input: &Path,
+    output_dir: &Path,
+    name_template: &str,
+    on_conflict: ConflictPolicy,
+    runtime: &GhostscriptRuntime,
+    ctx: JobContext<'_>,
+) -> Result<Vec<String>, CommandError> {
+    let template = match name_template.trim() {
+        "" => DEFAULT_SPLIT_TEMPLATE,
+        template => template,
+    };
+    if !template.contains("{page") {
+        return Err(CommandError::InvalidTemplate {
+            template: template.to_string(),
+            reason: String::from("it needs a {page} placeholder so each page gets its own file."),
+        });
+    }
+    if !input.is_file() {
+        return Err(CommandError::NotFound {
+            path: input.to_string_lossy().to_string(),
+        });
+    }
+    let mut name_context = OutputNameContext::for_input(input, None);
+    name_context.page = Some(1);
+    render_output_name(template, &name_context, "pdf")?;
+
+    std::fs::create_dir_all(output_dir).map_err(|e| CommandError::Io {
+        action: "create_output_directory",
+        error: format!("'{}': {}", output_dir.display(), e),
+    })?;
+    // The pages together are about as large as the input.
+    ensure_output_writable(output_dir, file_size(input)?)?;
+
+    let total_pages = document_page_count(input, runtime, ctx.hooks()).ok();
+    // Temp pages live in the output directory so the final renames never cross devices.
+    let temp_stem = format!(".{}", unique_temp_stem("split"));
+    let temp_page = |page: u32| output_dir.join(format!("{}_{}.pdf", temp_stem, page));
+    let pattern = PathBuf::from(output_dir.to_string_lossy().replace('%', "%%"))
+        .join(format!("{}_%d.pdf", temp_stem));
+    let output_file_arg = format!("-sOutputFile={}", pattern.display());
+
+    let mut temp_files = TempFileGuard::default();
+    let result = run_pdfwrite_to(&[input], &output_file_arg, &[], total_pages, runtime, ctx);
+    let mut page_count = 0;
+    while temp_page(page_count + 1).exists() {
+        page_count += 1;
+        temp_files.track(temp_page(page_count));
+    }
+    result?;
+
+    let mut names = Vec::with_capacity(page_count as usize);
+    for page in 1..=page_count {
+        name_context.page = Some(page);
+        names.push(output_dir.join(render_output_name(template, &name_context, "pdf")?));
+    }
+    let final_paths = claim_output_paths(&names, on_conflict)?;
+
+    // Skipped pages are left to `temp_files` to delete.
+    let mut created = TempFileGuard::default();
+    let mut written = Vec::with_capacity(final_paths.len());
+    for (index, (requested, final_path)) in names.iter().zip(&final_paths).enumerate() {
+        let Some(final_path) = final_path else {
+            continue;
+        };
+        if let Some(path) = commit_output(
+            &temp_page(index as u32 + 1),
+            requested,
+            final_path,
+            on_conflict,
+        )? {
+            written.push(created.track(path).to_string_lossy().to_string());
+        }
+    }
+    created.keep();
+    Ok(written)
+}
+
+impl RenderingIntent {
+    fn ghostscript_value(self) -> u32 {
+        match self {
+            RenderingIntent::Perceptual => 0,
+            RenderingIntent::Relative => 1,
+            RenderingIntent::Saturation => 2,
+            RenderingIntent::Absolute => 3,
+        }
+    }
+}
+
+/// Check that `path` holds an ICC profile for the `expected` data color space signature
+/// (e.g. `b"CMYK"`), reading only the 128-byte header.
+fn validate_icc_profile(path: &Path, expected: &[u8; 4]) -> Result<(), CommandError> {
+    use std::io::Read;
+
+    let mut header = [0u8; 128];
+    std::fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut header))
+        .map_err(|e| CommandError::Io {
+            action: "read_icc_profile",
+            error: format!("'{}': {}", path.display(), e),
+        })?;
+    let invalid = |found: Option<&[u8]>| CommandError::InvalidIccProfile {
+        path: path.to_string_lossy().to_string(),
+        found: found.map(|found| String::from_utf8_lossy(found).trim().to_string()),
+        expected: String::from_utf8_lossy(expected).trim().to_string(),
+    };
+    if &header[36..40] != b"acsp" {
+        return Err(invalid(None));
+    }
+    if &header[16..20] != expected {
+        return Err(invalid(Some(&header[16..20])));
+    }
+    Ok(())
+}
+
+/// pdfwrite's color conversion only became ICC-managed in 9.11; older releases would
+/// silently ignore an output profile.
+const ICC_OUTPUT_GHOSTSCRIPT_VERSION: GhostscriptVersion = GhostscriptVersion {
+    major: 9,
+    minor: 11,
+    patch: 0,
+};
+
+/// Convert all color to DeviceCMYK, through `icc_profile` when given (e.g. PSO Coated v3)
+/// or Ghostscript's default CMYK profile otherwise.
+fn cmyk_with_ghostscript(
+    input: &Path,
+    output: &Path,
+    icc_profile: Option<&Path>,
+    intent: RenderingIntent,
+    runtime: &GhostscriptRuntime,
+    ctx: JobContext<'_>,
+) -> Result<CmykResult, CommandError> {
+    if !input.is_file() {
+        return Err(CommandError::NotFound {
+            path: input.to_string_lossy().to_string(),
+        });
+    }
+    ensure_distinct_paths(input, output)?;
+
+    let mut device_args = vec![
+        String::from("-sColorConversionStrategy=CMYK"),
+        String::from("-dProcessColorModel=/DeviceCMYK"),
+        String::from("-dCompatibilityLevel=1.7"),
+        format!("-dRenderIntent={}", intent.ghostscript_value()),
+    ];
+    if let Some(profile) = icc_profile {
+        validate_icc_profile(profile, b"CMYK")?;
+        require_ghostscript_version(ICC_OUTPUT_GHOSTSCRIPT_VERSION, runtime, ctx.hooks())?;
+        let profile = profile.to_string_lossy();
+        device_args.push(format!("--permit-file-read={}", profile));
+        device_args.push(format!("-sOutputICCProfile={}", profile));
+    }
+
+    let total_pages = document_page_count(input, runtime, ctx.hooks()).ok();
+    run_pdfwrite(&[input], output, &device_args, total_pages, runtime, ctx)?;
+
+    Ok(CmykResult {
+        output_path: output.to_string_lossy().to_string(),
+        icc_profile: icc_profile.map(|profile| profile.to_string_lossy().to_string()),
+        rendering_intent: intent,
+    })
+}
+
+/// Whether `dict` is a transparency group (`/Group << /S /Transparency >>`).
+fn is_transparency_group(doc: &lopdf::Document, dict: &lopdf::Dictionary) -> bool {
+    dict.get(b"Group")
+        .ok()
+        .and_then(|group| pdf_dict(doc, group))
+        .and_then(|group| group.get(b"S").and_then(lopdf::Object::as_name).ok())
+        == Some(&b"Transparency"[..])
+}
+
+/// Whether the page uses live transparency: transparency groups, soft masks, constant
+/// alpha below 1, or a blend mode other than Normal.
+fn page_has_transparency(doc: &lopdf::Document, page_id: lopdf::ObjectId) -> bool {
+    page_transparency(doc, 0, page_id).any()
+}
+
+/// The live transparency features page `page` and its forms refer to.
+fn page_transparency(
+    doc: &lopdf::Document,
+    page: u32,
+    page_id: lopdf::ObjectId,
+) -> PageTransparency {
+    let mut report = PageTransparency {
+        page,
+        groups: doc
+            .get_dictionary(page_id)
+            .is_ok_and(|page| is_transparency_group(doc, page)),
+        ..PageTransparency::default()
+    };
+    for scope in page_content_scopes(doc, page_id) {
+        let states = resource_category(doc, scope.resources, b"ExtGState");
+        for (_, state) in states.iter().flat_map(|states| states.iter()) {
+            let Some(state) = pdf_dict(doc, state) else {
+                continue;
+            };
+            report.soft_masks |= state
+                .get(b"SMask")
+                .is_ok_and(|mask| mask.as_name().ok() != Some(&b"None"[..]));
+            report.constant_alpha |= [&b"CA"[..], b"ca"].iter().any(|key| {
+                state
+                    .get(key)
+                    .ok()
+                    .and_then(|value| pdf_number(doc, value))
+                    .is_some_and(|value| value < 1.0)
+            });
+            // `/BM` may also be an array of modes in order of preference.
+            let modes = match state.get(b"BM") {
+                Ok(lopdf::Object::Array(modes)) => modes.iter().collect(),
+                Ok(mode) => vec![mode],
+                Err(_) => Vec::new(),
+            };
+            for mode in modes.into_iter().filter_map(|mode| mode.as_name().ok()) {
+                let mode = String::from_utf8_lossy(mode).to_string();
+                if mode != "Normal" && mode != "Compatible" && !report.blend_modes.contains(&mode) {
+                    report.blend_modes.push(mode);
+                }
+            }
+        }
+        let xobjects = resource_category(doc, scope.resources, b"XObject");
+        for (_, xobject) in xobjects.iter().flat_map(|xobjects| xobjects.iter()) {
+            if let Some(dict) = pdf_dict(doc, xobject) {
+                report.soft_masks |= dict.has(b"SMask");
+                report.groups |= is_transparency_group(doc, dict);
+            }
+        }
+    }
+    report
+}
+
+impl PageTransparency {
+    fn any(&self) -> bool {
+        self.groups || self.soft_masks || self.constant_alpha || !self.blend_modes.is_empty()
+    }
+}
+
+impl PdfaLevel {
+    fn part(self) -> u32 {
+        match self {
+            PdfaLevel::Pdfa1b => 1,
+            PdfaLevel::Pdfa2b => 2,
+            PdfaLevel::Pdfa3b => 3,
+        }
+    }
+}
+
+/// Whether Ghostscript said it gave up on PDF/A or PDF/X and wrote a plain PDF instead.
+fn abandons_standard(warnings: &[String]) -> bool {
+    warnings.iter().any(|line| {
+        let line = line.to_ascii_lowercase();
+        line.contains("reverting") || line.contains("aborting") || line.contains("not permitted")
+    })
+}
+
+/// PostScript prologue (Ghostscript's PDFA_def.ps) that embeds `icc_profile` as the sRGB
+/// output intent.
+fn pdfa_definition(icc_profile: &Path) -> String {
+    let profile = ps_string_literal(&icc_profile.to_string_lossy());
+    format!(
+        "%!\n\
+         [/_objdef {{icc_PDFA}} /type /stream /OBJ pdfmark\n\
+         [{{icc_PDFA}} << /N 3 >> /PUT pdfmark\n\
+         [{{icc_PDFA}} {} (r) file /PUT pdfmark\n\
+         [/_objdef {{OutputIntent_PDFA}} /type /dict /OBJ pdfmark\n\
+         [{{OutputIntent_PDFA}} << /Type /OutputIntent /S /GTS_PDFA1 \
+         /DestOutputProfile {{icc_PDFA}} /OutputConditionIdentifier (sRGB IEC61966-2.1) \
+         /Info (sRGB IEC61966-2.1) >> /PUT pdfmark\n\
+         [{{Catalog}} << /OutputIntents [ {{OutputIntent_PDFA}} ] >> /PUT pdfmark\n",
+        profile
+    )
+}
+
+/// Whether a written file claims PDF/A: an XMP `pdfaid:part` entry plus output intents.
+fn claims_pdfa(path: &Path) -> bool {
+    let Ok(doc) = load_pdf_document(path) else {
+        return false;
+    };
+    let Some(catalog) = doc
+        .trailer
+        .get(b"Root")
+        .ok()
+        .and_then(|root| pdf_dict(&doc, root))
+    else {
+        return false;
+    };
+    let identified = catalog
+        .get(b"Metadata")
+        .ok()
+        .and_then(|metadata| metadata.as_reference().ok())
+        .and_then(|id| doc.get_object(id).and_then(lopdf::Object::as_stream).ok())
+        .and_then(|stream| stream_content(stream).ok())
+        .is_some_and(|xmp| String::from_utf8_lossy(&xmp).contains("pdfaid:part"));
+    identified && catalog.has(b"OutputIntents")
+}
+
+/// Convert to PDF/A-1b, -2b or -3b with an sRGB output intent. Ghostscript still writes a
+/// file when it has to abandon PDF/A, so conformance is re-checked on the output and its
+/// warnings are passed back. Transparent PDF/A-1 sources are flattened in a first pass.
+fn pdfa_with_ghostscript(
+    input: &Path,
+    output: &Path,
+    level: PdfaLevel,
+    runtime: &GhostscriptRuntime,
+    ctx: JobContext<'_>,
+) -> Result<PdfaResult, CommandError> {
+    if !input.is_file() {
+        return Err(CommandError::NotFound {
+            path: input.to_string_lossy().to_string(),
+        });
+    }
+    ensure_distinct_paths(input, output)?;
+    require_minimum_ghostscript(runtime, ctx.hooks())?;
+    let icc_profile = runtime.bundled_icc_profile("srgb.icc").ok_or_else(|| {
+        CommandError::MissingBundledFile {
+            name: String::from("srgb.icc"),
+        }
+    })?;
+
+    let mut temp_files = TempFileGuard::default();
+    let definition = temp_files.track(unique_temp_path("pdfa_def", "ps"));
+    std::fs::write(&definition, pdfa_definition(&icc_profile)).map_err(|e| CommandError::Io {
+        action: "write_pdf_a_definition",
+        error: e.to_string(),
+    })?;
+
+    let source = load_pdf_document(input).ok();
+    let total_pages = match &source {
+        Some(doc) => Some(doc.get_pages().len() as u32),
+        None => ghostscript_page_count(input, runtime, ctx.hooks()).ok(),
+    };
+    let transparent = matches!(level, PdfaLevel::Pdfa1b)
+        && source.as_ref().is_some_and(|doc| {
+            doc.get_pages()
+                .values()
+                .any(|page_id| page_has_transparency(doc, *page_id))
+        });
+    drop(source);
+
+    let mut pdfa_input = input.to_path_buf();
+    if transparent {
+        // pdfwrite rasterizes transparent areas when targeting PDF 1.3.
+        let flattened = temp_files.track(unique_temp_path("pdfa_flat", "pdf"));
+        let flatten_args = [String::from("-dCompatibilityLevel=1.3")];
+        run_pdfwrite(
+            &[input],
+            &flattened,
+            &flatten_args,
+            total_pages,
+            runtime,
+            ctx,
+        )?;
+        pdfa_input = flattened;
+    }
+
+    let device_args = [
+        format!("-dPDFA={}", level.part()),
+        String::from("-sColorConversionStrategy=UseDeviceIndependentColor"),
+        format!("--permit-file-read={}", icc_profile.to_string_lossy()),
+    ];
+    let result = run_pdfwrite(
+        &[&definition, &pdfa_input],
+        output,
+        &device_args,
+        total_pages,
+        runtime,
+        ctx,
+    )?;
+
+    let warnings = ghostscript_messages(&result, |line| line.contains("PDF/A"));
+    Ok(PdfaResult {
+        output_path: output.to_string_lossy().to_string(),
+        conformant: !abandons_standard(&warnings) && claims_pdfa(output),
+        warnings,
+        transparency_flattened: transparent,
+    })
+}
+
+/// Whether a color space (a family name or array) is RGB, directly or as the base of an
+/// indexed or pattern space.
+fn is_rgb_color_space(doc: &lopdf::Document, space: &lopdf::Object) -> bool {
+    let Ok((_, space)) = doc.dereference(space) else {
+        return false;
+    };
+    match space {
+        lopdf::Object::Name(name) => matches!(name.as_slice(), b"DeviceRGB" | b"RGB"),
+        lopdf::Object::Array(items) => match items.first().and_then(|item| item.as_name().ok()) {
+            Some(b"CalRGB") => true,
+            Some(b"ICCBased") => {
+                items
+                    .get(1)
+                    .and_then(|profile| pdf_dict(doc, profile))
+                    .and_then(|profile| profile.get(b"N").ok())
+                    .and_then(|n| n.as_i64().ok())
+                    == Some(3)
+            }
+            Some(b"Indexed") | Some(b"I") | Some(b"Pattern") => items
+                .get(1)
+                .is_some_and(|base| is_rgb_color_space(doc, base)),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Whether the page paints with RGB: `rg`/`RG` operators, or RGB color spaces, images or
+/// shadings in the resources it uses.
+fn page_uses_rgb(doc: &lopdf::Document, page_id: lopdf::ObjectId) -> bool {
+    page_paints_with(doc, page_id, ["rg", "RG"], is_rgb_color_space)
+}
+
+/// Like `is_rgb_color_space`, for CMYK.
+fn is_cmyk_color_space(doc: &lopdf::Document, space: &lopdf::Object) -> bool {
+    let Ok((_, space)) = doc.dereference(space) else {
+        return false;
+    };
+    match space {
+        lopdf::Object::Name(name) => matches!(name.as_slice(), b"DeviceCMYK" | b"CMYK"),
+        lopdf::Object::Array(items) => match items.first().and_then(|item| item.as_name().ok()) {
+            Some(b"ICCBased") => {
+                items
+                    .get(1)
+                    .and_then(|profile| pdf_dict(doc, profile))
+                    .and_then(|profile| profile.get(b"N").ok())
+                    .and_then(|n| n.as_i64().ok())
+                    == Some(4)
+            }
+            Some(b"Indexed") | Some(b"I") | Some(b"Pattern") => items
+                .get(1)
+                .is_some_and(|base| is_cmyk_color_space(doc, base)),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Like `page_uses_rgb`, for CMYK (`k`/`K`).
+fn page_uses_cmyk(doc: &lopdf::Document, page_id: lopdf::ObjectId) -> bool {
+    page_paints_with(doc, page_id, ["k", "K"], is_cmyk_color_space)
+}
+
+/// Whether the page uses one of the color `operators`, or color spaces, images or shadings
+/// matching `is_space`, in the resources it uses.
+fn page_paints_with(
+    doc: &lopdf::Document,
+    page_id: lopdf::ObjectId,
+    operators: [&str; 2],
+    is_space: fn(&lopdf::Document, &lopdf::Object) -> bool,
+) -> bool {
+    page_content_scopes(doc, page_id).iter().any(|scope| {
+        let uses_operator = scope
+            .operations
+            .iter()
+            .any(|op| operators.contains(&op.operator.as_str()));
+        let matches = |dict: &lopdf::Dictionary| {
+            dict.get(b"ColorSpace")
+                .is_ok_and(|space| is_space(doc, space))
+        };
+        let spaces = resource_category(doc, scope.resources, b"ColorSpace")
+            .is_some_and(|spaces| spaces.iter().any(|(_, space)| is_space(doc, space)));
+        let images_or_shadings = [&b"XObject"[..], b"Shading"].iter().any(|key| {
+            resource_category(doc, scope.resources, key).is_some_and(|entries| {
+                entries
+                    .iter()
+                    .any(|(_, entry)| pdf_dict(doc, entry).is_some_and(matches))
+            })
+        });
+        uses_operator || spaces || images_or_shadings
+    })
+}
+
+/// Color space families (and spot colorants) a page paints with, kept in order of use.
+#[derive(Default)]
+struct ColorUsage {
+    vector: Vec<String>,
+    images: Vec<String>,
+    spot_colors: Vec<String>,
+}
+
+impl ColorUsage {
+    /// Record `space`, a color space object or the name of one in `resources`.
+    fn add(
+        &mut self,
+        doc: &lopdf::Document,
+        resources: Option<&lopdf::Dictionary>,
+        space: &lopdf::Object,
+        image: bool,
+    ) {
+        let Ok((_, space)) = doc.dereference(space) else {
+            return;
+        };
+        let family = match space {
+            lopdf::Object::Name(name) => match name.as_slice() {
+                b"DeviceRGB" | b"RGB" => "DeviceRGB",
+                b"DeviceCMYK" | b"CMYK" => "DeviceCMYK",
+                b"DeviceGray" | b"G" => "DeviceGray",
+                b"Pattern" => "Pattern",
+                _ => {
+                    if let Some(space) = resource_category(doc, resources, b"ColorSpace")
+                        .and_then(|spaces| spaces.get(name).ok())
+                    {
+                        self.add(doc, None, space, image);
+                    }
+                    return;
+                }
+            },
+            lopdf::Object::Array(items) => {
+                let family = items.first().and_then(|item| item.as_name().ok());
+                match family {
+                    // Report what an indexed or uncolored pattern space is based on.
+                    Some(b"Indexed") | Some(b"I") => {
+                        if let Some(base) = items.get(1) {
+                            self.add(doc, resources, base, image);
+                        }
+                        return;
+                    }
+                    Some(b"Pattern") => {
+                        if let Some(base) = items.get(1) {
+                            self.add(doc, resources, base, image);
+                        }
+                        "Pattern"
+                    }
+                    Some(b"Separation") => {
+                        if let Some(name) = items.get(1).and_then(|name| name.as_name().ok()) {
+                            self.add_spot(name);
+                        }
+                        "Separation"
+                    }
+                    Some(b"DeviceN") => {
+                        let names = items
+                            .get(1)
+                            .and_then(|names| doc.dereference(names).ok())
+                            .and_then(|(_, names)| names.as_array().ok());
+                        for name in names.into_iter().flatten() {
+                            if let Ok(name) = name.as_name() {
+                                self.add_spot(name);
+                            }
+                        }
+                        "DeviceN"
+                    }
+                    Some(b"ICCBased") => "ICCBased",
+                    Some(b"CalRGB") => "CalRGB",
+                    Some(b"CalGray") => "CalGray",
+                    Some(b"Lab") => "Lab",
+                    _ => return,
+                }
+            }
+            _ => return,
+        };
+        let families = if image {
+            &mut self.images
+        } else {
+            &mut self.vector
+        };
+        if !families.iter().any(|known| known == family) {
+            families.push(String::from(family));
+        }
+    }
+
+    /// Process colorants and `/All` or `/None` are not spot colors.
+    fn add_spot(&mut self, name: &[u8]) {
+        if matches!(
+            name,
+            b"Cyan" | b"Magenta" | b"Yellow" | b"Black" | b"All" | b"None"
+        ) {
+            return;
+        }
+        let name = String::from_utf8_lossy(name).to_string();
+        if !self.spot_colors.contains(&name) {
+            self.spot_colors.push(name);
+        }
+    }
+}
+
+/// Color spaces the page's content (and its forms') paints with: color operators, shadings
+/// and patterns in the resources, and the images it draws, inline ones included.
+fn page_colors(doc: &lopdf::Document, page: u32, page_id: lopdf::ObjectId) -> PageColors {
+    fn name_operand(op: &lopdf::content::Operation) -> Option<&[u8]> {
+        op.operands.first().and_then(|name| name.as_name().ok())
+    }
+
+    let mut usage = ColorUsage::default();
+    for scope in page_content_scopes(doc, page_id) {
+        let resources = scope.resources;
+        for op in &scope.operations {
+            let device = |name: &[u8]| lopdf::Object::Name(name.to_vec());
+            match op.operator.as_str() {
+                "rg" | "RG" => usage.add(doc, resources, &device(b"DeviceRGB"), false),
+                "k" | "K" => usage.add(doc, resources, &device(b"DeviceCMYK"), false),
+                "g" | "G" => usage.add(doc, resources, &device(b"DeviceGray"), false),
+                "cs" | "CS" => {
+                    if let Some(name) = name_operand(op) {
+                        usage.add(doc, resources, &device(name), false);
+                    }
+                }
+                "Do" => {
+                    let image = name_operand(op)
+                        .and_then(|name| {
+                            resource_category(doc, resources, b"XObject")?
+                                .get(name)
+                                .ok()
+                        })
+                        .and_then(|xobject| pdf_dict(doc, xobject))
+                        .filter(|dict| {
+                            dict.get(b"Subtype").and_then(lopdf::Object::as_name).ok()
+                                == Some(&b"Image"[..])
+                        });
+                    if let Some(space) = image.and_then(|image| image.get(b"ColorSpace").ok()) {
+                        usage.add(doc, resources, space, true);
+                    }
+                }
+                // Inline images abbreviate `/ColorSpace` as `/CS`.
+                "BI" => {
+                    let header = op.operands.iter().find_map(|operand| match operand {
+                        lopdf::Object::Dictionary(dict) => Some(dict),
+                        lopdf::Object::Stream(stream) => Some(&stream.dict),
+                        _ => None,
+                    });
+                    if let Some(space) = header.and_then(|header| {
+                        header
+                            .get(b"CS")
+                            .or_else(|_| header.get(b"ColorSpace"))
+                            .ok()
+                    }) {
+                        usage.add(doc, resources, space, true);
+                    }
+                }
+                _ => {}
+            }
+        }
+        let shadings = resource_category(doc, resources, b"Shading");
+        let patterns = resource_category(doc, resources, b"Pattern");
+        let pattern_shadings = patterns
+            .iter()
+            .flat_map(|patterns| patterns.iter())
+            .filter_map(|(_, pattern)| pdf_dict(doc, pattern)?.get(b"Shading").ok());
+        let shadings = shadings
+            .iter()
+            .flat_map(|shadings| shadings.iter())
+            .map(|(_, shading)| shading)
+            .chain(pattern_shadings);
+        for shading in shadings {
+            if let Some(space) =
+                pdf_dict(doc, shading).and_then(|dict| dict.get(b"ColorSpace").ok())
+            {
+                usage.add(doc, resources, space, false);
+            }
+        }
+    }
+    PageColors {
+        page,
+        vector: usage.vector,
+        images: usage.images,
+        spot_colors: usage.spot_colors,
+    }
+}
+
+/// Base names of fonts without an embedded font program, in page order without repeats.
+/// Type 3 fonts are defined in the file and always count as embedded.
+fn unembedded_fonts(doc: &lopdf::Document) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for font in document_fonts(doc) {
+        if !font.embedded && !names.contains(&font.name) {
+            names.push(font.name);
+        }
+    }
+    names
+}
+
+/// PostScript prologue (Ghostscript's PDFX_def.ps) declaring PDF/X-1a with `icc_profile`
+/// as the CMYK output intent.
+fn pdfx_definition(icc_profile: &Path, title: &str) -> String {
+    let profile = ps_string_literal(&icc_profile.to_string_lossy());
+    let condition = ps_string_literal(
+        &icc_profile
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_else(|| String::from("Custom")),
+    );
+    format!(
+        "%!\n\
+         [ /GTS_PDFXVersion (PDF/X-1a:2001) /GTS_PDFXConformance (PDF/X-1a:2001) \
+         /Title {} /Trapped /False /DOCINFO pdfmark\n\
+         [/_objdef {{icc_PDFX}} /type /stream /OBJ pdfmark\n\
+         [{{icc_PDFX}} << /N 4 >> /PUT pdfmark\n\
+         [{{icc_PDFX}} {} (r) file /PUT pdfmark\n\
+         [/_objdef {{OutputIntent_PDFX}} /type /dict /OBJ pdfmark\n\
+         [{{OutputIntent_PDFX}} << /Type /OutputIntent /S /GTS_PDFX \
+         /OutputCondition {} /OutputConditionIdentifier (Custom) /Info {} \
+         /DestOutputProfile {{icc_PDFX}} >> /PUT pdfmark\n\
+         [{{Catalog}} << /OutputIntents [ {{OutputIntent_PDFX}} ] >> /PUT pdfmark\n",
+        ps_string_literal(title),
+        profile,
+        condition,
+        condition
+    )
+}
+
+/// Whether a written file claims PDF/X: a `GTS_PDFXVersion` info entry plus output intents.
+fn claims_pdfx(path: &Path) -> bool {
+    let Ok(doc) = load_pdf_document(path) else {
+        return false;
+    };
+    let versioned = doc
+        .trailer
+        .get(b"Info")
+        .ok()
+        .and_then(|info| pdf_dict(&doc, info))
+        .is_some_and(|info| info.has(b"GTS_PDFXVersion"));
+    let intents = doc
+        .trailer
+        .get(b"Root")
+        .ok()
+        .and_then(|root| pdf_dict(&doc, root))
+        .is_some_and(|catalog| catalog.has(b"OutputIntents"));
+    versioned && intents
+}
+
+/// Produce PDF/X-1a for print: everything converted to CMYK against `icc_profile`, fonts
+/// embedded and transparency flattened (PDF/X-1a is based on PDF 1.3). The report lists
+/// what had to be fixed; `conformant` is false, with Ghostscript's reasons, when it gave up.
+fn pdfx_with_ghostscript(
+    input: &Path,
+    output: &Path,
+    icc_profile: &Path,
+    runtime: &GhostscriptRuntime,
+    ctx: JobContext<'_>,
+) -> Result<PdfxResult, CommandError> {
+    if !input.is_file() {
+        return Err(CommandError::NotFound {
+            path: input.to_string_lossy().to_string(),
+        });
+    }
+    ensure_distinct_paths(input, output)?;
+    validate_icc_profile(icc_profile, b"CMYK")?;
+    require_minimum_ghostscript(runtime, ctx.hooks())?;
+
+    let source = load_pdf_document(input).ok();
+    let total_pages = match &source {
+        Some(doc) => Some(doc.get_pages().len() as u32),
+        None => ghostscript_page_count(input, runtime, ctx.hooks()).ok(),
+    };
+    let (rgb_pages, unembedded_fonts, transparent) = match &source {
+        Some(doc) => {
+            let pages = doc.get_pages();
+            (
+                pages
+                    .iter()
+                    .filter(|(_, page_id)| page_uses_rgb(doc, **page_id))
+                    .map(|(page, _)| *page)
+                    .collect(),
+                unembedded_fonts(doc),
+                pages
+                    .values()
+                    .any(|page_id| page_has_transparency(doc, *page_id)),
+            )
+        }
+        None => (Vec::new(), Vec::new(), false),
+    };
+    drop(source);
+
+    let title = input
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_else(|| String::from("document"));
+    let mut temp_files = TempFileGuard::default();
+    let definition = temp_files.track(unique_temp_path("pdfx_def", "ps"));
+    std::fs::write(&definition, pdfx_definition(icc_profile, &title)).map_err(|e| {
+        CommandError::Io {
+            action: "write_pdf_x_definition",
+            error: e.to_string(),
+        }
+    })?;
+
+    let device_args = [
+        String::from("-dPDFX"),
+        String::from("-dCompatibilityLevel=1.3"),
+        String::from("-sColorConversionStrategy=CMYK"),
+        String::from("-dProcessColorModel=/DeviceCMYK"),
+        String::from("-dEmbedAllFonts=true"),
+        format!("-sOutputICCProfile={}", icc_profile.to_string_lossy()),
+        format!("--permit-file-read={}", icc_profile.to_string_lossy()),
+    ];
+    let result = run_pdfwrite(
+        &[&definition, input],
+        output,
+        &device_args,
+        total_pages,
+        runtime,
+        ctx,
+    )?;
+
+    let warnings = ghostscript_messages(&result, |line| line.contains("PDF/X"));
+    let font_substitutions = ghostscript_messages(&result, |line| {
+        line.to_ascii_lowercase().contains("substitut")
+    });
+    Ok(PdfxResult {
+        output_path: output.to_string_lossy().to_string(),
+        conformant: !abandons_standard(&warnings) && claims_pdfx(output),
+        warnings,
+        rgb_pages,
+        unembedded_fonts,
+        font_substitutions,
+        transparency_flattened: transparent,
+    })
+}
+
+/// Rewrite an encrypted PDF without encryption. Files restricted only by an owner password
+/// open without one; a missing user password yields `CommandError::PasswordRequired` and a
+/// wrong one `CommandError::WrongPassword`.
+fn decrypt_with_ghostscript(
+    input: &Path,
+    output: &Path,
+    password: Option<&str>,
+    runtime: &GhostscriptRuntime,
+    ctx: JobContext<'_>,
+) -> Result<PageCountResult, CommandError> {
+    if !input.is_file() {
+        return Err(CommandError::NotFound {
+            path: input.to_string_lossy().to_string(),
+        });
+    }
+    ensure_distinct_paths(input, output)?;
+
+    let mut device_args = Vec::new();
+    if let Some(password) = password.filter(|password| !password.is_empty()) {
+        device_args.push(format!("-sPDFPassword={}", password));
+    }
+    // The page count can't be read before decryption, so progress has no total.
+    match run_pdfwrite(&[input], output, &device_args, None, runtime, ctx) {
+        Err(CommandError::PasswordRequired) => {
+            let _ = std::fs::remove_file(output);
+            return Err(match device_args.is_empty() {
+                true => CommandError::PasswordRequired,
+                false => CommandError::WrongPassword,
+            });
+        }
+        result => result?,
+    };
+
+    Ok(PageCountResult {
+        output_path: output.to_string_lossy().to_string(),
+        page_count: document_page_count(output, runtime, GhostscriptHooks::default())?,
+    })
+}
+
+/// `/P` permission flags: everything denied except accessibility extraction, plus printing
+/// (low and high quality) and copying when allowed. Reserved bits are set as required.
+fn permission_flags(allow_printing: bool, allow_copying: bool) -> i32 {
+    const RESERVED: u32 = 0xFFFF_F0C0;
+    const PRINT: u32 = 1 << 2;
+    const COPY: u32 = 1 << 4;
+    const ACCESSIBILITY: u32 = 1 << 9;
+    const HIGH_QUALITY_PRINT: u32 = 1 << 11;
+
+    let mut flags = RESERVED | ACCESSIBILITY;
+    if allow_printing {
+        flags |= PRINT | HIGH_QUALITY_PRINT;
+    }
+    if allow_copying {
+        flags |= COPY;
+    }
+    flags as i32
+}
+
+/// Encrypt the PDF with the standard security handler. pdfwrite only writes RC4, so the
+/// strongest available level (128-bit, revision 3) is used and the result says so.
+#[allow(clippy::too_many_arguments)]
+fn encrypt_with_ghostscript(
+    input: &Path,
+    output: &Path,
+    user_password: &str,
+    owner_password: &str,
+    allow_printing: bool,
+    allow_copying: bool,
+    runtime: &GhostscriptRuntime,
+    ctx: JobContext<'_>,
+) -> Result<EncryptResult, CommandError> {
+    if owner_password.is_empty() {
+        return Err(CommandError::MissingInput {
+            field: "owner_password",
+        });
+    }
+    if owner_password == user_password {
+        return Err(CommandError::PasswordsMustDiffer);
+    }
+    if !input.is_file() {
+        return Err(CommandError::NotFound {
+            path: input.to_string_lossy().to_string(),
+        });
+    }
+    ensure_distinct_paths(input, output)?;
+
+    const REVISION: u32 = 3;
+    const KEY_LENGTH_BITS: u32 = 128;
+    let mut device_args = vec![
+        format!("-sOwnerPassword={}", owner_password),
+        format!(
+            "-dPermissions={}",
+            permission_flags(allow_printing, allow_copying)
+        ),
+        format!("-dEncryptionR={}", REVISION),
+        format!("-dKeyLength={}", KEY_LENGTH_BITS),
+    ];
+    if !user_password.is_empty() {
+        device_args.push(format!("-sUserPassword={}", user_password));
+    }
+    let total_pages = document_page_count(input, runtime, ctx.hooks()).ok();
+    run_pdfwrite(&[input], output, &device_args, total_pages, runtime, ctx)?;
+
+    Ok(EncryptResult {
+        output_path: output.to_string_lossy().to_string(),
+        algorithm: format!("RC4-{}", KEY_LENGTH_BITS),
+        key_length_bits: KEY_LENGTH_BITS,
+        revision: REVISION,
+        warnings: vec![String::from(
+            "Ghostscript cannot write AES encryption; RC4 128-bit was applied instead.",
+        )],
+    })
+}
+
+/// PDFDocEncoding code points that differ from Latin-1, for bytes 0x18-0x1F and 0x80-0xA0.
+fn pdf_doc_encoding_char(byte: u8) -> char {
+    const LOW: [char; 8] = [
+        '\u{02D8}', '\u{02C7}', '\u{02C6}', '\u{02D9}', '\u{02DD}', '\u{02DB}', '\u{02DA}',
+        '\u{02DC}',
+    ];
+    const HIGH: [char; 33] = [
+        '\u{2022}', '\u{2020}', '\u{2021}', '\u{2026}', '\u{2014}', '\u{2013}', '\u{0192}',
+        '\u{2044}', '\u{2039}', '\u{203A}', '\u{2212}', '\u{2030}', '\u{201E}', '\u{201C}',
+        '\u{201D}', '\u{2018}', '\u{2019}', '\u{201A}', '\u{2122}', '\u{FB01}', '\u{FB02}',
+        '\u{0141}', '\u{0152}', '\u{0160}', '\u{0178}', '\u{017D}', '\u{0131}', '\u{0142}',
+        '\u{0153}', '\u{0161}', '\u{017E}', '\u{FFFD}', '\u{20AC}',
+    ];
+    match byte {
+        0x18..=0x1F => LOW[usize::from(byte - 0x18)],
+        0x80..=0xA0 => HIGH[usize::from(byte - 0x80)],
+        _ => char::from(byte),
+    }
+}
+
+/// Decode a PDF text string: UTF-16BE or UTF-8 with a byte order mark, else PDFDocEncoding.
+fn decode_pdf_text(bytes: &[u8]) -> String {
+    if let Some(utf16) = bytes.strip_prefix(&[0xFE, 0xFF]) {
+        let units: Vec<u16> = utf16
+            .chunks_exact(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+            .collect();
+        return String::from_utf16_lossy(&units);
+    }
+    if let Some(utf8) = bytes.strip_prefix(&[0xEF, 0xBB, 0xBF]) {
+        return String::from_utf8_lossy(utf8).to_string();
+    }
+    bytes
+        .iter()
+        .map(|byte| pdf_doc_encoding_char(*byte))
+        .collect()
+}
+
+/// Convert a PDF date (`D:YYYYMMDDHHmmSSOHH'mm'`, trailing parts optional) to RFC 3339.
+fn pdf_date_to_rfc3339(value: &str) -> Option<String> {
+    let value = value.trim();
+    let value = value.strip_prefix("D:").unwrap_or(value);
+    let digits: String = value.chars().take_while(char::is_ascii_digit).collect();
+    if digits.len() < 4 || digits.len() % 2 != 0 {
+        return None;
+    }
+    let field =
+        |start: usize, default: &'static str| digits.get(start..start + 2).unwrap_or(default);
+    let date = format!(
+        "{}-{}-{}T{}:{}:{}",
+        &digits[..4],
+        field(4, "01"),
+        field(6, "01"),
+        field(8, "00"),
+        field(10, "00"),
+        field(12, "00")
+    );
+    let zone = &value[digits.len()..];
+    let offset = match zone.chars().next() {
+        // Dates without a zone are unspecified local time; UTC is the closest reading.
+        None | Some('Z') => String::from("Z"),
+        Some(sign @ ('+' | '-')) => {
+            let zone_digits: String = zone[1..].chars().filter(char::is_ascii_digit).collect();
+            let hours = zone_digits.get(..2)?;
+            let minutes = zone_digits.get(2..4).unwrap_or("00");
+            format!("{}{}:{}", sign, hours, minutes)
+        }
+        Some(_) => return None,
+    };
+    Some(date + &offset)
+}
+
+fn unescape_xml(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find('&') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest.find(';') else {
+            break;
+        };
+        let entity = &rest[1..end];
+        let decoded = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(str::parse))
+                .and_then(|code| code.ok())
+                .and_then(char::from_u32),
+        };
+        match decoded {
+            Some(c) => {
+                result.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                result.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// First value of an XMP property, written either as an element (for language
+/// alternatives and lists, its first `rdf:li`) or as an attribute of `rdf:Description`.
+fn xmp_value(xmp: &str, property: &str) -> Option<String> {
+    let open = format!("<{}", property);
+    let mut search = xmp;
+    while let Some(start) = search.find(&open) {
+        let after = &search[start + open.len()..];
+        // Skip longer names sharing the prefix, e.g. `dc:titleExtra`.
+        if after.starts_with(|c: char| c == '>' || c.is_whitespace()) {
+            let body_start = after.find('>')? + 1;
+            let close = format!("</{}>", property);
+            let body = &after[body_start..after.find(&close)?];
+            let body = match body.find("<rdf:li") {
+                Some(li) => {
+                    let li = &body[li..];
+                    let content = &li[li.find('>')? + 1..];
+                    &content[..content.find("</rdf:li>")?]
+                }
+                None => body,
+            };
+            let text = unescape_xml(body.trim());
+            return (!text.is_empty()).then_some(text);
+        }
+        search = after;
+    }
+    for quote in ['"', '\''] {
+        let attribute = format!("{}={}", property, quote);
+        if let Some(start) = xmp.find(&attribute) {
+            let value = &xmp[start + attribute.len()..];
+            let text = unescape_xml(&value[..value.find(quote)?]);
+            return (!text.is_empty()).then_some(text);
+        }
+    }
+    None
+}
+
+/// The catalog's XMP metadata packet as text, if any.
+fn document_xmp(doc: &lopdf::Document) -> Option<String> {
+    let catalog = pdf_dict(doc, doc.trailer.get(b"Root").ok()?)?;
+    let id = catalog.get(b"Metadata").ok()?.as_reference().ok()?;
+    let stream = doc.get_object(id).and_then(lopdf::Object::as_stream).ok()?;
+    Some(String::from_utf8_lossy(&stream_content(stream).ok()?).to_string())
+}
+
+/// Whether the file declares encryption, looking for an `/Encrypt` entry in the trailer
+/// regions at the start (linearized files) and end of the file.
+fn declares_encryption(path: &Path) -> bool {
+    use std::io::{Read, Seek, SeekFrom};
+
+    const REGION: u64 = 64 * 1024;
+    let Ok(mut file) = std::fs::File::open(path) else {
+        return false;
+    };
+    let length = file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+    let mut regions = vec![0];
+    if length > REGION {
+        regions.push(length - REGION);
+    }
+    regions.into_iter().any(|offset| {
+        let mut buffer = Vec::new();
+        file.seek(SeekFrom::Start(offset)).is_ok()
+            && (&mut file).take(REGION).read_to_end(&mut buffer).is_ok()
+            && buffer.windows(8).any(|window| window == b"/Encrypt")
+    })
+}
+
+/// Encode a PDF text string: plain bytes when ASCII, otherwise UTF-16BE with a byte order mark.
+fn encode_pdf_text(value: &str) -> lopdf::Object {
+    if value.is_ascii() {
+        return lopdf::Object::String(value.as_bytes().to_vec(), lopdf::StringFormat::Literal);
+    }
+    let mut bytes = vec![0xFE, 0xFF];
+    for unit in value.encode_utf16() {
+        bytes.extend_from_slice(&unit.to_be_bytes());
+    }
+    lopdf::Object::String(bytes, lopdf::StringFormat::Hexadecimal)
+}
+
+/// Convert an RFC 3339 timestamp (or a bare `YYYY-MM-DD` date) to `D:YYYYMMDDHHmmSSOHH'mm'`.
+fn rfc3339_to_pdf_date(value: &str) -> Result<String, CommandError> {
+    let invalid = || CommandError::InvalidInput {
+        field: "date",
+        value: value.to_string(),
+        expected: String::from("an RFC 3339 date"),
+    };
+    let bytes = value.trim().as_bytes();
+    let digits = |range: std::ops::Range<usize>| {
+        let part = bytes.get(range).ok_or_else(invalid)?;
+        if part.iter().all(u8::is_ascii_digit) {
+            Ok(String::from_utf8_lossy(part).to_string())
+        } else {
+            Err(invalid())
+        }
+    };
+    let separator = |index: usize, expected: &[u8]| match bytes.get(index) {
+        Some(byte) if expected.contains(byte) => Ok(()),
+        _ => Err(invalid()),
+    };
+
+    let (year, month, day) = (digits(0..4)?, digits(5..7)?, digits(8..10)?);
+    separator(4, b"-")?;
+    separator(7, b"-")?;
+    if bytes.len() == 10 {
+        return Ok(format!("D:{}{}{}", year, month, day));
+    }
+    separator(10, b"Tt ")?;
+    let (hour, minute, second) = (digits(11..13)?, digits(14..16)?, digits(17..19)?);
+    separator(13, b":")?;
+    separator(16, b":")?;
+
+    let mut zone = &bytes[19..];
+    if zone.first() == Some(&b'.') {
+        let fraction = zone[1..]
+            .iter()
+            .take_while(|byte| byte.is_ascii_digit())
+            .count();
+        zone = &zone[1 + fraction..];
+    }
+    let offset = match zone {
+        [b'Z' | b'z'] => String::from("Z"),
+        [sign @ (b'+' | b'-'), h1, h2, b':', m1, m2]
+            if [h1, h2, m1, m2].iter().all(|byte| byte.is_ascii_digit()) =>
+        {
+            format!(
+                "{}{}{}'{}{}'",
+                char::from(*sign),
+                char::from(*h1),
+                char::from(*h2),
+                char::from(*m1),
+                char::from(*m2)
+            )
+        }
+        _ => return Err(invalid()),
+    };
+    Ok(format!(
+        "D:{}{}{}{}{}{}{}",
+        year, month, day, hour, minute, second, offset
+    ))
+}
+
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Replace the first value of an XMP property written in either of the forms `xmp_value`
+/// reads. Returns `None` when the packet doesn't contain the property.
+fn xmp_replace(xmp: &str, property: &str, value: &str) -> Option<String> {
+    let escaped = escape_xml(value);
+    let open = format!("<{}", property);
+    let mut offset = 0;
+    while let Some(found) = xmp[offset..].find(&open) {
+        let start = offset + found + open.len();
+        if xmp[start..].starts_with(|c: char| c == '>' || c.is_whitespace()) {
+            let body_start = start + xmp[start..].find('>')? + 1;
+            let body_end = body_start + xmp[body_start..].find(&format!("</{}>", property))?;
+            let (value_start, value_end) = match xmp[body_start..body_end].find("<rdf:li") {
+                Some(li) => {
+                    let li = body_start + li;
+                    let content = li + xmp[li..].find('>')? + 1;
+                    (content, content + xmp[content..].find("</rdf:li>")?)
+                }
+                None => (body_start, body_end),
+            };
+            return Some(format!(
+                "{}{}{}",
+                &xmp[..value_start],
+                escaped,
+                &xmp[value_end..]
+            ));
+        }
+        offset = start;
+    }
+    for quote in ['"', '\''] {
+        let attribute = format!("{}={}", property, quote);
+        if let Some(found) = xmp.find(&attribute) {
+            let value_start = found + attribute.len();
+            let value_end = value_start + xmp[value_start..].find(quote)?;
+            return Some(format!(
+                "{}{}{}",
+                &xmp[..value_start],
+                escaped,
+                &xmp[value_end..]
+            ));
+        }
+    }
+    None
+}
+
+/// Apply `patch` to the Info dictionary, creating it if needed, and to the matching XMP
+/// properties that already exist, so viewers preferring XMP don't show stale values.
+fn write_pdf_metadata(
+    input: &Path,
+    output: &Path,
+    patch: &MetadataPatch,
+) -> Result<PdfMetadata, CommandError> {
+    ensure_distinct_paths(input, output)?;
+    let date = |value: &Option<String>| -> Result<Option<(String, String)>, CommandError> {
+        match value.as_deref() {
+            None => Ok(None),
+            Some("") => Ok(Some((String::new(), String::new()))),
+            Some(value) => Ok(Some((
+                rfc3339_to_pdf_date(value)?,
+                value.trim().to_string(),
+            ))),
+        }
+    };
+    let text = |value: &Option<String>| value.as_ref().map(|value| (value.clone(), value.clone()));
+    // (Info key, XMP property, Some((Info value, XMP value)) when changing)
+    let fields = [
+        ("Title", "dc:title", text(&patch.title)),
+        ("Author", "dc:creator", text(&patch.author)),
+        ("Subject", "dc:description", text(&patch.subject)),
+        ("Keywords", "pdf:Keywords", text(&patch.keywords)),
+        ("Creator", "xmp:CreatorTool", text(&patch.creator)),
+        ("Producer", "pdf:Producer", text(&patch.producer)),
+        (
+            "CreationDate",
+            "xmp:CreateDate",
+            date(&patch.creation_date)?,
+        ),
+        ("ModDate", "xmp:ModifyDate", date(&patch.modification_date)?),
+    ];
+
+    let mut doc = load_pdf_document(input)?;
+    let info_ref = doc
+        .trailer
+        .get(b"Info")
+        .ok()
+        .and_then(|info| info.as_reference().ok());
+    let info = match info_ref {
+        Some(id) => doc.get_dictionary_mut(id).map_err(|e| CommandError::Io {
+            action: "read_document_info",
+            error: e.to_string(),
+        })?,
+        None => {
+            let existing = doc
+                .trailer
+                .get(b"Info")
+                .ok()
+                .and_then(|info| info.as_dict().ok())
+                .cloned()
+                .unwrap_or_default();
+            let id = doc.add_object(existing);
+            doc.trailer.set("Info", id);
+            doc.get_dictionary_mut(id).map_err(|e| CommandError::Io {
+                action: "read_document_info",
+                error: e.to_string(),
+            })?
+        }
+    };
+    for (key, _, value) in &fields {
+        match value {
+            Some((value, _)) if value.is_empty() => {
+                info.remove(key.as_bytes());
+            }
+            Some((value, _)) => info.set(*key, encode_pdf_text(value)),
+            None => {}
+        }
+    }
+
+    let metadata_id = doc
+        .trailer
+        .get(b"Root")
+        .ok()
+        .and_then(|root| pdf_dict(&doc, root))
+        .and_then(|catalog| catalog.get(b"Metadata").ok())
+        .and_then(|metadata| metadata.as_reference().ok());
+    if let (Some(id), Some(mut xmp)) = (metadata_id, document_xmp(&doc)) {
+        let mut changed = false;
+        for (_, property, value) in &fields {
+            if let Some((_, value)) = value {
+                if let Some(updated) = xmp_replace(&xmp, property, value) {
+                    xmp = updated;
+                    changed = true;
+                }
+            }
+        }
+        if changed {
+            if let Ok(stream) = doc
+                .get_object_mut(id)
+                .and_then(lopdf::Object::as_stream_mut)
+            {
+                stream.set_plain_content(xmp.into_bytes());
+            }
+        }
+    }
+
+    save_pdf_document(&mut doc, output)?;
+    read_pdf_metadata(output)
+}
+
+fn read_pdf_metadata(path: &Path) -> Result<PdfMetadata, CommandError> {
+    let doc = load_pdf_document(path)?;
+    let info = doc
+        .trailer
+        .get(b"Info")
+        .ok()
+        .and_then(|info| pdf_dict(&doc, info));
+    let info_text = |key: &[u8]| {
+        let (_, value) = doc.dereference(info?.get(key).ok()?).ok()?;
+        let text = decode_pdf_text(value.as_str().ok()?);
+        let text = text.trim_end_matches('\0').trim();
+        (!text.is_empty()).then(|| text.to_string())
+    };
+    let xmp = document_xmp(&doc);
+    let xmp_text = |property: &str| xmp.as_deref().and_then(|xmp| xmp_value(xmp, property));
+    let date = |key: &[u8], property: &str| {
+        info_text(key)
+            .map(|raw| pdf_date_to_rfc3339(&raw).unwrap_or(raw))
+            .or_else(|| xmp_text(property))
+    };
+
+    Ok(PdfMetadata {
+        title: info_text(b"Title").or_else(|| xmp_text("dc:title")),
+        author: info_text(b"Author").or_else(|| xmp_text("dc:creator")),
+        subject: info_text(b"Subject").or_else(|| xmp_text("dc:description")),
+        keywords: info_text(b"Keywords").or_else(|| xmp_text("pdf:Keywords")),
+        creator: info_text(b"Creator").or_else(|| xmp_text("xmp:CreatorTool")),
+        producer: info_text(b"Producer").or_else(|| xmp_text("pdf:Producer")),
+        creation_date: date(b"CreationDate", "xmp:CreateDate"),
+        modification_date: date(b"ModDate", "xmp:ModifyDate"),
+        page_count: doc.get_pages().len() as u32,
+        encrypted: doc.is_encrypted() || declares_encryption(path),
+    })
+}
+
+fn read_page_sizes(path: &Path) -> Result<Vec<PageSize>, CommandError> {
+    let doc = load_pdf_document(path)?;
+    Ok(read_page_geometry(&doc)
+        .into_iter()
+        .map(|geometry| {
+            let visible_size = geometry.box_size(geometry.visible_box());
+            let orientation = if visible_size.width_pt > visible_size.height_pt {
+                PageOrientation::Landscape
+            } else {
+                PageOrientation::Portrait
+            };
+            PageSize {
+                page: geometry.page,
+                media_box: geometry.media_box,
+                crop_box: geometry.crop_box,
+                rotate: geometry.rotate,
+                user_unit: geometry.user_unit,
+                media_size: geometry.displayed_media_size(),
+                visible_size,
+                orientation,
+            }
+        })
+        .collect())
+}
+
+/// Anti-aliased rendering of the CropBox, as a viewer would show the page.
+const RASTER_PREVIEW_ARGS: [&str; 3] =
+    ["-dUseCropBox", "-dTextAlphaBits=4", "-dGraphicsAlphaBits=4"];
+
+/// The requested resolution, lowered until a `size` page stays within `max_pixels`. Pages
+/// of unknown size render at no more than 72 dpi.
+fn capped_dpi(dpi: u32, size: Option<PageDimensions>, max_pixels: u32) -> u32 {
+    let Some(size) = size else {
+        return dpi.min(72);
+    };
+    let square_inches = (size.width_pt / 72.0) * (size.height_pt / 72.0);
+    if square_inches <= 0.0 {
+        return dpi;
+    }
+    let limit = (f64::from(max_pixels) / square_inches).sqrt().floor();
+    (f64::from(dpi).min(limit) as u32).max(1)
+}
+
+fn render_page_png(
+    input: &Path,
+    page: u32,
+    dpi: u32,
+    max_pixels: u32,
+    transparent: bool,
+    runtime: &GhostscriptRuntime,
+    ctx: JobContext<'_>,
+) -> Result<Vec<u8>, CommandError> {
+    if dpi == 0 || max_pixels == 0 {
+        return Err(CommandError::OutOfRange {
+            field: if dpi == 0 { "dpi" } else { "max_pixels" },
+            value: String::from("0"),
+            expected: String::from("greater than zero"),
+        });
+    }
+    if !input.is_file() {
+        return Err(CommandError::NotFound {
+            path: input.to_string_lossy().to_string(),
+        });
+    }
+    let geometry = load_pdf_document(input)
+        .map(|doc| read_page_geometry(&doc))
+        .ok();
+    let page_count = match &geometry {
+        Some(geometry) => geometry.len() as u32,
+        None => ghostscript_page_count(input, runtime, ctx.hooks())?,
+    };
+    if page == 0 || page > page_count {
+        return Err(CommandError::PageOutOfRange { page, page_count });
+    }
+
+    let size = geometry
+        .as_ref()
+        .and_then(|geometry| geometry.get(page as usize - 1))
+        .map(|geometry| geometry.box_size(geometry.visible_box()));
+
+    let mut temp_files = TempFileGuard::default();
+    let output = temp_files.track(unique_temp_path("preview", "png"));
+    let device = if transparent { "pngalpha" } else { "png16m" };
+    let mut device_args: Vec<String> = RASTER_PREVIEW_ARGS
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+    device_args.extend([
+        format!("-r{}", capped_dpi(dpi, size, max_pixels)),
+        format!("-dFirstPage={}", page),
+        format!("-dLastPage={}", page),
+    ]);
+    run_device_to(
+        device,
+        &[input],
+        &gs_output_file_arg(&output),
+        &device_args,
+        Some(1),
+        runtime,
+        ctx,
+    )?;
+    std::fs::read(&output).map_err(|e| CommandError::Io {
+        action: "read_rendered_preview",
+        error: e.to_string(),
+    })
+}
+
+/// Cache directory for `input`'s thumbnails, named by a hash of its path plus one of its
+/// size and mtime. Directories left over from earlier versions of the file are removed.
+fn thumbnail_cache_dir(cache_root: &Path, input: &Path) -> Result<PathBuf, CommandError> {
+    use std::hash::{Hash, Hasher};
+
+    let canonical = input.canonicalize().map_err(|e| CommandError::Io {
+        action: "read",
+        error: format!("'{}': {}", input.display(), e),
+    })?;
+    let metadata = canonical.metadata().map_err(|e| CommandError::Io {
+        action: "read",
+        error: format!("'{}': {}", input.display(), e),
+    })?;
+    let mut path_hasher = std::collections::hash_map::DefaultHasher::new();
+    canonical.hash(&mut path_hasher);
+    let mut version_hasher = std::collections::hash_map::DefaultHasher::new();
+    metadata.len().hash(&mut version_hasher);
+    metadata.modified().ok().hash(&mut version_hasher);
+
+    let prefix = format!("{:016x}-", path_hasher.finish());
+    let name = format!("{}{:016x}", prefix, version_hasher.finish());
+    let thumbnails_root = cache_root.join("thumbnails");
+    if let Ok(entries) = std::fs::read_dir(&thumbnails_root) {
+        for entry in entries.flatten() {
+            let entry_name = entry.file_name().to_string_lossy().to_string();
+            if entry_name.starts_with(&prefix) && entry_name != name {
+                let _ = std::fs::remove_dir_all(entry.path());
+            }
+        }
+    }
+    let dir = thumbnails_root.join(name);
+    std::fs::create_dir_all(&dir).map_err(|e| CommandError::Io {
+        action: "create_thumbnail_cache",
+        error: format!("'{}': {}", dir.display(), e),
+    })?;
+    Ok(dir)
+}
+
+/// Render `pages` at `height_px` tall into `cache_root`, reusing thumbnails from earlier
+/// calls. Missing pages of the same height render together in one `%d` Ghostscript run.
+fn generate_page_thumbnails(
+    input: &Path,
+    pages: Option<Vec<u32>>,
+    height_px: u32,
+    cache_root: &Path,
+    runtime: &GhostscriptRuntime,
+    ctx: JobContext<'_>,
+) -> Result<Vec<Thumbnail>, CommandError> {
+    // Pages whose size cannot be read are assumed to be US Letter tall.
+    const FALLBACK_HEIGHT_PT: f64 = 792.0;
+
+    if height_px == 0 {
+        return Err(CommandError::OutOfRange {
+            field: "thumbnail_height",
+            value: String::from("0"),
+            expected: String::from("greater than zero"),
+        });
+    }
+    if !input.is_file() {
+        return Err(CommandError::NotFound {
+            path: input.to_string_lossy().to_string(),
+        });
+    }
+    let cache_dir = thumbnail_cache_dir(cache_root, input)?;
+    let geometry = load_pdf_document(input)
+        .map(|doc| read_page_geometry(&doc))
+        .ok();
+    let page_count = match &geometry {
+        Some(geometry) => geometry.len() as u32,
+        None => ghostscript_page_count(input, runtime, ctx.hooks())?,
+    };
+    let pages = resolve_page_selection(pages, page_count)?;
+    let thumbnail_path = |page: u32| cache_dir.join(format!("h{}_p{}.png", height_px, page));
+
+    // Group the missing pages by the resolution that makes them `height_px` tall.
+    let mut batches: Vec<(u32, Vec<u32>)> = Vec::new();
+    for &page in pages
+        .iter()
+        .filter(|page| !thumbnail_path(**page).is_file())
+    {
+        let height_pt = geometry
+            .as_ref()
+            .and_then(|geometry| geometry.get(page as usize - 1))
+            .map(|geometry| geometry.box_size(geometry.visible_box()).height_pt)
+            .filter(|height| *height > 0.0)
+            .unwrap_or(FALLBACK_HEIGHT_PT);
+        let dpi = ((f64::from(height_px) * 72.0 / height_pt).round() as u32).max(1);
+        match batches.iter_mut().find(|(batch_dpi, _)| *batch_dpi == dpi) {
+            Some((_, batch)) => batch.push(page),
+            None => batches.push((dpi, vec![page])),
+        }
+    }
+
+    let render_stem = format!(".{}", unique_temp_stem("thumbs"));
+    let render_path = |index: usize| cache_dir.join(format!("{}_{}.png", render_stem, index));
+    let pattern = PathBuf::from(cache_dir.to_string_lossy().replace('%', "%%"))
+        .join(format!("{}_%d.png", render_stem));
+    let output_file_arg = format!("-sOutputFile={}", pattern.display());
+    for (dpi, batch) in batches {
+        let mut device_args: Vec<String> = RASTER_PREVIEW_ARGS
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        device_args.push(format!("-r{}", dpi));
+        device_args.push(page_list_arg(&batch));
+
+        let mut temp_files = TempFileGuard::default();
+        let result = run_device_to(
+            "png16m",
+            &[input],
+            &output_file_arg,
+            &device_args,
+            Some(batch.len() as u32),
+            runtime,
+            ctx,
+        );
+        for index in 1..=batch.len() {
+            temp_files.track(render_path(index));
+        }
+        result?;
+        for (index, page) in batch.iter().enumerate() {
+            let target = thumbnail_path(*page);
+            std::fs::rename(render_path(index + 1), &target).map_err(|e| CommandError::Io {
+                action: "cache_thumbnail",
+                error: format!("'{}': {}", target.display(), e),
+            })?;
+        }
+    }
+
+    Ok(pages
+        .into_iter()
+        .map(|page| Thumbnail {
+            page,
+            path: thumbnail_path(page).to_string_lossy().to_string(),
+        })
+        .collect())
+}
+
+impl ImageFormat {
+    fn device(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png16m",
+            ImageFormat::Jpeg => "jpeg",
+            ImageFormat::Tiff => "tiff24nc",
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Jpeg => "jpg",
+            ImageFormat::Tiff => "tif",
+        }
+    }
+}
+
+/// Render `pages` (all when `None`) to `{name}_p{page}.{ext}` files in `output_dir`, page
+/// numbers zero-padded to the width of the page count. Existing files are never
+/// overwritten, and nothing is left behind if rendering fails.
+#[allow(clippy::too_many_arguments)]
+fn export_images_with_ghostscript(
+    input: &Path,
+    output_dir: &Path,
+    format: ImageFormat,
+    dpi: u32,
+    pages: Option<Vec<u32>>,
+    jpeg_quality: u8,
+    tiff_lzw: bool,
+    on_conflict: ConflictPolicy,
+    runtime: &GhostscriptRuntime,
+    ctx: JobContext<'_>,
+) -> Result<Vec<String>, CommandError> {
+    if dpi == 0 {
+        return Err(CommandError::OutOfRange {
+            field: "dpi",
+            value: String::from("0"),
+            expected: String::from("greater than zero"),
+        });
+    }
+    if !(1..=100).contains(&jpeg_quality) {
+        return Err(CommandError::OutOfRange {
+            field: "jpeg_quality",
+            value: jpeg_quality.to_string(),
+            expected: String::from("between 1 and 100"),
+        });
+    }
+    if !input.is_file() {
+        return Err(CommandError::NotFound {
+            path: input.to_string_lossy().to_string(),
+        });
+    }
+    let page_count = document_page_count(input, runtime, ctx.hooks())?;
+    let all_pages = pages.is_none();
+    let pages = resolve_page_selection(pages, page_count)?;
+    let name = input
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_else(|| String::from("document"));
+    let width = page_count.to_string().len();
+    let names: Vec<PathBuf> = pages
+        .iter()
+        .map(|page| {
+            output_dir.join(format!(
+                "{}_p{:0width$}.{}",
+                name,
+                page,
+                format.extension(),
+                width = width
+            ))
+        })
+        .collect();
+    let final_paths = claim_output_paths(&names, on_conflict)?;
+
+    std::fs::create_dir_all(output_dir).map_err(|e| CommandError::Io {
+        action: "create_output_directory",
+        error: format!("'{}': {}", output_dir.display(), e),
+    })?;
+    // Raster sizes can't be predicted, so only writability is checked.
+    ensure_output_writable(output_dir, 0)?;
+
+    let mut device_args: Vec<String> = RASTER_PREVIEW_ARGS
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+    device_args.push(format!("-r{}", dpi));
+    match format {
+        ImageFormat::Jpeg => device_args.push(format!("-dJPEGQ={}", jpeg_quality)),
+        ImageFormat::Tiff if tiff_lzw => device_args.push(String::from("-sCompression=lzw")),
+        _ => {}
+    }
+    if !all_pages {
+        device_args.push(page_list_arg(&pages));
+    }
+
+    // Temp images live in the output directory so the final renames never cross devices.
+    let temp_stem = format!(".{}", unique_temp_stem("images"));
+    let temp_image =
+        |index: usize| output_dir.join(format!("{}_{}.{}", temp_stem, index, format.extension()));
+    let pattern = PathBuf::from(output_dir.to_string_lossy().replace('%', "%%")).join(format!(
+        "{}_%d.{}",
+        temp_stem,
+        format.extension()
+    ));
+    let output_file_arg = format!("-sOutputFile={}", pattern.display());
+
+    let mut temp_files = TempFileGuard::default();
+    let result = run_device_to(
+        format.device(),
+        &[input],
+        &output_file_arg,
+        &device_args,
+        Some(pages.len() as u32),
+        runtime,
+        ctx,
+    );
+    for index in 1..=pages.len() {
+        temp_files.track(temp_image(index));
+    }
+    result?;
+
+    let mut created = TempFileGuard::default();
+    let mut written = Vec::with_capacity(final_paths.len());
+    for (index, (requested, final_path)) in names.iter().zip(&final_paths).enumerate() {
+        let Some(final_path) = final_path else {
+            continue;
+        };
+        if let Some(path) =
+            commit_output(&temp_image(index + 1), requested, final_path, on_conflict)?
+        {
+            written.push(created.track(path).to_string_lossy().to_string());
+        }
+    }
+    created.keep();
+    Ok(written)
+}
+
+/// The four coverage fractions of an `inkcov` line such as
+/// ` 0.02616  0.01406  0.00923  0.19520 CMYK OK`. Decimal commas are accepted in case a
+/// build formats numbers for the locale.
+fn parse_inkcov_line(line: &str) -> Option<[f64; 4]> {
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    if tokens.len() < 5 || tokens[4] != "CMYK" {
+        return None;
+    }
+    let mut coverage = [0.0; 4];
+    for (value, token) in coverage.iter_mut().zip(&tokens) {
+        *value = token.replace(',', ".").parse().ok()?;
+    }
+    Some(coverage)
+}
+
+/// Per-page CMYK ink coverage from Ghostscript's `inkcov` device, as percentages of the
+/// page area.
+fn ink_coverage_with_ghostscript(
+    input: &Path,
+    pages: Option<Vec<u32>>,
+    runtime: &GhostscriptRuntime,
+    ctx: JobContext<'_>,
+) -> Result<Vec<InkCoverage>, CommandError> {
+    if !input.is_file() {
+        return Err(CommandError::NotFound {
+            path: input.to_string_lossy().to_string(),
+        });
+    }
+    let page_count = document_page_count(input, runtime, ctx.hooks())?;
+    let all_pages = pages.is_none();
+    let pages = resolve_page_selection(pages, page_count)?;
+
+    let mut device_args = vec![String::from("-dUseCropBox")];
+    if !all_pages {
+        device_args.push(page_list_arg(&pages));
+    }
+    let result = run_device_to(
+        "inkcov",
+        &[input],
+        "-sOutputFile=%stdout",
+        &device_args,
+        Some(pages.len() as u32),
+        runtime,
+        ctx,
+    )?;
+
+    // Each coverage line follows the `Page N` line of its page; the requested pages in
+    // order are the fallback if those are missing.
+    let mut coverage = Vec::new();
+    let mut current_page = None;
+    for line in result.stdout.lines() {
+        if let Some(page) = parse_page_line(line) {
+            current_page = Some(page);
+        } else if let Some(fractions) = parse_inkcov_line(line) {
+            let Some(page) = current_page
+                .take()
+                .or_else(|| pages.get(coverage.len()).copied())
+            else {
+                break;
+            };
+            let [cyan, magenta, yellow, black] = fractions.map(|fraction| fraction * 100.0);
+            coverage.push(InkCoverage {
+                page,
+                cyan,
+                magenta,
+                yellow,
+                black,
+                total: cyan + magenta + yellow + black,
+            });
+        }
+    }
+    if coverage.len() != pages.len() {
+        return Err(CommandError::InkCoverageIncomplete {
+            reported: coverage.len() as u32,
+            page_count: pages.len() as u32,
+        });
+    }
+    Ok(coverage)
+}
+
+/// Text of each selected page (ascending) via Ghostscript's `txtwrite` device, in the
+/// reading order it reconstructs. Pages without text, such as scans, give empty strings.
+fn extract_text_with_ghostscript(
+    input: &Path,
+    pages: Option<Vec<u32>>,
+    runtime: &GhostscriptRuntime,
+    ctx: JobContext<'_>,
+) -> Result<Vec<String>, CommandError> {
+    if !input.is_file() {
+        return Err(CommandError::NotFound {
+            path: input.to_string_lossy().to_string(),
+        });
+    }
+    let page_count = document_page_count(input, runtime, ctx.hooks())?;
+    let all_pages = pages.is_none();
+    let pages = resolve_page_selection(pages, page_count)?;
+
+    // txtwrite writes UTF-8, one file per page for a `%d` output pattern.
+    let temp_stem = unique_temp_stem("text");
+    let temp_dir = std::env::temp_dir();
+    let temp_text = |index: usize| temp_dir.join(format!("{}_{}.txt", temp_stem, index));
+    let pattern = PathBuf::from(temp_dir.to_string_lossy().replace('%', "%%"))
+        .join(format!("{}_%d.txt", temp_stem));
+    let output_file_arg = format!("-sOutputFile={}", pattern.display());
+    let mut device_args = Vec::new();
+    if !all_pages {
+        device_args.push(page_list_arg(&pages));
+    }
+
+    let mut temp_files = TempFileGuard::default();
+    let result = run_device_to(
+        "txtwrite",
+        &[input],
+        &output_file_arg,
+        &device_args,
+        Some(pages.len() as u32),
+        runtime,
+        ctx,
+    );
+    for index in 1..=pages.len() {
+        temp_files.track(temp_text(index));
+    }
+    result?;
+
+    Ok((1..=pages.len())
+        .map(|index| {
+            std::fs::read(temp_text(index))
+                .map(|text| String::from_utf8_lossy(&text).trim_end().to_string())
+                .unwrap_or_default()
+        })
+        .collect())
+}
+
+impl PostScriptDevice {
+    fn device(self) -> &'static str {
+        match self {
+            PostScriptDevice::Ps2write => "ps2write",
+            PostScriptDevice::Eps2write => "eps2write",
+        }
+    }
+}
+
+fn postscript_with_ghostscript(
+    input: &Path,
+    output: &Path,
+    device: PostScriptDevice,
+    pages: Option<Vec<u32>>,
+    language_level: Option<u8>,
+    runtime: &GhostscriptRuntime,
+    ctx: JobContext<'_>,
+) -> Result<PostScriptResult, CommandError> {
+    if !input.is_file() {
+        return Err(CommandError::NotFound {
+            path: input.to_string_lossy().to_string(),
+        });
+    }
+    ensure_distinct_paths(input, output)?;
+    if let Some(level) = language_level.filter(|level| !(2..=3).contains(level)) {
+        return Err(CommandError::OutOfRange {
+            field: "language_level",
+            value: level.to_string(),
+            expected: String::from("2 or 3"),
+        });
+    }
+
+    let source = load_pdf_document(input).ok();
+    let page_count = match &source {
+        Some(doc) => doc.get_pages().len() as u32,
+        None => ghostscript_page_count(input, runtime, ctx.hooks())?,
+    };
+    let all_pages = pages.is_none();
+    let pages = resolve_page_selection(pages, page_count)?;
+    if device == PostScriptDevice::Eps2write && pages.len() != 1 {
+        return Err(CommandError::EpsNeedsOnePage {
+            selected: pages.len() as u32,
+        });
+    }
+    let transparent_pages = source
+        .as_ref()
+        .map(|doc| {
+            let page_ids = doc.get_pages();
+            pages
+                .iter()
+                .copied()
+                .filter(|page| {
+                    page_ids
+                        .get(page)
+                        .is_some_and(|page_id| page_has_transparency(doc, *page_id))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    drop(source);
+
+    let mut device_args = Vec::new();
+    if let Some(level) = language_level {
+        device_args.push(format!("-dLanguageLevel={}", level));
+    }
+    if !all_pages {
+        device_args.push(page_list_arg(&pages));
+    }
+    let result = run_device_to(
+        device.device(),
+        &[input],
+        &gs_output_file_arg(output),
+        &device_args,
+        Some(pages.len() as u32),
+        runtime,
+        ctx,
+    )?;
+
+    let warnings = ghostscript_messages(&result, |line| {
+        let line = line.to_ascii_lowercase();
+        line.contains("transparen") || line.contains("rasteri")
+    });
+    Ok(PostScriptResult {
+        output_path: output.to_string_lossy().to_string(),
+        transparent_pages,
+        warnings,
+    })
+}
+
+struct TrueTypeCmap {
+    data: Vec<u8>,
+    /// Offset of the chosen subtable within `data`.
+    subtable: usize,
+    format: u16,
+}
+
+fn be_u16(data: &[u8], offset: usize) -> Option<u16> {
+    data.get(offset..offset + 2)
+        .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+fn be_u32(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+impl TrueTypeCmap {
+    fn load(path: &Path) -> Result<Self, CommandError> {
+        let data = std::fs::read(path).map_err(|e| CommandError::Io {
+            action: "read_font",
+            error: format!("'{}': {}", path.display(), e),
+        })?;
+        let not_supported = || CommandError::UnsupportedFont {
+            path: path.to_string_lossy().to_string(),
+        };
+        // 0x00010000 or 'true'; CFF-flavoured OpenType ('OTTO') and collections are out.
+        if !matches!(be_u32(&data, 0), Some(0x0001_0000) | Some(0x7472_7565)) {
+            return Err(not_supported());
+        }
+        let table_count = be_u16(&data, 4).ok_or_else(not_supported)? as usize;
+        let cmap = (0..table_count)
+            .map(|index| 12 + 16 * index)
+            .find(|record| data.get(*record..*record + 4) == Some(b"cmap"))
+            .and_then(|record| be_u32(&data, record + 8))
+            .ok_or_else(not_supported)? as usize;
+
+        // Prefer full-repertoire format 12 subtables over BMP-only format 4 ones.
+        let mut best: Option<(u8, usize, u16)> = None;
+        let subtable_count = be_u16(&data, cmap + 2).ok_or_else(not_supported)? as usize;
+        for index in 0..subtable_count {
+            let record = cmap + 4 + 8 * index;
+            let (Some(platform), Some(encoding), Some(offset)) = (
+                be_u16(&data, record),
+                be_u16(&data, record + 2),
+                be_u32(&data, record + 4),
+            ) else {
+                break;
+            };
+            let unicode = platform == 0 || (platform == 3 && matches!(encoding, 1 | 10));
+            let subtable = cmap + offset as usize;
+            let rank = match be_u16(&data, subtable) {
+                Some(12) if unicode => 2,
+                Some(4) if unicode => 1,
+                _ => continue,
+            };
+            if best.map_or(true, |(best_rank, _, _)| rank > best_rank) {
+                best = Some((rank, subtable, if rank == 2 { 12 } else { 4 }));
+            }
+        }
+        let (_, subtable, format) = best.ok_or_else(not_supported)?;
+        Ok(TrueTypeCmap {
+            data,
+            subtable,
+            format,
+        })
+    }
+
+    /// Glyph ID for `c`, or 0 (`.notdef`) when the font does not cover it.
+    fn glyph(&self, c: char) -> u16 {
+        let code = c as u32;
+        let data = &self.data;
+        let table = self.subtable;
+        if self.format == 12 {
+            let group_count = be_u32(data, table + 12).unwrap_or(0) as usize;
+            for index in 0..group_count {
+                let group = table + 16 + 12 * index;
+                let (Some(start), Some(end), Some(first_glyph)) = (
+                    be_u32(data, group),
+                    be_u32(data, group + 4),
+                    be_u32(data, group + 8),
+                ) else {
+                    break;
+                };
+                if (start..=end).contains(&code) {
+                    return u16::try_from(first_glyph + (code - start)).unwrap_or(0);
+                }
+            }
+            return 0;
+        }
+
+        let Ok(code) = u16::try_from(code) else {
+            return 0;
+        };
+        let segment_count = be_u16(data, table + 6).unwrap_or(0) as usize / 2;
+        let end_codes = table + 14;
+        let start_codes = end_codes + 2 * segment_count + 2;
+        let deltas = start_codes + 2 * segment_count;
+        let range_offsets = deltas + 2 * segment_count;
+        for segment in 0..segment_count {
+            let (Some(end), Some(start), Some(delta), Some(range_offset)) = (
+                be_u16(data, end_codes + 2 * segment),
+                be_u16(data, start_codes + 2 * segment),
+                be_u16(data, deltas + 2 * segment),
+                be_u16(data, range_offsets + 2 * segment),
+            ) else {
+                break;
+            };
+            if code > end {
+                continue;
+            }
+            if code < start {
+                return 0;
+            }
+            if range_offset == 0 {
+                return code.wrapping_add(delta);
+            }
+            let address =
+                range_offsets + 2 * segment + range_offset as usize + 2 * (code - start) as usize;
+            return match be_u16(data, address) {
+                Some(0) | None => 0,
+                Some(glyph) => glyph.wrapping_add(delta),
+            };
+        }
+        0
+    }
+}
+
+impl Default for WatermarkOptions {
+    fn default() -> Self {
+        WatermarkOptions {
+            font_size: 48.0,
+            opacity: 0.3,
+            rotation: 45.0,
+            color: String::from("#808080"),
+            position: WatermarkPosition::Center,
+            pages: None,
+            font_path: None,
+        }
+    }
+}
+
+/// System TrueType fonts with Latin, Cyrillic and Greek coverage, in preference order.
+#[cfg(target_os = "windows")]
+const OVERLAY_FONT_CANDIDATES: [&str; 3] = [
+    "C:\\Windows\\Fonts\\arial.ttf",
+    "C:\\Windows\\Fonts\\segoeui.ttf",
+    "C:\\Windows\\Fonts\\tahoma.ttf",
+];
+#[cfg(target_os = "macos")]
+const OVERLAY_FONT_CANDIDATES: [&str; 3] = [
+    "/System/Library/Fonts/Supplemental/Arial.ttf",
+    "/System/Library/Fonts/Supplemental/Arial Unicode.ttf",
+    "/Library/Fonts/Arial Unicode.ttf",
+];
+#[cfg(all(not(target_os = "windows"), not(target_os = "macos")))]
+const OVERLAY_FONT_CANDIDATES: [&str; 4] = [
+    "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/TTF/DejaVuSans.ttf",
+    "/usr/share/fonts/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/truetype/liberation/LiberationSans-Regular.ttf",
+];
+
+/// `#rrggbb` as PostScript `setrgbcolor` operands.
+fn parse_hex_color(color: &str) -> Result<[f64; 3], CommandError> {
+    let invalid = || CommandError::InvalidInput {
+        field: "color",
+        value: color.to_string(),
+        expected: String::from("#rrggbb"),
+    };
+    let hex = color.strip_prefix('#').ok_or_else(invalid)?;
+    if hex.len() != 6 || !hex.is_ascii() {
+        return Err(invalid());
+    }
+    let mut rgb = [0.0; 3];
+    for (index, channel) in rgb.iter_mut().enumerate() {
+        let value =
+            u8::from_str_radix(&hex[2 * index..2 * index + 2], 16).map_err(|_| invalid())?;
+        *channel = f64::from(value) / 255.0;
+    }
+    Ok(rgb)
+}
+
+/// The font to draw overlay text with. An explicit `font_path` must cover every character
+/// of `sample`; otherwise the first system candidate that does is used.
+fn overlay_font(
+    sample: &str,
+    font_path: Option<&str>,
+) -> Result<(PathBuf, TrueTypeCmap), CommandError> {
+    let missing = |cmap: &TrueTypeCmap| {
+        let mut missing = String::new();
+        for c in sample.chars().filter(|c| cmap.glyph(*c) == 0) {
+            if !missing.contains(c) {
+                missing.push(c);
+            }
+        }
+        missing
+    };
+
+    if let Some(font_path) = font_path {
+        let path = PathBuf::from(font_path);
+        let cmap = TrueTypeCmap::load(&path)?;
+        let missing = missing(&cmap);
+        if !missing.is_empty() {
+            return Err(CommandError::MissingGlyphs {
+                path: font_path.to_string(),
+                characters: missing,
+            });
+        }
+        return Ok((path, cmap));
+    }
+    for candidate in OVERLAY_FONT_CANDIDATES {
+        let path = PathBuf::from(candidate);
+        if !path.is_file() {
+            continue;
+        }
+        if let Ok(cmap) = TrueTypeCmap::load(&path) {
+            if missing(&cmap).is_empty() {
+                return Ok((path, cmap));
+            }
+        }
+    }
+    Err(CommandError::NoOverlayFont)
+}
+
+/// `text` as a PostScript hex string of big-endian glyph IDs, for the Identity-H font set
+/// up by `OVERLAY_FONT_PROLOGUE`.
+fn glyph_string(cmap: &TrueTypeCmap, text: &str) -> String {
+    use std::fmt::Write;
+
+    let mut hex = String::with_capacity(text.len() * 4 + 2);
+    hex.push('<');
+    for c in text.chars() {
+        let _ = write!(hex, "{:04X}", cmap.glyph(c));
+    }
+    hex.push('>');
+    hex
+}
+
+/// Composes `/PdfResizerOverlay-Identity-H` from the CIDFont that `overlay_font_args`
+/// maps with Identity ordering, so strings select glyphs by ID.
+const OVERLAY_FONT_PROLOGUE: &str = "/PdfResizerOverlay-Identity-H /Identity-H\n\
+     [/PdfResizerOverlay /CIDFont findresource] composefont pop\n";
+
+/// Write a `cidfmap` naming `font` `/PdfResizerOverlay` and return the Ghostscript
+/// arguments that load it.
+fn overlay_font_args(
+    temp_files: &mut TempFileGuard,
+    font: &Path,
+) -> Result<Vec<String>, CommandError> {
+    let cidfmap = temp_files.track(unique_temp_path("overlay_cidfmap", "ps"));
+    let font = font.to_string_lossy();
+    let entry = format!(
+        "/PdfResizerOverlay << /FileType /TrueType /Path {} \
+         /SubfontID 0 /CSI [(Identity) 0] >> ;\n",
+        ps_string_literal(&font)
+    );
+    std::fs::write(&cidfmap, entry).map_err(|e| CommandError::Io {
+        action: "write_font_map",
+        error: e.to_string(),
+    })?;
+    Ok(vec![
+        format!("-sCIDFMAP={}", cidfmap.to_string_lossy()),
+        format!("--permit-file-read={}", cidfmap.to_string_lossy()),
+        format!("--permit-file-read={}", font),
+    ])
+}
+
+/// A PostScript dictionary with `pages` as keys, or `null` for every page.
+fn ps_page_set(pages: Option<&[u32]>) -> String {
+    match pages {
+        Some(pages) => {
+            let entries: Vec<String> = pages.iter().map(|page| format!("{} true", page)).collect();
+            format!("<< {} >>", entries.join(" "))
+        }
+        None => String::from("null"),
+    }
+}
+
+/// PostScript that installs an `/EndPage` procedure drawing the watermark over `pages`
+/// (every page when `None`) once their content is complete. Its state lives in a private
+/// dictionary because the PDF interpreter's dictionaries are on the stack at `/EndPage`.
+fn watermark_program(
+    text: &str,
+    rgb: [f64; 3],
+    pages: Option<&[u32]>,
+    options: &WatermarkOptions,
+) -> String {
+    let pages = ps_page_set(pages);
+    let size = options.font_size;
+    let placement = match options.position {
+        WatermarkPosition::Center => String::from(
+            "wm_w 2 div wm_h 2 div translate wm_angle rotate\n\
+             wm_tw 2 div neg wm_size -0.35 mul moveto wm_text show\n",
+        ),
+        WatermarkPosition::Tile => String::from(
+            "wm_w 2 div wm_h 2 div translate wm_angle rotate\n\
+             /wm_d wm_w dup mul wm_h dup mul add sqrt 2 div def\n\
+             wm_d neg wm_size 4 mul wm_d {\n\
+             /wm_y exch def\n\
+             wm_d neg wm_tw wm_size 2 mul add wm_d { wm_y moveto wm_text show } for\n\
+             } for\n",
+        ),
+        corner => {
+            let (x, y, dx, dy) = match corner {
+                WatermarkPosition::TopLeft => ("wm_m", "wm_h wm_m sub", "0", "wm_size neg"),
+                WatermarkPosition::TopRight => {
+                    ("wm_w wm_m sub", "wm_h wm_m sub", "wm_tw neg", "wm_size neg")
+                }
+                WatermarkPosition::BottomRight => ("wm_w wm_m sub", "wm_m", "wm_tw neg", "0"),
+                _ => ("wm_m", "wm_m", "0", "0"),
+            };
+            format!(
+                "{} {} translate wm_angle rotate {} {} moveto wm_text show\n",
+                x, y, dx, dy
+            )
+        }
+    };
+    format!(
+        "%!\n\
+         {prologue}\
+         /wm_dict 32 dict def\n\
+         wm_dict begin\n\
+         /wm_text {text} def\n\
+         /wm_pages {pages} def\n\
+         /wm_size {size} def\n\
+         /wm_m {margin} def\n\
+         /wm_angle {angle} def\n\
+         /wm_wanted {{ wm_pages null eq {{ pop true }} {{ wm_pages exch known }} ifelse }} bind def\n\
+         /wm_setalpha {{\n\
+         /.setfillconstantalpha where {{ pop .setfillconstantalpha }}\n\
+         {{ /.setopacityalpha where {{ pop .setopacityalpha }} {{ pop }} ifelse }} ifelse\n\
+         }} bind def\n\
+         /wm_draw {{\n\
+         gsave\n\
+         /PdfResizerOverlay-Identity-H findfont wm_size scalefont setfont\n\
+         {r} {g} {b} setrgbcolor {opacity} wm_setalpha\n\
+         currentpagedevice /PageSize get aload pop /wm_h exch def /wm_w exch def\n\
+         /wm_tw wm_text stringwidth pop def\n\
+         {placement}\
+         grestore\n\
+         }} bind def\n\
+         end\n\
+         << /EndPage {{\n\
+         //wm_dict begin\n\
+         exch 1 add exch 2 ne dup {{ exch wm_wanted {{ wm_draw }} if }} {{ exch pop }} ifelse\n\
+         end\n\
+         }} bind >> setpagedevice\n",
+        prologue = OVERLAY_FONT_PROLOGUE,
+        text = text,
+        pages = pages,
+        size = size,
+        margin = size / 2.0,
+        angle = options.rotation,
+        r = rgb[0],
+        g = rgb[1],
+        b = rgb[2],
+        opacity = options.opacity,
+        placement = placement,
+    )
+}
+
+fn watermark_with_ghostscript(
+    input: &Path,
+    output: &Path,
+    text: &str,
+    options: &WatermarkOptions,
+    runtime: &GhostscriptRuntime,
+    ctx: JobContext<'_>,
+) -> Result<WatermarkResult, CommandError> {
+    if !input.is_file() {
+        return Err(CommandError::NotFound {
+            path: input.to_string_lossy().to_string(),
+        });
+    }
+    ensure_distinct_paths(input, output)?;
+    if text.trim().is_empty() {
+        return Err(CommandError::MissingInput { field: "text" });
+    }
+    if options.font_size.is_nan() || options.font_size <= 0.0 {
+        return Err(CommandError::OutOfRange {
+            field: "font_size",
+            value: options.font_size.to_string(),
+            expected: String::from("greater than zero"),
+        });
+    }
+    if !(0.0..=1.0).contains(&options.opacity) {
+        return Err(CommandError::OutOfRange {
+            field: "opacity",
+            value: options.opacity.to_string(),
+            expected: String::from("between 0 and 1"),
+        });
+    }
+    let rgb = parse_hex_color(&options.color)?;
+    let (font_path, cmap) = overlay_font(text, options.font_path.as_deref())?;
+
+    let page_count = document_page_count(input, runtime, ctx.hooks())?;
+    let pages = resolve_page_selection(options.pages.clone(), page_count)?;
+    let selection = options.pages.is_some().then_some(pages.as_slice());
+
+    let mut temp_files = TempFileGuard::default();
+    let program = temp_files.track(unique_temp_path("watermark", "ps"));
+    std::fs::write(
+        &program,
+        watermark_program(&glyph_string(&cmap, text), rgb, selection, options),
+    )
+    .map_err(|e| CommandError::Io {
+        action: "write_watermark_program",
+        error: e.to_string(),
+    })?;
+
+    let mut device_args = overlay_font_args(&mut temp_files, &font_path)?;
+    // Lets the PostScript alpha operators through to pdfwrite (Ghostscript 9.52+).
+    device_args.push(String::from("-dALLOWPSTRANSPARENCY"));
+    run_pdfwrite(
+        &[&program, input],
+        output,
+        &device_args,
+        Some(page_count),
+        runtime,
+        ctx,
+    )?;
+
+    Ok(WatermarkResult {
+        output_path: output.to_string_lossy().to_string(),
+        font_path: font_path.to_string_lossy().to_string(),
+        pages_watermarked: pages.len() as u32,
+    })
+}
+
+impl Default for PageNumberOptions {
+    fn default() -> Self {
+        PageNumberOptions {
+            format: String::from("{n}"),
+            font_path: None,
+            font_size: 10.0,
+            color: String::from("#000000"),
+            position: PageNumberPosition::FooterCenter,
+            margin_pt: 24.0,
+            start_number: 1,
+            skip_first: 0,
+        }
+    }
+}
+
+/// The page's CropBox in the coordinates Ghostscript draws the page in: upright as
+/// displayed, with the MediaBox's lower-left corner at the origin.
+fn displayed_crop_rect(geometry: &PageGeometry) -> [f64; 4] {
+    let media = PageGeometry {
+        crop_box: None,
+        ..geometry.clone()
+    };
+    let (matrix, _) = media.upright_matrix();
+    transform_rect(matrix, geometry.visible_box())
+}
+
+/// PostScript that installs an `/EndPage` procedure drawing each page's label inside its
+/// displayed CropBox. `labels` maps 1-based pages to a glyph string and that box.
+fn page_number_program(
+    labels: &[(u32, String, [f64; 4])],
+    rgb: [f64; 3],
+    options: &PageNumberOptions,
+) -> String {
+    let entries: Vec<String> = labels
+        .iter()
+        .map(|(page, text, rect)| {
+            format!(
+                "{} [{} {:.4} {:.4} {:.4} {:.4}]",
+                page, text, rect[0], rect[1], rect[2], rect[3]
+            )
+        })
+        .collect();
+    let x = match options.position {
+        PageNumberPosition::HeaderLeft | PageNumberPosition::FooterLeft => "pn_x0 pn_m add",
+        PageNumberPosition::HeaderCenter | PageNumberPosition::FooterCenter => {
+            "pn_x0 pn_x1 add 2 div pn_tw 2 div sub"
+        }
+        PageNumberPosition::HeaderRight | PageNumberPosition::FooterRight => {
+            "pn_x1 pn_m sub pn_tw sub"
+        }
+    };
+    // Header baselines sit a cap height below the margin.
+    let y = match options.position {
+        PageNumberPosition::HeaderLeft
+        | PageNumberPosition::HeaderCenter
+        | PageNumberPosition::HeaderRight => "pn_y1 pn_m sub pn_size 0.7 mul sub",
+        _ => "pn_y0 pn_m add",
+    };
+    format!(
+        "%!\n\
+         {prologue}\
+         /pn_dict 32 dict def\n\
+         pn_dict begin\n\
+         /pn_labels << {entries} >> def\n\
+         /pn_size {size} def\n\
+         /pn_m {margin} def\n\
+         /pn_draw {{\n\
+         aload pop /pn_y1 exch def /pn_x1 exch def /pn_y0 exch def /pn_x0 exch def\n\
+         /pn_text exch def\n\
+         gsave\n\
+         /PdfResizerOverlay-Identity-H findfont pn_size scalefont setfont\n\
+         {r} {g} {b} setrgbcolor\n\
+         /pn_tw pn_text stringwidth pop def\n\
+         {x} {y} moveto pn_text show\n\
+         grestore\n\
+         }} bind def\n\
+         end\n\
+         << /EndPage {{\n\
+         //pn_dict begin\n\
+         exch 1 add exch 2 ne dup {{\n\
+         exch pn_labels exch 2 copy known {{ get pn_draw }} {{ pop pop }} ifelse\n\
+         }} {{ exch pop }} ifelse\n\
+         end\n\
+         }} bind >> setpagedevice\n",
+        prologue = OVERLAY_FONT_PROLOGUE,
+        entries = entries.join(" "),
+        size = options.font_size,
+        margin = options.margin_pt,
+        r = rgb[0],
+        g = rgb[1],
+        b = rgb[2],
+        x = x,
+        y = y,
+    )
+}
+
+fn page_numbers_with_ghostscript(
+    input: &Path,
+    output: &Path,
+    options: &PageNumberOptions,
+    runtime: &GhostscriptRuntime,
+    ctx: JobContext<'_>,
+) -> Result<PageNumberResult, CommandError> {
+    if !input.is_file() {
+        return Err(CommandError::NotFound {
+            path: input.to_string_lossy().to_string(),
+        });
+    }
+    ensure_distinct_paths(input, output)?;
+    if options.font_size.is_nan() || options.font_size <= 0.0 {
+        return Err(CommandError::OutOfRange {
+            field: "font_size",
+            value: options.font_size.to_string(),
+            expected: String::from("greater than zero"),
+        });
+    }
+    if !options.margin_pt.is_finite() || options.margin_pt < 0.0 {
+        return Err(CommandError::OutOfRange {
+            field: "margin",
+            value: options.margin_pt.to_string(),
+            expected: String::from("zero or positive"),
+        });
+    }
+    let rgb = parse_hex_color(&options.color)?;
+
+    // Page boxes are needed to keep the numbers inside the CropBox.
+    let geometry = read_page_geometry(&load_pdf_document(input)?);
+    let numbered = geometry.len().saturating_sub(options.skip_first as usize);
+    if numbered == 0 {
+        return Err(CommandError::AllPagesSkipped {
+            skipped: options.skip_first,
+            page_count: geometry.len() as u32,
+        });
+    }
+    let total = options.start_number as u64 + numbered as u64 - 1;
+    let texts: Vec<(u32, String)> = geometry
+        .iter()
+        .skip(options.skip_first as usize)
+        .zip(options.start_number as u64..)
+        .map(|(page, number)| {
+            let text = options
+                .format
+                .replace("{n}", &number.to_string())
+                .replace("{total}", &total.to_string());
+            (page.page, text)
+        })
+        .collect();
+    let sample: String = texts.iter().map(|(_, text)| text.as_str()).collect();
+    let (font_path, cmap) = overlay_font(&sample, options.font_path.as_deref())?;
+    let labels: Vec<(u32, String, [f64; 4])> = texts
+        .iter()
+        .map(|(page, text)| {
+            (
+                *page,
+                glyph_string(&cmap, text),
+                displayed_crop_rect(&geometry[*page as usize - 1]),
+            )
+        })
+        .collect();
+
+    let mut temp_files = TempFileGuard::default();
+    let program = temp_files.track(unique_temp_path("page_numbers", "ps"));
+    std::fs::write(&program, page_number_program(&labels, rgb, options)).map_err(|e| {
+        CommandError::Io {
+            action: "write_page_number_program",
+            error: e.to_string(),
+        }
+    })?;
+    let mut device_args = overlay_font_args(&mut temp_files, &font_path)?;
+    // Keep pdfwrite from turning pages to follow the new text's orientation.
+    device_args.push(String::from("-dAutoRotatePages=/None"));
+    run_pdfwrite(
+        &[&program, input],
+        output,
+        &device_args,
+        Some(geometry.len() as u32),
+        runtime,
+        ctx,
+    )?;
+
+    Ok(PageNumberResult {
+        output_path: output.to_string_lossy().to_string(),
+        font_path: font_path.to_string_lossy().to_string(),
+        pages_numbered: labels.len() as u32,
+    })
+}
+
+impl Default for StampPlacement {
+    fn default() -> Self {
+        StampPlacement {
+            layer: StampLayer::Over,
+            scale: StampScale::Actual,
+            width_pt: None,
+            anchor: StampAnchor::Center,
+            offset_x_pt: 0.0,
+            offset_y_pt: 0.0,
+            pages: None,
+            stamp_page: 1,
+        }
+    }
+}
+
+/// The inverse transform, or `None` for a degenerate matrix.
+fn invert_matrix(matrix: PdfMatrix) -> Option<PdfMatrix> {
+    let [a, b, c, d, e, f] = matrix;
+    let determinant = a * d - b * c;
+    if determinant.abs() < f64::EPSILON {
+        return None;
+    }
+    Some([
+        d / determinant,
+        -b / determinant,
+        -c / determinant,
+        a / determinant,
+        (c * f - d * e) / determinant,
+        (b * e - a * f) / determinant,
+    ])
+}
+
+/// Deep-copy `object` from `source` into `target`, adding every object it references.
+/// `imported` maps source IDs to their copies, so shared objects are copied once.
+fn import_object(
+    target: &mut lopdf::Document,
+    source: &lopdf::Document,
+    object: &lopdf::Object,
+    imported: &mut HashMap<lopdf::ObjectId, lopdf::ObjectId>,
+) -> lopdf::Object {
+    match object {
+        lopdf::Object::Reference(id) => {
+            if let Some(copy_id) = imported.get(id) {
+                return lopdf::Object::Reference(*copy_id);
+            }
+            let copy_id = target.new_object_id();
+            imported.insert(*id, copy_id);
+            let copy = match source.get_object(*id) {
+                Ok(object) => import_object(target, source, object, imported),
+                Err(_) => lopdf::Object::Null,
+            };
+            target.objects.insert(copy_id, copy);
+            lopdf::Object::Reference(copy_id)
+        }
+        lopdf::Object::Array(items) => lopdf::Object::Array(
+            items
+                .iter()
+                .map(|item| import_object(target, source, item, imported))
+                .collect(),
+        ),
+        lopdf::Object::Dictionary(dict) => {
+            lopdf::Object::Dictionary(import_dictionary(target, source, dict, imported))
+        }
+        lopdf::Object::Stream(stream) => {
+            let mut copy = stream.clone();
+            copy.dict = import_dictionary(target, source, &stream.dict, imported);
+            lopdf::Object::Stream(copy)
+        }
+        other => other.clone(),
+    }
+}
+
+fn import_dictionary(
+    target: &mut lopdf::Document,
+    source: &lopdf::Document,
+    dict: &lopdf::Dictionary,
+    imported: &mut HashMap<lopdf::ObjectId, lopdf::ObjectId>,
+) -> lopdf::Dictionary {
+    let mut copy = lopdf::Dictionary::new();
+    for (key, value) in dict.iter() {
+        copy.set(key.clone(), import_object(target, source, value, imported));
+    }
+    copy
+}
+
+/// Give the page its own copy of its (possibly inherited or shared) resources and
+/// register `xobject` in them under an unused `/{prefix}N` name, which is returned.
+fn add_page_xobject(
+    doc: &mut lopdf::Document,
+    page_id: lopdf::ObjectId,
+    prefix: &str,
+    xobject: lopdf::ObjectId,
+) -> Result<String, CommandError> {
+    let mut resources = inherited_page_attribute(doc, page_id, b"Resources")
+        .and_then(|object| pdf_dict(doc, object))
+        .cloned()
+        .unwrap_or_default();
+    let mut xobjects = resources
+        .get(b"XObject")
+        .ok()
+        .and_then(|object| pdf_dict(doc, object))
+        .cloned()
+        .unwrap_or_default();
+    let mut index = 0;
+    let name = loop {
+        let name = format!("{}{}", prefix, index);
+        if !xobjects.has(name.as_bytes()) {
+            break name;
+        }
+        index += 1;
+    };
+    xobjects.set(name.clone(), xobject);
+    resources.set("XObject", xobjects);
+    doc.get_dictionary_mut(page_id)
+        .map_err(|e| CommandError::Io {
+            action: "read_page",
+            error: e.to_string(),
+        })?
+        .set("Resources", resources);
+    Ok(name)
+}
+
+/// Surround the page's content streams with `before` and `after`; empty parts are skipped.
+fn wrap_page_content(
+    doc: &mut lopdf::Document,
+    page_id: lopdf::ObjectId,
+    before: &str,
+    after: &str,
+) -> Result<(), CommandError> {
+    let mut contents: Vec<lopdf::Object> = doc
+        .get_page_contents(page_id)
+        .into_iter()
+        .map(lopdf::Object::Reference)
+        .collect();
+    if !before.is_empty() {
+        let stream = lopdf::Stream::new(lopdf::Dictionary::new(), before.as_bytes().to_vec());
+        contents.insert(0, lopdf::Object::Reference(doc.add_object(stream)));
+    }
+    if !after.is_empty() {
+        let stream = lopdf::Stream::new(lopdf::Dictionary::new(), after.as_bytes().to_vec());
+        contents.push(lopdf::Object::Reference(doc.add_object(stream)));
+    }
+    doc.get_dictionary_mut(page_id)
+        .map_err(|e| CommandError::Io {
+            action: "read_page",
+            error: e.to_string(),
+        })?
+        .set("Contents", contents);
+    Ok(())
+}
+
+fn read_png(path: &Path) -> Result<PngImage, CommandError> {
+    let bytes = std::fs::read(path).map_err(|e| CommandError::Io {
+        action: "read",
+        error: format!("'{}': {}", path.display(), e),
+    })?;
+    let invalid = || CommandError::InvalidImage {
+        path: path.to_string_lossy().to_string(),
+        reason: "not_png",
+    };
+    if !bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return Err(invalid());
+    }
+    let mut image = PngImage {
+        path: path.to_string_lossy().to_string(),
+        width: 0,
+        height: 0,
+        bit_depth: 0,
+        color_type: 0,
+        palette: Vec::new(),
+        transparency: None,
+        data: Vec::new(),
+        dpi: None,
+    };
+    let mut offset = 8;
+    while let Some(length) = be_u32(&bytes, offset) {
+        let kind = bytes.get(offset + 4..offset + 8).ok_or_else(invalid)?;
+        let chunk = bytes
+            .get(offset + 8..offset + 8 + length as usize)
+            .ok_or_else(invalid)?;
+        match kind {
+            b"IHDR" => {
+                image.width = be_u32(chunk, 0).ok_or_else(invalid)?;
+                image.height = be_u32(chunk, 4).ok_or_else(invalid)?;
+                image.bit_depth = *chunk.get(8).ok_or_else(invalid)?;
+                image.color_type = *chunk.get(9).ok_or_else(invalid)?;
+                if chunk.get(12) != Some(&0) {
+                    return Err(CommandError::InvalidImage {
+                        path: image.path,
+                        reason: "interlaced",
+                    });
+                }
+            }
+            b"PLTE" => image.palette = chunk.to_vec(),
+            b"tRNS" => image.transparency = Some(chunk.to_vec()),
+            b"pHYs" if chunk.get(8) == Some(&1) => {
+                image.dpi = be_u32(chunk, 0)
+                    .filter(|per_meter| *per_meter > 0)
+                    .map(|per_meter| f64::from(per_meter) * 0.0254);
+            }
+            b"IDAT" => image.data.extend_from_slice(chunk),
+            b"IEND" => break,
+            _ => {}
+        }
+        offset += 12 + length as usize;
+    }
+    if image.width == 0 || image.height == 0 || image.data.is_empty() {
+        return Err(invalid());
+    }
+    Ok(image)
+}
+
+/// Undo PNG scanline filters, returning `height` rows of `row_bytes` without filter bytes.
+fn unfilter_png_rows(
+    png: &PngImage,
+    data: &[u8],
+    row_bytes: usize,
+    bytes_per_pixel: usize,
+    height: usize,
+) -> Result<Vec<u8>, CommandError> {
+    let mut rows = vec![0u8; row_bytes * height];
+    for row in 0..height {
+        let line = data
+            .get(row * (row_bytes + 1)..(row + 1) * (row_bytes + 1))
+            .ok_or_else(|| CommandError::InvalidImage {
+                path: png.path.clone(),
+                reason: "truncated",
+            })?;
+        let (done, rest) = rows.split_at_mut(row * row_bytes);
+        let previous = row.checked_sub(1).map(|_| &done[done.len() - row_bytes..]);
+        let current = &mut rest[..row_bytes];
+        for i in 0..row_bytes {
+            let left = if i >= bytes_per_pixel {
+                current[i - bytes_per_pixel]
+            } else {
+                0
+            };
+            let up = previous.map_or(0, |previous| previous[i]);
+            let up_left = match previous {
+                Some(previous) if i >= bytes_per_pixel => previous[i - bytes_per_pixel],
+                _ => 0,
+            };
+            let predictor = match line[0] {
+                0 => 0,
+                1 => left,
+                2 => up,
+                3 => ((u16::from(left) + u16::from(up)) / 2) as u8,
+                4 => {
+                    let estimate = i16::from(left) + i16::from(up) - i16::from(up_left);
+                    let to_left = (estimate - i16::from(left)).abs();
+                    let to_up = (estimate - i16::from(up)).abs();
+                    let to_up_left = (estimate - i16::from(up_left)).abs();
+                    if to_left <= to_up && to_left <= to_up_left {
+                        left
+                    } else if to_up <= to_up_left {
+                        up
+                    } else {
+                        up_left
+                    }
+                }
+                _ => {
+                    return Err(CommandError::InvalidImage {
+                        path: png.path.clone(),
+                        reason: "unknown_filter",
+                    })
+                }
+            };
+            current[i] = line[1 + i].wrapping_add(predictor);
+        }
+    }
+    Ok(rows)
+}
+
+/// Add `png` to `doc` as an image XObject. Images without alpha keep their compressed
+/// data; alpha channels and palette transparency become a soft mask.
+fn png_image_xobject(
+    doc: &mut lopdf::Document,
+    png: &PngImage,
+) -> Result<lopdf::ObjectId, CommandError> {
+    let channels: u32 = match png.color_type {
+        0 | 3 => 1,
+        2 => 3,
+        4 => 2,
+        6 => 4,
+        _ => {
+            return Err(CommandError::InvalidImage {
+                path: png.path.clone(),
+                reason: "unsupported_color_type",
+            })
+        }
+    };
+    let bits = u32::from(png.bit_depth);
+    let mut dict = lopdf::Dictionary::new();
+    dict.set("Type", "XObject");
+    dict.set("Subtype", "Image");
+    dict.set("Width", i64::from(png.width));
+    dict.set("Height", i64::from(png.height));
+    dict.set("BitsPerComponent", i64::from(png.bit_depth));
+    match png.color_type {
+        0 | 4 => dict.set("ColorSpace", "DeviceGray"),
+        2 | 6 => dict.set("ColorSpace", "DeviceRGB"),
+        _ => dict.set(
+            "ColorSpace",
+            vec![
+                lopdf::Object::from("Indexed"),
+                lopdf::Object::from("DeviceRGB"),
+                lopdf::Object::from((png.palette.len() / 3).saturating_sub(1) as i64),
+                lopdf::Object::String(png.palette.clone(), lopdf::StringFormat::Hexadecimal),
+            ],
+        ),
+    }
+
+    let has_alpha = matches!(png.color_type, 4 | 6);
+    let palette_alpha = png.color_type == 3 && png.transparency.is_some();
+    if !has_alpha && !palette_alpha {
+        // PDF's PNG predictor reads the IDAT stream as is.
+        let mut parms = lopdf::Dictionary::new();
+        parms.set("Predictor", 15);
+        parms.set("Colors", i64::from(channels));
+        parms.set("BitsPerComponent", i64::from(png.bit_depth));
+        parms.set("Columns", i64::from(png.width));
+        dict.set("Filter", "FlateDecode");
+        dict.set("DecodeParms", parms);
+        if let Some(key) = &png.transparency {
+            // Gray or RGB color-key transparency as 16-bit sample values.
+            let mask: Vec<lopdf::Object> = key
+                .chunks(2)
+                .take(channels as usize)
+                .flat_map(|sample| {
+                    let value = i64::from(u16::from_be_bytes([
+                        sample[0],
+                        *sample.get(1).unwrap_or(&0),
+                    ]));
+                    [lopdf::Object::from(value), lopdf::Object::from(value)]
+                })
+                .collect();
+            dict.set("Mask", mask);
+        }
+        return Ok(doc.add_object(lopdf::Stream::new(dict, png.data.clone())));
+    }
+
+    let mut compressed = lopdf::Dictionary::new();
+    compressed.set("Filter", "FlateDecode");
+    let inflated = lopdf::Stream::new(compressed, png.data.clone())
+        .decompressed_content()
+        .map_err(|e| CommandError::Io {
+            action: "decompress_png_image",
+            error: e.to_string(),
+        })?;
+    let width = png.width as usize;
+    let height = png.height as usize;
+    let bits_per_pixel = (channels * bits) as usize;
+    let row_bytes = (width * bits_per_pixel + 7) / 8;
+    let rows = unfilter_png_rows(
+        png,
+        &inflated,
+        row_bytes,
+        (bits_per_pixel / 8).max(1),
+        height,
+    )?;
+
+    let (color, alpha, alpha_bits) = if palette_alpha {
+        let alphas = png.transparency.as_deref().unwrap_or_default();
+        let bit_depth = bits as usize;
+        let mut alpha = Vec::with_capacity(width * height);
+        for row in rows.chunks(row_bytes) {
+            for x in 0..width {
+                let bit = x * bit_depth;
+                let index =
+                    (row[bit / 8] >> (8 - bit_depth - bit % 8)) & ((1u16 << bit_depth) - 1) as u8;
+                alpha.push(alphas.get(index as usize).copied().unwrap_or(255));
+            }
+        }
+        (rows, alpha, 8)
+    } else {
+        let sample_bytes = (bits / 8) as usize;
+        let pixel_bytes = channels as usize * sample_bytes;
+        let color_bytes = pixel_bytes - sample_bytes;
+        let mut color = Vec::with_capacity(width * height * color_bytes);
+        let mut alpha = Vec::with_capacity(width * height * sample_bytes);
+        for pixel in rows.chunks(pixel_bytes) {
+            color.extend_from_slice(&pixel[..color_bytes]);
+            alpha.extend_from_slice(&pixel[color_bytes..]);
+        }
+        (color, alpha, bits)
+    };
+
+    let mut mask_dict = lopdf::Dictionary::new();
+    mask_dict.set("Type", "XObject");
+    mask_dict.set("Subtype", "Image");
+    mask_dict.set("Width", i64::from(png.width));
+    mask_dict.set("Height", i64::from(png.height));
+    mask_dict.set("ColorSpace", "DeviceGray");
+    mask_dict.set("BitsPerComponent", i64::from(alpha_bits));
+    let mut mask = lopdf::Stream::new(mask_dict, alpha);
+    let mut image = lopdf::Stream::new(dict, color);
+    for stream in [&mut mask, &mut image] {
+        stream.compress().map_err(|e| CommandError::Io {
+            action: "compress_stamp_image",
+            error: e.to_string(),
+        })?;
+    }
+    let mask_id = doc.add_object(mask);
+    image.dict.set("SMask", mask_id);
+    Ok(doc.add_object(image))
+}
+
+/// A ZIP archive of `entries`, deflated and stamped with the current UTC time.
+fn zip_archive(entries: &[(&str, &[u8])]) -> zip::result::ZipResult<Vec<u8>> {
+    use std::io::Write;
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or(0);
+    let (year, month, day) = civil_from_days(now.div_euclid(86_400));
+    let seconds = now.rem_euclid(86_400);
+    let modified = zip::DateTime::from_date_and_time(
+        year.clamp(1980, 2107) as u16,
+        month as u8,
+        day as u8,
+        (seconds / 3600) as u8,
+        (seconds / 60 % 60) as u8,
+        (seconds % 60) as u8,
+    )
+    .unwrap_or_default();
+    let options = zip::write::FileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated)
+        .last_modified_time(modified);
+
+    let mut archive = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    for (name, contents) in entries {
+        archive.start_file(*name, options)?;
+        archive.write_all(contents)?;
+    }
+    Ok(archive.finish()?.into_inner())
+}
+
+/// zlib-compress `data` with lopdf's encoder, or as stored deflate blocks when lopdf
+/// declines because compression would not make it smaller.
+fn zlib_compress(data: Vec<u8>) -> Result<Vec<u8>, CommandError> {
+    let mut stream = lopdf::Stream::new(lopdf::Dictionary::new(), data);
+    stream.compress().map_err(|e| CommandError::Io {
+        action: "compress_image_data",
+        error: e.to_string(),
+    })?;
+    if stream.dict.has(b"Filter") {
+        return Ok(stream.content);
+    }
+    let data = stream.content;
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in &data {
+        a = (a + u32::from(*byte)) % 65521;
+        b = (b + a) % 65521;
+    }
+    let mut output = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xFFFF).peekable();
+    if blocks.peek().is_none() {
+        output.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let length = block.len() as u16;
+        output.push(u8::from(blocks.peek().is_none()));
+        output.extend_from_slice(&length.to_le_bytes());
+        output.extend_from_slice(&(!length).to_le_bytes());
+        output.extend_from_slice(block);
+    }
+    output.extend_from_slice(&((b << 16) | a).to_be_bytes());
+    Ok(output)
+}
+
+/// A PNG file from unfiltered `rows` of samples: PNG color type 0 (gray), 2 (RGB),
+/// 3 (`palette` of RGB triples), 4 (gray and alpha) or 6 (RGBA).
+fn encode_png(
+    width: u32,
+    height: u32,
+    bit_depth: u8,
+    color_type: u8,
+    palette: &[u8],
+    rows: &[u8],
+) -> Result<Vec<u8>, CommandError> {
+    let row_bytes = rows.len() / height.max(1) as usize;
+    let mut filtered = Vec::with_capacity(rows.len() + height as usize);
+    for row in rows.chunks(row_bytes.max(1)) {
+        filtered.push(0);
+        filtered.extend_from_slice(row);
+    }
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    header.extend_from_slice(&[bit_depth, color_type, 0, 0, 0]);
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    let mut chunk = |kind: &[u8], data: &[u8]| {
+        png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        let start = png.len();
+        png.extend_from_slice(kind);
+        png.extend_from_slice(data);
+        let crc = crc32fast::hash(&png[start..]);
+        png.extend_from_slice(&crc.to_be_bytes());
+    };
+    chunk(b"IHDR", &header);
+    if color_type == 3 {
+        chunk(b"PLTE", palette);
+    }
+    chunk(b"IDAT", &zlib_compress(filtered)?);
+    chunk(b"IEND", &[]);
+    Ok(png)
+}
+
+/// Components per pixel of an image color space a PNG can hold: 1 for gray, 3 for RGB, or
+/// an RGB palette for indexed spaces over either. CMYK, spot and Lab spaces give `None`.
+fn png_color_model(doc: &lopdf::Document, space: &lopdf::Object) -> Option<(u8, Vec<u8>)> {
+    let (_, space) = doc.dereference(space).ok()?;
+    let items = match space {
+        lopdf::Object::Name(name) => {
+            return match name.as_slice() {
+                b"DeviceGray" | b"G" => Some((1, Vec::new())),
+                b"DeviceRGB" | b"RGB" => Some((3, Vec::new())),
+                _ => None,
+            }
+        }
+        lopdf::Object::Array(items) => items,
+        _ => return None,
+    };
+    match items.first()?.as_name().ok()? {
+        b"CalGray" => Some((1, Vec::new())),
+        b"CalRGB" => Some((3, Vec::new())),
+        b"ICCBased" => match pdf_dict(doc, items.get(1)?)?
+            .get(b"N")
+            .ok()?
+            .as_i64()
+            .ok()?
+        {
+            1 => Some((1, Vec::new())),
+            3 => Some((3, Vec::new())),
+            _ => None,
+        },
+        b"Indexed" | b"I" => {
+            let (base, _) = png_color_model(doc, items.get(1)?)?;
+            let (_, lookup) = doc.dereference(items.get(3)?).ok()?;
+            let lookup = match lookup {
+                lopdf::Object::String(bytes, _) => bytes.clone(),
+                lopdf::Object::Stream(stream) => stream_content(stream).ok()?,
+                _ => return None,
+            };
+            let palette = match base {
+                1 => lookup.iter().flat_map(|gray| [*gray; 3]).collect(),
+                _ => lookup,
+            };
+            Some((0, palette))
+        }
+        _ => None,
+    }
+}
+
+/// The image XObject as a file: `(extension, bytes, soft_mask_dropped)`. JPEG and JPEG 2000
+/// data is written as stored; other images are decoded and written as PNG.
+fn encode_extracted_image(
+    doc: &lopdf::Document,
+    stream: &lopdf::Stream,
+) -> Result<(&'static str, Vec<u8>, bool), ImageSkip> {
+    let dict = &stream.dict;
+    if dict
+        .get(b"ImageMask")
+        .and_then(lopdf::Object::as_bool)
+        .unwrap_or(false)
+    {
+        return Err(ImageSkip::StencilMask);
+    }
+    let filters: Vec<&[u8]> = match dict.get(b"Filter") {
+        Ok(lopdf::Object::Name(name)) => vec![name.as_slice()],
+        Ok(lopdf::Object::Array(names)) => names
+            .iter()
+            .filter_map(|name| name.as_name().ok())
+            .collect(),
+        _ => Vec::new(),
+    };
+    let soft_mask = dict
+        .get(b"SMask")
+        .ok()
+        .and_then(|mask| doc.dereference(mask).ok())
+        .and_then(|(_, mask)| mask.as_stream().ok());
+    let decode = dict.get(b"Decode").is_ok();
+    match filters.as_slice() {
+        [b"DCTDecode"] if decode => return Err(ImageSkip::DecodedJpeg),
+        [b"DCTDecode"] => return Ok(("jpg", stream.content.clone(), soft_mask.is_some())),
+        [b"JPXDecode"] => return Ok(("jp2", stream.content.clone(), soft_mask.is_some())),
+        _ => {}
+    }
+    if decode {
+        return Err(ImageSkip::CustomDecode);
+    }
+    let (components, palette) = dict
+        .get(b"ColorSpace")
+        .ok()
+        .and_then(|space| png_color_model(doc, space))
+        .ok_or(ImageSkip::UnsupportedColorSpace)?;
+    let bits = dict
+        .get(b"BitsPerComponent")
+        .and_then(lopdf::Object::as_i64)
+        .unwrap_or(8);
+    let supported = match components {
+        0 => matches!(bits, 1 | 2 | 4 | 8),
+        1 => matches!(bits, 1 | 2 | 4 | 8 | 16),
+        _ => matches!(bits, 8 | 16),
+    };
+    if !supported {
+        return Err(ImageSkip::UnsupportedBitDepth { bits });
+    }
+    let samples = stream_content(stream).map_err(|_| ImageSkip::UnsupportedFilter {
+        filters: filters
+            .iter()
+            .map(|name| String::from_utf8_lossy(name).to_string())
+            .collect(),
+    })?;
+    let width = dict
+        .get(b"Width")
+        .and_then(lopdf::Object::as_i64)
+        .unwrap_or(0) as usize;
+    let height = dict
+        .get(b"Height")
+        .and_then(lopdf::Object::as_i64)
+        .unwrap_or(0) as usize;
+    let channels = usize::from(components.max(1));
+    let row_bytes = (width * channels * bits as usize + 7) / 8;
+    let samples = samples
+        .get(..row_bytes * height)
+        .ok_or(ImageSkip::Truncated)?;
+
+    // An 8-bit soft mask of the same size becomes the PNG's alpha channel.
+    let alpha = soft_mask
+        .filter(|_| components != 0 && bits == 8)
+        .filter(|mask| {
+            let mask = &mask.dict;
+            mask.get(b"Width").and_then(lopdf::Object::as_i64).ok() == Some(width as i64)
+                && mask.get(b"Height").and_then(lopdf::Object::as_i64).ok() == Some(height as i64)
+                && mask
+                    .get(b"BitsPerComponent")
+                    .and_then(lopdf::Object::as_i64)
+                    .ok()
+                    == Some(8)
+                && !mask.has(b"Decode")
+        })
+        .and_then(|mask| stream_content(mask).ok())
+        .filter(|alpha| alpha.len() >= width * height);
+    let soft_mask_dropped = soft_mask.is_some() && alpha.is_none();
+    let (color_type, rows) = match (components, alpha) {
+        (0, _) => (3, samples.to_vec()),
+        (components, Some(alpha)) => {
+            let mut rows = Vec::with_capacity(width * height * (channels + 1));
+            for (pixel, alpha) in samples.chunks(channels).zip(&alpha) {
+                rows.extend_from_slice(pixel);
+                rows.push(*alpha);
+            }
+            (if components == 1 { 4 } else { 6 }, rows)
+        }
+        (1, None) => (0, samples.to_vec()),
+        (_, None) => (2, samples.to_vec()),
+    };
+    let png = encode_png(
+        width as u32,
+        height as u32,
+        bits as u8,
+        color_type,
+        &palette,
+        &rows,
+    )
+    .map_err(|error| ImageSkip::EncodeFailed {
+        error: error.message(),
+    })?;
+    Ok(("png", png, soft_mask_dropped))
+}
+
+/// Write the raster images the pages draw (forms included) to `output_dir` as
+/// `{name}_p{page}_obj{object}.{jpg,jp2,png}`, skipping images under `min_pixels` pixels.
+fn extract_pdf_images(
+    input: &Path,
+    output_dir: &Path,
+    min_pixels: u32,
+    on_conflict: ConflictPolicy,
+) -> Result<Vec<ExtractedImage>, CommandError> {
+    let doc = load_pdf_document(input)?;
+    let name = input
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_else(|| String::from("document"));
+    std::fs::create_dir_all(output_dir).map_err(|e| CommandError::Io {
+        action: "create_output_directory",
+        error: format!("'{}': {}", output_dir.display(), e),
+    })?;
+    ensure_output_writable(output_dir, file_size(input)?)?;
+
+    let mut images = Vec::new();
+    let mut seen = HashSet::new();
+    for (page, page_id) in doc.get_pages() {
+        for scope in page_content_scopes(&doc, page_id) {
+            let Some(xobjects) = resource_category(&doc, scope.resources, b"XObject") else {
+                continue;
+            };
+            for (_, xobject) in xobjects.iter() {
+                let Ok(id) = xobject.as_reference() else {
+                    continue;
+                };
+                let Ok(stream) = doc.get_object(id).and_then(lopdf::Object::as_stream) else {
+                    continue;
+                };
+                let dict = &stream.dict;
+                if dict.get(b"Subtype").and_then(lopdf::Object::as_name).ok() != Some(&b"Image"[..])
+                    || !seen.insert(id)
+                {
+                    continue;
+                }
+                let dimension = |key: &[u8]| {
+                    dict.get(key)
+                        .and_then(lopdf::Object::as_i64)
+                        .ok()
+                        .and_then(|value| u32::try_from(value).ok())
+                        .unwrap_or(0)
+                };
+                let (width, height) = (dimension(b"Width"), dimension(b"Height"));
+                if u64::from(width) * u64::from(height) < u64::from(min_pixels) {
+                    continue;
+                }
+                let color_space = dict
+                    .get(b"ColorSpace")
+                    .ok()
+                    .and_then(|space| doc.dereference(space).ok())
+                    .and_then(|(_, space)| match space {
+                        lopdf::Object::Name(name) => Some(name.as_slice()),
+                        lopdf::Object::Array(items) => items.first()?.as_name().ok(),
+                        _ => None,
+                    })
+                    .map(|name| String::from_utf8_lossy(name).to_string())
+                    .unwrap_or_default();
+                let mut image = ExtractedImage {
+                    page,
+                    object: id.0,
+                    width,
+                    height,
+                    color_space,
+                    bits_per_component: dimension(b"BitsPerComponent"),
+                    path: None,
+                    skipped: None,
+                    soft_mask_dropped: false,
+                };
+                match encode_extracted_image(&doc, stream) {
+                    Ok((extension, bytes, soft_mask_dropped)) => {
+                        let name = output_dir
+                            .join(format!("{}_p{}_obj{}.{}", name, page, id.0, extension));
+                        let Some(path) = claim_output_path(&name, on_conflict, &[])? else {
+                            image.skipped = Some(ImageSkip::OutputExists {
+                                path: name.to_string_lossy().to_string(),
+                            });
+                            images.push(image);
+                            continue;
+                        };
+                        let mut temp_files = TempFileGuard::default();
+                        let temp = temp_files.track(sibling_temp_path(&path));
+                        std::fs::write(&temp, bytes).map_err(|e| CommandError::Io {
+                            action: "write",
+                            error: format!("'{}': {}", path.display(), e),
+                        })?;
+                        match commit_output(&temp, &name, &path, on_conflict)? {
+                            Some(path) => {
+                                image.path = Some(path.to_string_lossy().to_string());
+                                image.soft_mask_dropped = soft_mask_dropped;
+                            }
+                            None => {
+                                image.skipped = Some(ImageSkip::OutputExists {
+                                    path: name.to_string_lossy().to_string(),
+                                });
+                            }
+                        }
+                    }
+                    Err(skip) => image.skipped = Some(skip),
+                }
+                images.push(image);
+            }
+        }
+    }
+    Ok(images)
+}
+
+/// Place a stamp of `size` points inside a `page` of displayed size, per `placement`.
+/// Returns the uniform scale and the lower-left corner in displayed coordinates.
+fn stamp_position(
+    size: PageDimensions,
+    page: PageDimensions,
+    placement: &StampPlacement,
+) -> Result<(f64, f64, f64), CommandError> {
+    let scale = match placement.scale {
+        StampScale::Actual => 1.0,
+        StampScale::Fit => (page.width_pt / size.width_pt).min(page.height_pt / size.height_pt),
+        StampScale::Width => match placement.width_pt {
+            Some(width) if width > 0.0 => width / size.width_pt,
+            Some(width) => {
+                return Err(CommandError::OutOfRange {
+                    field: "width_pt",
+                    value: width.to_string(),
+                    expected: String::from("positive"),
+                })
+            }
+            None => return Err(CommandError::MissingInput { field: "width_pt" }),
+        },
+    };
+    let width = size.width_pt * scale;
+    let height = size.height_pt * scale;
+    let (dx, dy) = (placement.offset_x_pt, placement.offset_y_pt);
+    let (x, y) = match placement.anchor {
+        StampAnchor::Center => (
+            (page.width_pt - width) / 2.0 + dx,
+            (page.height_pt - height) / 2.0 + dy,
+        ),
+        StampAnchor::TopLeft => (dx, page.height_pt - height - dy),
+        StampAnchor::TopRight => (page.width_pt - width - dx, page.height_pt - height - dy),
+        StampAnchor::BottomLeft => (dx, dy),
+        StampAnchor::BottomRight => (page.width_pt - width - dx, dy),
+    };
+    Ok((scale, x, y))
+}
+
+/// The page's `/Annots` entries, usually references to the annotation dictionaries.
+fn page_annotations(doc: &lopdf::Document, page_id: lopdf::ObjectId) -> Vec<lopdf::Object> {
+    doc.get_dictionary(page_id)
+        .ok()
+        .and_then(|page| page.get(b"Annots").ok())
+        .and_then(|annots| doc.dereference(annots).ok())
+        .and_then(|(_, annots)| annots.as_array().ok())
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// Replace the page's `/Annots`, removing the key when none are left.
+fn set_page_annotations(
+    doc: &mut lopdf::Document,
+    page_id: lopdf::ObjectId,
+    annotations: Vec<lopdf::Object>,
+) {
+    if let Ok(page) = doc.get_dictionary_mut(page_id) {
+        if annotations.is_empty() {
+            page.remove(b"Annots");
+        } else {
+            page.set("Annots", annotations);
+        }
+    }
+}
+
+fn annotation_subtype(doc: &lopdf::Document, annotation: &lopdf::Object) -> Option<String> {
+    pdf_dict(doc, annotation)
+        .and_then(|dict| dict.get(b"Subtype").and_then(lopdf::Object::as_name).ok())
+        .map(|subtype| String::from_utf8_lossy(subtype).to_string())
+}
+
+#[derive(Clone, Copy)]
+enum UnsafeAction {
+    JavaScript,
+    Launch,
+    Uri,
+}
+
+fn unsafe_action(dict: &lopdf::Dictionary, keep_uri_links: bool) -> Option<UnsafeAction> {
+    match dict.get(b"S").and_then(lopdf::Object::as_name).ok()? {
+        b"JavaScript" => Some(UnsafeAction::JavaScript),
+        b"Launch" => Some(UnsafeAction::Launch),
+        b"URI" if !keep_uri_links => Some(UnsafeAction::Uri),
+        _ => None,
+    }
+}
+
+impl SanitizeReport {
+    fn count_action(&mut self, action: UnsafeAction) {
+        match action {
+            UnsafeAction::JavaScript => self.javascript += 1,
+            UnsafeAction::Launch => self.launch_actions += 1,
+            UnsafeAction::Uri => self.uri_actions += 1,
+        }
+    }
+}
+
+/// Remove `/AA` and unsafe `/A` and `/Next` actions from `object` and everything nested in
+/// it. `actions` classifies the indirect action objects, which are left for pruning.
+fn strip_unsafe_actions(
+    object: &mut lopdf::Object,
+    actions: &HashMap<lopdf::ObjectId, UnsafeAction>,
+    keep_uri_links: bool,
+    report: &mut SanitizeReport,
+) {
+    let classify = |object: &lopdf::Object| match object {
+        lopdf::Object::Reference(id) => actions.get(id).copied(),
+        lopdf::Object::Dictionary(dict) => unsafe_action(dict, keep_uri_links),
+        _ => None,
+    };
+    let dict = match object {
+        lopdf::Object::Dictionary(dict) => dict,
+        lopdf::Object::Stream(stream) => &mut stream.dict,
+        lopdf::Object::Array(items) => {
+            for item in items {
+                strip_unsafe_actions(item, actions, keep_uri_links, report);
+            }
+            return;
+        }
+        _ => return,
+    };
+    if dict.remove(b"AA").is_some() {
+        report.additional_actions += 1;
+    }
+    if let Some(action) = dict.get(b"A").ok().and_then(classify) {
+        dict.remove(b"A");
+        report.count_action(action);
+    }
+    // `/Next` is a single action or an array of them, run after this one.
+    if let Ok(next) = dict.get_mut(b"Next") {
+        if let lopdf::Object::Array(items) = next {
+            items.retain(|item| match classify(item) {
+                Some(action) => {
+                    report.count_action(action);
+                    false
+                }
+                None => true,
+            });
+        } else if let Some(action) = classify(next) {
+            dict.remove(b"Next");
+            report.count_action(action);
+        }
+    }
+    for (_, value) in dict.iter_mut() {
+        strip_unsafe_actions(value, actions, keep_uri_links, report);
+    }
+}
+
+/// The `(key, value)` leaves of a name tree, in key order, following `/Kids`.
+fn name_tree_entries<'a>(
+    doc: &'a lopdf::Document,
+    node: &'a lopdf::Object,
+    entries: &mut Vec<(&'a [u8], &'a lopdf::Object)>,
+    depth: u32,
+) {
+    let Some(node) = pdf_dict(doc, node).filter(|_| depth < 32) else {
+        return;
+    };
+    let array = |key: &[u8]| {
+        node.get(key)
+            .ok()
+            .and_then(|array| doc.dereference(array).ok())
+            .and_then(|(_, array)| array.as_array().ok())
+    };
+    for pair in array(b"Names")
+        .into_iter()
+        .flat_map(|names| names.chunks_exact(2))
+    {
+        if let Ok(key) = pair[0].as_str() {
+            entries.push((key, &pair[1]));
+        }
+    }
+    for kid in array(b"Kids").into_iter().flatten() {
+        name_tree_entries(doc, kid, entries, depth + 1);
+    }
+}
+
+/// Resolves outline and link destinations, explicit or named, to page numbers.
+struct DestinationResolver<'a> {
+    doc: &'a lopdf::Document,
+    pages: HashMap<lopdf::ObjectId, u32>,
+    /// Entries of the catalog's `/Names /Dests` tree.
+    named: Vec<(&'a [u8], &'a lopdf::Object)>,
+    /// The catalog's `/Dests` dictionary, used by PDF 1.1 files.
+    legacy: Option<&'a lopdf::Dictionary>,
+    page_count: u32,
+}
+
+impl<'a> DestinationResolver<'a> {
+    fn new(doc: &'a lopdf::Document) -> Self {
+        let page_ids = doc.get_pages();
+        let catalog = doc.catalog().ok();
+        let mut named = Vec::new();
+        if let Some(tree) = catalog
+            .and_then(|catalog| catalog.get(b"Names").ok())
+            .and_then(|names| pdf_dict(doc, names))
+            .and_then(|names| names.get(b"Dests").ok())
+        {
+            name_tree_entries(doc, tree, &mut named, 0);
+        }
+        DestinationResolver {
+            doc,
+            page_count: page_ids.len() as u32,
+            pages: page_ids.into_iter().map(|(page, id)| (id, page)).collect(),
+            named,
+            legacy: catalog
+                .and_then(|catalog| catalog.get(b"Dests").ok())
+                .and_then(|dests| pdf_dict(doc, dests)),
+        }
+    }
+
+    /// The page a `/Dest` value (explicit array, name or string) points at.
+    fn page(&self, destination: &lopdf::Object, depth: u32) -> Option<u32> {
+        let (_, destination) = self.doc.dereference(destination).ok()?;
+        match destination {
+            lopdf::Object::Array(items) => match items.first()? {
+                lopdf::Object::Reference(id) => self.pages.get(id).copied(),
+                // Some writers use 0-based page indices, as remote destinations do.
+                lopdf::Object::Integer(index) => u32::try_from(*index + 1)
+                    .ok()
+                    .filter(|page| *page <= self.page_count),
+                _ => None,
+            },
+            lopdf::Object::String(name, _) if depth < 8 => {
+                let (_, target) = self.named.iter().find(|(key, _)| key == name)?;
+                self.page(self.named_target(target)?, depth + 1)
+            }
+            lopdf::Object::Name(name) if depth < 8 => {
+                let target = self.legacy?.get(name).ok()?;
+                self.page(self.named_target(target)?, depth + 1)
+            }
+            _ => None,
+        }
+    }
+
+    /// Named destinations map to an array, or to a dictionary holding it as `/D`.
+    fn named_target(&self, target: &'a lopdf::Object) -> Option<&'a lopdf::Object> {
+        match pdf_dict(self.doc, target) {
+            Some(dict) => dict.get(b"D").ok(),
+            None => Some(target),
+        }
+    }
+
+    /// The page an outline item or link annotation goes to, via `/Dest` or a GoTo `/A`.
+    fn item_page(&self, item: &lopdf::Dictionary) -> Option<u32> {
+        if let Ok(destination) = item.get(b"Dest") {
+            return self.page(destination, 0);
+        }
+        let action = item
+            .get(b"A")
+            .ok()
+            .and_then(|action| pdf_dict(self.doc, action))?;
+        if action.get(b"S").and_then(lopdf::Object::as_name).ok() != Some(&b"GoTo"[..]) {
+            return None;
+        }
+        self.page(action.get(b"D").ok()?, 0)
+    }
+}
+
+/// The outline items from `first` along their `/Next` links, with their children.
+fn outline_nodes(
+    resolver: &DestinationResolver<'_>,
+    first: Option<&lopdf::Object>,
+    depth: u32,
+    visited: &mut HashSet<lopdf::ObjectId>,
+) -> Vec<OutlineNode> {
+    let doc = resolver.doc;
+    let mut nodes = Vec::new();
+    let mut next = first.and_then(|item| item.as_reference().ok());
+    // Each item is visited once, which also stops `/Next` or `/First` cycles.
+    while let Some(id) = next.filter(|id| depth < 64 && visited.insert(*id)) {
+        let Ok(item) = doc.get_dictionary(id) else {
+            break;
+        };
+        let title = match item.get(b"Title") {
+            Ok(lopdf::Object::String(bytes, _)) => decode_pdf_text(bytes),
+            _ => String::new(),
+        };
+        nodes.push(OutlineNode {
+            title,
+            page: resolver.item_page(item),
+            depth,
+            children: outline_nodes(resolver, item.get(b"First").ok(), depth + 1, visited),
+        });
+        next = item
+            .get(b"Next")
+            .ok()
+            .and_then(|item| item.as_reference().ok());
+    }
+    nodes
+}
+
+/// Embedded files of the `/EmbeddedFiles` name tree and of file attachment annotations,
+/// each with the ID of its embedded file stream.
+fn document_attachments(doc: &lopdf::Document) -> Vec<(Attachment, lopdf::ObjectId)> {
+    let mut specs: Vec<(Option<u32>, &[u8], &lopdf::Object)> = Vec::new();
+    let tree = doc
+        .catalog()
+        .ok()
+        .and_then(|catalog| catalog.get(b"Names").ok())
+        .and_then(|names| pdf_dict(doc, names))
+        .and_then(|names| names.get(b"EmbeddedFiles").ok());
+    if let Some(tree) = tree {
+        let mut entries = Vec::new();
+        name_tree_entries(doc, tree, &mut entries, 0);
+        specs.extend(entries.into_iter().map(|(key, spec)| (None, key, spec)));
+    }
+    for (page, page_id) in doc.get_pages() {
+        for annotation in doc
+            .get_dictionary(page_id)
+            .ok()
+            .and_then(|page| page.get(b"Annots").ok())
+            .and_then(|annots| doc.dereference(annots).ok())
+            .and_then(|(_, annots)| annots.as_array().ok())
+            .into_iter()
+            .flatten()
+        {
+            let Some(dict) = pdf_dict(doc, annotation) else {
+                continue;
+            };
+            if dict.get(b"Subtype").and_then(lopdf::Object::as_name).ok()
+                == Some(&b"FileAttachment"[..])
+            {
+                if let Ok(spec) = dict.get(b"FS") {
+                    specs.push((Some(page), &[][..], spec));
+                }
+            }
+        }
+    }
+
+    let mut attachments = Vec::new();
+    for (page, key, spec) in specs {
+        let Some(spec) = pdf_dict(doc, spec) else {
+            continue;
+        };
+        let text = |key: &[u8]| match spec.get(key) {
+            Ok(lopdf::Object::String(bytes, _)) => Some(decode_pdf_text(bytes)),
+            _ => None,
+        };
+        let embedded = spec
+            .get(b"EF")
+            .ok()
+            .and_then(|files| pdf_dict(doc, files))
+            .and_then(|files| files.get(b"UF").or_else(|_| files.get(b"F")).ok())
+            .and_then(|file| file.as_reference().ok());
+        let Some(file_id) = embedded else {
+            continue;
+        };
+        let stream = doc
+            .get_object(file_id)
+            .and_then(lopdf::Object::as_stream)
+            .ok();
+        let size = stream.and_then(|stream| {
+            stream
+                .dict
+                .get(b"Params")
+                .ok()
+                .and_then(|params| pdf_dict(doc, params))
+                .and_then(|params| params.get(b"Size").and_then(lopdf::Object::as_i64).ok())
+                .and_then(|size| u64::try_from(size).ok())
+                .or_else(|| stream_content(stream).ok().map(|data| data.len() as u64))
+        });
+        let name_value = |object: Result<&lopdf::Object, lopdf::Error>| {
+            object
+                .and_then(lopdf::Object::as_name)
+                .ok()
+                .map(|name| String::from_utf8_lossy(name).to_string())
+        };
+        let name = text(b"UF")
+            .or_else(|| text(b"F"))
+            .unwrap_or_else(|| decode_pdf_text(key));
+        attachments.push((
+            Attachment {
+                name,
+                size,
+                mime_type: stream.and_then(|stream| name_value(stream.dict.get(b"Subtype"))),
+                description: text(b"Desc"),
+                relationship: name_value(spec.get(b"AFRelationship")),
+                page,
+            },
+            file_id,
+        ));
+    }
+    attachments
+}
+
+/// Save the first attachment named `name` (see `Attachment::name`) to `output`.
+fn extract_pdf_attachment(input: &Path, name: &str, output: &Path) -> Result<(), CommandError> {
+    ensure_distinct_paths(input, output)?;
+    let doc = load_pdf_document(input)?;
+    let file_id = document_attachments(&doc)
+        .into_iter()
+        .find(|(attachment, _)| attachment.name == name)
+        .map(|(_, file_id)| file_id)
+        .ok_or_else(|| CommandError::AttachmentNotFound {
+            path: input.to_string_lossy().to_string(),
+            name: name.to_string(),
+        })?;
+    let data = doc
+        .get_object(file_id)
+        .and_then(lopdf::Object::as_stream)
+        .map_err(|e| CommandError::Io {
+            action: "read_attachment",
+            error: format!("'{}': {}", name, e),
+        })
+        .and_then(stream_content)?;
+    std::fs::write(output, data).map_err(|e| CommandError::Io {
+        action: "write",
+        error: format!("'{}': {}", output.display(), e),
+    })
+}
+
+/// Remove scripts, launch (and optionally URI) actions, automatic actions and embedded
+/// files by walking the document, counting what was removed.
+fn sanitize_pdf_document(
+    input: &Path,
+    output: &Path,
+    options: &SanitizeOptions,
+) -> Result<SanitizeReport, CommandError> {
+    ensure_distinct_paths(input, output)?;
+    let mut doc = load_pdf_document(input)?;
+    let mut report = SanitizeReport::default();
+    let catalog_id = doc
+        .trailer
+        .get(b"Root")
+        .and_then(lopdf::Object::as_reference)
+        .map_err(|e| CommandError::Io {
+            action: "read_document_catalog",
+            error: e.to_string(),
+        })?;
+
+    // Document-level scripts and attachments live in the catalog's name trees.
+    let names = doc
+        .get_dictionary(catalog_id)
+        .ok()
+        .and_then(|catalog| catalog.get(b"Names").ok())
+        .cloned();
+    if let Some(names) = names {
+        let names_dict = pdf_dict(&doc, &names);
+        let tree_len = |key: &[u8]| {
+            names_dict
+                .and_then(|dict| dict.get(key).ok())
+                .map_or(0, |tree| {
+                    let mut entries = Vec::new();
+                    name_tree_entries(&doc, tree, &mut entries, 0);
+                    entries.len() as u32
+                })
+        };
+        report.javascript += tree_len(b"JavaScript");
+        report.embedded_files += tree_len(b"EmbeddedFiles");
+        let names_dict = match names {
+            lopdf::Object::Reference(id) => doc.get_dictionary_mut(id).ok(),
+            _ => doc
+                .get_dictionary_mut(catalog_id)
+                .ok()
+                .and_then(|catalog| catalog.get_mut(b"Names").ok())
+                .and_then(|names| names.as_dict_mut().ok()),
+        };
+        if let Some(names_dict) = names_dict {
+            names_dict.remove(b"JavaScript");
+            names_dict.remove(b"EmbeddedFiles");
+        }
+    }
+    let catalog = doc
+        .get_dictionary_mut(catalog_id)
+        .map_err(|e| CommandError::Io {
+            action: "read_document_catalog",
+            error: e.to_string(),
+        })?;
+    if catalog.remove(b"OpenAction").is_some() {
+        report.open_actions += 1;
+    }
+    // PDF/A-3 associated files point at the same attachments.
+    catalog.remove(b"AF");
+
+    for page_id in doc.get_pages().into_values() {
+        let mut annotations = page_annotations(&doc, page_id);
+        let count = annotations.len();
+        annotations.retain(|annotation| {
+            annotation_subtype(&doc, annotation).as_deref() != Some("FileAttachment")
+        });
+        if annotations.len() != count {
+            report.embedded_files += (count - annotations.len()) as u32;
+            set_page_annotations(&mut doc, page_id, annotations);
+        }
+    }
+
+    let actions: HashMap<lopdf::ObjectId, UnsafeAction> = doc
+        .objects
+        .iter()
+        .filter_map(|(id, object)| {
+            let dict = match object {
+                lopdf::Object::Dictionary(dict) => dict,
+                _ => return None,
+            };
+            Some((*id, unsafe_action(dict, options.keep_uri_links)?))
+        })
+        .collect();
+    for (id, object) in doc.objects.iter_mut() {
+        // The actions themselves go with pruning; walking them would count their chains.
+        if !actions.contains_key(id) {
+            strip_unsafe_actions(object, &actions, options.keep_uri_links, &mut report);
+        }
+    }
+    doc.prune_objects();
+    save_pdf_document(&mut doc, output)?;
+
+    report.output_path = output.to_string_lossy().to_string();
+    Ok(report)
+}
+
+/// Drop every page's annotations, optionally keeping Link annotations. Widgets go too, so
+/// the form (`/AcroForm`) is removed with them.
+fn remove_pdf_annotations(
+    input: &Path,
+    output: &Path,
+    keep_links: bool,
+) -> Result<RemoveAnnotationsResult, CommandError> {
+    ensure_distinct_paths(input, output)?;
+    let mut doc = load_pdf_document(input)?;
+
+    let mut removed: Vec<AnnotationCount> = Vec::new();
+    let mut kept_links = 0;
+    for page_id in doc.get_pages().into_values() {
+        let annotations = page_annotations(&doc, page_id);
+        let mut kept = Vec::new();
+        for annotation in annotations {
+            let subtype =
+                annotation_subtype(&doc, &annotation).unwrap_or_else(|| String::from("Unknown"));
+            if keep_links && subtype == "Link" {
+                kept.push(annotation);
+                kept_links += 1;
+                continue;
+            }
+            match removed.iter_mut().find(|entry| entry.subtype == subtype) {
+                Some(entry) => entry.count += 1,
+                None => removed.push(AnnotationCount { subtype, count: 1 }),
+            }
+        }
+        set_page_annotations(&mut doc, page_id, kept);
+    }
+    removed.sort_by(|a, b| a.subtype.cmp(&b.subtype));
+
+    let catalog_id = doc
+        .trailer
+        .get(b"Root")
+        .and_then(lopdf::Object::as_reference)
+        .map_err(|e| CommandError::Io {
+            action: "read_document_catalog",
+            error: e.to_string(),
+        })?;
+    doc.get_dictionary_mut(catalog_id)
+        .map_err(|e| CommandError::Io {
+            action: "read_document_catalog",
+            error: e.to_string(),
+        })?
+        .remove(b"AcroForm");
+    doc.prune_objects();
+    save_pdf_document(&mut doc, output)?;
+
+    Ok(RemoveAnnotationsResult {
+        output_path: output.to_string_lossy().to_string(),
+        removed,
+        kept_links,
+    })
+}
+
+/// A form field's fully qualified name: the `/T` partial names from the root field down,
+/// joined with dots.
+fn field_name(doc: &lopdf::Document, field: &lopdf::Dictionary) -> String {
+    let mut parts = Vec::new();
+    let mut node = Some(field);
+    // Bounded so a cyclic `/Parent` chain in a damaged file can't loop forever.
+    for _ in 0..64 {
+        let Some(dict) = node else {
+            break;
+        };
+        if let Ok(lopdf::Object::String(name, _)) = dict.get(b"T") {
+            parts.push(String::from_utf8_lossy(name).to_string());
+        }
+        node = dict
+            .get(b"Parent")
+            .ok()
+            .and_then(|parent| pdf_dict(doc, parent));
+    }
+    parts.reverse();
+    parts.join(".")
+}
+
+/// The normal appearance stream of `widget` for its current state: `/AP /N` itself, or for
+/// checkboxes and radio buttons the entry of `/AP /N` named by `/AS`. `Ok(None)` when the
+/// widget is switched to a state without an appearance ("Off" usually has one, but needn't);
+/// `Err` when it has no usable appearance at all.
+fn widget_appearance(
+    doc: &lopdf::Document,
+    widget: &lopdf::Dictionary,
+) -> Result<Option<lopdf::ObjectId>, ()> {
+    let normal = widget
+        .get(b"AP")
+        .ok()
+        .and_then(|appearances| pdf_dict(doc, appearances))
+        .and_then(|appearances| appearances.get(b"N").ok())
+        .ok_or(())?;
+    // A direct stream can't be drawn as an XObject, so only references count as streams.
+    let states = match normal {
+        lopdf::Object::Reference(id) => match doc.get_object(*id) {
+            Ok(lopdf::Object::Stream(_)) => return Ok(Some(*id)),
+            Ok(lopdf::Object::Dictionary(states)) => states,
+            _ => return Err(()),
+        },
+        lopdf::Object::Dictionary(states) => states,
+        _ => return Err(()),
+    };
+    let Ok(state) = widget.get(b"AS").and_then(lopdf::Object::as_name) else {
+        return Ok(None);
+    };
+    match states.get(state) {
+        Ok(appearance) => appearance.as_reference().map(Some).map_err(|_| ()),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Matrix that maps an appearance stream's `/BBox` (after its own `/Matrix`) onto the
+/// annotation's `/Rect`, as viewers place appearances.
+fn appearance_placement(
+    doc: &lopdf::Document,
+    appearance: &lopdf::Dictionary,
+    rect: [f64; 4],
+) -> Option<PdfMatrix> {
+    let bbox = pdf_rect(doc, appearance.get(b"BBox").ok()?)?;
+    let mut matrix: PdfMatrix = [1.0, 0.0, 0.0, 1.0, 0.0, 0.0];
+    if let Some(values) = appearance
+        .get(b"Matrix")
+        .ok()
+        .and_then(|values| values.as_array().ok())
+        .filter(|values| values.len() == 6)
+    {
+        for (slot, value) in matrix.iter_mut().zip(values) {
+            *slot = pdf_number(doc, value)?;
+        }
+    }
+    let [min_x, min_y, max_x, max_y] = transform_rect(matrix, bbox);
+    if max_x - min_x <= 0.0 || max_y - min_y <= 0.0 {
+        return None;
+    }
+    let scale_x = (rect[2] - rect[0]) / (max_x - min_x);
+    let scale_y = (rect[3] - rect[1]) / (max_y - min_y);
+    Some([
+        scale_x,
+        0.0,
+        0.0,
+        scale_y,
+        rect[0] - min_x * scale_x,
+        rect[1] - min_y * scale_y,
+    ])
+}
+
+/// Draw each visible form field's current appearance into its page's content, then drop
+/// the widgets and the form so the values can no longer be edited. Other annotations stay.
+fn flatten_pdf_forms(input: &Path, output: &Path) -> Result<FlattenFormsResult, CommandError> {
+    ensure_distinct_paths(input, output)?;
+    let mut doc = load_pdf_document(input)?;
+    let catalog_id = doc
+        .trailer
+        .get(b"Root")
+        .and_then(lopdf::Object::as_reference)
+        .map_err(|e| CommandError::Io {
+            action: "read_document_catalog",
+            error: e.to_string(),
+        })?;
+    let stale_appearances = doc
+        .get_dictionary(catalog_id)
+        .ok()
+        .and_then(|catalog| catalog.get(b"AcroForm").ok())
+        .and_then(|form| pdf_dict(&doc, form))
+        .and_then(|form| form.get(b"NeedAppearances").ok())
+        .and_then(|value| value.as_bool().ok())
+        .unwrap_or(false);
+
+    let mut fields_flattened = 0;
+    let mut missing_appearances = Vec::new();
+    for page_id in doc.get_pages().into_values() {
+        let annotations = page_annotations(&doc, page_id);
+        let mut kept = Vec::new();
+        // (appearance, placement) of each field to draw on this page.
+        let mut draws = Vec::new();
+        for annotation in annotations {
+            let Some(widget) = pdf_dict(&doc, &annotation)
+                .filter(|dict| {
+                    dict.get(b"Subtype").and_then(lopdf::Object::as_name).ok()
+                        == Some(&b"Widget"[..])
+                })
+                .cloned()
+            else {
+                kept.push(annotation);
+                continue;
+            };
+            // Hidden (2) and NoView (32) widgets are not shown, so nothing is drawn.
+            let flags = widget
+                .get(b"F")
+                .and_then(lopdf::Object::as_i64)
+                .unwrap_or(0);
+            if flags & (2 | 32) != 0 {
+                continue;
+            }
+            let appearance = match widget_appearance(&doc, &widget) {
+                Ok(Some(id)) => id,
+                Ok(None) => continue,
+                Err(()) => {
+                    missing_appearances.push(field_name(&doc, &widget));
+                    continue;
+                }
+            };
+            let placement = widget
+                .get(b"Rect")
+                .ok()
+                .and_then(|rect| pdf_rect(&doc, rect))
+                .zip(
+                    doc.get_object(appearance)
+                        .and_then(lopdf::Object::as_stream)
+                        .ok(),
+                )
+                .and_then(|(rect, stream)| appearance_placement(&doc, &stream.dict, rect));
+            match placement {
+                Some(placement) => draws.push((appearance, placement)),
+                None => missing_appearances.push(field_name(&doc, &widget)),
+            }
+        }
+
+        let mut draw = String::new();
+        for (appearance, placement) in draws {
+            // Appearance streams may omit the XObject keys viewers don't need.
+            if let Ok(lopdf::Object::Stream(stream)) = doc.get_object_mut(appearance) {
+                stream
+                    .dict
+                    .set("Type", lopdf::Object::Name(b"XObject".to_vec()));
+                stream
+                    .dict
+                    .set("Subtype", lopdf::Object::Name(b"Form".to_vec()));
+            }
+            let name = add_page_xobject(&mut doc, page_id, "Field", appearance)?;
+            draw.push_str(&format!(
+                "q {} cm /{} Do Q\n",
+                pdf_matrix_operands(placement),
+                name
+            ));
+            fields_flattened += 1;
+        }
+        if !draw.is_empty() {
+            wrap_page_content(&mut doc, page_id, "q\n", &format!("Q\n{}", draw))?;
+        }
+        set_page_annotations(&mut doc, page_id, kept);
+    }
+
+    doc.get_dictionary_mut(catalog_id)
+        .map_err(|e| CommandError::Io {
+            action: "read_document_catalog",
+            error: e.to_string(),
+        })?
+        .remove(b"AcroForm");
+    doc.prune_objects();
+    save_pdf_document(&mut doc, output)?;
+
+    Ok(FlattenFormsResult {
+        output_path: output.to_string_lossy().to_string(),
+        fields_flattened,
+        missing_appearances,
+        stale_appearances,
+    })
+}
+
+/// Overlay the first page of a PDF (or `stamp_page`) or a PNG onto the selected pages,
+/// upright as displayed even on rotated pages.
+fn stamp_pdf_pages(
+    input: &Path,
+    output: &Path,
+    stamp: &Path,
+    placement: &StampPlacement,
+) -> Result<StampResult, CommandError> {
+    ensure_distinct_paths(input, output)?;
+    if !stamp.is_file() {
+        return Err(CommandError::NotFound {
+            path: stamp.to_string_lossy().to_string(),
+        });
+    }
+    let mut doc = load_pdf_document(input)?;
+    let geometry = read_page_geometry(&doc);
+    let page_ids = doc.get_pages();
+    let selected = resolve_page_selection(placement.pages.clone(), page_ids.len() as u32)?;
+
+    let is_png = stamp
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("png"));
+    // The stamp XObject, a matrix mapping it onto an upright box at the origin, and the
+    // size of that box.
+    let (stamp_id, stamp_matrix, stamp_size) = if is_png {
+        let png = read_png(stamp)?;
+        let pixel_size = 72.0 / png.dpi.unwrap_or(72.0);
+        let size = PageDimensions {
+            width_pt: f64::from(png.width) * pixel_size,
+            height_pt: f64::from(png.height) * pixel_size,
+        };
+        let id = png_image_xobject(&mut doc, &png)?;
+        (
+            id,
+            [size.width_pt, 0.0, 0.0, size.height_pt, 0.0, 0.0],
+            size,
+        )
+    } else {
+        let stamp_doc = load_pdf_document(stamp)?;
+        let stamp_geometry = read_page_geometry(&stamp_doc);
+        let stamp_page = stamp_geometry
+            .get((placement.stamp_page as usize).wrapping_sub(1))
+            .ok_or(CommandError::PageOutOfRange {
+                page: placement.stamp_page,
+                page_count: stamp_geometry.len() as u32,
+            })?;
+        let stamp_page_id = stamp_doc.get_pages()[&stamp_page.page];
+        let form = page_form_stream(&stamp_doc, stamp_page_id, stamp_page.visible_box())?;
+        let mut imported = HashMap::new();
+        let form = import_object(
+            &mut doc,
+            &stamp_doc,
+            &lopdf::Object::Stream(form),
+            &mut imported,
+        );
+        let (matrix, size) = stamp_page.upright_matrix();
+        (doc.add_object(form), matrix, size)
+    };
+    if stamp_size.width_pt <= 0.0 || stamp_size.height_pt <= 0.0 {
+        return Err(CommandError::NoVisibleArea {
+            path: stamp.to_string_lossy().to_string(),
+            page: placement.stamp_page,
+        });
+    }
+
+    for page in &selected {
+        let page_geometry = &geometry[*page as usize - 1];
+        let (upright, displayed) = page_geometry.upright_matrix();
+        let to_page = invert_matrix(upright).ok_or_else(|| CommandError::NoVisibleArea {
+            path: input.to_string_lossy().to_string(),
+            page: *page,
+        })?;
+        let (scale, x, y) = stamp_position(stamp_size, displayed, placement)?;
+        let matrix = concat_matrix(
+            concat_matrix(stamp_matrix, [scale, 0.0, 0.0, scale, x, y]),
+            to_page,
+        );
+
+        let page_id = page_ids[page];
+        let name = add_page_xobject(&mut doc, page_id, "Stamp", stamp_id)?;
+        let draw = format!("q {} cm /{} Do Q\n", pdf_matrix_operands(matrix), name);
+        match placement.layer {
+            // The page content is isolated so its graphics state can't leak onto the stamp.
+            StampLayer::Over => {
+                wrap_page_content(&mut doc, page_id, "q\n", &format!("Q\n{}", draw))?
+            }
+            StampLayer::Under => wrap_page_content(&mut doc, page_id, &draw, "")?,
+        }
+    }
+
+    save_pdf_document(&mut doc, output)?;
+    Ok(StampResult {
+        output_path: output.to_string_lossy().to_string(),
+        pages_stamped: selected.len() as u32,
+    })
+}
+
+impl OverlayRepeat {
+    /// The 0-based overlay page for 0-based base page `index`, if any.
+    fn overlay_index(self, index: usize, overlay_pages: usize) -> Option<usize> {
+        match self {
+            OverlayRepeat::Once => (index < overlay_pages).then_some(index),
+            OverlayRepeat::Cycle => Some(index % overlay_pages),
+            OverlayRepeat::StretchLast => Some(index.min(overlay_pages - 1)),
+        }
+    }
+}
+
+/// Composite the pages of `overlay` over or under the pages of `base`, page by page,
+/// centered at native size or scaled to fit each base page with `fit`.
+fn overlay_pdf_documents(
+    base: &Path,
+    overlay: &Path,
+    output: &Path,
+    layer: StampLayer,
+    repeat: OverlayRepeat,
+    fit: bool,
+) -> Result<OverlayResult, CommandError> {
+    ensure_distinct_paths(base, output)?;
+    ensure_distinct_paths(overlay, output)?;
+    let mut doc = load_pdf_document(base)?;
+    let overlay_doc = load_pdf_document(overlay)?;
+    let geometry = read_page_geometry(&doc);
+    let page_ids = doc.get_pages();
+    let overlay_geometry = read_page_geometry(&overlay_doc);
+    let overlay_ids = overlay_doc.get_pages();
+    if overlay_geometry.is_empty() {
+        return Err(CommandError::NoPages {
+            path: overlay.to_string_lossy().to_string(),
+        });
+    }
+    let placement = StampPlacement {
+        scale: if fit {
+            StampScale::Fit
+        } else {
+            StampScale::Actual
+        },
+        ..StampPlacement::default()
+    };
+
+    // Overlay pages are imported once each, sharing their common resources.
+    let mut imported = HashMap::new();
+    let mut forms: HashMap<usize, lopdf::ObjectId> = HashMap::new();
+    let mut pages_overlaid = 0;
+    for (index, page_geometry) in geometry.iter().enumerate() {
+        let Some(overlay_index) = repeat.overlay_index(index, overlay_geometry.len()) else {
+            continue;
+        };
+        let overlay_page = &overlay_geometry[overlay_index];
+        let overlay_page_id = overlay_ids[&overlay_page.page];
+        let form_id = match forms.get(&overlay_index) {
+            Some(id) => *id,
+            None => {
+                let form =
+                    page_form_stream(&overlay_doc, overlay_page_id, overlay_page.visible_box())?;
+                let form = import_object(
+                    &mut doc,
+                    &overlay_doc,
+                    &lopdf::Object::Stream(form),
+                    &mut imported,
+                );
+                let id = doc.add_object(form);
+                forms.insert(overlay_index, id);
+                id
+            }
+        };
+        let (overlay_matrix, overlay_size) = overlay_page.upright_matrix();
+        if overlay_size.width_pt <= 0.0 || overlay_size.height_pt <= 0.0 {
+            continue;
+        }
+
+        let (upright, displayed) = page_geometry.upright_matrix();
+        let to_page = invert_matrix(upright).ok_or_else(|| CommandError::NoVisibleArea {
+            path: base.to_string_lossy().to_string(),
+            page: page_geometry.page,
+        })?;
+        let (scale, x, y) = stamp_position(overlay_size, displayed, &placement)?;
+        let matrix = concat_matrix(
+            concat_matrix(overlay_matrix, [scale, 0.0, 0.0, scale, x, y]),
+            to_page,
+        );
+
+        let page_id = page_ids[&page_geometry.page];
+        // A base page without its own transparency group takes the overlay's, so the
+        // overlay blends in the color space it was made for.
+        let overlay_group = overlay_doc
+            .get_dictionary(overlay_page_id)
+            .ok()
+            .and_then(|page| page.get(b"Group").ok())
+            .map(|group| import_object(&mut doc, &overlay_doc, group, &mut imported));
+        if let Some(group) = overlay_group {
+            let page = doc
+                .get_dictionary_mut(page_id)
+                .map_err(|e| CommandError::Io {
+                    action: "read_page",
+                    error: e.to_string(),
+                })?;
+            if !page.has(b"Group") {
+                page.set("Group", group);
+            }
+        }
+        let name = add_page_xobject(&mut doc, page_id, "Overlay", form_id)?;
+        let draw = format!("q {} cm /{} Do Q\n", pdf_matrix_operands(matrix), name);
+        match layer {
+            StampLayer::Over => {
+                wrap_page_content(&mut doc, page_id, "q\n", &format!("Q\n{}", draw))?
+            }
+            StampLayer::Under => wrap_page_content(&mut doc, page_id, &draw, "")?,
+        }
+        pages_overlaid += 1;
+    }
+
+    save_pdf_document(&mut doc, output)?;
+    Ok(OverlayResult {
+        output_path: output.to_string_lossy().to_string(),
+        pages_overlaid,
+    })
+}
+
+/// PostScript that mirrors `pages` (every page when `None`) by extending the device's
+/// `/Install` procedure, which sets the default matrix each page is drawn with. Pages are
+/// counted in `/EndPage`, and mirroring happens in device space, i.e. the page as
+/// displayed, so it composes with `/Rotate`.
+fn mirror_program(axis: MirrorAxis, pages: Option<&[u32]>) -> String {
+    let pages = ps_page_set(pages);
+    let transform = match axis {
+        MirrorAxis::Horizontal => "currentpagedevice /PageSize get 0 get 0 translate -1 1 scale",
+        MirrorAxis::Vertical => "0 currentpagedevice /PageSize get 1 get translate 1 -1 scale",
+    };
+    format!(
+        "%!\n\
+         /mp_dict 8 dict def\n\
+         mp_dict begin\n\
+         /mp_pages {pages} def\n\
+         /mp_next 1 def\n\
+         /mp_install currentpagedevice /Install get def\n\
+         end\n\
+         << /Install {{\n\
+         //mp_dict begin\n\
+         mp_install exec\n\
+         mp_pages null eq {{ true }} {{ mp_pages mp_next known }} ifelse {{ {transform} }} if\n\
+         end\n\
+         }} bind\n\
+         /EndPage {{ exch 2 add //mp_dict exch /mp_next exch put 2 ne }} bind\n\
+         >> setpagedevice\n",
+        pages = pages,
+        transform = transform,
+    )
+}
+
+fn mirror_with_ghostscript(
+    input: &Path,
+    output: &Path,
+    axis: MirrorAxis,
+    pages: Option<Vec<u32>>,
+    runtime: &GhostscriptRuntime,
+    ctx: JobContext<'_>,
+) -> Result<MirrorResult, CommandError> {
+    if !input.is_file() {
+        return Err(CommandError::NotFound {
+            path: input.to_string_lossy().to_string(),
+        });
+    }
+    ensure_distinct_paths(input, output)?;
+    let page_count = document_page_count(input, runtime, ctx.hooks())?;
+    let all_pages = pages.is_none();
+    let pages = resolve_page_selection(pages, page_count)?;
+
+    let mut temp_files = TempFileGuard::default();
+    let program = temp_files.track(unique_temp_path("mirror", "ps"));
+    std::fs::write(
+        &program,
+        mirror_program(axis, (!all_pages).then_some(pages.as_slice())),
+    )
+    .map_err(|e| CommandError::Io {
+        action: "write_mirror_program",
+        error: e.to_string(),
+    })?;
+    // Mirrored text must not make pdfwrite rotate pages to match it.
+    let device_args = [String::from("-dAutoRotatePages=/None")];
+    run_pdfwrite(
+        &[&program, input],
+        output,
+        &device_args,
+        Some(page_count),
+        runtime,
+        ctx,
+    )?;
+
+    Ok(MirrorResult {
+        output_path: output.to_string_lossy().to_string(),
+        mirrored_pages: pages.len() as u32,
+    })
+}
+
+/// Lowercase letters and digits of a font name, for matching `ArialMT` against `arial.ttf`.
+fn font_name_key(name: &str) -> String {
+    name.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// `(requested, used)` font pairs from Ghostscript's font loading messages, from both the
+/// PostScript-based PDF interpreter ("Substituting font Helvetica for ArialMT.") and the
+/// newer one ("Loading font ArialMT (or substitute) from .../NimbusSans-Regular.t1").
+/// Fonts loaded from a file whose name matches the requested font are not included.
+fn font_substitutions(output: &GhostscriptExecOutput) -> Vec<(String, String)> {
+    let unquote = |name: &str| {
+        name.trim()
+            .trim_end_matches('.')
+            .trim_matches(|c| c == '"' || c == '/')
+            .to_string()
+    };
+    let mut substitutions = Vec::new();
+    for line in output.stdout.lines().chain(output.stderr.lines()) {
+        let line = line.trim();
+        if let Some((used, requested)) = line
+            .strip_prefix("Substituting font ")
+            .and_then(|rest| rest.split_once(" for "))
+        {
+            substitutions.push((unquote(requested), unquote(used)));
+        } else if let Some((requested, file)) = line
+            .strip_prefix("Loading font ")
+            .or_else(|| line.strip_prefix("Loading CIDFont "))
+            .and_then(|rest| rest.split_once(" (or substitute) from "))
+        {
+            let used = Path::new(file.trim())
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let (requested_key, used_key) = (font_name_key(requested), font_name_key(&used));
+            if !used_key.is_empty() && !requested_key.starts_with(&used_key) {
+                substitutions.push((unquote(requested), used));
+            }
+        } else if let Some((used, requested)) = line
+            .strip_prefix("Attempting to substitute CIDFont ")
+            .and_then(|rest| rest.split_once(" for "))
+        {
+            let requested = requested.split(',').next().unwrap_or(requested);
+            substitutions.push((unquote(requested), unquote(used)));
+        }
+    }
+    substitutions
+}
+
+/// Rewrite the PDF with every font embedded (and subset), loading missing fonts from the
+/// system. With `substitute_missing` false, any font that had to be replaced or could not
+/// be embedded is an error and no output is kept: a silent substitute is worse than a
+/// rejected file when printing.
+fn embed_fonts_with_ghostscript(
+    input: &Path,
+    output: &Path,
+    substitute_missing: bool,
+    runtime: &GhostscriptRuntime,
+    ctx: JobContext<'_>,
+) -> Result<EmbedFontsResult, CommandError> {
+    if !input.is_file() {
+        return Err(CommandError::NotFound {
+            path: input.to_string_lossy().to_string(),
+        });
+    }
+    ensure_distinct_paths(input, output)?;
+
+    let source = load_pdf_document(input)?;
+    let input_fonts = document_fonts(&source);
+    let total_pages = Some(source.get_pages().len() as u32);
+    drop(source);
+
+    let device_args = [
+        String::from("-dEmbedAllFonts=true"),
+        String::from("-dSubsetFonts=true"),
+    ];
+    let result = run_pdfwrite(&[input], output, &device_args, total_pages, runtime, ctx)?;
+    let substitutions = font_substitutions(&result);
+    let output_fonts = load_pdf_document(output)
+        .map(|doc| document_fonts(&doc))
+        .unwrap_or_default();
+
+    let mut fonts: Vec<FontEmbedding> = Vec::new();
+    for font in input_fonts {
+        if fonts.iter().any(|known| known.name == font.name) {
+            continue;
+        }
+        let name = without_subset_tag(&font.name);
+        let substitute = substitutions
+            .iter()
+            .find(|(requested, _)| requested == name)
+            .map(|(_, used)| used.clone());
+        let embedded = output_fonts.iter().any(|output_font| {
+            output_font.embedded && without_subset_tag(&output_font.name) == name
+        });
+        let status = if font.embedded {
+            FontEmbeddingStatus::Unchanged
+        } else if substitute.is_some() {
+            FontEmbeddingStatus::Substituted
+        } else if embedded {
+            FontEmbeddingStatus::Embedded
+        } else {
+            FontEmbeddingStatus::Missing
+        };
+        fonts.push(FontEmbedding {
+            name: font.name,
+            status,
+            substitute: substitute.filter(|_| status == FontEmbeddingStatus::Substituted),
+        });
+    }
+
+    if !substitute_missing {
+        let failures: Vec<String> = fonts
+            .iter()
+            .filter(|font| {
+                matches!(
+                    font.status,
+                    FontEmbeddingStatus::Substituted | FontEmbeddingStatus::Missing
+                )
+            })
+            .map(|font| font.name.clone())
+            .collect();
+        if !failures.is_empty() {
+            let _ = std::fs::remove_file(output);
+            return Err(CommandError::FontsNotEmbedded { fonts: failures });
+        }
+    }
+
+    Ok(EmbedFontsResult {
+        output_path: output.to_string_lossy().to_string(),
+        fonts,
+    })
+}
+
+/// Which Ghostscript is in effect, its parsed version and whether it is recent enough.
+#[tauri::command]
+async fn ghostscript_info(app: tauri::AppHandle) -> Result<GhostscriptInfo, CommandError> {
+    run_blocking(move || {
+        let runtime = app.state::<GhostscriptRuntime>();
+        let mut probe = GhostscriptProbeLog::default();
+        let result = run_ghostscript(
+            &["--version"],
+            &runtime,
+            Some(&mut probe),
+            GhostscriptHooks::default(),
+        );
+        let (version_string, error) = match result {
+            Ok(output) => (Some(output.stdout.trim().to_string()), None),
+            Err(error) => {
+                // Missing Ghostscript is an expected state in dev; keep logs quiet for it.
+                if !matches!(error, CommandError::GsNotFound(_)) {
+                    log_warn!("Ghostscript availability check failed: {:?}", error);
+                }
+                (None, Some(error))
+            }
+        };
+        let version = version_string
+            .as_deref()
+            .and_then(parse_ghostscript_version);
+        Ok(GhostscriptInfo {
+            found: version_string.is_some(),
+            path: probe.selected,
+            version_string,
+            version,
+            source: probe.selected_source.map(GhostscriptSource::label),
+            minimum_version: MIN_GHOSTSCRIPT_VERSION,
+            meets_minimum: version.is_some_and(|version| version >= MIN_GHOSTSCRIPT_VERSION),
+            error,
+        })
+    })
+    .await
+}
+
+#[tauri::command]
+async fn debug_ghostscript_probe(
+    app: tauri::AppHandle,
+) -> Result<GhostscriptProbeResult, CommandError> {
+    run_blocking(move || {
+        let runtime = app.state::<GhostscriptRuntime>();
+        if !cfg!(debug_assertions) {
+            return Ok(GhostscriptProbeResult {
+                attempted: Vec::new(),
+                selected: None,
+                last_error: Some(String::from(
+                    "debug_ghostscript_probe is disabled in production builds.",
+                )),
+                mac_root: runtime.mac_root_string(),
+                windows_root: runtime.windows_root_string(),
+            });
+        }
+
+        // Walk every candidate rather than reusing the cached one.
+        runtime.invalidate_resolved();
+        let mut probe = GhostscriptProbeLog::default();
+        let result = run_ghostscript(
+            &["--version"],
+            &runtime,
+            Some(&mut probe),
+            GhostscriptHooks::default(),
+        );
+        if let Err(error) = result {
+            if probe.last_error.is_none() {
+                probe.last_error = Some(error.detailed_message());
+            }
+        }
+
+        Ok(GhostscriptProbeResult {
+            attempted: probe.attempted,
+            selected: probe.selected,
+            last_error: probe.last_error,
+            mac_root: runtime.mac_root_string(),
+            windows_root: runtime.windows_root_string(),
+        })
+    })
+    .await
+}
+
+/// The Ghostscript binary that answers `--version`, i.e. the one jobs run with.
+fn active_ghostscript_path(runtime: &GhostscriptRuntime) -> Option<String> {
+    let mut probe = GhostscriptProbeLog::default();
+    run_ghostscript(
+        &["--version"],
+        runtime,
+        Some(&mut probe),
+        GhostscriptHooks::default(),
+    )
+    .ok()?;
+    probe.selected
+}
+
+/// Report which Ghostscript binary is currently in effect.
+#[tauri::command]
+fn get_ghostscript_path(runtime: State<'_, GhostscriptRuntime>) -> GhostscriptPathInfo {
+    ghostscript_path_info(&runtime, &mut GhostscriptProbeLog::default())
+}
+
+fn ghostscript_path_info(
+    runtime: &GhostscriptRuntime,
+    probe: &mut GhostscriptProbeLog,
+) -> GhostscriptPathInfo {
+    let version = run_ghostscript(
+        &["--version"],
+        runtime,
+        Some(&mut *probe),
+        GhostscriptHooks::default(),
+    )
+    .ok()
+    .map(|output| output.stdout.trim().to_string());
+    GhostscriptPathInfo {
+        path: probe.selected.clone(),
+        version,
+        custom_path: runtime
+            .custom_command()
+            .map(|p| p.to_string_lossy().to_string()),
+    }
+}
+
+/// Name and version of the operating system, e.g. `macOS 14.5`.
+fn os_version() -> Option<String> {
+    #[cfg(target_os = "macos")]
+    {
+        let output = std::process::Command::new("sw_vers")
+            .arg("-productVersion")
+            .output()
+            .ok()?;
+        Some(format!(
+            "macOS {}",
+            String::from_utf8_lossy(&output.stdout).trim()
+        ))
+    }
+    #[cfg(target_os = "windows")]
+    {
+        let output = std::process::Command::new("cmd")
+            .args(["/C", "ver"])
+            .output()
+            .ok()?;
+        Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+    {
+        let release = std::fs::read_to_string("/etc/os-release").ok()?;
+        release.lines().find_map(|line| {
+            line.strip_prefix("PRETTY_NAME=")
+                .map(|name| name.trim_matches('"').to_string())
+        })
+    }
+}
+
+/// `settings` as JSON with secret-looking values hidden.
+fn redacted_settings(settings: &Settings) -> Result<Vec<u8>, CommandError> {
+    let mut value = serde_json::to_value(settings).map_err(|e| CommandError::Io {
+        action: "serialize_settings",
+        error: e.to_string(),
+    })?;
+    if let serde_json::Value::Object(fields) = &mut value {
+        for (name, field) in fields.iter_mut() {
+            let name = name.to_lowercase();
+            if SECRET_SETTING_FRAGMENTS
+                .iter()
+                .any(|fragment| name.contains(fragment))
+            {
+                *field = serde_json::Value::from("***");
+            }
+        }
+    }
+    serde_json::to_vec_pretty(&value).map_err(|e| CommandError::Io {
+        action: "serialize_settings",
+        error: e.to_string(),
+    })
+}
+
+fn run_self_test(runtime: &GhostscriptRuntime) -> SelfTestResult {
+    let started = std::time::Instant::now();
+    let result = flatten_with_ghostscript(
+        SELF_TEST_PDF.to_vec(),
+        &FlattenOptions::default(),
+        DEFAULT_PIPE_THRESHOLD_BYTES,
+        runtime,
+        JobContext::default(),
+    );
+    let duration_ms = started.elapsed().as_millis() as u64;
+    match result {
+        Ok(output) => SelfTestResult {
+            passed: output.starts_with(b"%PDF"),
+            duration_ms,
+            output_bytes: Some(output.len() as u64),
+            error: None,
+        },
+        Err(error) => SelfTestResult {
+            passed: false,
+            duration_ms,
+            output_bytes: None,
+            error: Some(error),
+        },
+    }
+}
+
+/// Every Ghostscript the resolver would consider, in its order, each tried with
+/// `--version`. Bare command names are expanded to each match on `PATH`.
+#[tauri::command]
+async fn list_ghostscript_candidates(
+    app: tauri::AppHandle,
+) -> Result<Vec<GhostscriptCandidateInfo>, CommandError> {
+    run_blocking(move || {
+        let runtime = app.state::<GhostscriptRuntime>();
+        let active = active_ghostscript_path(&runtime);
+        let mut seen = HashSet::new();
+        let mut infos = Vec::new();
+        for candidate in collect_candidates(&runtime, ghostscript_env_override()) {
+            let bare = !candidate.command.is_absolute();
+            // Only the first match on PATH is what a bare name runs.
+            let bare_in_use =
+                bare && active.as_deref() == Some(&*candidate.command.to_string_lossy());
+            let commands = if bare {
+                find_on_path(&candidate.command.to_string_lossy())
+            } else {
+                vec![candidate.command.clone()]
+            };
+            for (index, command) in commands.into_iter().enumerate() {
+                let key = std::fs::canonicalize(&command).unwrap_or_else(|_| command.clone());
+                if !seen.insert(key) {
+                    continue;
+                }
+                let path = command.to_string_lossy().to_string();
+                let exists = command.is_file();
+                let (version, error) = if exists {
+                    let envs = candidate
+                        .gs_root
+                        .as_deref()
+                        .map(collect_ghostscript_env)
+                        .unwrap_or_default();
+                    let candidate = GhostscriptCandidate {
+                        command,
+                        ..candidate.clone()
+                    };
+                    match run_candidate(
+                        &candidate,
+                        &envs,
+                        &["--version"],
+                        &mut GhostscriptProbeLog::default(),
+                        GhostscriptHooks::default(),
+                    ) {
+                        Ok(output) => (Some(output.stdout.trim().to_string()), None),
+                        Err(error) => (None, Some(error.detailed_message())),
+                    }
+                } else {
+                    (None, None)
+                };
+                infos.push(GhostscriptCandidateInfo {
+                    in_use: if bare {
+                        bare_in_use && index == 0
+                    } else {
+                        active.as_deref() == Some(path.as_str())
+                    },
+                    path,
+                    source: candidate.source.label(),
+                    exists,
+                    executed: version.is_some(),
+                    version,
+                    error,
+                });
+            }
+        }
+        Ok(infos)
+    })
+    .await
+}
+
+/// Output devices of the Ghostscript in effect, e.g. to hide exports it can't do.
+#[tauri::command]
+async fn ghostscript_devices(app: tauri::AppHandle) -> Result<Vec<String>, CommandError> {
+    run_blocking(move || {
+        let runtime = app.state::<GhostscriptRuntime>();
+        runtime.devices(GhostscriptHooks::default())
+    })
+    .await
+}
+
+/// Forget which Ghostscript the last run used, so the next one searches again, e.g.
+/// after installing or removing one.
+#[tauri::command]
+fn invalidate_ghostscript_cache(runtime: State<'_, GhostscriptRuntime>) {
+    runtime.invalidate_resolved();
+}
+
+/// Use the given Ghostscript executable ahead of the bundled and PATH candidates.
+/// The binary must answer `--version`; an empty path clears the override.
+#[tauri::command]
+async fn set_ghostscript_path(path: String, app: tauri::AppHandle) -> Result<String, CommandError> {
+    run_blocking(move || {
+        let runtime = app.state::<GhostscriptRuntime>();
+        let trimmed = path.trim();
+        if trimmed.is_empty() {
+            runtime.set_custom_command(None)?;
+            return Ok(String::new());
+        }
+
+        let command = PathBuf::from(trimmed);
+        if !command.is_file() {
+            return Err(CommandError::NotAFile {
+                path: command.to_string_lossy().to_string(),
+            });
+        }
+        let candidate = GhostscriptCandidate {
+            command: command.clone(),
+            gs_root: None,
+            source: GhostscriptSource::Custom,
+        };
+        let output = run_candidate(
+            &candidate,
+            &HashMap::new(),
+            &["--version"],
+            &mut GhostscriptProbeLog::default(),
+            GhostscriptHooks::default(),
+        )
+        .map_err(|e| {
+            CommandError::GsNotFound(format!(
+                "'{}' is not a working Ghostscript: {}",
+                command.display(),
+                e.detailed_message()
+            ))
+        })?;
+
+        runtime.set_custom_command(Some(command))?;
+        Ok(output.stdout.trim().to_string())
+    })
+    .await
+}
+
+/// Flatten a PDF using Ghostscript (bundled sidecar preferred). `options` may be `{}` for
+/// the defaults: fonts outlined, PDF 1.7.
+///
+/// Pass a `job_id` (see `create_job_id`) to be able to abort the run with `cancel_job`.
+/// Emits `pdf-progress` events to the calling window as pages are processed.
+#[tauri::command]
+async fn flatten_pdf(
+    pdf_bytes: Vec<u8>,
+    options: FlattenOptions,
+    job_id: Option<String>,
+    window: tauri::Window,
+) -> Result<Vec<u8>, CommandError> {
+    run_blocking(move || {
+        let runtime = window.state::<GhostscriptRuntime>();
+        let jobs = window.state::<RunningJobs>();
+        let settings = window.state::<SettingsStore>();
+        let job = jobs.register(job_id, options.timeout_secs)?;
+        let on_progress = progress_emitter(&window, &job.job_id);
+        flatten_with_ghostscript(
+            pdf_bytes,
+            &options,
+            settings.get().pipe_threshold_bytes,
+            &runtime,
+            job.context(&on_progress),
+        )
+    })
+    .await
+}
+
+/// Resize every page to `width_pt` x `height_pt`. `content_mode` picks between scaling
+/// the artwork to fit and keeping it at 100%, placed by `anchor` (center by default).
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn resize_pdf(
+    input_path: String,
+    output_path: String,
+    width_pt: f64,
+    height_pt: f64,
+    content_mode: ContentMode,
+    anchor: Option<ResizeAnchor>,
+    linearize: Option<bool>,
+    output_options: Option<OutputOptions>,
+    job_id: Option<String>,
+    window: tauri::Window,
+) -> Result<Written<ResizeResult>, CommandError> {
+    run_blocking(move || {
+        let runtime = window.state::<GhostscriptRuntime>();
+        let jobs = window.state::<RunningJobs>();
+        let job = jobs.register(
+            job_id,
+            output_options.and_then(|options| options.timeout_secs),
+        )?;
+        let on_progress = progress_emitter(&window, &job.job_id);
+        write_output(
+            Path::new(&input_path),
+            Path::new(&output_path),
+            output_options.unwrap_or_default(),
+            |input, output, warnings| {
+                resize_with_ghostscript(
+                    input,
+                    output,
+                    PageDimensions {
+                        width_pt,
+                        height_pt,
+                    },
+                    content_mode,
+                    anchor.unwrap_or(ResizeAnchor::Center),
+                    linearize.unwrap_or(false),
+                    &runtime,
+                    job.context(&on_progress).collecting(warnings),
+                )
+            },
+        )
+    })
+    .await
+}
+
+/// Fit every page onto a standard sheet (`A4`, `Letter`, ...) with `margin_pt` on all
+/// sides. Returns the scale applied to each page.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn fit_to_page(
+    input_path: String,
+    output_path: String,
+    preset: String,
+    margin_pt: f64,
+    orientation: FitOrientation,
+    linearize: Option<bool>,
+    output_options: Option<OutputOptions>,
+    job_id: Option<String>,
+    window: tauri::Window,
+) -> Result<Written<FitResult>, CommandError> {
+    run_blocking(move || {
+        let runtime = window.state::<GhostscriptRuntime>();
+        let jobs = window.state::<RunningJobs>();
+        let job = jobs.register(
+            job_id,
+            output_options.and_then(|options| options.timeout_secs),
+        )?;
+        let on_progress = progress_emitter(&window, &job.job_id);
+        write_output(
+            Path::new(&input_path),
+            Path::new(&output_path),
+            output_options.unwrap_or_default(),
+            |input, output, warnings| {
+                fit_to_page_with_ghostscript(
+                    input,
+                    output,
+                    &preset,
+                    margin_pt,
+                    orientation,
+                    linearize.unwrap_or(false),
+                    &runtime,
+                    job.context(&on_progress).collecting(warnings),
+                )
+            },
+        )
+    })
+    .await
+}
+
+/// Crop pages to the box `left`/`bottom`/`right`/`top` (points from the MediaBox origin).
+/// Applies to every page when `pages` is omitted.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn crop_pdf(
+    input_path: String,
+    output_path: String,
+    left: f64,
+    bottom: f64,
+    right: f64,
+    top: f64,
+    pages: Option<Vec<u32>>,
+    set_media_box: Option<bool>,
+    output_options: Option<OutputOptions>,
+) -> Result<Written<CropResult>, CommandError> {
+    run_blocking(move || {
+        write_output(
+            Path::new(&input_path),
+            Path::new(&output_path),
+            output_options.unwrap_or_default(),
+            |input, output, _| {
+                crop_pdf_pages(
+                    input,
+                    output,
+                    [left, bottom, right, top],
+                    pages.clone(),
+                    set_media_box.unwrap_or(false),
+                )
+            },
+        )
+    })
+    .await
+}
+
+/// Rotate the selected pages (all when omitted) by 90/180/270 degrees, or negative
+/// equivalents. Returns the final rotation of every page.
+#[tauri::command]
+async fn rotate_pages(
+    input_path: String,
+    output_path: String,
+    rotation: i32,
+    pages: Option<Vec<u32>>,
+    output_options: Option<OutputOptions>,
+) -> Result<Written<RotateResult>, CommandError> {
+    run_blocking(move || {
+        write_output(
+            Path::new(&input_path),
+            Path::new(&output_path),
+            output_options.unwrap_or_default(),
+            |input, output, _| rotate_pdf_pages(input, output, rotation, pages.clone()),
+        )
+    })
+    .await
+}
+
+/// Split a PDF into one file per page inside `output_dir`, named by `name_template`
+/// (default `{name}_p{page:03}.pdf`). Returns the created paths in page order.
+#[tauri::command]
+async fn split_pdf(
+    input_path: String,
+    output_dir: String,
+    name_template: Option<String>,
+    output_options: Option<OutputOptions>,
+    job_id: Option<String>,
+    window: tauri::Window,
+) -> Result<Vec<String>, CommandError> {
+    run_blocking(move || {
+        let runtime = window.state::<GhostscriptRuntime>();
+        let jobs = window.state::<RunningJobs>();
+        let job = jobs.register(
+            job_id,
+            output_options.and_then(|options| options.timeout_secs),
+        )?;
+        let on_progress = progress_emitter(&window, &job.job_id);
+        split_pdf_pages(
+            Path::new(&input_path),
+            Path::new(&output_dir),
+            name_template.as_deref().unwrap_or(DEFAULT_SPLIT_TEMPLATE),
+            output_options.unwrap_or_default().on_conflict,
+            &runtime,
+            job.context(&on_progress),
+        )
+    })
+    .await
+}
+
+/// Extract inclusive page ranges, e.g. `[[3, 7], [12, 14]]`, into a new PDF in the order
+/// given.
+#[tauri::command]
+async fn extract_pages(
+    input_path: String,
+    output_path: String,
+    ranges: Vec<(u32, u32)>,
+    output_options: Option<OutputOptions>,
+    job_id: Option<String>,
+    window: tauri::Window,
+) -> Result<Written<PageCountResult>, CommandError> {
+    run_blocking(move || {
+        let runtime = window.state::<GhostscriptRuntime>();
+        let jobs = window.state::<RunningJobs>();
+        let job = jobs.register(
+            job_id,
+            output_options.and_then(|options| options.timeout_secs),
+        )?;
+        let on_progress = progress_emitter(&window, &job.job_id);
+        write_output(
+            Path::new(&input_path),
+            Path::new(&output_path),
+            output_options.unwrap_or_default(),
+            |input, output, warnings| {
+                extract_pdf_pages(
+                    input,
+                    output,
+                    &ranges,
+                    &runtime,
+                    job.context(&on_progress).collecting(warnings),
+                )
+            },
+        )
+    })
+    .await
+}
+
+/// Write a copy of the PDF without the listed 1-based pages. Duplicates are ignored.
+#[tauri::command]
+async fn delete_pages(
+    input_path: String,
+    output_path: String,
+    pages: Vec<u32>,
+    output_options: Option<OutputOptions>,
+    job_id: Option<String>,
+    window: tauri::Window,
+) -> Result<Written<PageCountResult>, CommandError> {
+    run_blocking(move || {
+        let runtime = window.state::<GhostscriptRuntime>();
+        let jobs = window.state::<RunningJobs>();
+        let job = jobs.register(
+            job_id,
+            output_options.and_then(|options| options.timeout_secs),
+        )?;
+        let on_progress = progress_emitter(&window, &job.job_id);
+        write_output(
+            Path::new(&input_path),
+            Path::new(&output_path),
+            output_options.unwrap_or_default(),
+            |input, output, warnings| {
+                delete_pdf_pages(
+                    input,
+                    output,
+                    pages.clone(),
+                    &runtime,
+                    job.context(&on_progress).collecting(warnings),
+                )
+            },
+        )
+    })
+    .await
+}
+
+/// Write the pages in the given sequence of 1-based page numbers. Pages may repeat or be
+/// left out.
+#[tauri::command]
+async fn reorder_pages(
+    input_path: String,
+    output_path: String,
+    order: Vec<u32>,
+    output_options: Option<OutputOptions>,
+    job_id: Option<String>,
+    window: tauri::Window,
+) -> Result<Written<PageCountResult>, CommandError> {
+    run_blocking(move || {
+        let runtime = window.state::<GhostscriptRuntime>();
+        let jobs = window.state::<RunningJobs>();
+        let job = jobs.register(
+            job_id,
+            output_options.and_then(|options| options.timeout_secs),
+        )?;
+        let on_progress = progress_emitter(&window, &job.job_id);
+        write_output(
+            Path::new(&input_path),
+            Path::new(&output_path),
+            output_options.unwrap_or_default(),
+            |input, output, warnings| {
+                reorder_pdf_pages(
+                    input,
+                    output,
+                    &order,
+                    &runtime,
+                    job.context(&on_progress).collecting(warnings),
+                )
+            },
+        )
+    })
+    .await
+}
+
+/// Print `cols` x `rows` pages per sheet of a standard size (`A4`, `Letter`, ...), in
+/// reading order, `gap_pt` apart and optionally framed by a hairline `border`.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn nup_pdf(
+    input_path: String,
+    output_path: String,
+    cols: u32,
+    rows: u32,
+    sheet_size: String,
+    gap_pt: Option<f64>,
+    border: Option<bool>,
+    output_options: Option<OutputOptions>,
+    job_id: Option<String>,
+    window: tauri::Window,
+) -> Result<Written<NupResult>, CommandError> {
+    run_blocking(move || {
+        let runtime = window.state::<GhostscriptRuntime>();
+        let jobs = window.state::<RunningJobs>();
+        let job = jobs.register(
+            job_id,
+            output_options.and_then(|options| options.timeout_secs),
+        )?;
+        let on_progress = progress_emitter(&window, &job.job_id);
+        let layout = NupLayout {
+            cols,
+            rows,
+            sheet_size: &sheet_size,
+            gap_pt: gap_pt.unwrap_or(0.0),
+            border: border.unwrap_or(false),
+        };
+        write_output(
+            Path::new(&input_path),
+            Path::new(&output_path),
+            output_options.unwrap_or_default(),
+            |input, output, warnings| {
+                nup_with_ghostscript(
+                    input,
+                    output,
+                    layout,
+                    &runtime,
+                    job.context(&on_progress).collecting(warnings),
+                )
+            },
+        )
+    })
+    .await
+}
+
+/// Impose a saddle-stitched booklet on landscape sheets of a standard size, padding with
+/// blank pages to a multiple of four. `creep_pt` offsets each inner sheet by that much more.
+#[tauri::command]
+async fn booklet_pdf(
+    input_path: String,
+    output_path: String,
+    sheet_size: String,
+    creep_pt: Option<f64>,
+    output_options: Option<OutputOptions>,
+) -> Result<Written<BookletResult>, CommandError> {
+    run_blocking(move || {
+        write_output(
+            Path::new(&input_path),
+            Path::new(&output_path),
+            output_options.unwrap_or_default(),
+            |input, output, _| {
+                booklet_pdf_pages(input, output, &sheet_size, creep_pt.unwrap_or(0.0))
+            },
+        )
+    })
+    .await
+}
+
+/// Shrink a PDF with one of Ghostscript's presets: `screen` (72 dpi), `ebook` (150 dpi),
+/// `printer` (300 dpi) or `prepress`. Never returns a file larger than the input.
+#[tauri::command]
+async fn compress_pdf(
+    input_path: String,
+    output_path: String,
+    preset: CompressPreset,
+    output_options: Option<OutputOptions>,
+    job_id: Option<String>,
+    window: tauri::Window,
+) -> Result<Written<CompressResult>, CommandError> {
+    run_blocking(move || {
+        let runtime = window.state::<GhostscriptRuntime>();
+        let jobs = window.state::<RunningJobs>();
+        let job = jobs.register(
+            job_id,
+            output_options.and_then(|options| options.timeout_secs),
+        )?;
+        let on_progress = progress_emitter(&window, &job.job_id);
+        write_output(
+            Path::new(&input_path),
+            Path::new(&output_path),
+            output_options.unwrap_or_default(),
+            |input, output, warnings| {
+                compress_with_ghostscript(
+                    input,
+                    output,
+                    preset,
+                    &runtime,
+                    job.context(&on_progress).collecting(warnings),
+                )
+            },
+        )
+    })
+    .await
+}
+
+/// Resample color, grayscale and monochrome images to the given resolutions and optionally
+/// re-encode color/gray images as JPEG at `jpeg_quality` (1-100). 0 leaves a setting alone.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn downsample_images(
+    input_path: String,
+    output_path: String,
+    color_dpi: u32,
+    gray_dpi: u32,
+    mono_dpi: u32,
+    jpeg_quality: u32,
+    output_options: Option<OutputOptions>,
+    job_id: Option<String>,
+    window: tauri::Window,
+) -> Result<Written<DownsampleResult>, CommandError> {
+    run_blocking(move || {
+        let runtime = window.state::<GhostscriptRuntime>();
+        let jobs = window.state::<RunningJobs>();
+        let job = jobs.register(
+            job_id,
+            output_options.and_then(|options| options.timeout_secs),
+        )?;
+        let on_progress = progress_emitter(&window, &job.job_id);
+        write_output(
+            Path::new(&input_path),
+            Path::new(&output_path),
+            output_options.unwrap_or_default(),
+            |input, output, warnings| {
+                downsample_with_ghostscript(
+                    input,
+                    output,
+                    [color_dpi, gray_dpi, mono_dpi],
+                    jpeg_quality,
+                    &runtime,
+                    job.context(&on_progress).collecting(warnings),
+                )
+            },
+        )
+    })
+    .await
+}
+
+/// Convert a PDF to grayscale. `color_pages` lists any pages where color survived.
+#[tauri::command]
+async fn convert_to_grayscale(
+    input_path: String,
+    output_path: String,
+    output_options: Option<OutputOptions>,
+    job_id: Option<String>,
+    window: tauri::Window,
+) -> Result<Written<GrayscaleResult>, CommandError> {
+    run_blocking(move || {
+        let runtime = window.state::<GhostscriptRuntime>();
+        let jobs = window.state::<RunningJobs>();
+        let job = jobs.register(
+            job_id,
+            output_options.and_then(|options| options.timeout_secs),
+        )?;
+        let on_progress = progress_emitter(&window, &job.job_id);
+        write_output(
+            Path::new(&input_path),
+            Path::new(&output_path),
+            output_options.unwrap_or_default(),
+            |input, output, warnings| {
+                grayscale_with_ghostscript(
+                    input,
+                    output,
+                    &runtime,
+                    job.context(&on_progress).collecting(warnings),
+                )
+            },
+        )
+    })
+    .await
+}
+
+/// Convert a PDF to CMYK, optionally against a specific output ICC profile. The rendering
+/// intent defaults to perceptual.
+#[tauri::command]
+async fn convert_to_cmyk(
+    input_path: String,
+    output_path: String,
+    icc_profile_path: Option<String>,
+    rendering_intent: Option<RenderingIntent>,
+    output_options: Option<OutputOptions>,
+    job_id: Option<String>,
+    window: tauri::Window,
+) -> Result<Written<CmykResult>, CommandError> {
+    run_blocking(move || {
+        let runtime = window.state::<GhostscriptRuntime>();
+        let jobs = window.state::<RunningJobs>();
+        let job = jobs.register(
+            job_id,
+            output_options.and_then(|options| options.timeout_secs),
+        )?;
+        let on_progress = progress_emitter(&window, &job.job_id);
+        write_output(
+            Path::new(&input_path),
+            Path::new(&output_path),
+            output_options.unwrap_or_default(),
+            |input, output, warnings| {
+                cmyk_with_ghostscript(
+                    input,
+                    output,
+                    icc_profile_path.as_deref().map(Path::new),
+                    rendering_intent.unwrap_or(RenderingIntent::Perceptual),
+                    &runtime,
+                    job.context(&on_progress).collecting(warnings),
+                )
+            },
+        )
+    })
+    .await
+}
+
+/// Convert a PDF to PDF/A (`1b`, `2b` or `3b`). `conformant` is false, with Ghostscript's
+/// reasons in `warnings`, when the result is not actually PDF/A.
+#[tauri::command]
+async fn convert_to_pdfa(
+    input_path: String,
+    output_path: String,
+    level: PdfaLevel,
+    output_options: Option<OutputOptions>,
+    job_id: Option<String>,
+    window: tauri::Window,
+) -> Result<Written<PdfaResult>, CommandError> {
+    run_blocking(move || {
+        let runtime = window.state::<GhostscriptRuntime>();
+        let jobs = window.state::<RunningJobs>();
+        let job = jobs.register(
+            job_id,
+            output_options.and_then(|options| options.timeout_secs),
+        )?;
+        let on_progress = progress_emitter(&window, &job.job_id);
+        write_output(
+            Path::new(&input_path),
+            Path::new(&output_path),
+            output_options.unwrap_or_default(),
+            |input, output, warnings| {
+                pdfa_with_ghostscript(
+                    input,
+                    output,
+                    level,
+                    &runtime,
+                    job.context(&on_progress).collecting(warnings),
+                )
+            },
+        )
+    })
+    .await
+}
+
+/// Export PDF/X-1a for print submission with `output_intent_icc` (a CMYK profile) as the
+/// output intent. Reports RGB pages converted, fonts that were not embedded, and whether
+/// transparency was flattened.
+#[tauri::command]
+async fn convert_to_pdfx(
+    input_path: String,
+    output_path: String,
+    output_intent_icc: String,
+    output_options: Option<OutputOptions>,
+    job_id: Option<String>,
+    window: tauri::Window,
+) -> Result<Written<PdfxResult>, CommandError> {
+    run_blocking(move || {
+        let runtime = window.state::<GhostscriptRuntime>();
+        let jobs = window.state::<RunningJobs>();
+        let job = jobs.register(
+            job_id,
+            output_options.and_then(|options| options.timeout_secs),
+        )?;
+        let on_progress = progress_emitter(&window, &job.job_id);
+        write_output(
+            Path::new(&input_path),
+            Path::new(&output_path),
+            output_options.unwrap_or_default(),
+            |input, output, warnings| {
+                pdfx_with_ghostscript(
+                    input,
+                    output,
+                    Path::new(&output_intent_icc),
+                    &runtime,
+                    job.context(&on_progress).collecting(warnings),
+                )
+            },
+        )
+    })
+    .await
+}
+
+/// Write a linearized copy for fast web view; `linearized` reports whether the output
+/// really is.
+#[tauri::command]
+async fn linearize_pdf(
+    input_path: String,
+    output_path: String,
+    output_options: Option<OutputOptions>,
+    job_id: Option<String>,
+    window: tauri::Window,
+) -> Result<Written<LinearizeResult>, CommandError> {
+    run_blocking(move || {
+        let runtime = window.state::<GhostscriptRuntime>();
+        let jobs = window.state::<RunningJobs>();
+        let job = jobs.register(
+            job_id,
+            output_options.and_then(|options| options.timeout_secs),
+        )?;
+        let on_progress = progress_emitter(&window, &job.job_id);
+        write_output(
+            Path::new(&input_path),
+            Path::new(&output_path),
+            output_options.unwrap_or_default(),
+            |input, output, warnings| {
+                linearize_with_ghostscript(
+                    input,
+                    output,
+                    &runtime,
+                    job.context(&on_progress).collecting(warnings),
+                )
+            },
+        )
+    })
+    .await
+}
+
+/// Write an unencrypted copy of a password-protected PDF. Fails with `"wrong-password"` when
+/// the password is missing or incorrect.
+#[tauri::command]
+async fn decrypt_pdf(
+    input_path: String,
+    output_path: String,
+    password: Option<String>,
+    output_options: Option<OutputOptions>,
+    job_id: Option<String>,
+    window: tauri::Window,
+) -> Result<Written<PageCountResult>, CommandError> {
+    run_blocking(move || {
+        let runtime = window.state::<GhostscriptRuntime>();
+        let jobs = window.state::<RunningJobs>();
+        let job = jobs.register(
+            job_id,
+            output_options.and_then(|options| options.timeout_secs),
+        )?;
+        let on_progress = progress_emitter(&window, &job.job_id);
+        write_output(
+            Path::new(&input_path),
+            Path::new(&output_path),
+            output_options.unwrap_or_default(),
+            |input, output, warnings| {
+                decrypt_with_ghostscript(
+                    input,
+                    output,
+                    password.as_deref(),
+                    &runtime,
+                    job.context(&on_progress).collecting(warnings),
+                )
+            },
+        )
+    })
+    .await
+}
+
+/// Password-protect a PDF. `owner_password` is required; an empty or missing
+/// `user_password` lets anyone open the file subject to the permissions. Reports the
+/// encryption actually applied.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn encrypt_pdf(
+    input_path: String,
+    output_path: String,
+    user_password: Option<String>,
+    owner_password: String,
+    allow_printing: bool,
+    allow_copying: bool,
+    output_options: Option<OutputOptions>,
+    job_id: Option<String>,
+    window: tauri::Window,
+) -> Result<Written<EncryptResult>, CommandError> {
+    run_blocking(move || {
+        let runtime = window.state::<GhostscriptRuntime>();
+        let jobs = window.state::<RunningJobs>();
+        let job = jobs.register(
+            job_id,
+            output_options.and_then(|options| options.timeout_secs),
+        )?;
+        let on_progress = progress_emitter(&window, &job.job_id);
+        write_output(
+            Path::new(&input_path),
+            Path::new(&output_path),
+            output_options.unwrap_or_default(),
+            |input, output, warnings| {
+                encrypt_with_ghostscript(
+                    input,
+                    output,
+                    user_password.as_deref().unwrap_or(""),
+                    &owner_password,
+                    allow_printing,
+                    allow_copying,
+                    &runtime,
+                    job.context(&on_progress).collecting(warnings),
+                )
+            },
+        )
+    })
+    .await
+}
+
+/// Title, author, dates and other document information for the inspector panel.
+#[tauri::command]
+fn get_pdf_metadata(path: String) -> Result<PdfMetadata, CommandError> {
+    read_pdf_metadata(Path::new(&path))
+}
+
+/// Write a copy with the given metadata fields changed and return the resulting metadata.
+/// Omitted fields are kept; an empty string removes one.
+#[tauri::command]
+fn set_pdf_metadata(
+    input_path: String,
+    output_path: String,
+    fields: MetadataPatch,
+    output_options: Option<OutputOptions>,
+) -> Result<Written<MetadataResult>, CommandError> {
+    write_output(
+        Path::new(&input_path),
+        Path::new(&output_path),
+        output_options.unwrap_or_default(),
+        |input, output, _| {
+            write_pdf_metadata(input, output, &fields).map(|metadata| MetadataResult {
+                output_path: output.to_string_lossy().to_string(),
+                metadata,
+            })
+        },
+    )
+}
+
+/// Number of pages in a PDF, without processing it.
+#[tauri::command]
+async fn get_page_count(path: String, app: tauri::AppHandle) -> Result<u32, CommandError> {
+    run_blocking(move || {
+        let runtime = app.state::<GhostscriptRuntime>();
+        quick_page_count(Path::new(&path), &runtime)
+    })
+    .await
+}
+
+/// Page boxes and displayed sizes for every page, in points.
+#[tauri::command]
+fn get_page_sizes(path: String) -> Result<Vec<PageSize>, CommandError> {
+    read_page_sizes(Path::new(&path))
+}
+
+/// Render one page to PNG bytes. `dpi` is lowered as needed to keep the bitmap under
+/// `max_pixels`; `transparent` keeps the page background transparent.
+#[tauri::command]
+async fn render_page_preview(
+    path: String,
+    page: u32,
+    dpi: u32,
+    max_pixels: u32,
+    transparent: Option<bool>,
+    job_id: Option<String>,
+    window: tauri::Window,
+) -> Result<Vec<u8>, CommandError> {
+    run_blocking(move || {
+        let runtime = window.state::<GhostscriptRuntime>();
+        let jobs = window.state::<RunningJobs>();
+        let job = jobs.register(job_id, None)?;
+        let on_progress = progress_emitter(&window, &job.job_id);
+        render_page_png(
+            Path::new(&path),
+            page,
+            dpi,
+            max_pixels,
+            transparent.unwrap_or(false),
+            &runtime,
+            job.context(&on_progress),
+        )
+    })
+    .await
+}
+
+/// PNG thumbnails `height_px` tall for `pages` (all pages when omitted), cached per file
+/// in the app cache directory until the file changes.
+#[tauri::command]
+async fn generate_thumbnails(
+    path: String,
+    pages: Option<Vec<u32>>,
+    height_px: u32,
+    job_id: Option<String>,
+    window: tauri::Window,
+) -> Result<Vec<Thumbnail>, CommandError> {
+    run_blocking(move || {
+        let runtime = window.state::<GhostscriptRuntime>();
+        let jobs = window.state::<RunningJobs>();
+        let cache_root = window
+            .app_handle()
+            .path_resolver()
+            .app_cache_dir()
+            .unwrap_or_else(|| std::env::temp_dir().join("pdfresizer_cache"));
+        let job = jobs.register(job_id, None)?;
+        let on_progress = progress_emitter(&window, &job.job_id);
+        generate_page_thumbnails(
+            Path::new(&path),
+            pages,
+            height_px,
+            &cache_root,
+            &runtime,
+            job.context(&on_progress),
+        )
+    })
+    .await
+}
+
+/// Write pages as PNG, JPEG or TIFF files and return their paths. `jpeg_quality` defaults
+/// to 90 and TIFFs are LZW-compressed unless `tiff_lzw` is false.
+#[allow(clippy::too_many_arguments)]
+#[tauri::command]
+async fn export_pages_as_images(
+    input_path: String,
+    output_dir: String,
+    format: ImageFormat,
+    dpi: u32,
+    pages: Option<Vec<u32>>,
+    jpeg_quality: Option<u8>,
+    tiff_lzw: Option<bool>,
+    output_options: Option<OutputOptions>,
+    job_id: Option<String>,
+    window: tauri::Window,
+) -> Result<Vec<String>, CommandError> {
+    run_blocking(move || {
+        let runtime = window.state::<GhostscriptRuntime>();
+        let jobs = window.state::<RunningJobs>();
+        let job = jobs.register(
+            job_id,
+            output_options.and_then(|options| options.timeout_secs),
+        )?;
+        let on_progress = progress_emitter(&window, &job.job_id);
+        export_images_with_ghostscript(
+            Path::new(&input_path),
+            Path::new(&output_dir),
+            format,
+            dpi,
+            pages,
+            jpeg_quality.unwrap_or(90),
+            tiff_lzw.unwrap_or(true),
+            output_options.unwrap_or_default().on_conflict,
+            &runtime,
+            job.context(&on_progress),
+        )
+    })
+    .await
+}
+
+/// Convert to PostScript or single-page EPS for RIPs that do not take PDF.
+/// `language_level` (2 or 3) is left to Ghostscript when omitted.
+#[allow(clippy::too_many_arguments)]
+#[tauri::command]
+async fn export_postscript(
+    input_path: String,
+    output_path: String,
+    device: PostScriptDevice,
+    pages: Option<Vec<u32>>,
+    language_level: Option<u8>,
+    output_options: Option<OutputOptions>,
+    job_id: Option<String>,
+    window: tauri::Window,
+) -> Result<Written<PostScriptResult>, CommandError> {
+    run_blocking(move || {
+        let runtime = window.state::<GhostscriptRuntime>();
+        let jobs = window.state::<RunningJobs>();
+        let job = jobs.register(
+            job_id,
+            output_options.and_then(|options| options.timeout_secs),
+        )?;
+        let on_progress = progress_emitter(&window, &job.job_id);
+        write_output(
+            Path::new(&input_path),
+            Path::new(&output_path),
+            output_options.unwrap_or_default(),
+            |input, output, warnings| {
+                postscript_with_ghostscript(
+                    input,
+                    output,
+                    device,
+                    pages.clone(),
+                    language_level,
+                    &runtime,
+                    job.context(&on_progress).collecting(warnings),
+                )
+            },
+        )
+    })
+    .await
+}
+
+/// Draw `text` over the page content of the selected pages.
+#[tauri::command]
+async fn add_watermark(
+    input_path: String,
+    output_path: String,
+    text: String,
+    options: WatermarkOptions,
+    output_options: Option<OutputOptions>,
+    job_id: Option<String>,
+    window: tauri::Window,
+) -> Result<Written<WatermarkResult>, CommandError> {
+    run_blocking(move || {
+        let runtime = window.state::<GhostscriptRuntime>();
+        let jobs = window.state::<RunningJobs>();
+        let job = jobs.register(
+            job_id,
+            output_options.and_then(|options| options.timeout_secs),
+        )?;
+        let on_progress = progress_emitter(&window, &job.job_id);
+        write_output(
+            Path::new(&input_path),
+            Path::new(&output_path),
+            output_options.unwrap_or_default(),
+            |input, output, warnings| {
+                watermark_with_ghostscript(
+                    input,
+                    output,
+                    &text,
+                    &options,
+                    &runtime,
+                    job.context(&on_progress).collecting(warnings),
+                )
+            },
+        )
+    })
+    .await
+}
+
+/// Overlay a PDF page or PNG image (letterhead, approval stamp) above or below the
+/// content of the selected pages.
+#[tauri::command]
+async fn stamp_pdf(
+    input_path: String,
+    output_path: String,
+    stamp_path: String,
+    placement: StampPlacement,
+    output_options: Option<OutputOptions>,
+) -> Result<Written<StampResult>, CommandError> {
+    run_blocking(move || {
+        write_output(
+            Path::new(&input_path),
+            Path::new(&output_path),
+            output_options.unwrap_or_default(),
+            |input, output, _| stamp_pdf_pages(input, output, Path::new(&stamp_path), &placement),
+        )
+    })
+    .await
+}
+
+/// Number pages with a `{n}` / `{total}` format string, drawn horizontally inside each
+/// page's CropBox.
+#[tauri::command]
+async fn add_page_numbers(
+    input_path: String,
+    output_path: String,
+    options: PageNumberOptions,
+    output_options: Option<OutputOptions>,
+    job_id: Option<String>,
+    window: tauri::Window,
+) -> Result<Written<PageNumberResult>, CommandError> {
+    run_blocking(move || {
+        let runtime = window.state::<GhostscriptRuntime>();
+        let jobs = window.state::<RunningJobs>();
+        let job = jobs.register(
+            job_id,
+            output_options.and_then(|options| options.timeout_secs),
+        )?;
+        let on_progress = progress_emitter(&window, &job.job_id);
+        write_output(
+            Path::new(&input_path),
+            Path::new(&output_path),
+            output_options.unwrap_or_default(),
+            |input, output, warnings| {
+                page_numbers_with_ghostscript(
+                    input,
+                    output,
+                    &options,
+                    &runtime,
+                    job.context(&on_progress).collecting(warnings),
+                )
+            },
+        )
+    })
+    .await
+}
+
+/// Put each page on a sheet grown by `bleed_pt` plus the mark area, with crop marks at
+/// the trim corners and optional registration marks and color bars.
+#[tauri::command]
+fn add_printer_marks(
+    input_path: String,
+    output_path: String,
+    bleed_pt: f64,
+    mark_length_pt: f64,
+    mark_offset_pt: f64,
+    marks: PrinterMarks,
+    output_options: Option<OutputOptions>,
+) -> Result<Written<PrinterMarksResult>, CommandError> {
+    write_output(
+        Path::new(&input_path),
+        Path::new(&output_path),
+        output_options.unwrap_or_default(),
+        |input, output, _| {
+            printer_marks_pdf_pages(
+                input,
+                output,
+                bleed_pt,
+                mark_length_pt,
+                mark_offset_pt,
+                marks,
+            )
+        },
+    )
+}
+
+/// Mirror the selected pages for film output, keeping text as text.
+#[tauri::command]
+async fn mirror_pages(
+    input_path: String,
+    output_path: String,
+    axis: MirrorAxis,
+    pages: Option<Vec<u32>>,
+    output_options: Option<OutputOptions>,
+    job_id: Option<String>,
+    window: tauri::Window,
+) -> Result<Written<MirrorResult>, CommandError> {
+    run_blocking(move || {
+        let runtime = window.state::<GhostscriptRuntime>();
+        let jobs = window.state::<RunningJobs>();
+        let job = jobs.register(
+            job_id,
+            output_options.and_then(|options| options.timeout_secs),
+        )?;
+        let on_progress = progress_emitter(&window, &job.job_id);
+        write_output(
+            Path::new(&input_path),
+            Path::new(&output_path),
+            output_options.unwrap_or_default(),
+            |input, output, warnings| {
+                mirror_with_ghostscript(
+                    input,
+                    output,
+                    axis,
+                    pages.clone(),
+                    &runtime,
+                    job.context(&on_progress).collecting(warnings),
+                )
+            },
+        )
+    })
+    .await
+}
+
+/// Rewrite a damaged PDF as leniently as Ghostscript allows and report what came out.
+#[tauri::command]
+async fn repair_pdf(
+    input_path: String,
+    output_path: String,
+    output_options: Option<OutputOptions>,
+    job_id: Option<String>,
+    window: tauri::Window,
+) -> Result<Written<RepairReport>, CommandError> {
+    run_blocking(move || {
+        let runtime = window.state::<GhostscriptRuntime>();
+        let jobs = window.state::<RunningJobs>();
+        let job = jobs.register(
+            job_id,
+            output_options.and_then(|options| options.timeout_secs),
+        )?;
+        let on_progress = progress_emitter(&window, &job.job_id);
+        write_output(
+            Path::new(&input_path),
+            Path::new(&output_path),
+            output_options.unwrap_or_default(),
+            |input, output, warnings| {
+                repair_with_ghostscript(
+                    input,
+                    output,
+                    &runtime,
+                    job.context(&on_progress).collecting(warnings),
+                )
+            },
+        )
+    })
+    .await
+}
+
+/// Fonts used in the document, with their type and embedding status.
+#[tauri::command]
+async fn list_fonts(path: String) -> Result<Vec<FontInfo>, CommandError> {
+    run_blocking(move || Ok(document_fonts(&load_pdf_document(Path::new(&path))?))).await
+}
+
+/// Embed every font, from the system where the input lacks it. See
+/// `embed_fonts_with_ghostscript` for what `substitute_missing: false` rejects.
+#[tauri::command]
+async fn embed_fonts(
+    input_path: String,
+    output_path: String,
+    substitute_missing: bool,
+    output_options: Option<OutputOptions>,
+    job_id: Option<String>,
+    window: tauri::Window,
+) -> Result<Written<EmbedFontsResult>, CommandError> {
+    run_blocking(move || {
+        let runtime = window.state::<GhostscriptRuntime>();
+        let jobs = window.state::<RunningJobs>();
+        let job = jobs.register(
+            job_id,
+            output_options.and_then(|options| options.timeout_secs),
+        )?;
+        let on_progress = progress_emitter(&window, &job.job_id);
+        write_output(
+            Path::new(&input_path),
+            Path::new(&output_path),
+            output_options.unwrap_or_default(),
+            |input, output, warnings| {
+                embed_fonts_with_ghostscript(
+                    input,
+                    output,
+                    substitute_missing,
+                    &runtime,
+                    job.context(&on_progress).collecting(warnings),
+                )
+            },
+        )
+    })
+    .await
+}
+
+/// Which pages use live transparency, and how.
+#[tauri::command]
+fn detect_transparency(path: String) -> Result<TransparencyReport, CommandError> {
+    let doc = load_pdf_document(Path::new(&path))?;
+    let pages: Vec<PageTransparency> = doc
+        .get_pages()
+        .into_iter()
+        .map(|(page, page_id)| page_transparency(&doc, page, page_id))
+        .collect();
+    Ok(TransparencyReport {
+        transparent_pages: pages
+            .iter()
+            .filter(|page| page.any())
+            .map(|page| page.page)
+            .collect(),
+        pages,
+    })
+}
+
+/// Color spaces and spot colors used on each page.
+#[tauri::command]
+fn analyze_colors(path: String) -> Result<ColorReport, CommandError> {
+    let doc = load_pdf_document(Path::new(&path))?;
+    let pages: Vec<PageColors> = doc
+        .get_pages()
+        .into_iter()
+        .map(|(page, page_id)| page_colors(&doc, page, page_id))
+        .collect();
+    let mut spot_colors: Vec<String> = Vec::new();
+    for name in pages.iter().flat_map(|page| &page.spot_colors) {
+        if !spot_colors.contains(name) {
+            spot_colors.push(name.clone());
+        }
+    }
+    Ok(ColorReport { spot_colors, pages })
+}
+
+/// CMYK ink coverage of the selected pages (all when omitted).
+#[tauri::command]
+async fn ink_coverage(
+    path: String,
+    pages: Option<Vec<u32>>,
+    job_id: Option<String>,
+    window: tauri::Window,
+) -> Result<Vec<InkCoverage>, CommandError> {
+    run_blocking(move || {
+        let runtime = window.state::<GhostscriptRuntime>();
+        let jobs = window.state::<RunningJobs>();
+        let job = jobs.register(job_id, None)?;
+        let on_progress = progress_emitter(&window, &job.job_id);
+        ink_coverage_with_ghostscript(Path::new(&path), pages, &runtime, job.context(&on_progress))
+    })
+    .await
+}
+
+/// Strip comments, markup and other annotations; see `remove_pdf_annotations`.
+#[tauri::command]
+fn remove_annotations(
+    input_path: String,
+    output_path: String,
+    keep_links: bool,
+    output_options: Option<OutputOptions>,
+) -> Result<Written<RemoveAnnotationsResult>, CommandError> {
+    write_output(
+        Path::new(&input_path),
+        Path::new(&output_path),
+        output_options.unwrap_or_default(),
+        |input, output, _| remove_pdf_annotations(input, output, keep_links),
+    )
+}
+
+/// Bake filled-in form fields into the pages; see `flatten_pdf_forms`.
+#[tauri::command]
+async fn flatten_forms(
+    input_path: String,
+    output_path: String,
+    output_options: Option<OutputOptions>,
+) -> Result<Written<FlattenFormsResult>, CommandError> {
+    run_blocking(move || {
+        write_output(
+            Path::new(&input_path),
+            Path::new(&output_path),
+            output_options.unwrap_or_default(),
+            |input, output, _| flatten_pdf_forms(input, output),
+        )
+    })
+    .await
+}
+
+/// Strip JavaScript, launch and URI actions and attachments; see `sanitize_pdf_document`.
+#[tauri::command]
+async fn sanitize_pdf(
+    input_path: String,
+    output_path: String,
+    options: SanitizeOptions,
+    output_options: Option<OutputOptions>,
+) -> Result<Written<SanitizeReport>, CommandError> {
+    run_blocking(move || {
+        write_output(
+            Path::new(&input_path),
+            Path::new(&output_path),
+            output_options.unwrap_or_default(),
+            |input, output, _| sanitize_pdf_document(input, output, &options),
+        )
+    })
+    .await
+}
+
+/// Plain text of the selected pages (all when omitted), one string per page.
+#[tauri::command]
+async fn extract_text(
+    path: String,
+    pages: Option<Vec<u32>>,
+    job_id: Option<String>,
+    window: tauri::Window,
+) -> Result<Vec<String>, CommandError> {
+    run_blocking(move || {
+        let runtime = window.state::<GhostscriptRuntime>();
+        let jobs = window.state::<RunningJobs>();
+        let job = jobs.register(job_id, None)?;
+        let on_progress = progress_emitter(&window, &job.job_id);
+        extract_text_with_ghostscript(Path::new(&path), pages, &runtime, job.context(&on_progress))
+    })
+    .await
+}
+
+/// Save the document's images to `output_dir`; see `extract_pdf_images`.
+#[tauri::command]
+async fn extract_images(
+    path: String,
+    output_dir: String,
+    min_pixels: u32,
+    output_options: Option<OutputOptions>,
+) -> Result<Vec<ExtractedImage>, CommandError> {
+    run_blocking(move || {
+        extract_pdf_images(
+            Path::new(&path),
+            Path::new(&output_dir),
+            min_pixels,
+            output_options.unwrap_or_default().on_conflict,
+        )
+    })
+    .await
+}
+
+/// Files embedded in the document or attached to its pages.
+#[tauri::command]
+fn list_attachments(path: String) -> Result<Vec<Attachment>, CommandError> {
+    let doc = load_pdf_document(Path::new(&path))?;
+    Ok(document_attachments(&doc)
+        .into_iter()
+        .map(|(attachment, _)| attachment)
+        .collect())
+}
+
+/// Save one attachment, by the name `list_attachments` reports, to `output_path`. Reports
+/// the path written, which differs under `ConflictPolicy::Rename`.
+#[tauri::command]
+fn extract_attachment(
+    path: String,
+    name: String,
+    output_path: String,
+    output_options: Option<OutputOptions>,
+) -> Result<Written<ExtractedAttachment>, CommandError> {
+    write_output(
+        Path::new(&path),
+        Path::new(&output_path),
+        output_options.unwrap_or_default(),
+        |input, output, _| {
+            extract_pdf_attachment(input, &name, output).map(|()| ExtractedAttachment {
+                output_path: output.to_string_lossy().to_string(),
+            })
+        },
+    )
+}
+
+/// The bookmark tree with each bookmark's target page.
+#[tauri::command]
+fn get_outline(path: String) -> Result<Vec<OutlineNode>, CommandError> {
+    let doc = load_pdf_document(Path::new(&path))?;
+    let resolver = DestinationResolver::new(&doc);
+    let first = doc
+        .catalog()
+        .ok()
+        .and_then(|catalog| catalog.get(b"Outlines").ok())
+        .and_then(|outlines| pdf_dict(&doc, outlines))
+        .and_then(|outlines| outlines.get(b"First").ok());
+    Ok(outline_nodes(&resolver, first, 0, &mut HashSet::new()))
+}
+
+/// Set TrimBox and BleedBox on the selected pages (all when omitted).
+#[tauri::command]
+fn set_page_boxes(
+    input_path: String,
+    output_path: String,
+    trim: Option<[f64; 4]>,
+    bleed: Option<[f64; 4]>,
+    pages: Option<Vec<u32>>,
+    output_options: Option<OutputOptions>,
+) -> Result<Written<PageBoxesResult>, CommandError> {
+    write_output(
+        Path::new(&input_path),
+        Path::new(&output_path),
+        output_options.unwrap_or_default(),
+        |input, output, _| set_pdf_page_boxes(input, output, trim, bleed, pages.clone()),
+    )
+}
+
+/// Composite another PDF's pages onto `base_path` page by page, e.g. letterhead or a
+/// "draft" background. `repeat` decides what a shorter overlay does past its last page.
+#[tauri::command]
+async fn overlay_pdfs(
+    base_path: String,
+    overlay_path: String,
+    output_path: String,
+    mode: StampLayer,
+    repeat: OverlayRepeat,
+    fit: Option<bool>,
+    output_options: Option<OutputOptions>,
+) -> Result<Written<OverlayResult>, CommandError> {
+    run_blocking(move || {
+        write_output(
+            Path::new(&base_path),
+            Path::new(&output_path),
+            output_options.unwrap_or_default(),
+            |input, output, _| {
+                overlay_pdf_documents(
+                    input,
+                    Path::new(&overlay_path),
+                    output,
+                    mode,
+                    repeat,
+                    fit.unwrap_or(false),
+                )
+            },
+        )
+    })
+    .await
+}
+
+/// The temp directory intermediate files go to, with its volume and free space, for
+/// diagnosing disk space reports.
+#[tauri::command]
+fn get_temp_info() -> WritableCheck {
+    check_output_location(&std::env::temp_dir(), 0)
+}
+
+/// Pre-flight check of an output folder: writability, free space against `needed_bytes`,
+/// and whether it is on a removable or network volume.
+#[tauri::command]
+fn check_output_writable(dir: String, needed_bytes: u64) -> WritableCheck {
+    check_output_location(Path::new(&dir), needed_bytes)
+}
+
+/// Size, modification time and flags of every path, for the file list. Missing paths get
+/// an `exists: false` entry.
+#[tauri::command]
+fn get_file_info(paths: Vec<String>) -> Vec<FileInfo> {
+    paths
+        .iter()
+        .map(|path| file_info(Path::new(path)))
+        .collect()
+}
+
+/// Check an output name template for the settings screen; unknown placeholders and
+/// unclosed braces are errors.
+#[tauri::command]
+fn validate_output_template(template: String) -> Result<(), CommandError> {
+    parse_name_template(&template, false).map(|_| ())
+}
+
+/// The output path for `input_path` under `template`, in `output_dir` or next to the input.
+#[tauri::command]
+fn render_output_path(
+    input_path: String,
+    template: String,
+    output_dir: Option<String>,
+    preset: Option<String>,
+) -> Result<String, CommandError> {
+    let input = Path::new(&input_path);
+    let context = OutputNameContext::for_input(input, preset);
+    let name = render_output_name(&template, &context, "pdf")?;
+    let dir = match &output_dir {
+        Some(dir) => PathBuf::from(dir),
+        None => input.parent().map(Path::to_path_buf).unwrap_or_default(),
+    };
+    Ok(dir.join(name).to_string_lossy().to_string())
+}
+
+/// Header check for files about to be added, so non-PDFs are rejected up front rather
+/// than failing later in Ghostscript.
+#[tauri::command]
+fn check_pdf_files(paths: Vec<String>) -> Vec<PdfCheck> {
+    paths
+        .iter()
+        .map(|path| check_pdf_file(Path::new(path)))
+        .collect()
+}
+
+/// Structural check with a pass/fail verdict; see `validate_pdf_file`.
+#[tauri::command]
+async fn validate_pdf(
+    path: String,
+    job_id: Option<String>,
+    window: tauri::Window,
+) -> Result<ValidationReport, CommandError> {
+    run_blocking(move || {
+        let runtime = window.state::<GhostscriptRuntime>();
+        let jobs = window.state::<RunningJobs>();
+        let job = jobs.register(job_id, None)?;
+        let on_progress = progress_emitter(&window, &job.job_id);
+        validate_pdf_file(Path::new(&path), &runtime, job.context(&on_progress))
+    })
+    .await
+}
+
+/// Show a file, e.g. a job's output, in the platform's file manager.
+#[tauri::command]
+fn reveal_in_file_manager(path: String) -> Result<(), CommandError> {
+    reveal_path(Path::new(&path))
+}
+
+/// Open a document, e.g. a job's output, in its default app.
+#[tauri::command]
+fn open_with_default_app(path: String) -> Result<(), CommandError> {
+    open_document(Path::new(&path))
+}
+
+/// Move `path` to the top of the recent files, adding it if new.
+#[tauri::command]
+fn add_recent_file(path: String, recent: State<'_, RecentFiles>) -> Result<(), CommandError> {
+    let path = canonical_open_path(&path);
+    let key = open_path_key(&path);
+    let last_used_ms = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or(0);
+    recent.update(|entries| {
+        entries.retain(|entry| open_path_key(&entry.path) != key);
+        entries.insert(0, RecentFileEntry { path, last_used_ms });
+        entries.truncate(MAX_RECENT_FILES);
+    })
+}
+
+/// The most recently used files first, at most `limit` of them.
+#[tauri::command]
+fn get_recent_files(limit: Option<usize>, recent: State<'_, RecentFiles>) -> Vec<RecentFile> {
+    let entries = recent.entries.lock().expect("recent files mutex poisoned");
+    entries
+        .iter()
+        .take(limit.unwrap_or(MAX_RECENT_FILES))
+        .map(|entry| RecentFile {
+            path: entry.path.clone(),
+            last_used_ms: entry.last_used_ms,
+            missing: !path_exists(Path::new(&entry.path)),
+        })
+        .collect()
+}
+
+#[tauri::command]
+fn clear_recent_files(recent: State<'_, RecentFiles>) -> Result<(), CommandError> {
+    recent.update(Vec::clear)
+}
+
+/// Finished queue jobs matching `filter`, most recent first, at most `limit` of them.
+#[tauri::command]
+fn get_job_history(
+    limit: Option<usize>,
+    filter: Option<JobHistoryFilter>,
+    history: State<'_, JobHistory>,
+) -> Vec<JobHistoryEntry> {
+    let filter = filter.unwrap_or_default();
+    let path = filter.path.as_deref().map(str::to_lowercase);
+    let entries = history.entries.lock().expect("job history mutex poisoned");
+    entries
+        .iter()
+        .filter(|entry| {
+            path.as_deref().map_or(true, |path| {
+                entry.input_path.to_lowercase().contains(path)
+                    || entry
+                        .output_path
+                        .as_deref()
+                        .is_some_and(|output| output.to_lowercase().contains(path))
+            })
+        })
+        .filter(|entry| filter.state.map_or(true, |state| entry.state == state))
+        .filter(|entry| {
+            filter
+                .kind
+                .as_deref()
+                .map_or(true, |kind| entry.operation.kind() == kind)
+        })
+        .filter(|entry| {
+            filter
+                .since_ms
+                .map_or(true, |since| entry.finished_ms >= since)
+        })
+        .filter(|entry| {
+            filter
+                .until_ms
+                .map_or(true, |until| entry.finished_ms < until)
+        })
+        .take(limit.unwrap_or(MAX_JOB_HISTORY))
+        .cloned()
+        .collect()
+}
+
+#[tauri::command]
+fn clear_job_history(history: State<'_, JobHistory>) -> Result<(), CommandError> {
+    history.update(Vec::clear)
+}
+
+#[tauri::command]
+fn get_settings(store: State<'_, SettingsStore>) -> Settings {
+    store.get()
+}
+
+/// Change the settings named in `patch`, keeping the rest; `null` clears an optional one.
+/// Returns the settings now in effect.
+#[tauri::command]
+fn update_settings(
+    patch: serde_json::Map<String, serde_json::Value>,
+    store: State<'_, SettingsStore>,
+) -> Result<Settings, CommandError> {
+    let mut settings = serde_json::to_value(store.get()).map_err(|e| CommandError::Io {
+        action: "serialize_settings",
+        error: e.to_string(),
+    })?;
+    if let serde_json::Value::Object(fields) = &mut settings {
+        for (name, value) in patch {
+            if name != "schema_version" {
+                fields.insert(name, value);
+            }
+        }
+    }
+    let settings: Settings =
+        serde_json::from_value(settings).map_err(|e| CommandError::InvalidSettings {
+            error: e.to_string(),
+        })?;
+    apply_settings(&settings);
+    store.replace(settings)
+}
+
+/// Restore the default settings, forgetting any from newer versions too.
+#[tauri::command]
+fn reset_settings(store: State<'_, SettingsStore>) -> Result<Settings, CommandError> {
+    apply_settings(&Settings::default());
+    store.replace(Settings::default())
+}
+
+/// Zip what support asks for first to `output_path`: app, OS and Ghostscript versions, a
+/// self-test flatten, the settings and the end of the log. Neither documents nor
+/// passwords are included. Returns the path written.
+#[tauri::command]
+async fn create_diagnostics_bundle(
+    output_path: String,
+    app: tauri::AppHandle,
+) -> Result<String, CommandError> {
+    run_blocking(move || {
+        let runtime = app.state::<GhostscriptRuntime>();
+        let store = app.state::<SettingsStore>();
+        let mut probe = GhostscriptProbeLog::default();
+        let ghostscript = ghostscript_path_info(&runtime, &mut probe);
+        let report = DiagnosticsReport {
+            app_version: app.package_info().version.to_string(),
+            os: std::env::consts::OS,
+            os_version: os_version(),
+            arch: std::env::consts::ARCH,
+            ghostscript,
+            ghostscript_attempted: probe.attempted,
+            self_test: run_self_test(&runtime),
+        };
+        let report = serde_json::to_vec_pretty(&report).map_err(|e| CommandError::Io {
+            action: "serialize_diagnostics_report",
+            error: e.to_string(),
+        })?;
+        let settings = redacted_settings(&store.get())?;
+        let log = log_tail(DIAGNOSTICS_LOG_TAIL_BYTES);
+        let archive = zip_archive(&[
+            ("report.json", &report),
+            ("settings.json", &settings),
+            ("log.txt", &log),
+        ])
+        .map_err(|e| CommandError::Io {
+            action: "zip_diagnostics_bundle",
+            error: e.to_string(),
+        })?;
+        write_file_atomically(Path::new(&output_path), &archive).map_err(|e| CommandError::Io {
+            action: "write",
+            error: format!("'{}': {}", output_path, e),
+        })?;
+        log_info!("Wrote diagnostics bundle to {}", output_path);
+        Ok(output_path)
+    })
+    .await
+}
+
+/// Where the log is written, so support can ask for it; `None` when it couldn't be opened.
+#[tauri::command]
+fn get_log_file_path() -> Option<String> {
+    log_file_path().map(|path| path.to_string_lossy().to_string())
+}
+
+/// Change how much is logged from now on, and on later starts.
+#[tauri::command]
+fn set_log_level(level: LogLevel, store: State<'_, SettingsStore>) -> Result<(), CommandError> {
+    let mut settings = store.get();
+    settings.log_level = level;
+    store.replace(settings)?;
+    apply_log_level(level);
+    log_info!("Log level set to {:?}", level);
+    Ok(())
+}
+
+#[tauri::command]
+fn list_presets(store: State<'_, PresetStore>) -> Vec<Preset> {
+    store.list()
+}
+
+/// Save `operation` as a user preset, replacing one of the same name.
+#[tauri::command]
+fn save_preset(
+    name: String,
+    operation: Operation,
+    store: State<'_, PresetStore>,
+) -> Result<Preset, CommandError> {
+    validate_preset_name(&name)?;
+    let preset = Preset {
+        name,
+        operation,
+        built_in: false,
+    };
+    store.update(|presets| {
+        match presets.iter_mut().find(|p| p.name == preset.name) {
+            Some(existing) => *existing = preset.clone(),
+            None => presets.push(preset.clone()),
+        }
+        Ok(())
+    })?;
+    Ok(preset)
+}
+
+/// Copy any preset, built-in ones included, to a new user preset.
+#[tauri::command]
+fn duplicate_preset(
+    name: String,
+    new_name: String,
+    store: State<'_, PresetStore>,
+) -> Result<Preset, CommandError> {
+    validate_preset_name(&new_name)?;
+    let source = store
+        .find(&name)
+        .ok_or_else(|| CommandError::PresetNotFound { name: name.clone() })?;
+    let preset = Preset {
+        name: new_name,
+        operation: source.operation,
+        built_in: false,
+    };
+    store.update(|presets| {
+        if presets.iter().any(|p| p.name == preset.name) {
+            return Err(CommandError::PresetExists {
+                name: preset.name.clone(),
+            });
+        }
+        presets.push(preset.clone());
+        Ok(())
+    })?;
+    Ok(preset)
+}
+
+#[tauri::command]
+fn delete_preset(name: String, store: State<'_, PresetStore>) -> Result<(), CommandError> {
+    store.update(|presets| {
+        let index = user_preset_index(presets, &name)?;
+        presets.remove(index);
+        Ok(())
+    })
+}
+
+#[tauri::command]
+fn rename_preset(
+    old_name: String,
+    new_name: String,
+    store: State<'_, PresetStore>,
+) -> Result<(), CommandError> {
+    validate_preset_name(&new_name)?;
+    store.update(|presets| {
+        let index = user_preset_index(presets, &old_name)?;
+        if presets
+            .iter()
+            .any(|preset| preset.name == new_name && preset.name != old_name)
+        {
+            return Err(CommandError::PresetExists { name: new_name });
+        }
+        presets[index].name = new_name;
+        Ok(())
+    })
+}
+
+/// Write the user's presets to `path` as one JSON document, for `import_presets`.
+#[tauri::command]
+fn export_presets(path: String, store: State<'_, PresetStore>) -> Result<usize, CommandError> {
+    let presets = store
+        .presets
+        .lock()
+        .expect("presets mutex poisoned")
+        .clone();
+    let contents = serde_json::to_string_pretty(&PresetExport {
+        version: PRESET_EXPORT_VERSION,
+        presets: &presets,
+    })
+    .map_err(|e| CommandError::Io {
+        action: "serialize_presets",
+        error: e.to_string(),
+    })?;
+    write_file_atomically(Path::new(&path), contents.as_bytes()).map_err(|e| CommandError::Io {
+        action: "write",
+        error: format!("'{}': {}", path, e),
+    })?;
+    Ok(presets.len())
+}
+
+/// Add the presets exported to `path`. `on_conflict` decides what happens to those whose
+/// name is taken; `error` rejects them.
+#[tauri::command]
+fn import_presets(
+    path: String,
+    on_conflict: Option<ConflictPolicy>,
+    store: State<'_, PresetStore>,
+) -> Result<PresetImportReport, CommandError> {
+    let contents = std::fs::read_to_string(&path).map_err(|e| CommandError::Io {
+        action: "read",
+        error: format!("'{}': {}", path, e),
+    })?;
+    let document: serde_json::Value =
+        serde_json::from_str(&contents).map_err(|e| CommandError::NotAPresetFile {
+            path: path.clone(),
+            error: e.to_string(),
+        })?;
+    let mut report = PresetImportReport::default();
+    store.update(|presets| {
+        report = import_preset_entries(
+            &document,
+            presets,
+            &built_in_presets(),
+            on_conflict.unwrap_or(ConflictPolicy::Skip),
+        )
+        .map_err(|error| CommandError::NotAPresetFile {
+            path: path.clone(),
+            error,
+        })?;
+        Ok(())
+    })?;
+    Ok(report)
+}
+
+/// Run one operation, given inline or as a preset's name, on each input, writing into
+/// `output_dir` under names from `name_template` (e.g. `{name}-{preset}`). A failed file
+/// is reported and the rest still run; cancelling stops the whole batch.
+#[allow(clippy::too_many_arguments)]
+#[tauri::command]
+async fn process_batch(
+    input_paths: Vec<String>,
+    output_dir: String,
+    operation: Option<Operation>,
+    preset: Option<String>,
+    name_template: Option<String>,
+    output_options: Option<OutputOptions>,
+    job_id: Option<String>,
+    window: tauri::Window,
+) -> Result<Vec<BatchItemResult>, CommandError> {
+    run_blocking(move || {
+        let runtime = window.state::<GhostscriptRuntime>();
+        let jobs = window.state::<RunningJobs>();
+        let presets = window.state::<PresetStore>();
+        let operation = match (operation, &preset) {
+            (Some(operation), _) => operation,
+            (None, Some(name)) => {
+                presets
+                    .find(name)
+                    .ok_or_else(|| CommandError::PresetNotFound { name: name.clone() })?
+                    .operation
+            }
+            (None, None) => return Err(CommandError::MissingInput { field: "operation" }),
+        };
+        let template = name_template.unwrap_or_else(|| String::from("{name}"));
+        parse_name_template(&template, false)?;
+        let output_dir = PathBuf::from(output_dir);
+        std::fs::create_dir_all(&output_dir).map_err(|e| CommandError::Io {
+            action: "create",
+            error: format!("'{}': {}", output_dir.display(), e),
+        })?;
+        let input_bytes = input_paths
+            .iter()
+            .filter_map(|path| std::fs::metadata(path).ok())
+            .map(|metadata| metadata.len())
+            .sum();
+        ensure_output_writable(&output_dir, input_bytes)?;
+
+        let job = jobs.register(
+            job_id,
+            output_options.and_then(|options| options.timeout_secs),
+        )?;
+        let on_progress = progress_emitter(&window, &job.job_id);
+        let options = output_options.unwrap_or_default();
+        let mut results = Vec::with_capacity(input_paths.len());
+        for input_path in input_paths {
+            let input = Path::new(&input_path);
+            let context = OutputNameContext::for_input(input, preset.clone());
+            let written = render_output_name(&template, &context, "pdf").and_then(|name| {
+                run_operation(
+                    &operation,
+                    input,
+                    &output_dir.join(name),
+                    options,
+                    &runtime,
+                    job.context(&on_progress),
+                )
+            });
+            match written {
+                Err(error @ CommandError::Cancelled) => return Err(error),
+                Ok((output_path, warnings)) => results.push(BatchItemResult {
+                    input_path,
+                    output_path: Some(output_path),
+                    error: None,
+                    warnings,
+                }),
+                Err(error) => results.push(BatchItemResult {
+                    input_path,
+                    output_path: None,
+                    error: Some(error),
+                    warnings: Vec::new(),
+                }),
+            }
+        }
+        Ok(results)
+    })
+    .await
+}
+
+/// Process every PDF that lands in `folder` with `preset`, writing the results into
+/// `output_dir` under the settings' output name template, renamed on conflicts. Files
+/// already in the folder are left alone, and new ones are picked up once their size and
+/// modification time stop changing. The folder is scanned when the OS reports a change.
+/// A file that fails is reported and the watcher keeps going; it is only tried again
+/// once it changes. Emits `watch-file-changed` per file; returns the ID for `stop_watch`.
+#[tauri::command]
+fn start_watch(
+    folder: String,
+    preset: String,
+    output_dir: String,
+    app: tauri::AppHandle,
+    watchers: State<'_, FolderWatchers>,
+    presets: State<'_, PresetStore>,
+) -> Result<String, CommandError> {
+    let folder = PathBuf::from(folder);
+    if !folder.is_dir() {
+        return Err(CommandError::NotAFolder {
+            path: folder.to_string_lossy().to_string(),
+        });
+    }
+    let preset = presets
+        .find(&preset)
+        .ok_or_else(|| CommandError::PresetNotFound {
+            name: preset.clone(),
+        })?;
+    let output_dir = PathBuf::from(output_dir);
+    std::fs::create_dir_all(&output_dir).map_err(|e| CommandError::Io {
+        action: "create",
+        error: format!("'{}': {}", output_dir.display(), e),
+    })?;
+    if std::fs::canonicalize(&folder).ok() == std::fs::canonicalize(&output_dir).ok() {
+        return Err(CommandError::OutputIsWatchedFolder {
+            path: output_dir.to_string_lossy().to_string(),
+        });
+    }
+
+    let watcher_id = format!(
+        "watch-{}-{}",
+        std::process::id(),
+        JOB_ID_COUNTER.fetch_add(1, Ordering::Relaxed) + 1
+    );
+    log_info!(
+        "Watching '{}' with preset '{}' into '{}'",
+        folder.display(),
+        preset.name,
+        output_dir.display()
+    );
+    let (wake, events) = std::sync::mpsc::channel();
+    let watch_error = |e: notify::Error| CommandError::Io {
+        action: "watch_folder",
+        error: format!("'{}': {}", folder.display(), e),
+    };
+    let mut debouncer = new_debouncer(WATCH_DEBOUNCE, wake.clone()).map_err(watch_error)?;
+    debouncer
+        .watcher()
+        .watch(&folder, RecursiveMode::NonRecursive)
+        .map_err(watch_error)?;
+    let control = Arc::new(WatcherControl::default());
+    let thread = {
+        let watcher_id = watcher_id.clone();
+        let control = control.clone();
+        std::thread::spawn(move || {
+            // Events stop once the debouncer is dropped with the thread.
+            let _debouncer = debouncer;
+            watch_folder(
+                &app,
+                &watcher_id,
+                &control,
+                &events,
+                &folder,
+                &preset,
+                &output_dir,
+            )
+        })
+    };
+    let watcher = FolderWatcher {
+        control,
+        wake,
+        thread,
+    };
+    watchers
+        .0
+        .lock()
+        .expect("folder watchers mutex poisoned")
+        .insert(watcher_id.clone(), watcher);
+    Ok(watcher_id)
 }
 
-#[derive(Clone, Default)]
-struct GhostscriptProbeLog {
-    attempted: Vec<String>,
-    selected: Option<String>,
-    last_error: Option<String>,
+/// Stop a watcher from `start_watch`, cancelling the file it is processing. Returns
+/// `false` if there is no such watcher.
+#[tauri::command]
+fn stop_watch(watcher_id: String, watchers: State<'_, FolderWatchers>) -> bool {
+    watchers.stop(&watcher_id).is_some()
 }
 
-#[derive(serde::Serialize)]
-struct GhostscriptProbeResult {
-    attempted: Vec<String>,
-    selected: Option<String>,
-    last_error: Option<String>,
-    mac_root: Option<String>,
-    windows_root: Option<String>,
-}
+impl FolderWatchers {
+    /// Stop the watcher and hand back its thread, which ends shortly after.
+    fn stop(&self, watcher_id: &str) -> Option<std::thread::JoinHandle<()>> {
+        let watcher = self
+            .0
+            .lock()
+            .expect("folder watchers mutex poisoned")
+            .remove(watcher_id)?;
+        watcher.stop();
+        log_info!("Stopped folder watcher {}", watcher_id);
+        Some(watcher.thread)
+    }
 
-#[derive(Clone)]
-struct GhostscriptCandidate {
-    command: PathBuf,
-    gs_root: Option<PathBuf>,
+    fn stop_all(&self) -> Vec<std::thread::JoinHandle<()>> {
+        let watchers = std::mem::take(&mut *self.0.lock().expect("folder watchers mutex poisoned"));
+        watchers
+            .into_values()
+            .map(|watcher| {
+                watcher.stop();
+                watcher.thread
+            })
+            .collect()
+    }
 }
 
-struct GhostscriptExecOutput {
-    status: std::process::ExitStatus,
-    stdout: String,
-    stderr: String,
+impl FolderWatcher {
+    fn stop(&self) {
+        self.control.stop();
+        let _ = self.wake.send(Ok(Vec::new()));
+    }
 }
 
-impl GhostscriptRuntime {
-    fn mac_root_string(&self) -> Option<String> {
-        #[cfg(target_os = "macos")]
+impl WatcherControl {
+    fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst) || SHUTTING_DOWN.load(Ordering::SeqCst)
+    }
+
+    fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        if let Some(job) = self
+            .current_job
+            .lock()
+            .expect("watcher job mutex poisoned")
+            .as_ref()
         {
-            return self.roots.first().map(|p| p.to_string_lossy().to_string());
+            job.cancel();
         }
-        #[cfg(not(target_os = "macos"))]
-        {
-            None
+    }
+}
+
+/// The watcher thread: scan `folder` on each batch of change `events`, and every
+/// `WATCH_RESCAN_INTERVAL` while files are settling, until stopped.
+fn watch_folder(
+    app: &tauri::AppHandle,
+    watcher_id: &str,
+    control: &WatcherControl,
+    events: &std::sync::mpsc::Receiver<DebounceEventResult>,
+    folder: &Path,
+    preset: &Preset,
+    output_dir: &Path,
+) {
+    let emit = |input: &Path, state, output_path, error, warnings| {
+        let _ = app.emit_all(
+            "watch-file-changed",
+            WatchFileChanged {
+                watcher_id: watcher_id.to_string(),
+                input_path: input.to_string_lossy().to_string(),
+                state,
+                output_path,
+                error,
+                warnings,
+            },
+        );
+    };
+    let started = std::time::Instant::now();
+    let mut files: HashMap<PathBuf, WatchedFile> = scan_watched_folder(folder)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(path, signature)| {
+            let file = WatchedFile {
+                signature,
+                seen: started,
+                handled: true,
+            };
+            (path, file)
+        })
+        .collect();
+    let mut scan_failing = false;
+    while !control.is_stopped() {
+        let settling = files.values().any(|file| !file.handled);
+        match events.recv_timeout(WATCH_RESCAN_INTERVAL) {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => log_warn!("Watching '{}' failed: {}", folder.display(), e),
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout) if settling => {}
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => continue,
+            Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+        if control.is_stopped() {
+            break;
+        }
+        let scanned = match scan_watched_folder(folder) {
+            Ok(scanned) => {
+                scan_failing = false;
+                scanned
+            }
+            Err(e) => {
+                // Keep trying: the folder may be on a drive that comes back.
+                if !scan_failing {
+                    log_warn!("Failed to scan '{}': {}", folder.display(), e);
+                }
+                scan_failing = true;
+                continue;
+            }
+        };
+        files.retain(|path, _| scanned.contains_key(path));
+
+        let mut ready = Vec::new();
+        for (path, signature) in scanned {
+            if let Some(file) = files.get(&path).filter(|file| file.signature == signature) {
+                if !file.handled && file.seen.elapsed() >= WATCH_SETTLE_TIME && !is_locked(&path) {
+                    ready.push(path);
+                }
+                continue;
+            }
+            // New, or changed since the last scan. Still being copied counts as queued once.
+            if !files.get(&path).is_some_and(|file| !file.handled) {
+                emit(&path, JobState::Queued, None, None, Vec::new());
+            }
+            let file = WatchedFile {
+                signature,
+                seen: std::time::Instant::now(),
+                handled: false,
+            };
+            files.insert(path, file);
+        }
+
+        for path in ready {
+            if control.is_stopped() {
+                break;
+            }
+            process_watched_file(app, control, &path, preset, output_dir, &emit);
+            if let Some(file) = files.get_mut(&path) {
+                file.handled = true;
+            }
         }
     }
+}
 
-    fn windows_root_string(&self) -> Option<String> {
-        #[cfg(target_os = "windows")]
-        {
-            return self.roots.first().map(|p| p.to_string_lossy().to_string());
+/// Size and modification time of the PDFs directly in `folder`, hidden ones excepted.
+fn scan_watched_folder(folder: &Path) -> std::io::Result<HashMap<PathBuf, FileSignature>> {
+    let mut files = HashMap::new();
+    for entry in std::fs::read_dir(folder)? {
+        let Ok(entry) = entry else {
+            continue;
+        };
+        let path = entry.path();
+        if is_hidden_entry(&entry) || !has_pdf_extension(&path) {
+            continue;
         }
-        #[cfg(not(target_os = "windows"))]
-        {
-            None
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        if metadata.is_file() {
+            let signature = FileSignature {
+                len: metadata.len(),
+                modified: metadata.modified().ok(),
+            };
+            files.insert(path, signature);
         }
     }
+    Ok(files)
 }
 
-#[tauri::command]
-fn check_file_existence(file_paths: Vec<String>) -> Vec<bool> {
-    file_paths
-        .into_iter()
-        .map(|path| Path::new(&path).exists())
-        .collect()
+fn process_watched_file(
+    app: &tauri::AppHandle,
+    control: &WatcherControl,
+    input: &Path,
+    preset: &Preset,
+    output_dir: &Path,
+    emit: &impl Fn(&Path, JobState, Option<String>, Option<CommandError>, Vec<GhostscriptWarning>),
+) {
+    emit(input, JobState::Running, None, None, Vec::new());
+    let settings = app.state::<SettingsStore>().get();
+    let runtime = app.state::<GhostscriptRuntime>();
+    let jobs = app.state::<RunningJobs>();
+    let template = settings
+        .output_name_template
+        .unwrap_or_else(|| String::from("{name}"));
+    let options = OutputOptions {
+        on_conflict: ConflictPolicy::Rename,
+        preserve_mtime: settings.preserve_mtime,
+        ..OutputOptions::default()
+    };
+    let outcome = jobs.register(None, None).and_then(|job| {
+        *control
+            .current_job
+            .lock()
+            .expect("watcher job mutex poisoned") = Some(job.control.clone());
+        if control.is_stopped() {
+            job.control.cancel();
+        }
+        let context = OutputNameContext::for_input(input, Some(preset.name.clone()));
+        let written = render_output_name(&template, &context, "pdf").and_then(|name| {
+            run_operation(
+                &preset.operation,
+                input,
+                &output_dir.join(name),
+                options,
+                &runtime,
+                JobContext {
+                    control: Some(&job.control),
+                    ..JobContext::default()
+                },
+            )
+        });
+        *control
+            .current_job
+            .lock()
+            .expect("watcher job mutex poisoned") = None;
+        written
+    });
+    match outcome {
+        Ok((output_path, warnings)) => {
+            emit(input, JobState::Done, Some(output_path), None, warnings);
+        }
+        Err(CommandError::Cancelled) => {
+            emit(input, JobState::Cancelled, None, None, Vec::new());
+        }
+        Err(error) => {
+            log_warn!("Hot folder failed on '{}': {}", input.display(), error);
+            emit(input, JobState::Failed, None, Some(error), Vec::new());
+        }
+    }
 }
 
-#[tauri::command]
-fn greet(name: &str) -> String {
-    format!("Hello, {}! You've been greeted from Rust!", name)
+/// Runs a processing command's `work` on the blocking thread pool, so the command
+/// itself is a future that shutdown can await instead of a handler stuck on Ghostscript.
+async fn run_blocking<T>(
+    work: impl FnOnce() -> Result<T, CommandError> + Send + 'static,
+) -> Result<T, CommandError>
+where
+    T: Send + 'static,
+{
+    tauri::async_runtime::spawn_blocking(work)
+        .await
+        .map_err(|e| CommandError::TaskFailed {
+            error: e.to_string(),
+        })?
 }
 
-#[tauri::command]
-fn log_path(path: String) {
-    println!("Received path from frontend: {}", path);
+fn progress_emitter<'a>(
+    window: &'a tauri::Window,
+    job_id: &'a str,
+) -> impl Fn(u32, Option<u32>) + Sync + 'a {
+    move |current_page, total_pages| {
+        let _ = window.emit(
+            "pdf-progress",
+            PdfProgress {
+                job_id: job_id.to_string(),
+                current_page,
+                total_pages,
+            },
+        );
+    }
 }
 
+/// Reserve a fresh job ID the frontend can pass to a processing command and later cancel.
 #[tauri::command]
-fn take_pending_open_paths(state: State<'_, PendingOpenPaths>) -> Vec<String> {
-    let mut guard = state.0.lock().expect("pending paths mutex poisoned");
-    let paths = guard.clone();
-    guard.clear();
-    paths
+fn create_job_id() -> String {
+    next_job_id()
 }
 
-#[cfg(target_os = "windows")]
-const GHOSTSCRIPT_FALLBACK_COMMANDS: [&str; 3] = ["gswin64c", "gswin32c", "gs"];
-#[cfg(target_os = "macos")]
-const GHOSTSCRIPT_FALLBACK_COMMANDS: [&str; 3] =
-    ["gs", "/opt/homebrew/bin/gs", "/usr/local/bin/gs"];
-#[cfg(all(not(target_os = "windows"), not(target_os = "macos")))]
-const GHOSTSCRIPT_FALLBACK_COMMANDS: [&str; 1] = ["gs"];
-
-fn collect_gs_lib_entries(gs_root: &Path) -> Option<String> {
-    let share_ghostscript = gs_root.join("share").join("ghostscript");
-    if !share_ghostscript.exists() {
-        return None;
-    }
-    let mut entries = Vec::new();
-    let mut push_if_exists = |path: PathBuf| {
-        if path.exists() {
-            entries.push(path);
+/// Kill the Ghostscript process of a running job, or take a queued one off the queue.
+/// Returns `false` if no such job is running or queued.
+#[tauri::command]
+fn cancel_job(
+    job_id: String,
+    app: tauri::AppHandle,
+    jobs: State<'_, RunningJobs>,
+    queue: State<'_, JobQueue>,
+) -> bool {
+    let mut guard = queue.0.lock().expect("job queue mutex poisoned");
+    match guard.jobs.iter().position(|entry| entry.job_id == job_id) {
+        Some(index) if guard.jobs[index].state == JobState::Queued => {
+            let entry = guard.jobs.remove(index);
+            drop(guard);
+            emit_job_state(
+                &app,
+                JobStateChanged {
+                    job_id,
+                    state: JobState::Cancelled,
+                    input_path: entry.request.input_path,
+                    output_path: None,
+                    error: None,
+                    warnings: Vec::new(),
+                },
+            );
+            true
         }
-    };
-
-    // Newer Ghostscript distributions (e.g. Homebrew) flatten these directly under
-    // `share/ghostscript`, while some layouts place them in a versioned subdirectory.
-    push_if_exists(share_ghostscript.clone());
-    push_if_exists(share_ghostscript.join("lib"));
-    push_if_exists(share_ghostscript.join("Resource"));
-    push_if_exists(share_ghostscript.join("Resource").join("Init"));
-    push_if_exists(share_ghostscript.join("Resource").join("Font"));
-    push_if_exists(share_ghostscript.join("fonts"));
-    push_if_exists(share_ghostscript.join("iccprofiles"));
-
-    if let Ok(dirs) = std::fs::read_dir(&share_ghostscript) {
-        for entry in dirs.flatten() {
-            let path = entry.path();
-            if !path.is_dir() {
-                continue;
-            }
-            push_if_exists(path.join("lib"));
-            push_if_exists(path.join("Resource"));
-            push_if_exists(path.join("Resource").join("Init"));
-            push_if_exists(path.join("Resource").join("Font"));
-            push_if_exists(path.join("fonts"));
-            push_if_exists(path.join("iccprofiles"));
+        Some(index) => {
+            guard.jobs[index].cancel_requested = true;
+            drop(guard);
+            jobs.cancel(&job_id);
+            true
+        }
+        None => {
+            drop(guard);
+            jobs.cancel(&job_id)
         }
     }
+}
 
-    entries.sort();
-    entries.dedup();
+/// Add a file to the job queue, which runs at most `max_concurrency` jobs at once and
+/// reports each job's progress through `job-state-changed`. Returns the job's ID, which
+/// `cancel_job` accepts.
+#[tauri::command]
+fn enqueue_job(
+    request: QueuedOperation,
+    app: tauri::AppHandle,
+    queue: State<'_, JobQueue>,
+) -> String {
+    let job_id = next_job_id();
+    let input_path = request.input_path.clone();
+    queue
+        .0
+        .lock()
+        .expect("job queue mutex poisoned")
+        .jobs
+        .push(QueueEntry {
+            job_id: job_id.clone(),
+            request,
+            state: JobState::Queued,
+            cancel_requested: false,
+        });
+    emit_job_state(
+        &app,
+        JobStateChanged {
+            job_id: job_id.clone(),
+            state: JobState::Queued,
+            input_path,
+            output_path: None,
+            error: None,
+            warnings: Vec::new(),
+        },
+    );
+    start_queued_jobs(&app);
+    job_id
+}
 
-    if entries.is_empty() {
-        None
-    } else {
-        #[cfg(target_os = "windows")]
-        let separator = ";";
-        #[cfg(not(target_os = "windows"))]
-        let separator = ":";
-        Some(
-            entries
-                .iter()
-                .map(|p| p.to_string_lossy().to_string())
-                .collect::<Vec<_>>()
-                .join(separator),
-        )
+#[tauri::command]
+fn get_queue_status(queue: State<'_, JobQueue>) -> QueueStatus {
+    let guard = queue.0.lock().expect("job queue mutex poisoned");
+    let count = |state: JobState| {
+        guard
+            .jobs
+            .iter()
+            .filter(|entry| entry.state == state)
+            .count()
+    };
+    QueueStatus {
+        max_concurrency: guard.max_concurrency,
+        running: count(JobState::Running),
+        queued: count(JobState::Queued),
+        jobs: guard
+            .jobs
+            .iter()
+            .map(|entry| QueuedJobInfo {
+                job_id: entry.job_id.clone(),
+                input_path: entry.request.input_path.clone(),
+                state: entry.state,
+            })
+            .collect(),
     }
 }
 
-fn collect_ghostscript_env(gs_root: &Path) -> HashMap<&'static str, String> {
-    let mut envs = HashMap::new();
-    if let Some(gs_lib) = collect_gs_lib_entries(gs_root) {
-        envs.insert("GS_LIB", gs_lib);
+/// Change how many queued jobs run at once. Lowering it lets running jobs finish.
+#[tauri::command]
+fn set_max_concurrency(
+    max_concurrency: usize,
+    app: tauri::AppHandle,
+    queue: State<'_, JobQueue>,
+) -> Result<(), CommandError> {
+    if max_concurrency == 0 {
+        return Err(CommandError::OutOfRange {
+            field: "max_concurrency",
+            value: String::from("0"),
+            expected: String::from("at least 1"),
+        });
     }
-    envs
+    queue
+        .0
+        .lock()
+        .expect("job queue mutex poisoned")
+        .max_concurrency = max_concurrency;
+    start_queued_jobs(&app);
+    Ok(())
 }
 
-fn record_attempt(log: &mut GhostscriptProbeLog, command: &Path) {
-    log.attempted.push(command.to_string_lossy().to_string());
+/// Files, folders and `pdfresizer:` links passed on the command line; folders are
+/// expanded and links parsed when opened.
+fn collect_startup_file_paths() -> Vec<String> {
+    std::env::args()
+        .skip(1)
+        .filter_map(|arg| {
+            let p = Path::new(&arg);
+            if is_open_link(&arg) || p.is_file() || p.is_dir() {
+                Some(arg)
+            } else {
+                None
+            }
+        })
+        .collect()
 }
 
-fn run_candidate(
-    candidate: &GhostscriptCandidate,
-    args: &[&str],
-    log: &mut GhostscriptProbeLog,
-) -> Result<GhostscriptExecOutput, String> {
-    record_attempt(log, &candidate.command);
+/// URL scheme of links that open files in the app, e.g.
+/// `pdfresizer://open?path=%2FUsers%2Fme%2Fscan.pdf&preset=compress-email`.
+const OPEN_LINK_SCHEME: &str = "pdfresizer";
+
+fn is_open_link(argument: &str) -> bool {
+    argument
+        .get(..OPEN_LINK_SCHEME.len() + 1)
+        .map(|prefix| prefix.eq_ignore_ascii_case(&format!("{}:", OPEN_LINK_SCHEME)))
+        .unwrap_or(false)
+}
 
-    let mut cmd = std::process::Command::new(&candidate.command);
-    cmd.args(args);
-    if let Some(gs_root) = candidate.gs_root.as_deref() {
-        let envs = collect_ghostscript_env(gs_root);
-        if !envs.is_empty() {
-            cmd.envs(envs);
+/// Decode `%XX` escapes; the decoded bytes must be UTF-8. `+` is kept as is, since it is
+/// common in file names.
+fn percent_decode(text: &str) -> Result<String, String> {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'%' {
+            decoded.push(bytes[i]);
+            i += 1;
+            continue;
         }
+        let byte = text
+            .get(i + 1..i + 3)
+            .filter(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit()))
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            .ok_or_else(|| format!("Invalid escape at position {}.", i))?;
+        decoded.push(byte);
+        i += 3;
     }
+    String::from_utf8(decoded).map_err(|_| String::from("Escapes don't decode to UTF-8 text."))
+}
 
-    match cmd.output() {
-        Ok(output) if output.status.success() => {
-            let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-            let stderr = String::from_utf8_lossy(&output.stderr).to_string();
-            log.selected = Some(candidate.command.to_string_lossy().to_string());
-            Ok(GhostscriptExecOutput {
-                status: output.status,
-                stdout,
-                stderr,
-            })
-        }
-        Ok(output) => {
-            let stderr = String::from_utf8_lossy(&output.stderr).to_string();
-            let error = format!(
-                "Ghostscript command '{}' failed with status {:?}: {}",
-                candidate.command.display(),
-                output.status,
-                stderr
-            );
-            log.last_error = Some(error.clone());
-            Err(error)
-        }
-        Err(e) => {
-            let error = format!(
-                "Failed to execute Ghostscript command '{}': {}",
-                candidate.command.display(),
-                e
-            );
-            log.last_error = Some(error.clone());
-            Err(error)
+/// Parse `pdfresizer://open?path=...&path=...&preset=...`. Paths must be absolute; the
+/// preset is optional. `link` must pass `is_open_link`.
+fn parse_open_link(link: &str) -> Result<OpenLink, String> {
+    let rest = &link[OPEN_LINK_SCHEME.len() + 1..];
+    let rest = rest.strip_prefix("//").unwrap_or(rest);
+    let rest = rest.split('#').next().unwrap_or_default();
+    let (action, query) = rest.split_once('?').unwrap_or((rest, ""));
+    if !action.trim_end_matches('/').eq_ignore_ascii_case("open") {
+        return Err(format!("Unknown action '{}'.", action));
+    }
+    let mut link = OpenLink {
+        paths: Vec::new(),
+        preset: None,
+    };
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let value = percent_decode(value)?;
+        match percent_decode(key)?.as_str() {
+            "path" => {
+                if !Path::new(&value).is_absolute() {
+                    return Err(format!("Path '{}' is not absolute.", value));
+                }
+                link.paths.push(value);
+            }
+            "preset" if !value.is_empty() => link.preset = Some(value),
+            _ => {}
         }
     }
+    if link.paths.is_empty() {
+        return Err(String::from("The link names no path."));
+    }
+    Ok(link)
 }
 
-fn collect_candidates(runtime: &GhostscriptRuntime) -> Vec<GhostscriptCandidate> {
-    let mut candidates = Vec::new();
+/// Environment variables overriding `FolderScanLimits::default()`.
+const FOLDER_SCAN_DEPTH_ENV: &str = "PDFRESIZER_FOLDER_DEPTH";
+const FOLDER_SCAN_MAX_FILES_ENV: &str = "PDFRESIZER_FOLDER_MAX_FILES";
 
-    #[cfg(target_os = "macos")]
-    {
-        for root in &runtime.roots {
-            candidates.push(GhostscriptCandidate {
-                command: root.join("bin").join("gs"),
-                gs_root: Some(root.clone()),
-            });
-            candidates.push(GhostscriptCandidate {
-                command: root.join("gs"),
-                gs_root: Some(root.clone()),
-            });
+impl Default for FolderScanLimits {
+    fn default() -> Self {
+        Self {
+            max_depth: 3,
+            max_files: 500,
         }
     }
+}
 
-    #[cfg(target_os = "windows")]
-    {
-        for root in &runtime.roots {
-            candidates.push(GhostscriptCandidate {
-                command: root.join("bin").join("gswin64c.exe"),
-                gs_root: Some(root.clone()),
-            });
-            candidates.push(GhostscriptCandidate {
-                command: root.join("bin").join("gswin32c.exe"),
-                gs_root: Some(root.clone()),
-            });
+impl FolderScanLimits {
+    fn from_env() -> Self {
+        let read = |name: &str| {
+            std::env::var(name)
+                .ok()
+                .and_then(|value| value.trim().parse::<usize>().ok())
+        };
+        let defaults = Self::default();
+        Self {
+            max_depth: read(FOLDER_SCAN_DEPTH_ENV).unwrap_or(defaults.max_depth),
+            max_files: read(FOLDER_SCAN_MAX_FILES_ENV)
+                .filter(|&max_files| max_files > 0)
+                .unwrap_or(defaults.max_files),
         }
     }
+}
 
-    candidates
+fn has_pdf_extension(path: &Path) -> bool {
+    path.extension()
+        .map(|ext| ext.eq_ignore_ascii_case("pdf"))
+        .unwrap_or(false)
 }
 
-fn run_ghostscript(
-    args: &[&str],
-    runtime: &GhostscriptRuntime,
-    probe: Option<&mut GhostscriptProbeLog>,
-) -> Result<GhostscriptExecOutput, String> {
-    let mut log = GhostscriptProbeLog::default();
+/// Whether an opened file is one the app handles: named `.pdf`, or a PDF by its header
+/// whatever its name.
+fn is_supported_open_file(path: &Path) -> bool {
+    path.is_file() && (has_pdf_extension(path) || check_pdf_file(path).is_pdf)
+}
 
-    for candidate in collect_candidates(runtime) {
-        if !candidate.command.exists() {
-            continue;
-        }
-        if let Ok(output) = run_candidate(&candidate, args, &mut log) {
-            if let Some(probe_log) = probe {
-                *probe_log = log;
-            }
-            return Ok(output);
+fn is_hidden_entry(entry: &std::fs::DirEntry) -> bool {
+    if entry.file_name().to_string_lossy().starts_with('.') {
+        return true;
+    }
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::fs::MetadataExt;
+        const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+        if let Ok(metadata) = entry.metadata() {
+            return metadata.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0;
         }
     }
+    false
+}
 
-    // Last fallback to system Ghostscript on PATH.
-    for command in GHOSTSCRIPT_FALLBACK_COMMANDS {
-        let command_path = PathBuf::from(command);
-        if command.starts_with('/') && !command_path.exists() {
+/// The non-empty `.pdf` files in `folder` and, down to `limits.max_depth`, its subfolders,
+/// sorted naturally with each folder's files before its subfolders'. Hidden entries are
+/// skipped, and linked folders aren't followed so a loop can't stall the scan.
+fn scan_folder_for_pdfs(folder: &Path, limits: &FolderScanLimits) -> FolderScan {
+    let mut scan = FolderScan {
+        files: Vec::new(),
+        truncated: false,
+    };
+    let mut pending = vec![(folder.to_path_buf(), 0)];
+    while let Some((dir, depth)) = pending.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
             continue;
+        };
+        let mut files = Vec::new();
+        let mut subfolders = Vec::new();
+        for entry in entries.flatten() {
+            if is_hidden_entry(&entry) {
+                continue;
+            }
+            let path = entry.path();
+            if entry.file_type().map(|kind| kind.is_dir()).unwrap_or(false) {
+                if depth < limits.max_depth {
+                    subfolders.push(path);
+                }
+                continue;
+            }
+            let is_pdf = has_pdf_extension(&path);
+            let is_empty = std::fs::metadata(&path)
+                .map(|metadata| !metadata.is_file() || metadata.len() == 0)
+                .unwrap_or(true);
+            if is_pdf && !is_empty {
+                files.push(path);
+            }
         }
-        let candidate = GhostscriptCandidate {
-            command: command_path,
-            gs_root: None,
+        let by_name = |a: &PathBuf, b: &PathBuf| {
+            natural_cmp(
+                &a.file_name().unwrap_or_default().to_string_lossy(),
+                &b.file_name().unwrap_or_default().to_string_lossy(),
+            )
         };
-        if let Ok(output) = run_candidate(&candidate, args, &mut log) {
-            if let Some(probe_log) = probe {
-                *probe_log = log;
+        files.sort_by(by_name);
+        for file in files {
+            if scan.files.len() == limits.max_files {
+                scan.truncated = true;
+                return scan;
             }
-            return Ok(output);
+            scan.files.push(file);
         }
+        // Pushed in reverse so the stack yields the first subfolder next.
+        subfolders.sort_by(|a, b| by_name(b, a));
+        pending.extend(
+            subfolders
+                .into_iter()
+                .map(|subfolder| (subfolder, depth + 1)),
+        );
     }
+    scan
+}
 
-    let error = log
-        .last_error
-        .clone()
-        .unwrap_or_else(|| String::from("Ghostscript is not available."));
-    if let Some(probe_log) = probe {
-        *probe_log = log;
+/// The canonical form of an opened file, with `..` and links resolved. Windows paths lose
+/// the `\\?\` prefix canonicalization adds, which the frontend needn't see, unless they
+/// are too long to work without it.
+fn canonical_open_path(path: &str) -> String {
+    let Ok(canonical) = std::fs::canonicalize(path) else {
+        return path.to_string();
+    };
+    let canonical = canonical.to_string_lossy().to_string();
+    if cfg!(target_os = "windows") {
+        let plain = if let Some(share) = canonical.strip_prefix(r"\\?\UNC\") {
+            Some(format!(r"\\{}", share))
+        } else {
+            canonical.strip_prefix(r"\\?\").map(str::to_string)
+        };
+        if let Some(plain) = plain.filter(|plain| plain.len() < MAX_PATH_LENGTH) {
+            return plain;
+        }
     }
-    Err(error)
+    canonical
 }
 
-fn flatten_with_ghostscript(
-    pdf_bytes: Vec<u8>,
-    runtime: &GhostscriptRuntime,
-) -> Result<Vec<u8>, String> {
-    use std::io::Write;
-
-    let tmp_dir = std::env::temp_dir();
-    let input_path = tmp_dir.join("pdfresizer_flatten_input.pdf");
-    let output_path = tmp_dir.join("pdfresizer_flatten_output.pdf");
-
-    std::fs::File::create(&input_path)
-        .and_then(|mut f| f.write_all(&pdf_bytes))
-        .map_err(|e| format!("Failed to write temp input file: {}", e))?;
-
-    let output_file_arg = format!("-sOutputFile={}", output_path.display());
-    let input_file_arg = format!("{}", input_path.display());
-    let args = [
-        "-dBATCH",
-        "-dNOPAUSE",
-        "-dSAFER",
-        "-dQUIET",
-        "-sDEVICE=pdfwrite",
-        "-dNoOutputFonts",
-        "-dCompatibilityLevel=1.7",
-        output_file_arg.as_str(),
-        input_file_arg.as_str(),
-    ];
-    let result = run_ghostscript(&args, runtime, None)?;
-
-    if !result.status.success() {
-        let stderr = result.stderr;
-        let _ = std::fs::remove_file(&input_path);
-        let _ = std::fs::remove_file(&output_path);
-        return Err(format!("Ghostscript failed: {}", stderr));
+/// Key under which two opened paths count as the same file; the default file systems of
+/// Windows and macOS ignore case.
+fn open_path_key(path: &str) -> String {
+    if cfg!(any(target_os = "windows", target_os = "macos")) {
+        path.to_lowercase()
+    } else {
+        path.to_string()
     }
+}
 
-    let output_bytes = std::fs::read(&output_path)
-        .map_err(|e| format!("Failed to read flattened output: {}", e))?;
-
-    let _ = std::fs::remove_file(&input_path);
-    let _ = std::fs::remove_file(&output_path);
-
-    Ok(output_bytes)
+impl ResolvedOpenPaths {
+    /// Canonicalize `path`, replacing a folder by the PDFs in it.
+    fn add(&mut self, path: &str, limits: &FolderScanLimits) {
+        let path = canonical_open_path(path);
+        if !Path::new(&path).is_dir() {
+            if is_supported_open_file(Path::new(&path)) {
+                self.files.push(path);
+            } else {
+                self.unsupported.push(path);
+            }
+            return;
+        }
+        let scan = scan_folder_for_pdfs(Path::new(&path), limits);
+        self.files.extend(
+            scan.files
+                .iter()
+                .map(|file| canonical_open_path(&file.to_string_lossy())),
+        );
+        if scan.truncated {
+            self.truncated_folders.push(path);
+        }
+    }
 }
 
-/// Check if Ghostscript is available (bundled or on PATH).
-#[tauri::command]
-fn check_ghostscript(runtime: State<'_, GhostscriptRuntime>) -> String {
-    match run_ghostscript(&["--version"], &runtime, None) {
-        Ok(output) => output.stdout.trim().to_string(),
-        Err(e) => {
-            // Missing Ghostscript is an expected state in dev; keep logs quiet for ENOENT-like cases.
-            let lower = e.to_lowercase();
-            let missing = lower.contains("no such file or directory")
-                || lower.contains("not available")
-                || lower.contains("not found");
-            if !missing {
-                println!("Ghostscript availability check failed: {}", e);
+impl PendingOpen {
+    /// Queue newly opened files, leaving out those already queued.
+    fn queue(&mut self, resolved: ResolvedOpenPaths, preset_requests: Vec<PresetRequest>) {
+        for path in resolved.files {
+            let key = open_path_key(&path);
+            if !self.paths.iter().any(|queued| open_path_key(queued) == key) {
+                self.paths.push(path);
             }
-            String::new()
+        }
+        self.unsupported.extend(resolved.unsupported);
+        self.truncated_folders.extend(resolved.truncated_folders);
+        self.preset_requests.extend(preset_requests);
+    }
+
+    /// The frontend's listeners are registered: emit what was queued before.
+    fn set_frontend_ready(&mut self, emit: impl FnMut(&str, serde_json::Value)) {
+        self.frontend_ready = true;
+        self.emit_if_ready(emit);
+    }
+
+    /// Once the frontend is ready, emit and clear everything queued, the opened files
+    /// ahead of any presets requested for them. Before that, keep it all queued.
+    fn emit_if_ready(&mut self, mut emit: impl FnMut(&str, serde_json::Value)) {
+        if !self.frontend_ready {
+            return;
+        }
+        let unsupported = std::mem::take(&mut self.unsupported);
+        if !unsupported.is_empty() {
+            emit("unsupported-files-ignored", serde_json::json!(unsupported));
+        }
+        let truncated_folders = std::mem::take(&mut self.truncated_folders);
+        if !truncated_folders.is_empty() {
+            emit(
+                "external-folders-truncated",
+                serde_json::json!(TruncatedFolders {
+                    folders: truncated_folders,
+                    max_files: FolderScanLimits::from_env().max_files,
+                }),
+            );
+        }
+        let paths = std::mem::take(&mut self.paths);
+        if !paths.is_empty() {
+            emit("external-files-opened", serde_json::json!(paths));
+        }
+        for request in std::mem::take(&mut self.preset_requests) {
+            emit("external-preset-requested", serde_json::json!(request));
         }
     }
 }
 
-#[tauri::command]
-fn debug_ghostscript_probe(runtime: State<'_, GhostscriptRuntime>) -> GhostscriptProbeResult {
-    if !cfg!(debug_assertions) {
-        return GhostscriptProbeResult {
-            attempted: Vec::new(),
-            selected: None,
-            last_error: Some(String::from(
-                "debug_ghostscript_probe is disabled in production builds.",
-            )),
-            mac_root: runtime.mac_root_string(),
-            windows_root: runtime.windows_root_string(),
-        };
+/// Emits `PendingOpen`'s events to `window`.
+fn window_emitter(window: &tauri::Window) -> impl FnMut(&str, serde_json::Value) + '_ {
+    move |event, payload| {
+        let _ = window.emit(event, payload);
     }
+}
 
-    let mut probe = GhostscriptProbeLog::default();
-    let result = run_ghostscript(&["--version"], &runtime, Some(&mut probe));
-    if let Err(error) = result {
-        if probe.last_error.is_none() {
-            probe.last_error = Some(error);
+/// Hand files opened from outside the app to the frontend, queueing them until it calls
+/// `frontend_ready`. Folders are replaced by the PDFs in them, paths are canonicalized,
+/// and files already queued are left out. Files that aren't PDFs are reported in
+/// `unsupported-files-ignored` instead. Links open their paths the same way, plus an
+/// `external-preset-requested` if they name a preset; malformed ones are logged and
+/// dropped.
+fn open_external_files(app: &tauri::AppHandle, arguments: Vec<String>) {
+    let limits = FolderScanLimits::from_env();
+    let mut resolved = ResolvedOpenPaths::default();
+    let mut preset_requests = Vec::new();
+    for argument in arguments {
+        if !is_open_link(&argument) {
+            resolved.add(&argument, &limits);
+            continue;
+        }
+        let link = match parse_open_link(&argument) {
+            Ok(link) => link,
+            Err(e) => {
+                log_warn!("Ignoring link '{}': {}", argument, e);
+                continue;
+            }
+        };
+        let first_file = resolved.files.len();
+        for path in &link.paths {
+            resolved.add(path, &limits);
+        }
+        if let Some(preset) = link.preset {
+            let paths = resolved.files[first_file..].to_vec();
+            if !paths.is_empty() {
+                preset_requests.push(PresetRequest { paths, preset });
+            }
         }
     }
 
-    GhostscriptProbeResult {
-        attempted: probe.attempted,
-        selected: probe.selected,
-        last_error: probe.last_error,
-        mac_root: runtime.mac_root_string(),
-        windows_root: runtime.windows_root_string(),
+    let state: State<'_, PendingOpenPaths> = app.state();
+    let mut guard = state.0.lock().expect("pending paths mutex poisoned");
+    guard.queue(resolved, preset_requests);
+    if let Some(main_window) = app.get_window("main") {
+        guard.emit_if_ready(window_emitter(&main_window));
     }
 }
 
-/// Flatten a PDF using Ghostscript (bundled sidecar preferred).
-#[tauri::command]
-fn flatten_pdf(pdf_bytes: Vec<u8>, runtime: State<'_, GhostscriptRuntime>) -> Result<Vec<u8>, String> {
-    flatten_with_ghostscript(pdf_bytes, &runtime)
+/// Name shared by every copy of the app for its single-instance socket or mutex.
+const INSTANCE_NAME: &str = "com.pdfresizer.app";
+
+/// How long a second instance waits for the first one to take its files.
+const INSTANCE_FORWARD_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(3);
+
+#[cfg(unix)]
+type InstanceListener = std::os::unix::net::UnixListener;
+#[cfg(target_os = "windows")]
+type InstanceListener = std::net::TcpListener;
+
+enum InstanceRole {
+    /// This is the first instance; the listener, if it could be opened, receives the
+    /// files of later ones.
+    Primary(Option<InstanceListener>),
+    /// Another instance is running and has taken this one's files.
+    Forwarded,
 }
 
-fn collect_startup_file_paths() -> Vec<String> {
-    std::env::args()
-        .skip(1)
-        .filter_map(|arg| {
-            let p = Path::new(&arg);
-            if p.exists() && p.is_file() {
-                Some(arg)
+/// Send a later instance's files and links to the primary one as a line of JSON, which keeps any
+/// path intact, and wait for it to confirm receipt.
+fn forward_paths(
+    mut stream: impl std::io::Read + std::io::Write,
+    paths: &[String],
+) -> std::io::Result<()> {
+    let current_dir = std::env::current_dir()?;
+    let paths: Vec<String> = paths
+        .iter()
+        .map(|path| {
+            if is_open_link(path) {
+                path.clone()
             } else {
-                None
+                current_dir.join(path).to_string_lossy().to_string()
             }
         })
-        .collect()
+        .collect();
+    let message = serde_json::to_string(&paths)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string()))?;
+    stream.write_all(message.as_bytes())?;
+    stream.write_all(b"\n")?;
+    let mut reply = [0u8; 1];
+    stream.read_exact(&mut reply)
 }
 
-fn push_root_if_exists(roots: &mut Vec<PathBuf>, root: PathBuf) {
-    if root.exists() && root.is_dir() && !roots.iter().any(|existing| existing == &root) {
-        roots.push(root);
-    }
+fn receive_forwarded_paths(stream: impl std::io::Read + std::io::Write) -> Option<Vec<String>> {
+    use std::io::BufRead;
+
+    let mut reader = std::io::BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let paths = serde_json::from_str(&line).ok()?;
+    let _ = reader.get_mut().write_all(b"\n");
+    Some(paths)
 }
 
-fn resolve_ghostscript_runtime(app: &tauri::App) -> GhostscriptRuntime {
-    let resource_dir = app.path_resolver().resource_dir();
-    let manifest_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-    let exe_dir = std::env::current_exe()
-        .ok()
-        .and_then(|exe| exe.parent().map(|p| p.to_path_buf()));
+#[cfg(unix)]
+fn instance_socket_path() -> PathBuf {
+    // The temp dir is shared between users on Linux.
+    let user = std::env::var("USER").unwrap_or_default();
+    std::env::temp_dir().join(format!("{}-{}.sock", INSTANCE_NAME, user))
+}
 
-    let mut runtime = GhostscriptRuntime::default();
+/// Become the primary instance, or hand `paths` to the one already running.
+#[cfg(unix)]
+fn claim_instance(paths: &[String]) -> InstanceRole {
+    use std::os::unix::net::{UnixListener, UnixStream};
 
-    #[cfg(target_os = "macos")]
-    {
-        let mut roots = Vec::new();
-        if let Some(base) = resource_dir.as_ref() {
-            push_root_if_exists(&mut roots, base.join("bin").join("ghostscript"));
+    let socket_path = instance_socket_path();
+    if let Ok(stream) = UnixStream::connect(&socket_path) {
+        let _ = stream.set_read_timeout(Some(INSTANCE_FORWARD_TIMEOUT));
+        if forward_paths(stream, paths).is_ok() {
+            return InstanceRole::Forwarded;
         }
-        if let Some(base) = exe_dir.as_ref() {
-            if let Some(contents_dir) = base.parent() {
-                if let Some(app_dir) = contents_dir.parent() {
-                    push_root_if_exists(
-                        &mut roots,
-                        app_dir.join("Resources").join("bin").join("ghostscript"),
-                    );
+    }
+    // Nobody answered, so the socket file is left over from an instance that crashed.
+    let _ = std::fs::remove_file(&socket_path);
+    InstanceRole::Primary(UnixListener::bind(&socket_path).ok())
+}
+
+/// Become the primary instance, or hand `paths` to the one already running. A named
+/// mutex decides which instance is first; it listens on a loopback port that it records
+/// in the temp dir.
+#[cfg(target_os = "windows")]
+fn claim_instance(paths: &[String]) -> InstanceRole {
+    use std::net::{TcpListener, TcpStream};
+    use std::os::windows::ffi::OsStrExt;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn CreateMutexW(
+            attributes: *const std::ffi::c_void,
+            initial_owner: i32,
+            name: *const u16,
+        ) -> *mut std::ffi::c_void;
+        fn GetLastError() -> u32;
+    }
+    const ERROR_ALREADY_EXISTS: u32 = 183;
+
+    let name: Vec<u16> = std::ffi::OsStr::new(&format!("Local\\{}", INSTANCE_NAME))
+        .encode_wide()
+        .chain(std::iter::once(0))
+        .collect();
+    // SAFETY: `name` is NUL-terminated. The handle is never closed, so the mutex lives
+    // exactly as long as this process.
+    let mutex = unsafe { CreateMutexW(std::ptr::null(), 0, name.as_ptr()) };
+    if mutex.is_null() {
+        return InstanceRole::Primary(None);
+    }
+    let port_file = std::env::temp_dir().join(format!("{}.port", INSTANCE_NAME));
+    // SAFETY: reads the calling thread's last error, set by CreateMutexW just above.
+    if unsafe { GetLastError() } == ERROR_ALREADY_EXISTS {
+        // The first instance may still be starting up and not have recorded its port.
+        let deadline = std::time::Instant::now() + INSTANCE_FORWARD_TIMEOUT;
+        while std::time::Instant::now() < deadline {
+            let port = std::fs::read_to_string(&port_file)
+                .ok()
+                .and_then(|contents| contents.trim().parse::<u16>().ok());
+            if let Some(stream) = port.and_then(|port| TcpStream::connect(("127.0.0.1", port)).ok())
+            {
+                let _ = stream.set_read_timeout(Some(INSTANCE_FORWARD_TIMEOUT));
+                if forward_paths(stream, paths).is_ok() {
+                    return InstanceRole::Forwarded;
                 }
             }
+            std::thread::sleep(std::time::Duration::from_millis(100));
         }
-        push_root_if_exists(&mut roots, manifest_dir.join("bin").join("ghostscript"));
-        runtime.roots = roots;
+        // The first instance is unresponsive; run on our own rather than not at all.
+        return InstanceRole::Primary(None);
     }
+    let listener = TcpListener::bind(("127.0.0.1", 0)).ok().filter(|listener| {
+        listener
+            .local_addr()
+            .and_then(|address| std::fs::write(&port_file, address.port().to_string()))
+            .is_ok()
+    });
+    InstanceRole::Primary(listener)
+}
 
-    #[cfg(target_os = "windows")]
-    {
-        let mut roots = Vec::new();
-        if let Some(base) = resource_dir.as_ref() {
-            push_root_if_exists(&mut roots, base.join("bin").join("ghostscript-win"));
-            push_root_if_exists(&mut roots, base.join("ghostscript-win"));
-            push_root_if_exists(
-                &mut roots,
-                base.join("resources").join("bin").join("ghostscript-win"),
-            );
+/// Take the files of later instances as if they had been opened here, and bring the main
+/// window forward.
+fn serve_instance(app: tauri::AppHandle, listener: InstanceListener) {
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let Some(paths) = receive_forwarded_paths(stream) else {
+                continue;
+            };
+            open_external_files(&app, paths);
+            if let Some(main_window) = app.get_window("main") {
+                let _ = main_window.unminimize();
+                let _ = main_window.set_focus();
+            }
         }
-        if let Some(base) = exe_dir.as_ref() {
-            push_root_if_exists(&mut roots, base.join("bin").join("ghostscript-win"));
-            push_root_if_exists(&mut roots, base.join("ghostscript-win"));
-            push_root_if_exists(
-                &mut roots,
-                base.join("resources").join("bin").join("ghostscript-win"),
-            );
+    });
+}
+
+impl RecentFiles {
+    fn load(config_dir: Option<PathBuf>) -> Self {
+        let entries = config_dir
+            .as_deref()
+            .and_then(|dir| std::fs::read_to_string(dir.join(RECENT_FILES_FILE)).ok())
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+        Self {
+            config_dir,
+            entries: Mutex::new(entries),
+        }
+    }
+
+    /// Apply `change` to the list and save it.
+    fn update(&self, change: impl FnOnce(&mut Vec<RecentFileEntry>)) -> Result<(), CommandError> {
+        let mut entries = self.entries.lock().expect("recent files mutex poisoned");
+        change(&mut entries);
+        let Some(config_dir) = self.config_dir.as_deref() else {
+            return Ok(());
+        };
+        let contents = serde_json::to_string_pretty(&*entries).map_err(|e| CommandError::Io {
+            action: "serialize_recent_files",
+            error: e.to_string(),
+        })?;
+        std::fs::create_dir_all(config_dir)
+            .and_then(|_| {
+                write_file_atomically(&config_dir.join(RECENT_FILES_FILE), contents.as_bytes())
+            })
+            .map_err(|e| CommandError::Io {
+                action: "save_recent_files",
+                error: e.to_string(),
+            })
+    }
+}
+
+impl JobHistory {
+    fn load(data_dir: Option<PathBuf>) -> Self {
+        let entries = data_dir
+            .as_deref()
+            .and_then(|dir| std::fs::read_to_string(dir.join(JOB_HISTORY_FILE)).ok())
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+        Self {
+            data_dir,
+            entries: Mutex::new(entries),
         }
-        push_root_if_exists(&mut roots, manifest_dir.join("bin").join("ghostscript-win"));
-        runtime.roots = roots;
     }
 
-    runtime
+    /// Apply `change` to the history and save it.
+    fn update(&self, change: impl FnOnce(&mut Vec<JobHistoryEntry>)) -> Result<(), CommandError> {
+        let mut entries = self.entries.lock().expect("job history mutex poisoned");
+        change(&mut entries);
+        let Some(data_dir) = self.data_dir.as_deref() else {
+            return Ok(());
+        };
+        let contents = serde_json::to_string(&*entries).map_err(|e| CommandError::Io {
+            action: "serialize_job_history",
+            error: e.to_string(),
+        })?;
+        std::fs::create_dir_all(data_dir)
+            .and_then(|_| {
+                write_file_atomically(&data_dir.join(JOB_HISTORY_FILE), contents.as_bytes())
+            })
+            .map_err(|e| CommandError::Io {
+                action: "save_job_history",
+                error: e.to_string(),
+            })
+    }
 }
 
 fn main() {
+    let context = tauri::generate_context!();
+    if let Some(args) = cli::cli_arguments() {
+        std::process::exit(cli::run(args, &context));
+    }
+    let startup_paths = collect_startup_file_paths();
+    let instance_listener = match claim_instance(&startup_paths) {
+        InstanceRole::Primary(listener) => listener,
+        InstanceRole::Forwarded => return,
+    };
     tauri::Builder::default()
         .manage(PendingOpenPaths::default())
+        .manage(RunningJobs::default())
+        .manage(JobQueue::default())
+        .manage(FolderWatchers::default())
         .setup(|app| {
-            let gs_runtime = resolve_ghostscript_runtime(app);
+            let settings = SettingsStore::load(app.path_resolver().app_config_dir());
+            init_logging(app.path_resolver().app_log_dir(), settings.get().log_level);
+            apply_settings(&settings.get());
+            log_info!("PDF Resizer {} starting", app.package_info().version);
+            let gs_runtime = resolve_ghostscript_runtime(
+                app.path_resolver().resource_dir(),
+                app.path_resolver().app_config_dir(),
+            );
             app.manage(gs_runtime);
-            let startup_paths = collect_startup_file_paths();
-            if !startup_paths.is_empty() {
-                if let Some(main_window) = app.get_window("main") {
-                    let _ = main_window.emit("external-files-opened", startup_paths.clone());
-                }
-                let state: State<'_, PendingOpenPaths> = app.state();
-                let mut guard = state.0.lock().expect("pending paths mutex poisoned");
-                guard.extend(startup_paths);
+            app.manage(RecentFiles::load(app.path_resolver().app_config_dir()));
+            app.manage(settings);
+            app.manage(PresetStore::load(app.path_resolver().app_config_dir()));
+            app.manage(JobHistory::load(app.path_resolver().app_data_dir()));
+            if let Some(listener) = instance_listener {
+                serve_instance(app.handle(), listener);
             }
+            open_external_files(&app.handle(), startup_paths);
             Ok(())
         })
+        .on_window_event(|event| {
+            // Closing the last window ends the app; don't leave its jobs running meanwhile.
+            if let tauri::WindowEvent::Destroyed = event.event() {
+                let window = event.window();
+                let app = window.app_handle();
+                if app.windows().keys().all(|label| label == window.label()) {
+                    shut_down_processing(&app);
+                }
+            }
+        })
         .invoke_handler(tauri::generate_handler![
             greet,
             check_file_existence,
             log_path,
-            check_ghostscript,
+            ghostscript_info,
+            invalidate_ghostscript_cache,
+            list_ghostscript_candidates,
+            ghostscript_devices,
             debug_ghostscript_probe,
+            get_ghostscript_path,
+            set_ghostscript_path,
             flatten_pdf,
-            take_pending_open_paths
+            resize_pdf,
+            fit_to_page,
+            crop_pdf,
+            rotate_pages,
+            split_pdf,
+            extract_pages,
+            delete_pages,
+            reorder_pages,
+            nup_pdf,
+            booklet_pdf,
+            compress_pdf,
+            downsample_images,
+            convert_to_grayscale,
+            convert_to_cmyk,
+            convert_to_pdfa,
+            convert_to_pdfx,
+            linearize_pdf,
+            decrypt_pdf,
+            encrypt_pdf,
+            get_pdf_metadata,
+            set_pdf_metadata,
+            get_page_count,
+            get_page_sizes,
+            render_page_preview,
+            generate_thumbnails,
+            export_pages_as_images,
+            export_postscript,
+            add_watermark,
+            stamp_pdf,
+            add_page_numbers,
+            add_printer_marks,
+            mirror_pages,
+            repair_pdf,
+            list_fonts,
+            embed_fonts,
+            detect_transparency,
+            analyze_colors,
+            ink_coverage,
+            remove_annotations,
+            flatten_forms,
+            sanitize_pdf,
+            extract_text,
+            extract_images,
+            list_attachments,
+            extract_attachment,
+            get_outline,
+            set_page_boxes,
+            overlay_pdfs,
+            validate_pdf,
+            check_pdf_files,
+            get_file_info,
+            check_output_writable,
+            get_temp_info,
+            validate_output_template,
+            render_output_path,
+            reveal_in_file_manager,
+            open_with_default_app,
+            add_recent_file,
+            get_recent_files,
+            clear_recent_files,
+            get_settings,
+            update_settings,
+            reset_settings,
+            list_presets,
+            save_preset,
+            duplicate_preset,
+            delete_preset,
+            rename_preset,
+            export_presets,
+            import_presets,
+            enqueue_job,
+            get_queue_status,
+            set_max_concurrency,
+            get_job_history,
+            get_log_file_path,
+            create_diagnostics_bundle,
+            set_log_level,
+            clear_job_history,
+            process_batch,
+            start_watch,
+            stop_watch,
+            create_job_id,
+            cancel_job,
+            take_pending_open_paths,
+            take_pending_preset_requests,
+            frontend_ready
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(context)
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            if let tauri::RunEvent::ExitRequested { .. } | tauri::RunEvent::Exit = event {
+                shut_down_processing(app);
+                return;
+            }
+            // Finder, the Dock and `pdfresizer:` links deliver files as open events rather
+            // than arguments, both at launch and while the app is running.
+            #[cfg(target_os = "macos")]
+            if let tauri::RunEvent::Opened { urls } = event {
+                let arguments = urls
+                    .into_iter()
+                    .filter_map(|url| {
+                        if url.scheme().eq_ignore_ascii_case(OPEN_LINK_SCHEME) {
+                            Some(url.as_str().to_string())
+                        } else {
+                            url.to_file_path()
+                                .ok()
+                                .map(|path| path.to_string_lossy().to_string())
+                        }
+                    })
+                    .collect();
+                open_external_files(app, arguments);
+            }
+            #[cfg(not(target_os = "macos"))]
+            let _ = (app, event);
+        });
 }
@@ -0,0 +1,1289 @@
+//! Operations as presets, the queue and the command line name them, the Ghostscript passes
+//! behind them, and running one on a file.
+
+use std::path::{Path, PathBuf};
+
+use crate::error::CommandError;
+use crate::ghostscript::{
+    ghostscript_page_count, page_list_arg, run_pdfwrite, run_pdfwrite_piped, GhostscriptExecOutput,
+    GhostscriptHooks, GhostscriptRuntime, GhostscriptWarning, JobContext,
+};
+use crate::output::{
+    check_free_space, ensure_distinct_paths, unique_temp_path, write_output, OutputOptions,
+    OutputResult, TempFileGuard, Written,
+};
+use crate::pdf::{
+    carry_page_boxes, declared_page_count, document_fonts, has_print_boxes, is_linearized,
+    load_pdf_document, pages_with_color, placed_media_transform, read_page_geometry,
+    without_subset_tag, PdfMatrix,
+};
+
+/// One file to process through the queue.
+#[derive(Clone, serde::Deserialize)]
+pub(crate) struct QueuedOperation {
+    pub(crate) input_path: String,
+    pub(crate) output_path: String,
+    pub(crate) operation: Operation,
+    #[serde(default)]
+    pub(crate) output_options: OutputOptions,
+}
+
+/// A processing step with its options, as saved in presets and run by `process_batch`.
+/// The fields match the arguments of the command of the same name.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub(crate) enum Operation {
+    Resize {
+        width_pt: f64,
+        height_pt: f64,
+        content_mode: ContentMode,
+        #[serde(default)]
+        anchor: Option<ResizeAnchor>,
+        #[serde(default)]
+        linearize: bool,
+    },
+    FitToPage {
+        preset: String,
+        margin_pt: f64,
+        orientation: FitOrientation,
+        #[serde(default)]
+        linearize: bool,
+    },
+    Compress {
+        preset: CompressPreset,
+    },
+    DownsampleImages {
+        color_dpi: u32,
+        gray_dpi: u32,
+        mono_dpi: u32,
+        jpeg_quality: u32,
+    },
+    ConvertToGrayscale,
+}
+
+#[derive(serde::Serialize)]
+pub(crate) struct BatchItemResult {
+    pub(crate) input_path: String,
+    pub(crate) output_path: Option<String>,
+    pub(crate) error: Option<CommandError>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) warnings: Vec<GhostscriptWarning>,
+}
+
+#[derive(Clone, Copy, PartialEq, serde::Serialize)]
+pub(crate) struct PageDimensions {
+    pub(crate) width_pt: f64,
+    pub(crate) height_pt: f64,
+}
+
+/// How `resize_pdf` treats the artwork when the page size changes.
+#[derive(Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum ContentMode {
+    /// Scale the artwork to fit the new page, centered.
+    Scale,
+    /// Keep the artwork at 100% and place it on the new page by `ResizeAnchor`.
+    KeepSize,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum ResizeAnchor {
+    Center,
+    Top,
+    Bottom,
+    Left,
+    Right,
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+/// Options for `flatten_pdf`; omitted fields keep the defaults (fonts outlined, PDF 1.7).
+#[derive(serde::Deserialize)]
+#[serde(default)]
+pub(crate) struct FlattenOptions {
+    /// Convert text to outlines (`-dNoOutputFonts`). Breaks copy/paste and accessibility.
+    pub(crate) outline_fonts: bool,
+    /// With `outline_fonts`, outline only pages that use one of these fonts (names as
+    /// reported by `list_fonts`) and keep text on the rest. Ghostscript outlines whole pages,
+    /// so other fonts on those pages are outlined too. Empty outlines every page.
+    outline_only: Vec<String>,
+    pub(crate) compatibility_level: String,
+    /// Keep marked content (tags, optional content) through the rewrite. When false,
+    /// Ghostscript's own default applies.
+    preserve_marked_content: bool,
+    pub(crate) linearize: bool,
+    /// When the rewrite fails, run `repair_pdf`'s recovery pass and flatten its output.
+    pub(crate) try_repair: bool,
+    /// Overrides `Settings::ghostscript_timeout_secs` for this command.
+    pub(crate) timeout_secs: Option<u64>,
+}
+
+/// Ghostscript `-dPDFSETTINGS` distiller presets, smallest output first.
+#[derive(Clone, Copy, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum CompressPreset {
+    Screen,
+    Ebook,
+    Printer,
+    Prepress,
+}
+
+/// PDF versions pdfwrite accepts for `-dCompatibilityLevel`.
+pub(crate) const COMPATIBILITY_LEVELS: [&str; 7] =
+    ["1.2", "1.3", "1.4", "1.5", "1.6", "1.7", "2.0"];
+
+impl Default for FlattenOptions {
+    fn default() -> Self {
+        FlattenOptions {
+            outline_fonts: true,
+            outline_only: Vec::new(),
+            compatibility_level: String::from("1.7"),
+            preserve_marked_content: false,
+            linearize: false,
+            try_repair: false,
+            timeout_secs: None,
+        }
+    }
+}
+
+/// Flattens `pdf_bytes`. Inputs up to `pipe_threshold_bytes` go through Ghostscript's
+/// stdin and stdout when one pass does the job; larger ones, and runs that split pages,
+/// linearize or fall back to repair, use temp files.
+pub(crate) fn flatten_with_ghostscript(
+    pdf_bytes: Vec<u8>,
+    options: &FlattenOptions,
+    pipe_threshold_bytes: u64,
+    runtime: &GhostscriptRuntime,
+    ctx: JobContext<'_>,
+) -> Result<Vec<u8>, CommandError> {
+    use std::io::Write;
+
+    if !COMPATIBILITY_LEVELS.contains(&options.compatibility_level.as_str()) {
+        return Err(CommandError::InvalidInput {
+            field: "compatibility_level",
+            value: options.compatibility_level.clone(),
+            expected: COMPATIBILITY_LEVELS.join(", "),
+        });
+    }
+    let mut device_args = vec![format!(
+        "-dCompatibilityLevel={}",
+        options.compatibility_level
+    )];
+    if options.preserve_marked_content {
+        device_args.push(String::from("-dPreserveMarkedContent=true"));
+    }
+
+    let single_pass = !options.outline_fonts || options.outline_only.is_empty();
+    if pdf_bytes.len() as u64 <= pipe_threshold_bytes && single_pass && !options.linearize {
+        let mut piped_args = device_args.clone();
+        if options.outline_fonts {
+            piped_args.push(String::from("-dNoOutputFonts"));
+        }
+        let total_pages = match ctx.on_progress {
+            Some(_) => lopdf::Document::load_mem(&pdf_bytes)
+                .ok()
+                .map(|doc| doc.get_pages().len() as u32),
+            None => None,
+        };
+        match run_pdfwrite_piped(&pdf_bytes, &piped_args, total_pages, runtime, ctx) {
+            Ok(output) => return Ok(output),
+            // The repair pass below needs the input on disk.
+            Err(e) if options.try_repair && !matches!(e, CommandError::Cancelled) => {
+                log_info!("Piped flatten failed, retrying from temp files: {}", e);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    // The input and the output both sit in the temp directory while Ghostscript runs.
+    check_free_space(&std::env::temp_dir(), pdf_bytes.len() as u64 * 2)?;
+
+    let mut temp_files = TempFileGuard::default();
+    let input_path = temp_files.track(unique_temp_path("flatten_input", "pdf"));
+    let output_path = temp_files.track(unique_temp_path("flatten_output", "pdf"));
+
+    std::fs::File::create(&input_path)
+        .and_then(|mut f| f.write_all(&pdf_bytes))
+        .map_err(|e| CommandError::Io {
+            action: "write_temp_input",
+            error: e.to_string(),
+        })?;
+
+    let total_pages = match ctx.on_progress {
+        Some(_) => ghostscript_page_count(&input_path, runtime, ctx.hooks()).ok(),
+        None => None,
+    };
+    if options.linearize {
+        device_args.push(String::from(FAST_WEB_VIEW_ARG));
+    }
+    let result = flatten_pass(
+        &input_path,
+        &output_path,
+        &device_args,
+        options,
+        total_pages,
+        runtime,
+        ctx,
+    );
+    match result {
+        Err(e) if options.try_repair && !matches!(e, CommandError::Cancelled) => {
+            let repaired_path = temp_files.track(unique_temp_path("flatten_repaired", "pdf"));
+            let report = repair_with_ghostscript(&input_path, &repaired_path, runtime, ctx)?;
+            if !report.recovered {
+                return Err(e);
+            }
+            flatten_pass(
+                &repaired_path,
+                &output_path,
+                &device_args,
+                options,
+                report.output_pages,
+                runtime,
+                ctx,
+            )?;
+        }
+        result => {
+            result?;
+        }
+    }
+
+    std::fs::read(&output_path).map_err(|e| CommandError::Io {
+        action: "read_output",
+        error: e.to_string(),
+    })
+}
+
+/// What `flatten_file` leaves behind.
+#[derive(serde::Serialize)]
+pub(crate) struct FlattenFileResult {
+    pub(crate) output_path: String,
+    pub(crate) output_size: u64,
+}
+
+impl OutputResult for FlattenFileResult {
+    fn set_output_path(&mut self, path: &Path) {
+        self.output_path = path.to_string_lossy().to_string();
+    }
+}
+
+/// Flattens the PDF at `input` into `output` under `output_options`, as
+/// `flatten_with_ghostscript` does for bytes.
+pub(crate) fn flatten_file(
+    input: &Path,
+    output: &Path,
+    options: &FlattenOptions,
+    output_options: OutputOptions,
+    pipe_threshold_bytes: u64,
+    runtime: &GhostscriptRuntime,
+    ctx: JobContext<'_>,
+) -> Result<Written<FlattenFileResult>, CommandError> {
+    write_output(input, output, output_options, |input, output, warnings| {
+        let pdf_bytes = std::fs::read(input).map_err(|e| CommandError::Io {
+            action: "read",
+            error: format!("'{}': {}", input.display(), e),
+        })?;
+        let flattened = flatten_with_ghostscript(
+            pdf_bytes,
+            options,
+            pipe_threshold_bytes,
+            runtime,
+            ctx.collecting(warnings),
+        )?;
+        std::fs::write(output, &flattened).map_err(|e| CommandError::Io {
+            action: "write",
+            error: format!("'{}': {}", output.display(), e),
+        })?;
+        Ok::<_, CommandError>(FlattenFileResult {
+            output_path: output.to_string_lossy().to_string(),
+            output_size: flattened.len() as u64,
+        })
+    })
+}
+
+/// The page count and the ascending pages that use any of the fonts in `names`, compared
+/// without subset tags. `None` when the page tree could not be read.
+fn pages_using_fonts(path: &Path, names: &[String]) -> Option<(u32, Vec<u32>)> {
+    let doc = load_pdf_document(path).ok()?;
+    let names: Vec<&str> = names.iter().map(|name| without_subset_tag(name)).collect();
+    let mut pages: Vec<u32> = document_fonts(&doc)
+        .into_iter()
+        .filter(|font| names.contains(&without_subset_tag(&font.name)))
+        .flat_map(|font| font.pages)
+        .collect();
+    pages.sort_unstable();
+    pages.dedup();
+    Some((doc.get_pages().len() as u32, pages))
+}
+
+/// One pdfwrite rewrite of `input` for `flatten_with_ghostscript`, applying the font
+/// outlining options on top of `device_args`. With `outline_only`, consecutive runs of
+/// pages are rewritten with or without outlining and the parts joined in order; when no
+/// page uses the listed fonts, nothing is outlined.
+fn flatten_pass(
+    input: &Path,
+    output: &Path,
+    device_args: &[String],
+    options: &FlattenOptions,
+    total_pages: Option<u32>,
+    runtime: &GhostscriptRuntime,
+    ctx: JobContext<'_>,
+) -> Result<(), CommandError> {
+    let mut outlined_args = device_args.to_vec();
+    outlined_args.push(String::from("-dNoOutputFonts"));
+    if !options.outline_fonts {
+        return run_pdfwrite(&[input], output, device_args, total_pages, runtime, ctx).map(|_| ());
+    }
+    // Outline everything when the fonts can't be located, rather than leave one as text.
+    let (page_count, outline_pages) = match pages_using_fonts(input, &options.outline_only) {
+        Some((page_count, pages))
+            if !options.outline_only.is_empty() && pages.len() < page_count as usize =>
+        {
+            (page_count, pages)
+        }
+        _ => {
+            return run_pdfwrite(&[input], output, &outlined_args, total_pages, runtime, ctx)
+                .map(|_| ());
+        }
+    };
+    if outline_pages.is_empty() {
+        return run_pdfwrite(&[input], output, device_args, total_pages, runtime, ctx).map(|_| ());
+    }
+
+    let mut runs: Vec<(bool, Vec<u32>)> = Vec::new();
+    for page in 1..=page_count {
+        let outlined = outline_pages.contains(&page);
+        match runs.last_mut() {
+            Some((run_outlined, pages)) if *run_outlined == outlined => pages.push(page),
+            _ => runs.push((outlined, vec![page])),
+        }
+    }
+    let mut temp_files = TempFileGuard::default();
+    let mut parts = Vec::new();
+    for (outlined, pages) in &runs {
+        let part = temp_files.track(unique_temp_path("flatten_part", "pdf"));
+        let mut part_args: Vec<String> = if *outlined {
+            outlined_args.clone()
+        } else {
+            device_args.to_vec()
+        };
+        part_args.retain(|arg| arg != FAST_WEB_VIEW_ARG);
+        part_args.push(page_list_arg(pages));
+        run_pdfwrite(
+            &[input],
+            &part,
+            &part_args,
+            Some(pages.len() as u32),
+            runtime,
+            ctx,
+        )?;
+        parts.push(part);
+    }
+    let parts: Vec<&Path> = parts.iter().map(PathBuf::as_path).collect();
+    run_pdfwrite(&parts, output, device_args, Some(page_count), runtime, ctx)?;
+    Ok(())
+}
+
+/// The names `paper_size` knows, for error messages.
+pub(crate) const PAPER_SIZE_NAMES: &str =
+    "a0-a6, b4, b5, letter, legal, tabloid, ledger, executive";
+
+/// Portrait dimensions in points for the standard sheet names `fit_to_page` accepts.
+pub(crate) fn paper_size(name: &str) -> Option<PageDimensions> {
+    let (width_pt, height_pt) = match name.to_ascii_lowercase().as_str() {
+        "a0" => (2383.94, 3370.39),
+        "a1" => (1683.78, 2383.94),
+        "a2" => (1190.55, 1683.78),
+        "a3" => (841.89, 1190.55),
+        "a4" => (595.28, 841.89),
+        "a5" => (419.53, 595.28),
+        "a6" => (297.64, 419.53),
+        "b4" => (708.66, 1000.63),
+        "b5" => (498.90, 708.66),
+        "letter" => (612.0, 792.0),
+        "legal" => (612.0, 1008.0),
+        "tabloid" | "ledger" => (792.0, 1224.0),
+        "executive" => (522.0, 756.0),
+        _ => return None,
+    };
+    Some(PageDimensions {
+        width_pt,
+        height_pt,
+    })
+}
+
+impl PageDimensions {
+    pub(crate) fn is_landscape(self) -> bool {
+        self.width_pt > self.height_pt
+    }
+
+    pub(crate) fn oriented(self, landscape: bool) -> PageDimensions {
+        if self.is_landscape() == landscape {
+            self
+        } else {
+            PageDimensions {
+                width_pt: self.height_pt,
+                height_pt: self.width_pt,
+            }
+        }
+    }
+}
+
+impl ResizeAnchor {
+    /// Offset of a `source`-sized page anchored on `target`; negative when it overhangs.
+    pub(crate) fn offset(self, source: PageDimensions, target: PageDimensions) -> (f64, f64) {
+        let free_x = target.width_pt - source.width_pt;
+        let free_y = target.height_pt - source.height_pt;
+        let x = match self {
+            ResizeAnchor::Left | ResizeAnchor::TopLeft | ResizeAnchor::BottomLeft => 0.0,
+            ResizeAnchor::Right | ResizeAnchor::TopRight | ResizeAnchor::BottomRight => free_x,
+            _ => free_x / 2.0,
+        };
+        let y = match self {
+            ResizeAnchor::Bottom | ResizeAnchor::BottomLeft | ResizeAnchor::BottomRight => 0.0,
+            ResizeAnchor::Top | ResizeAnchor::TopLeft | ResizeAnchor::TopRight => free_y,
+            _ => free_y / 2.0,
+        };
+        (x, y)
+    }
+}
+
+impl CompressPreset {
+    pub(crate) fn pdf_settings(self) -> &'static str {
+        match self {
+            CompressPreset::Screen => "/screen",
+            CompressPreset::Ebook => "/ebook",
+            CompressPreset::Printer => "/printer",
+            CompressPreset::Prepress => "/prepress",
+        }
+    }
+}
+
+/// Run `operation` from `input` to `output` by way of `write_output`.
+pub(crate) fn run_operation(
+    operation: &Operation,
+    input: &Path,
+    output: &Path,
+    options: OutputOptions,
+    runtime: &GhostscriptRuntime,
+    ctx: JobContext<'_>,
+) -> Result<(String, Vec<GhostscriptWarning>), CommandError> {
+    match operation.clone() {
+        Operation::Resize {
+            width_pt,
+            height_pt,
+            content_mode,
+            anchor,
+            linearize,
+        } => write_output(input, output, options, |input, output, warnings| {
+            resize_with_ghostscript(
+                input,
+                output,
+                PageDimensions {
+                    width_pt,
+                    height_pt,
+                },
+                content_mode,
+                anchor.unwrap_or(ResizeAnchor::Center),
+                linearize,
+                runtime,
+                ctx.collecting(warnings),
+            )
+        })
+        .map(|written| (written.result.output_path, written.warnings)),
+        Operation::FitToPage {
+            preset,
+            margin_pt,
+            orientation,
+            linearize,
+        } => write_output(input, output, options, |input, output, warnings| {
+            fit_to_page_with_ghostscript(
+                input,
+                output,
+                &preset,
+                margin_pt,
+                orientation,
+                linearize,
+                runtime,
+                ctx.collecting(warnings),
+            )
+        })
+        .map(|written| (written.result.output_path, written.warnings)),
+        Operation::Compress { preset } => {
+            write_output(input, output, options, |input, output, warnings| {
+                compress_with_ghostscript(input, output, preset, runtime, ctx.collecting(warnings))
+            })
+            .map(|written| (written.result.output_path, written.warnings))
+        }
+        Operation::DownsampleImages {
+            color_dpi,
+            gray_dpi,
+            mono_dpi,
+            jpeg_quality,
+        } => write_output(input, output, options, |input, output, warnings| {
+            downsample_with_ghostscript(
+                input,
+                output,
+                [color_dpi, gray_dpi, mono_dpi],
+                jpeg_quality,
+                runtime,
+                ctx.collecting(warnings),
+            )
+        })
+        .map(|written| (written.result.output_path, written.warnings)),
+        Operation::ConvertToGrayscale => {
+            write_output(input, output, options, |input, output, warnings| {
+                grayscale_with_ghostscript(input, output, runtime, ctx.collecting(warnings))
+            })
+            .map(|written| (written.result.output_path, written.warnings))
+        }
+    }
+}
+
+impl Operation {
+    /// The `kind` tag this operation serializes with.
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            Operation::Resize { .. } => "resize",
+            Operation::FitToPage { .. } => "fit-to-page",
+            Operation::Compress { .. } => "compress",
+            Operation::DownsampleImages { .. } => "downsample-images",
+            Operation::ConvertToGrayscale => "convert-to-grayscale",
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+pub(crate) struct PageResize {
+    page: u32,
+    /// `None` when the source page tree could not be parsed.
+    original: Option<PageDimensions>,
+    resized: PageDimensions,
+    scale: Option<f64>,
+    /// In `keep-size` mode, the artwork is larger than the new page and was cut off.
+    clipped: bool,
+}
+
+#[derive(serde::Serialize)]
+pub(crate) struct ResizeResult {
+    pub(crate) output_path: String,
+    pages: Vec<PageResize>,
+    /// Only checked when linearization was requested.
+    linearized: bool,
+}
+
+#[derive(serde::Serialize)]
+pub(crate) struct RepairReport {
+    pub(crate) output_path: String,
+    /// Ghostscript wrote an output file with at least one page.
+    recovered: bool,
+    /// Ghostscript's `****` error and warning lines, or its failure message.
+    warnings: Vec<String>,
+    /// `/Count` of the page tree, when the input's structure could be read at all.
+    declared_pages: Option<u32>,
+    output_pages: Option<u32>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum FitOrientation {
+    /// Match each source page: landscape pages get a landscape target.
+    Auto,
+    Portrait,
+    Landscape,
+}
+
+#[derive(serde::Serialize)]
+pub(crate) struct PageFit {
+    page: u32,
+    original: PageDimensions,
+    target: PageDimensions,
+    scale: f64,
+}
+
+/// Transform that maps a source page onto its target sheet.
+pub(crate) struct PagePlacement {
+    page: u32,
+    target: PageDimensions,
+    scale: f64,
+    offset_x: f64,
+    offset_y: f64,
+}
+
+#[derive(serde::Serialize)]
+pub(crate) struct CompressResult {
+    pub(crate) output_path: String,
+    original_size: u64,
+    output_size: u64,
+    /// Share of the original size saved, negative if the output grew.
+    percent_saved: f64,
+    /// Ghostscript's output was no smaller, so the input was copied unchanged instead.
+    kept_original: bool,
+}
+
+#[derive(serde::Serialize)]
+pub(crate) struct DownsampleResult {
+    pub(crate) output_path: String,
+    original_size: u64,
+    output_size: u64,
+    percent_saved: f64,
+}
+
+#[derive(serde::Serialize)]
+pub(crate) struct GrayscaleResult {
+    pub(crate) output_path: String,
+    all_gray: bool,
+    /// Pages of the output that still draw with a non-gray color space.
+    color_pages: Vec<u32>,
+}
+
+#[derive(serde::Serialize)]
+pub(crate) struct FitResult {
+    pub(crate) output_path: String,
+    pages: Vec<PageFit>,
+    /// Only checked when linearization was requested.
+    linearized: bool,
+}
+
+#[derive(serde::Serialize)]
+pub(crate) struct LinearizeResult {
+    pub(crate) output_path: String,
+    /// Verified from the file itself, not just taken from Ghostscript's exit status.
+    linearized: bool,
+}
+
+/// Page count from the page tree when lopdf can parse the file, otherwise from Ghostscript.
+pub(crate) fn document_page_count(
+    path: &Path,
+    runtime: &GhostscriptRuntime,
+    hooks: GhostscriptHooks<'_>,
+) -> Result<u32, CommandError> {
+    match lopdf::Document::load(path) {
+        Ok(doc) => Ok(doc.get_pages().len() as u32),
+        Err(_) => ghostscript_page_count(path, runtime, hooks),
+    }
+}
+
+/// Rewrite `input` with pdfwrite, letting Ghostscript reconstruct broken cross-reference
+/// tables and skip damaged content instead of stopping. A failed rewrite is reported,
+/// not returned as an error.
+pub(crate) fn repair_with_ghostscript(
+    input: &Path,
+    output: &Path,
+    runtime: &GhostscriptRuntime,
+    ctx: JobContext<'_>,
+) -> Result<RepairReport, CommandError> {
+    if !input.is_file() {
+        return Err(CommandError::NotFound {
+            path: input.to_string_lossy().to_string(),
+        });
+    }
+    ensure_distinct_paths(input, output)?;
+    let declared_pages = declared_page_count(input);
+
+    let device_args = [
+        String::from("-dPDFSTOPONERROR=false"),
+        String::from("-dPDFSTOPONWARNING=false"),
+    ];
+    let (warnings, succeeded) =
+        match run_pdfwrite(&[input], output, &device_args, declared_pages, runtime, ctx) {
+            Ok(result) => (
+                ghostscript_messages(&result, |line| line.starts_with("****")),
+                true,
+            ),
+            Err(e @ CommandError::Cancelled) => return Err(e),
+            Err(e) => (vec![e.detailed_message()], false),
+        };
+    let output_pages = if succeeded {
+        document_page_count(output, runtime, ctx.hooks()).ok()
+    } else {
+        None
+    };
+    let recovered = output_pages.is_some_and(|pages| pages > 0);
+    if !recovered {
+        let _ = std::fs::remove_file(output);
+    }
+
+    Ok(RepairReport {
+        output_path: output.to_string_lossy().to_string(),
+        recovered,
+        warnings,
+        declared_pages,
+        output_pages,
+    })
+}
+
+pub(crate) const FAST_WEB_VIEW_ARG: &str = "-dFastWebView=true";
+
+/// Rewrite the PDF linearized ("fast web view") so it can be displayed while downloading.
+pub(crate) fn linearize_with_ghostscript(
+    input: &Path,
+    output: &Path,
+    runtime: &GhostscriptRuntime,
+    ctx: JobContext<'_>,
+) -> Result<LinearizeResult, CommandError> {
+    if !input.is_file() {
+        return Err(CommandError::NotFound {
+            path: input.to_string_lossy().to_string(),
+        });
+    }
+    ensure_distinct_paths(input, output)?;
+
+    let total_pages = document_page_count(input, runtime, ctx.hooks()).ok();
+    let device_args = [String::from(FAST_WEB_VIEW_ARG)];
+    run_pdfwrite(&[input], output, &device_args, total_pages, runtime, ctx)?;
+
+    Ok(LinearizeResult {
+        output_path: output.to_string_lossy().to_string(),
+        linearized: is_linearized(output),
+    })
+}
+
+pub(crate) fn validate_page_dimensions(size: PageDimensions) -> Result<(), CommandError> {
+    let valid = |value: f64| value.is_finite() && value > 0.0;
+    if !valid(size.width_pt) || !valid(size.height_pt) {
+        return Err(CommandError::OutOfRange {
+            field: "page_size",
+            value: format!("{} x {} pt", size.width_pt, size.height_pt),
+            expected: String::from("positive"),
+        });
+    }
+    Ok(())
+}
+
+impl PagePlacement {
+    fn same_transform(&self, other: &PagePlacement) -> bool {
+        self.target == other.target
+            && self.scale == other.scale
+            && self.offset_x == other.offset_x
+            && self.offset_y == other.offset_y
+    }
+}
+
+/// Scale and centering offset that place `source` inside `target` minus `margin_pt`.
+pub(crate) fn fit_placement(
+    source: PageDimensions,
+    target: PageDimensions,
+    margin_pt: f64,
+) -> Result<(f64, f64, f64), CommandError> {
+    let available_width = target.width_pt - 2.0 * margin_pt;
+    let available_height = target.height_pt - 2.0 * margin_pt;
+    if available_width <= 0.0 || available_height <= 0.0 {
+        return Err(CommandError::OutOfRange {
+            field: "margin",
+            value: margin_pt.to_string(),
+            expected: format!(
+                "less than {:.1}",
+                target.width_pt.min(target.height_pt) / 2.0
+            ),
+        });
+    }
+    let scale = (available_width / source.width_pt).min(available_height / source.height_pt);
+    let offset_x = (target.width_pt - source.width_pt * scale) / 2.0;
+    let offset_y = (target.height_pt - source.height_pt * scale) / 2.0;
+    Ok((scale, offset_x, offset_y))
+}
+
+/// Place every page centered on a standard sheet with a margin. Consecutive pages with
+/// the same placement are rendered in one run whose `BeginPage` procedure applies the
+/// scale and offset; multiple runs are merged at the end.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn fit_to_page_with_ghostscript(
+    input: &Path,
+    output: &Path,
+    preset: &str,
+    margin_pt: f64,
+    orientation: FitOrientation,
+    linearize: bool,
+    runtime: &GhostscriptRuntime,
+    ctx: JobContext<'_>,
+) -> Result<FitResult, CommandError> {
+    let sheet = paper_size(preset).ok_or_else(|| CommandError::InvalidInput {
+        field: "page_size",
+        value: preset.to_string(),
+        expected: String::from(PAPER_SIZE_NAMES),
+    })?;
+    if !margin_pt.is_finite() || margin_pt < 0.0 {
+        return Err(CommandError::OutOfRange {
+            field: "margin",
+            value: margin_pt.to_string(),
+            expected: String::from("zero or positive"),
+        });
+    }
+    if !input.is_file() {
+        return Err(CommandError::NotFound {
+            path: input.to_string_lossy().to_string(),
+        });
+    }
+    ensure_distinct_paths(input, output)?;
+
+    let geometry = read_page_geometry(&load_pdf_document(input)?);
+    let total_pages = Some(geometry.len() as u32);
+
+    let mut placements = Vec::with_capacity(geometry.len());
+    let mut pages = Vec::with_capacity(geometry.len());
+    for page in &geometry {
+        let original = page.displayed_media_size();
+        let target = match orientation {
+            FitOrientation::Auto => sheet.oriented(original.is_landscape()),
+            FitOrientation::Portrait => sheet.oriented(false),
+            FitOrientation::Landscape => sheet.oriented(true),
+        };
+        let (scale, offset_x, offset_y) = fit_placement(original, target, margin_pt)?;
+        placements.push(PagePlacement {
+            page: page.page,
+            target,
+            scale,
+            offset_x,
+            offset_y,
+        });
+        pages.push(PageFit {
+            page: page.page,
+            original,
+            target,
+            scale,
+        });
+    }
+
+    // Consecutive pages sharing the same placement are rendered in one run.
+    let mut runs: Vec<&[PagePlacement]> = Vec::new();
+    let mut run_start = 0;
+    for index in 1..=placements.len() {
+        if index == placements.len() || !placements[index].same_transform(&placements[run_start]) {
+            runs.push(&placements[run_start..index]);
+            run_start = index;
+        }
+    }
+
+    let mut temp_files = TempFileGuard::default();
+    // As in `resize_with_ghostscript`, carried boxes defer linearization to a last pass.
+    let carry_boxes = has_print_boxes(&geometry);
+    let rewrite_output = if carry_boxes && linearize {
+        temp_files.track(unique_temp_path("fit_output", "pdf"))
+    } else {
+        output.to_path_buf()
+    };
+    let fast_web_view = linearize && !carry_boxes;
+    let mut run_outputs = Vec::new();
+    for run in &runs {
+        let first = &run[0];
+        let last_page = run[run.len() - 1].page;
+        let program = format!(
+            "<< /BeginPage {{ pop {:.4} {:.4} translate {:.6} dup scale }} >> setpagedevice",
+            first.offset_x, first.offset_y, first.scale
+        );
+        let mut device_args = vec![
+            format!("-dDEVICEWIDTHPOINTS={:.3}", first.target.width_pt),
+            format!("-dDEVICEHEIGHTPOINTS={:.3}", first.target.height_pt),
+            String::from("-dFIXEDMEDIA"),
+            format!("-dFirstPage={}", first.page),
+            format!("-dLastPage={}", last_page),
+            String::from("-c"),
+            program,
+            String::from("-f"),
+        ];
+        let run_output = if runs.len() == 1 {
+            if fast_web_view {
+                device_args.push(String::from(FAST_WEB_VIEW_ARG));
+            }
+            rewrite_output.clone()
+        } else {
+            temp_files.track(unique_temp_path("fit_run", "pdf"))
+        };
+        run_pdfwrite(
+            &[input],
+            &run_output,
+            &device_args,
+            total_pages,
+            runtime,
+            ctx,
+        )?;
+        run_outputs.push(run_output);
+    }
+
+    if runs.len() > 1 {
+        let merge_inputs: Vec<&Path> = run_outputs.iter().map(PathBuf::as_path).collect();
+        let merge_args: Vec<String> = if fast_web_view {
+            vec![String::from(FAST_WEB_VIEW_ARG)]
+        } else {
+            Vec::new()
+        };
+        run_pdfwrite(
+            &merge_inputs,
+            &rewrite_output,
+            &merge_args,
+            None,
+            runtime,
+            JobContext {
+                on_progress: None,
+                ..ctx
+            },
+        )?;
+    }
+    if carry_boxes {
+        let transforms: Vec<PdfMatrix> = geometry
+            .iter()
+            .zip(&placements)
+            .map(|(page, placement)| {
+                placed_media_transform(
+                    page,
+                    placement.scale,
+                    (placement.offset_x, placement.offset_y),
+                )
+            })
+            .collect();
+        carry_page_boxes(&rewrite_output, &geometry, &transforms)?;
+        if linearize {
+            linearize_with_ghostscript(&rewrite_output, output, runtime, ctx)?;
+        }
+    }
+
+    Ok(FitResult {
+        output_path: output.to_string_lossy().to_string(),
+        pages,
+        linearized: linearize && is_linearized(output),
+    })
+}
+
+/// PostScript that moves the `n`th page drawn (0-based) by `offsets[n]`, using the page
+/// count `/BeginPage` is called with.
+pub(crate) fn keep_size_program(offsets: &[(f64, f64)]) -> String {
+    let offsets: Vec<String> = offsets
+        .iter()
+        .map(|(x, y)| format!("[{:.4} {:.4}]", x, y))
+        .collect();
+    format!(
+        "%!\n\
+         /rs_dict 1 dict def\n\
+         rs_dict /rs_offsets [{offsets}] put\n\
+         << /BeginPage {{\n\
+         //rs_dict /rs_offsets get exch\n\
+         dup 2 index length lt {{ get aload pop translate }} {{ pop pop }} ifelse\n\
+         }} bind >> setpagedevice\n",
+        offsets = offsets.join(" "),
+    )
+}
+
+/// Change every page to `target`. `ContentMode::Scale` scales the artwork to fit;
+/// `ContentMode::KeepSize` keeps it at 100% and places it by `anchor`, reporting pages
+/// whose artwork no longer fits.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn resize_with_ghostscript(
+    input: &Path,
+    output: &Path,
+    target: PageDimensions,
+    content_mode: ContentMode,
+    anchor: ResizeAnchor,
+    linearize: bool,
+    runtime: &GhostscriptRuntime,
+    ctx: JobContext<'_>,
+) -> Result<ResizeResult, CommandError> {
+    let scale_content = content_mode == ContentMode::Scale;
+    validate_page_dimensions(target)?;
+    if !input.is_file() {
+        return Err(CommandError::NotFound {
+            path: input.to_string_lossy().to_string(),
+        });
+    }
+    ensure_distinct_paths(input, output)?;
+
+    // Files lopdf can't parse may still be readable by Ghostscript; only the size
+    // report degrades in that case.
+    let geometry = load_pdf_document(input).map(|doc| read_page_geometry(&doc));
+    let total_pages = match &geometry {
+        Ok(pages) => Some(pages.len() as u32),
+        Err(_) => ghostscript_page_count(input, runtime, ctx.hooks()).ok(),
+    };
+
+    let mut device_args = vec![
+        format!("-dDEVICEWIDTHPOINTS={:.3}", target.width_pt),
+        format!("-dDEVICEHEIGHTPOINTS={:.3}", target.height_pt),
+        String::from("-dFIXEDMEDIA"),
+    ];
+    let mut temp_files = TempFileGuard::default();
+    let mut inputs = vec![input];
+    // Where each page's upright MediaBox lands on the new page, for keep-size.
+    let offsets: Vec<(f64, f64)> = match (&geometry, content_mode) {
+        (_, ContentMode::Scale) => Vec::new(),
+        (Ok(pages), ContentMode::KeepSize) => pages
+            .iter()
+            .map(|page| anchor.offset(page.displayed_media_size(), target))
+            .collect(),
+        // Placing the content needs the page sizes, which only lopdf reads.
+        (Err(error), ContentMode::KeepSize) => return Err(error.clone()),
+    };
+    let geometry = geometry.ok();
+    let program = temp_files.track(unique_temp_path("resize", "ps"));
+    if scale_content {
+        device_args.push(String::from("-dPDFFitPage"));
+    } else {
+        std::fs::write(&program, keep_size_program(&offsets)).map_err(|e| CommandError::Io {
+            action: "write_resize_program",
+            error: e.to_string(),
+        })?;
+        inputs.insert(0, program.as_path());
+    }
+    // Boxes are carried over with lopdf, which would undo linearization, so with boxes to
+    // carry the file is linearized in a last pass.
+    let carry_boxes = geometry.as_deref().is_some_and(has_print_boxes);
+    let rewrite_output = if carry_boxes && linearize {
+        temp_files.track(unique_temp_path("resize_output", "pdf"))
+    } else {
+        output.to_path_buf()
+    };
+    if linearize && !carry_boxes {
+        device_args.push(String::from(FAST_WEB_VIEW_ARG));
+    }
+    run_pdfwrite(
+        &inputs,
+        &rewrite_output,
+        &device_args,
+        total_pages,
+        runtime,
+        ctx,
+    )?;
+
+    // `-dPDFFitPage` scales each page to fit and centers it.
+    let page_scale = |original: PageDimensions| {
+        if scale_content {
+            (target.width_pt / original.width_pt).min(target.height_pt / original.height_pt)
+        } else {
+            1.0
+        }
+    };
+    if let Some(pages) = geometry.as_deref().filter(|_| carry_boxes) {
+        let transforms: Vec<PdfMatrix> = pages
+            .iter()
+            .enumerate()
+            .map(|(index, page)| {
+                let original = page.displayed_media_size();
+                let scale = page_scale(original);
+                let offset = if scale_content {
+                    (
+                        (target.width_pt - original.width_pt * scale) / 2.0,
+                        (target.height_pt - original.height_pt * scale) / 2.0,
+                    )
+                } else {
+                    offsets[index]
+                };
+                placed_media_transform(page, scale, offset)
+            })
+            .collect();
+        carry_page_boxes(&rewrite_output, pages, &transforms)?;
+        if linearize {
+            linearize_with_ghostscript(&rewrite_output, output, runtime, ctx)?;
+        }
+    }
+
+    let pages = match geometry {
+        Some(pages) => pages
+            .iter()
+            .map(|page| {
+                let original = page.displayed_media_size();
+                let scale = page_scale(original);
+                // Half a point of slack for sizes that only differ by rounding.
+                let clipped = !scale_content
+                    && (original.width_pt > target.width_pt + 0.5
+                        || original.height_pt > target.height_pt + 0.5);
+                PageResize {
+                    page: page.page,
+                    original: Some(original),
+                    resized: target,
+                    scale: Some(scale),
+                    clipped,
+                }
+            })
+            .collect(),
+        None => (1..=total_pages.unwrap_or(0))
+            .map(|page| PageResize {
+                page,
+                original: None,
+                resized: target,
+                scale: None,
+                clipped: false,
+            })
+            .collect(),
+    };
+
+    Ok(ResizeResult {
+        output_path: output.to_string_lossy().to_string(),
+        pages,
+        linearized: linearize && is_linearized(output),
+    })
+}
+
+pub(crate) fn file_size(path: &Path) -> Result<u64, CommandError> {
+    path.metadata()
+        .map(|metadata| metadata.len())
+        .map_err(|e| CommandError::Io {
+            action: "read",
+            error: format!("'{}': {}", path.display(), e),
+        })
+}
+
+pub(crate) fn percent_saved(original_size: u64, output_size: u64) -> f64 {
+    if original_size == 0 {
+        return 0.0;
+    }
+    (original_size as f64 - output_size as f64) / original_size as f64 * 100.0
+}
+
+/// Rewrite the PDF with a distiller preset. Already-optimized files can come out larger;
+/// the input is then copied to `output` as-is and `kept_original` is set.
+pub(crate) fn compress_with_ghostscript(
+    input: &Path,
+    output: &Path,
+    preset: CompressPreset,
+    runtime: &GhostscriptRuntime,
+    ctx: JobContext<'_>,
+) -> Result<CompressResult, CommandError> {
+    if !input.is_file() {
+        return Err(CommandError::NotFound {
+            path: input.to_string_lossy().to_string(),
+        });
+    }
+    ensure_distinct_paths(input, output)?;
+    let original_size = file_size(input)?;
+
+    let total_pages = document_page_count(input, runtime, ctx.hooks()).ok();
+    let device_args = [format!("-dPDFSETTINGS={}", preset.pdf_settings())];
+    run_pdfwrite(&[input], output, &device_args, total_pages, runtime, ctx)?;
+
+    let mut output_size = file_size(output)?;
+    let kept_original = output_size >= original_size;
+    if kept_original {
+        std::fs::copy(input, output).map_err(|e| CommandError::Io {
+            action: "keep_original",
+            error: format!("'{}': {}", output.display(), e),
+        })?;
+        output_size = original_size;
+    }
+
+    Ok(CompressResult {
+        output_path: output.to_string_lossy().to_string(),
+        original_size,
+        output_size,
+        percent_saved: percent_saved(original_size, output_size),
+        kept_original,
+    })
+}
+
+/// pdfwrite arguments for per-class image resolution targets; 0 leaves that class alone.
+/// Images are only resampled when above 1.5x the target (Ghostscript's default threshold).
+pub(crate) fn downsample_args(
+    color_dpi: u32,
+    gray_dpi: u32,
+    mono_dpi: u32,
+    jpeg_quality: u32,
+) -> Vec<String> {
+    let mut args = Vec::new();
+    for (class, dpi, method) in [
+        ("Color", color_dpi, "/Bicubic"),
+        ("Gray", gray_dpi, "/Bicubic"),
+        // Bicubic has no meaning for 1-bit images.
+        ("Mono", mono_dpi, "/Subsample"),
+    ] {
+        if dpi == 0 {
+            args.push(format!("-dDownsample{}Images=false", class));
+        } else {
+            args.push(format!("-dDownsample{}Images=true", class));
+            args.push(format!("-d{}ImageResolution={}", class, dpi));
+            args.push(format!("-d{}ImageDownsampleType={}", class, method));
+        }
+    }
+    if jpeg_quality > 0 {
+        // JPEGQ only applies once automatic filter selection is off.
+        for class in ["Color", "Gray"] {
+            args.push(format!("-dAutoFilter{}Images=false", class));
+            args.push(format!("-d{}ImageFilter=/DCTEncode", class));
+        }
+        args.push(format!("-dJPEGQ={}", jpeg_quality));
+    }
+    args
+}
+
+pub(crate) fn downsample_with_ghostscript(
+    input: &Path,
+    output: &Path,
+    dpi: [u32; 3],
+    jpeg_quality: u32,
+    runtime: &GhostscriptRuntime,
+    ctx: JobContext<'_>,
+) -> Result<DownsampleResult, CommandError> {
+    if jpeg_quality > 100 {
+        return Err(CommandError::OutOfRange {
+            field: "jpeg_quality",
+            value: jpeg_quality.to_string(),
+            expected: String::from("between 1 and 100, or 0 to leave it"),
+        });
+    }
+    if !input.is_file() {
+        return Err(CommandError::NotFound {
+            path: input.to_string_lossy().to_string(),
+        });
+    }
+    ensure_distinct_paths(input, output)?;
+    let original_size = file_size(input)?;
+
+    let total_pages = document_page_count(input, runtime, ctx.hooks()).ok();
+    let [color_dpi, gray_dpi, mono_dpi] = dpi;
+    let device_args = downsample_args(color_dpi, gray_dpi, mono_dpi, jpeg_quality);
+    run_pdfwrite(&[input], output, &device_args, total_pages, runtime, ctx)?;
+
+    let output_size = file_size(output)?;
+    Ok(DownsampleResult {
+        output_path: output.to_string_lossy().to_string(),
+        original_size,
+        output_size,
+        percent_saved: percent_saved(original_size, output_size),
+    })
+}
+
+/// Convert every color, including spot colors via their alternate space, to DeviceGray.
+/// The output is then scanned so leftover color can be flagged before printing.
+pub(crate) fn grayscale_with_ghostscript(
+    input: &Path,
+    output: &Path,
+    runtime: &GhostscriptRuntime,
+    ctx: JobContext<'_>,
+) -> Result<GrayscaleResult, CommandError> {
+    if !input.is_file() {
+        return Err(CommandError::NotFound {
+            path: input.to_string_lossy().to_string(),
+        });
+    }
+    ensure_distinct_paths(input, output)?;
+
+    let total_pages = document_page_count(input, runtime, ctx.hooks()).ok();
+    let device_args = [
+        String::from("-sColorConversionStrategy=Gray"),
+        String::from("-dProcessColorModel=/DeviceGray"),
+        // Older Ghostscript defaults to a 1.4 target where transparency groups can fail.
+        String::from("-dCompatibilityLevel=1.7"),
+    ];
+    run_pdfwrite(&[input], output, &device_args, total_pages, runtime, ctx)?;
+
+    let color_pages = pages_with_color(&load_pdf_document(output)?);
+    Ok(GrayscaleResult {
+        output_path: output.to_string_lossy().to_string(),
+        all_gray: color_pages.is_empty(),
+        color_pages,
+    })
+}
+
+/// Trimmed stdout and stderr lines of a Ghostscript run that match `filter`.
+pub(crate) fn ghostscript_messages(
+    output: &GhostscriptExecOutput,
+    filter: impl Fn(&str) -> bool,
+) -> Vec<String> {
+    output
+        .stdout
+        .lines()
+        .chain(output.stderr.lines())
+        .map(str::trim)
+        .filter(|line| filter(line))
+        .map(String::from)
+        .collect()
+}
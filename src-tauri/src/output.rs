@@ -0,0 +1,820 @@
+//! Writing results: output paths and conflicts, temp files, atomic replacement and retries
+//! on locked files. Every operation writes through `write_output`.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::error::CommandError;
+use crate::ghostscript::{GhostscriptWarning, WarningLog};
+use crate::pdf::{pdf_header, PDF_HEADER_WINDOW};
+
+pub(crate) const DEFAULT_IO_RETRY_ATTEMPTS: u32 = 3;
+
+pub(crate) const DEFAULT_IO_RETRY_BACKOFF_MS: u64 = 250;
+
+/// `Settings::io_retry_attempts` and `io_retry_backoff_ms` in effect.
+pub(crate) static IO_RETRY_ATTEMPTS: AtomicU32 = AtomicU32::new(DEFAULT_IO_RETRY_ATTEMPTS);
+
+pub(crate) static IO_RETRY_BACKOFF_MS: AtomicU64 = AtomicU64::new(DEFAULT_IO_RETRY_BACKOFF_MS);
+
+#[derive(serde::Serialize)]
+pub(crate) struct WritableCheck {
+    pub(crate) path: String,
+    pub(crate) exists: bool,
+    /// A probe file could be created and deleted in the folder.
+    pub(crate) writable: bool,
+    /// Space available to this user; `None` when the platform couldn't report it.
+    pub(crate) free_bytes: Option<u64>,
+    /// `free_bytes` covers the requested size, or the free space is unknown.
+    pub(crate) enough_space: bool,
+    /// USB sticks, SD cards and the like, which can disappear mid-batch.
+    pub(crate) removable: bool,
+    /// SMB, NFS, AFP and other network shares.
+    pub(crate) network: bool,
+    /// Mount point or drive root of the volume.
+    pub(crate) volume: Option<String>,
+    /// Why the folder can't be written, when it can't.
+    pub(crate) error: Option<CommandError>,
+}
+
+/// The volume a path lives on, as far as the platform tells.
+#[derive(Default)]
+struct VolumeInfo {
+    /// Mount point, or the drive root such as `D:\` on Windows.
+    mount_point: Option<String>,
+    free_bytes: Option<u64>,
+    removable: bool,
+    network: bool,
+}
+
+/// What a command does when its output path already exists.
+#[derive(Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ConflictPolicy {
+    #[default]
+    Error,
+    /// Write nothing and fail with `CommandError::OutputSkipped`.
+    Skip,
+    Overwrite,
+    /// Write `name (2).pdf`, `name (3).pdf`, ... instead, like Finder.
+    Rename,
+}
+
+/// Output handling shared by the file-writing commands; omitted fields keep the defaults
+/// (fail on existing outputs, never in place).
+#[derive(Clone, Copy, Default, serde::Deserialize)]
+#[serde(default)]
+pub(crate) struct OutputOptions {
+    pub(crate) on_conflict: ConflictPolicy,
+    /// Allow the output to be the input file itself. It is replaced only after the new
+    /// version has been written in full.
+    pub(crate) allow_in_place: bool,
+    /// Give the output the input's access and modification times.
+    pub(crate) preserve_mtime: bool,
+    /// Overrides `Settings::ghostscript_timeout_secs` for this command.
+    pub(crate) timeout_secs: Option<u64>,
+}
+
+/// A single-file command's result plus problems that didn't stop the output from being
+/// written, such as timestamps that couldn't be copied.
+#[derive(serde::Serialize)]
+pub(crate) struct Written<T> {
+    #[serde(flatten)]
+    pub(crate) result: T,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) output_warnings: Vec<CommandError>,
+    /// What Ghostscript complained about while writing the output.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) warnings: Vec<GhostscriptWarning>,
+    /// Whether the input or output path was too long for Ghostscript, so the job ran on a
+    /// copy in the temp dir; it is slower for the extra copying.
+    via_local_copy: bool,
+    /// How many times the input was locked by another program, e.g. a sync client, and
+    /// waited for.
+    retries: u32,
+}
+
+static TEMP_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Paths held by a live `TempFileGuard`, so `shut_down_processing` can delete what
+/// exiting threads never got to.
+pub(crate) static LIVE_TEMP_FILES: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Build a temp file path that is unique per process and per call, so concurrent
+/// jobs (or several running app instances) never share input/output files.
+pub(crate) fn unique_temp_path(label: &str, extension: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("{}.{}", unique_temp_stem(label), extension))
+}
+
+pub(crate) fn unique_temp_stem(label: &str) -> String {
+    let counter = TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    format!(
+        "pdfresizer_{}_{}_{}_{:08x}",
+        label,
+        std::process::id(),
+        counter,
+        nanos
+    )
+}
+
+/// Removes the tracked temp files when dropped, including on early `?` returns and
+/// while unwinding from a panic.
+#[derive(Default)]
+pub(crate) struct TempFileGuard {
+    pub(crate) paths: Vec<PathBuf>,
+}
+
+impl TempFileGuard {
+    pub(crate) fn track(&mut self, path: PathBuf) -> PathBuf {
+        self.paths.push(path.clone());
+        LIVE_TEMP_FILES
+            .lock()
+            .expect("temp file registry mutex poisoned")
+            .push(path.clone());
+        path
+    }
+
+    /// Stop tracking everything, e.g. once outputs are complete and should be kept.
+    pub(crate) fn keep(&mut self) {
+        forget_live_temp_files(&self.paths);
+        self.paths.clear();
+    }
+}
+
+impl Drop for TempFileGuard {
+    fn drop(&mut self) {
+        for path in &self.paths {
+            if path.exists() {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+        forget_live_temp_files(&self.paths);
+    }
+}
+
+fn forget_live_temp_files(paths: &[PathBuf]) {
+    if paths.is_empty() {
+        return;
+    }
+    let mut live = LIVE_TEMP_FILES
+        .lock()
+        .expect("temp file registry mutex poisoned");
+    for path in paths {
+        if let Some(index) = live.iter().position(|live_path| live_path == path) {
+            live.swap_remove(index);
+        }
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn volume_info(path: &Path) -> VolumeInfo {
+    use std::os::windows::ffi::OsStrExt;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetDiskFreeSpaceExW(
+            directory: *const u16,
+            free_bytes_available: *mut u64,
+            total_bytes: *mut u64,
+            total_free_bytes: *mut u64,
+        ) -> i32;
+        fn GetVolumePathNameW(path: *const u16, volume_path: *mut u16, length: u32) -> i32;
+        fn GetDriveTypeW(root_path: *const u16) -> u32;
+    }
+    const DRIVE_REMOVABLE: u32 = 2;
+    const DRIVE_REMOTE: u32 = 4;
+    const DRIVE_CDROM: u32 = 5;
+
+    let wide: Vec<u16> = path
+        .as_os_str()
+        .encode_wide()
+        .chain(std::iter::once(0))
+        .collect();
+    let mut info = VolumeInfo::default();
+    let mut free = 0u64;
+    // SAFETY: `wide` is NUL-terminated and the output pointers are valid or null.
+    let ok = unsafe {
+        GetDiskFreeSpaceExW(
+            wide.as_ptr(),
+            &mut free,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        )
+    };
+    if ok != 0 {
+        info.free_bytes = Some(free);
+    }
+    let mut root = vec![0u16; 1024];
+    // SAFETY: `root` has room for the `length` UTF-16 units passed.
+    let ok = unsafe { GetVolumePathNameW(wide.as_ptr(), root.as_mut_ptr(), root.len() as u32) };
+    if ok != 0 {
+        // SAFETY: `root` now holds a NUL-terminated volume path.
+        let drive_type = unsafe { GetDriveTypeW(root.as_ptr()) };
+        info.removable = matches!(drive_type, DRIVE_REMOVABLE | DRIVE_CDROM);
+        info.network = drive_type == DRIVE_REMOTE;
+        let end = root
+            .iter()
+            .position(|unit| *unit == 0)
+            .unwrap_or(root.len());
+        info.mount_point = Some(String::from_utf16_lossy(&root[..end]));
+    }
+    info
+}
+
+#[cfg(not(target_os = "windows"))]
+fn volume_info(path: &Path) -> VolumeInfo {
+    const NETWORK_FILESYSTEMS: [&str; 9] = [
+        "nfs",
+        "nfs4",
+        "smbfs",
+        "cifs",
+        "smb3",
+        "afpfs",
+        "webdav",
+        "fuse.sshfs",
+        "9p",
+    ];
+    let mut info = VolumeInfo::default();
+    // POSIX `df -P` prints the same columns on Linux and macOS: filesystem, 1024-blocks,
+    // used, available, capacity and mount point, either of which may contain spaces.
+    let Ok(output) = std::process::Command::new("df")
+        .arg("-Pk")
+        .arg(path)
+        .output()
+    else {
+        return info;
+    };
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let Some(line) = stdout.lines().nth(1) else {
+        return info;
+    };
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    let Some(capacity) = tokens.iter().position(|token| token.ends_with('%')) else {
+        return info;
+    };
+    if capacity < 4 {
+        return info;
+    }
+    info.free_bytes = tokens[capacity - 1]
+        .parse::<u64>()
+        .ok()
+        .map(|kilobytes| kilobytes * 1024);
+    let filesystem = tokens[..capacity - 3].join(" ");
+    let mount_point = tokens[capacity + 1..].join(" ");
+
+    let fs_type = mount_fs_type(&mount_point);
+    info.network = filesystem.starts_with("//")
+        || fs_type
+            .as_deref()
+            .is_some_and(|fs_type| NETWORK_FILESYSTEMS.contains(&fs_type));
+    info.removable = !info.network
+        && ["/Volumes/", "/media/", "/run/media/"]
+            .iter()
+            .any(|prefix| mount_point.starts_with(prefix));
+    info.mount_point = Some(mount_point);
+    info
+}
+
+/// The filesystem type of the volume mounted at `mount_point`, from the mount table.
+#[cfg(target_os = "linux")]
+fn mount_fs_type(mount_point: &str) -> Option<String> {
+    let mounts = std::fs::read_to_string("/proc/mounts").ok()?;
+    // Fields are space-separated, with spaces inside them escaped as `\040`.
+    mounts.lines().rev().find_map(|line| {
+        let mut fields = line.split(' ');
+        let _device = fields.next()?;
+        let target = fields.next()?.replace("\\040", " ");
+        let fs_type = fields.next()?;
+        (target == mount_point).then(|| fs_type.to_string())
+    })
+}
+
+/// The filesystem type of the volume mounted at `mount_point`, from `mount`'s
+/// `device on /path (type, options)` lines.
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+fn mount_fs_type(mount_point: &str) -> Option<String> {
+    let output = std::process::Command::new("mount").output().ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let marker = format!(" on {} (", mount_point);
+    stdout.lines().find_map(|line| {
+        let rest = &line[line.find(&marker)? + marker.len()..];
+        let end = rest.find([',', ')'])?;
+        Some(rest[..end].trim().to_string())
+    })
+}
+
+/// Probe whether files can be created in `dir` and whether its volume has `needed_bytes`
+/// free.
+pub(crate) fn check_output_location(dir: &Path, needed_bytes: u64) -> WritableCheck {
+    let volume = volume_info(dir);
+    let mut check = WritableCheck {
+        path: dir.to_string_lossy().to_string(),
+        exists: dir.is_dir(),
+        writable: false,
+        free_bytes: volume.free_bytes,
+        enough_space: volume.free_bytes.map_or(true, |free| free >= needed_bytes),
+        removable: volume.removable,
+        network: volume.network,
+        volume: volume.mount_point,
+        error: None,
+    };
+    if !check.exists {
+        check.error = Some(CommandError::NotFound {
+            path: check.path.clone(),
+        });
+        return check;
+    }
+    let not_writable = |e: std::io::Error| CommandError::NotWritable {
+        path: dir.to_string_lossy().to_string(),
+        error: e.to_string(),
+    };
+    let probe = dir.join(format!(".{}.tmp", unique_temp_stem("write-check")));
+    match std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)
+    {
+        Ok(file) => {
+            drop(file);
+            // A folder that lets files be created but not deleted would collect temp files.
+            match std::fs::remove_file(&probe) {
+                Ok(()) => check.writable = true,
+                Err(e) => check.error = Some(not_writable(e)),
+            }
+        }
+        Err(e) => check.error = Some(not_writable(e)),
+    }
+    check
+}
+
+/// `CommandError::DiskFull` when the volume holding `dir` has less than `needed_bytes`
+/// free. Unknown free space passes.
+pub(crate) fn check_free_space(dir: &Path, needed_bytes: u64) -> Result<(), CommandError> {
+    let volume = volume_info(dir);
+    match volume.free_bytes {
+        Some(free) if free < needed_bytes => Err(CommandError::DiskFull {
+            volume: volume
+                .mount_point
+                .unwrap_or_else(|| dir.to_string_lossy().to_string()),
+            needed_bytes,
+            available_bytes: free,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Fail before a multi-file job starts when `dir` is read-only or too full.
+pub(crate) fn ensure_output_writable(dir: &Path, needed_bytes: u64) -> Result<(), CommandError> {
+    let check = check_output_location(dir, 0);
+    if let Some(error) = check.error {
+        return Err(error);
+    }
+    check_free_space(dir, needed_bytes)
+}
+
+pub(crate) fn ensure_distinct_paths(input: &Path, output: &Path) -> Result<(), CommandError> {
+    let same = match (input.canonicalize(), output.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => input == output,
+    };
+    if same {
+        return Err(CommandError::SameInputAndOutput {
+            path: output.to_string_lossy().to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Whether both paths name the same existing file, after resolving links.
+pub(crate) fn is_same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// `name (2).ext`, `name (3).ext`, ...: the first that is neither on disk nor in `claimed`.
+fn numbered_path(path: &Path, claimed: &[PathBuf]) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let extension = path
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    (2..)
+        .map(|n| path.with_file_name(format!("{} ({}){}", stem, n, extension)))
+        .find(|candidate| !candidate.exists() && !claimed.contains(candidate))
+        .expect("unbounded range")
+}
+
+fn output_exists(path: &Path) -> CommandError {
+    CommandError::OutputExists {
+        path: path.to_string_lossy().to_string(),
+    }
+}
+
+/// The path to write for `path` under `policy`, or `None` when it should be skipped.
+/// `claimed` are paths the same job already chose; only renaming gets past those. The
+/// path is not reserved: `commit_output` applies `policy` again if it is taken by then.
+pub(crate) fn claim_output_path(
+    path: &Path,
+    policy: ConflictPolicy,
+    claimed: &[PathBuf],
+) -> Result<Option<PathBuf>, CommandError> {
+    let taken = claimed.iter().any(|claimed| claimed == path);
+    match policy {
+        _ if !taken && !path.exists() => Ok(Some(path.to_path_buf())),
+        ConflictPolicy::Rename => Ok(Some(numbered_path(path, claimed))),
+        ConflictPolicy::Overwrite if !taken => Ok(Some(path.to_path_buf())),
+        ConflictPolicy::Skip if !taken => Ok(None),
+        _ => Err(output_exists(path)),
+    }
+}
+
+/// `claim_output_path` for each of a job's outputs in turn.
+pub(crate) fn claim_output_paths(
+    paths: &[PathBuf],
+    policy: ConflictPolicy,
+) -> Result<Vec<Option<PathBuf>>, CommandError> {
+    let mut claimed = Vec::with_capacity(paths.len());
+    let mut claims = Vec::with_capacity(paths.len());
+    for path in paths {
+        let claim = claim_output_path(path, policy, &claimed)?;
+        claimed.extend(claim.clone());
+        claims.push(claim);
+    }
+    Ok(claims)
+}
+
+/// Results of the commands that write one file, which report where it went.
+pub(crate) trait OutputResult {
+    fn set_output_path(&mut self, path: &Path);
+}
+
+/// Give `output` the access and modification times of `source`.
+fn copy_file_times(source: &Path, output: &Path) -> std::io::Result<()> {
+    let metadata = std::fs::metadata(source)?;
+    filetime::set_file_times(
+        output,
+        filetime::FileTime::from_last_access_time(&metadata),
+        filetime::FileTime::from_last_modification_time(&metadata),
+    )
+}
+
+/// A hidden temp path next to `path`, so moving it into place never crosses devices.
+pub(crate) fn sibling_temp_path(path: &Path) -> PathBuf {
+    path.with_file_name(format!(".{}.tmp", unique_temp_stem("output")))
+}
+
+/// Whether a rename failed only because source and destination are on different volumes.
+fn is_cross_device(error: &std::io::Error) -> bool {
+    // EXDEV on Unix, ERROR_NOT_SAME_DEVICE on Windows.
+    #[cfg(target_os = "windows")]
+    const CROSS_DEVICE: i32 = 17;
+    #[cfg(not(target_os = "windows"))]
+    const CROSS_DEVICE: i32 = 18;
+    error.raw_os_error() == Some(CROSS_DEVICE)
+}
+
+/// Move `from` to `to` unless `to` exists, in one step: a hard link, which fails on an
+/// existing `to`, then removing `from`. `Ok(false)` when `to` exists. Volumes without
+/// hard links get an empty `create_new` placeholder at `to` that `from` is renamed over.
+fn move_without_replacing(from: &Path, to: &Path) -> std::io::Result<bool> {
+    let placeholder = match std::fs::hard_link(from, to) {
+        Ok(()) => {
+            let _ = std::fs::remove_file(from);
+            return Ok(true);
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists || is_cross_device(&e) => {
+            return Err(e)
+        }
+        Err(_) => std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(to),
+    };
+    drop(placeholder?);
+    if let Err(e) = std::fs::rename(from, to) {
+        let _ = std::fs::remove_file(to);
+        return Err(e);
+    }
+    Ok(true)
+}
+
+/// Move `from` to `to` atomically, over an existing `to` only when `replace` is set;
+/// `Ok(false)` when it was left alone. Across volumes `from` is first copied to a temp
+/// sibling of `to`, which is then moved, so `to` is never seen half-written.
+fn move_into_place(from: &Path, to: &Path, replace: bool) -> Result<bool, CommandError> {
+    let error = |e: std::io::Error| CommandError::Io {
+        action: "write",
+        error: format!("'{}': {}", to.display(), e),
+    };
+    let moved = if replace {
+        std::fs::rename(from, to).map(|()| true)
+    } else {
+        move_without_replacing(from, to)
+    };
+    match moved {
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Ok(false),
+        Err(e) if is_cross_device(&e) => {
+            let mut temp_files = TempFileGuard::default();
+            let copy = temp_files.track(sibling_temp_path(to));
+            std::fs::copy(from, &copy).map_err(error)?;
+            let moved = move_into_place(&copy, to, replace)?;
+            if moved {
+                let _ = std::fs::remove_file(from);
+            }
+            Ok(moved)
+        }
+        result => result.map_err(error),
+    }
+}
+
+/// Check a finished temp output before it replaces anything: it must not be empty, and
+/// PDFs must start with a `%PDF-` header.
+fn verify_output(temp: &Path, destination: &Path) -> Result<(), CommandError> {
+    use std::io::Read;
+
+    let mut head = Vec::with_capacity(PDF_HEADER_WINDOW);
+    std::fs::File::open(temp)
+        .and_then(|file| file.take(PDF_HEADER_WINDOW as u64).read_to_end(&mut head))
+        .map_err(|e| CommandError::Io {
+            action: "read_output",
+            error: e.to_string(),
+        })?;
+    let invalid = |reason| CommandError::OutputInvalid {
+        path: destination.to_string_lossy().to_string(),
+        reason,
+    };
+    if head.is_empty() {
+        return Err(invalid("empty"));
+    }
+    let is_pdf = destination
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("pdf"));
+    if is_pdf && pdf_header(&head).is_none() {
+        return Err(invalid("not_pdf"));
+    }
+    Ok(())
+}
+
+/// Verify the temp output `temp` and move it to `destination`, which `claim_output_path`
+/// chose for `requested` under `policy`. Another job or program may have created
+/// `destination` since; unless `policy` overwrites, it is then applied again. Returns the
+/// path written, or `None` when the output was skipped after all. The check and the move
+/// are one step, so an output appearing in between is never replaced.
+pub(crate) fn commit_output(
+    temp: &Path,
+    requested: &Path,
+    destination: &Path,
+    policy: ConflictPolicy,
+) -> Result<Option<PathBuf>, CommandError> {
+    verify_output(temp, destination)?;
+    let replace = policy == ConflictPolicy::Overwrite;
+    let mut destination = destination.to_path_buf();
+    while !move_into_place(temp, &destination, replace)? {
+        match policy {
+            ConflictPolicy::Rename => destination = numbered_path(requested, &[]),
+            ConflictPolicy::Skip => return Ok(None),
+            _ => return Err(output_exists(&destination)),
+        }
+    }
+    Ok(Some(destination))
+}
+
+/// Run `write` for `requested` as `options` allow: apply the conflict policy and refuse
+/// to write over `input` unless in-place writes are allowed. `write` gets the input to
+/// read and always a temp output, which replaces the destination only once it is
+/// complete and verified, so a crash never leaves a truncated file under the real name.
+/// Paths too long for Ghostscript are swapped for copies in the temp dir. The result
+/// reports the path actually written, plus the Ghostscript warnings `write` collected in
+/// the given `WarningLog`; those of attempts retried because the input was locked are
+/// dropped.
+pub(crate) fn write_output<T: OutputResult>(
+    input: &Path,
+    requested: &Path,
+    options: OutputOptions,
+    mut write: impl FnMut(&Path, &Path, &WarningLog) -> Result<T, CommandError>,
+) -> Result<Written<T>, CommandError> {
+    // In-place writes replace the input whatever the policy says.
+    let mut policy = options.on_conflict;
+    let mut destination = if is_same_file(input, requested) {
+        if !options.allow_in_place {
+            return Err(CommandError::SameInputAndOutput {
+                path: requested.to_string_lossy().to_string(),
+            });
+        }
+        policy = ConflictPolicy::Overwrite;
+        requested.to_path_buf()
+    } else {
+        match claim_output_path(requested, options.on_conflict, &[])? {
+            Some(destination) => destination,
+            None => {
+                return Err(CommandError::OutputSkipped {
+                    path: requested.to_string_lossy().to_string(),
+                })
+            }
+        }
+    };
+
+    let mut retries = 0;
+    wait_until_unlocked(input, &mut retries)?;
+    let mut temp_files = TempFileGuard::default();
+    let local_input = if needs_local_copy(input) {
+        let extension = input
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or("pdf");
+        let copy = temp_files.track(unique_temp_path("input", extension));
+        std::fs::copy(extended_length_path(input), &copy).map_err(|e| CommandError::Io {
+            action: "copy_input_locally",
+            error: format!("'{}': {}", input.display(), e),
+        })?;
+        Some(copy)
+    } else {
+        None
+    };
+    let local_output = needs_local_copy(&destination);
+    let temp = if local_output {
+        let extension = destination
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or("tmp");
+        temp_files.track(unique_temp_path("output", extension))
+    } else {
+        temp_files.track(sibling_temp_path(&destination))
+    };
+    let (mut result, warnings) = loop {
+        let warnings = WarningLog::default();
+        match write(local_input.as_deref().unwrap_or(input), &temp, &warnings) {
+            // Locked while Ghostscript ran; a local copy was taken before, so can't be.
+            Err(e)
+                if !matches!(e, CommandError::Cancelled)
+                    && local_input.is_none()
+                    && is_locked(input) =>
+            {
+                log_info!("'{}' was locked, retrying: {}", input.display(), e);
+                wait_until_unlocked(input, &mut retries)?;
+            }
+            result => break (result?, warnings),
+        }
+    };
+    let mut output_warnings = Vec::new();
+    // Some commands report failure in their result and write nothing, e.g. an
+    // unrecoverable repair; the destination is then left alone.
+    if temp.exists() {
+        // Times are copied before the move, while an in-place input still has its own.
+        if options.preserve_mtime {
+            if let Err(e) = copy_file_times(input, &temp) {
+                output_warnings.push(CommandError::Io {
+                    action: "keep_file_times",
+                    error: e.to_string(),
+                });
+            }
+        }
+        let skipped = || CommandError::OutputSkipped {
+            path: requested.to_string_lossy().to_string(),
+        };
+        destination = commit_output(&temp, requested, &destination, policy)?.ok_or_else(skipped)?;
+    }
+    result.set_output_path(&destination);
+    Ok(Written {
+        result,
+        output_warnings,
+        via_local_copy: local_input.is_some() || local_output,
+        retries,
+        warnings: warnings.into_warnings(),
+    })
+}
+
+/// Whether `error` is one that goes away on its own: another program holding the file
+/// open without sharing (Windows) or the file being busy (Unix).
+fn is_transient_io_error(error: &std::io::Error) -> bool {
+    #[cfg(windows)]
+    const TRANSIENT_CODES: [i32; 2] = [
+        32, // ERROR_SHARING_VIOLATION
+        33, // ERROR_LOCK_VIOLATION
+    ];
+    #[cfg(not(windows))]
+    const TRANSIENT_CODES: [i32; 2] = [
+        16, // EBUSY
+        26, // ETXTBSY
+    ];
+    error
+        .raw_os_error()
+        .is_some_and(|code| TRANSIENT_CODES.contains(&code))
+}
+
+/// Open `path` and read its first byte, as Ghostscript will.
+fn probe_readable(path: &Path) -> std::io::Result<()> {
+    use std::io::Read;
+    let mut file = std::fs::File::open(extended_length_path(path))?;
+    file.read(&mut [0; 1]).map(|_| ())
+}
+
+pub(crate) fn is_locked(path: &Path) -> bool {
+    probe_readable(path).is_err_and(|e| is_transient_io_error(&e))
+}
+
+/// Wait while `path` is locked, backing off from `Settings::io_retry_backoff_ms` and
+/// doubling, and fail once `retries` reaches `Settings::io_retry_attempts`. Other errors
+/// are not retried; they are left for the command to report.
+fn wait_until_unlocked(path: &Path, retries: &mut u32) -> Result<(), CommandError> {
+    let attempts = IO_RETRY_ATTEMPTS.load(Ordering::Relaxed);
+    let backoff_ms = IO_RETRY_BACKOFF_MS.load(Ordering::Relaxed);
+    loop {
+        match probe_readable(path) {
+            Err(e) if is_transient_io_error(&e) => {
+                if *retries >= attempts {
+                    return Err(CommandError::FileLocked {
+                        path: path.to_string_lossy().to_string(),
+                        error: e.to_string(),
+                    });
+                }
+                let delay = backoff_ms.saturating_mul(1 << (*retries).min(16));
+                std::thread::sleep(std::time::Duration::from_millis(delay));
+                *retries += 1;
+            }
+            _ => return Ok(()),
+        }
+    }
+}
+
+/// Replace `path` with `contents` via a synced temp sibling, so a crash leaves either the
+/// old file or the new one.
+pub(crate) fn write_file_atomically(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let temp = sibling_temp_path(path);
+    let written = std::fs::File::create(&temp).and_then(|mut file| {
+        file.write_all(contents)?;
+        file.sync_all()
+    });
+    match written.and_then(|()| std::fs::rename(&temp, path)) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            let _ = std::fs::remove_file(&temp);
+            Err(e)
+        }
+    }
+}
+
+/// Windows' `MAX_PATH`. Longer paths only work in the `\\?\` form, which Ghostscript
+/// can't be relied on to accept.
+pub(crate) const MAX_PATH_LENGTH: usize = 260;
+
+/// `path` in the `\\?\` extended-length form, which lifts `MAX_PATH` for local and UNC
+/// paths alike. It has to be absolute with `.` and `..` resolved, as Windows takes it
+/// literally.
+#[cfg(target_os = "windows")]
+pub(crate) fn extended_length_path(path: &Path) -> PathBuf {
+    use std::path::{Component, Prefix};
+
+    let absolute = match std::env::current_dir() {
+        Ok(current_dir) => current_dir.join(path),
+        Err(_) => return path.to_path_buf(),
+    };
+    let mut components = absolute.components();
+    let mut extended = match components.next() {
+        Some(Component::Prefix(prefix)) => match prefix.kind() {
+            Prefix::Disk(letter) => PathBuf::from(format!(r"\\?\{}:\", letter as char)),
+            Prefix::UNC(server, share) => PathBuf::from(format!(
+                r"\\?\UNC\{}\{}\",
+                server.to_string_lossy(),
+                share.to_string_lossy()
+            )),
+            // Already verbatim, or a device path.
+            _ => return absolute,
+        },
+        _ => return absolute,
+    };
+    for component in components {
+        match component {
+            Component::ParentDir => {
+                extended.pop();
+            }
+            Component::Normal(part) => extended.push(part),
+            Component::Prefix(_) | Component::RootDir | Component::CurDir => {}
+        }
+    }
+    extended
+}
+
+/// `path` itself; only Windows limits path length.
+#[cfg(not(target_os = "windows"))]
+pub(crate) fn extended_length_path(path: &Path) -> PathBuf {
+    path.to_path_buf()
+}
+
+/// Whether Ghostscript should be given a copy of `path` in the temp dir because `path`
+/// is too long for it.
+pub(crate) fn needs_local_copy(path: &Path) -> bool {
+    cfg!(target_os = "windows") && path.as_os_str().len() >= MAX_PATH_LENGTH
+}
@@ -0,0 +1,690 @@
+//! Reading and writing PDFs with lopdf: page geometry and boxes, content streams, fonts
+//! and colors.
+
+use std::collections::HashSet;
+use std::path::Path;
+
+use crate::error::CommandError;
+use crate::operations::PageDimensions;
+
+/// How far into the file a `%PDF-` header may start; readers accept some leading junk.
+pub(crate) const PDF_HEADER_WINDOW: usize = 1024;
+
+/// Offset of the `%PDF-` header near the start of `data`, and its `x.y` version if the
+/// header has one.
+pub(crate) fn pdf_header(data: &[u8]) -> Option<(usize, Option<String>)> {
+    let window = &data[..data.len().min(PDF_HEADER_WINDOW)];
+    let offset = window.windows(5).position(|bytes| bytes == b"%PDF-")?;
+    let version = match data.get(offset + 5..offset + 8) {
+        Some([major, b'.', minor]) if major.is_ascii_digit() && minor.is_ascii_digit() => {
+            Some(format!("{}.{}", char::from(*major), char::from(*minor)))
+        }
+        _ => None,
+    };
+    Some((offset, version))
+}
+
+/// Page boxes read from the page tree, with inherited attributes already resolved.
+#[derive(Clone)]
+pub(crate) struct PageGeometry {
+    pub(crate) page: u32,
+    pub(crate) media_box: [f64; 4],
+    pub(crate) crop_box: Option<[f64; 4]>,
+    pub(crate) trim_box: Option<[f64; 4]>,
+    pub(crate) bleed_box: Option<[f64; 4]>,
+    pub(crate) rotate: i32,
+    pub(crate) user_unit: f64,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum FontType {
+    Type1,
+    Mmtype1,
+    Truetype,
+    Type3,
+    /// A composite (Type 0) font over a CIDFont.
+    Cid,
+    Unknown,
+}
+
+#[derive(serde::Serialize)]
+pub(crate) struct FontInfo {
+    /// `/BaseFont`, including any subset tag.
+    pub(crate) name: String,
+    font_type: FontType,
+    /// Type 3 fonts are defined in the file and always count as embedded.
+    pub(crate) embedded: bool,
+    subset: bool,
+    /// 1-based pages whose resources refer to the font.
+    pub(crate) pages: Vec<u32>,
+}
+
+/// A decoded content stream together with the resources it draws from.
+pub(crate) struct ContentScope<'a> {
+    pub(crate) resources: Option<&'a lopdf::Dictionary>,
+    pub(crate) operations: Vec<lopdf::content::Operation>,
+}
+
+/// PDF transformation matrix `[a b c d e f]`.
+pub(crate) type PdfMatrix = [f64; 6];
+
+/// The page tree's `/Count`, without walking the tree.
+pub(crate) fn declared_page_count(path: &Path) -> Option<u32> {
+    let doc = lopdf::Document::load(path).ok()?;
+    let pages = doc.catalog().ok()?.get(b"Pages").ok()?;
+    let count = pdf_dict(&doc, pages)?.get(b"Count").ok()?.as_i64().ok()?;
+    u32::try_from(count).ok()
+}
+
+/// Whether the file opens with a linearization dictionary that has hint tables (`/H`) and
+/// whose `/L` matches the actual file length, which readers check before trusting it.
+pub(crate) fn is_linearized(path: &Path) -> bool {
+    use std::io::Read;
+
+    let Ok(file) = std::fs::File::open(path) else {
+        return false;
+    };
+    let Ok(length) = file.metadata().map(|metadata| metadata.len()) else {
+        return false;
+    };
+    // The linearization dictionary must be the first object in the file.
+    let mut head = Vec::new();
+    if file.take(1024).read_to_end(&mut head).is_err() {
+        return false;
+    }
+    let head = String::from_utf8_lossy(&head);
+    let Some(start) = head.find("/Linearized") else {
+        return false;
+    };
+    let dict = &head[start..];
+    let dict = &dict[..dict.find(">>").unwrap_or(dict.len())];
+    let key_value = |key: &str| {
+        dict.match_indices(key).find_map(|(index, _)| {
+            let rest = &dict[index + key.len()..];
+            if rest.starts_with(|c: char| c.is_ascii_alphanumeric()) {
+                return None;
+            }
+            Some(rest.trim_start())
+        })
+    };
+    let declared_length = key_value("/L").and_then(|value| {
+        value
+            .split(|c: char| !c.is_ascii_digit())
+            .next()
+            .and_then(|digits| digits.parse::<u64>().ok())
+    });
+    key_value("/H").is_some() && declared_length == Some(length)
+}
+
+pub(crate) fn load_pdf_document(path: &Path) -> Result<lopdf::Document, CommandError> {
+    lopdf::Document::load(path).map_err(|e| CommandError::InvalidPdf {
+        path: path.to_string_lossy().to_string(),
+        error: e.to_string(),
+    })
+}
+
+pub(crate) fn save_pdf_document(
+    doc: &mut lopdf::Document,
+    path: &Path,
+) -> Result<(), CommandError> {
+    doc.save(path).map(|_| ()).map_err(|e| CommandError::Io {
+        action: "write_pdf",
+        error: format!("'{}': {}", path.display(), e),
+    })
+}
+
+pub(crate) fn pdf_rect_object(rect: [f64; 4]) -> lopdf::Object {
+    lopdf::Object::Array(
+        rect.iter()
+            .map(|value| lopdf::Object::Real(*value as f32))
+            .collect(),
+    )
+}
+
+/// Look up a page attribute, following `/Parent` links for inheritable keys.
+pub(crate) fn inherited_page_attribute<'a>(
+    doc: &'a lopdf::Document,
+    page_id: lopdf::ObjectId,
+    key: &[u8],
+) -> Option<&'a lopdf::Object> {
+    let mut node = doc.get_dictionary(page_id).ok()?;
+    // Bounded so a cyclic `/Parent` chain in a damaged file can't loop forever.
+    for _ in 0..64 {
+        if let Ok(value) = node.get(key) {
+            return doc.dereference(value).ok().map(|(_, object)| object);
+        }
+        let parent = node.get(b"Parent").ok()?.as_reference().ok()?;
+        node = doc.get_dictionary(parent).ok()?;
+    }
+    None
+}
+
+pub(crate) fn pdf_number(doc: &lopdf::Document, object: &lopdf::Object) -> Option<f64> {
+    let (_, object) = doc.dereference(object).ok()?;
+    object.as_float().ok().map(f64::from)
+}
+
+/// Parse a PDF rectangle into normalized `[llx, lly, urx, ury]`.
+pub(crate) fn pdf_rect(doc: &lopdf::Document, object: &lopdf::Object) -> Option<[f64; 4]> {
+    let values = object.as_array().ok()?;
+    if values.len() != 4 {
+        return None;
+    }
+    let mut numbers = [0.0; 4];
+    for (slot, value) in numbers.iter_mut().zip(values) {
+        *slot = pdf_number(doc, value)?;
+    }
+    Some([
+        numbers[0].min(numbers[2]),
+        numbers[1].min(numbers[3]),
+        numbers[0].max(numbers[2]),
+        numbers[1].max(numbers[3]),
+    ])
+}
+
+pub(crate) fn normalize_rotation(rotation: i64) -> i32 {
+    rotation.rem_euclid(360) as i32
+}
+
+pub(crate) fn read_page_geometry(doc: &lopdf::Document) -> Vec<PageGeometry> {
+    // US Letter is the spec's fallback when a page has no MediaBox at all.
+    const DEFAULT_MEDIA_BOX: [f64; 4] = [0.0, 0.0, 612.0, 792.0];
+
+    doc.get_pages()
+        .into_iter()
+        .map(|(page, page_id)| {
+            let media_box = inherited_page_attribute(doc, page_id, b"MediaBox")
+                .and_then(|object| pdf_rect(doc, object))
+                .unwrap_or(DEFAULT_MEDIA_BOX);
+            let page_box = |key: &[u8]| {
+                inherited_page_attribute(doc, page_id, key).and_then(|object| pdf_rect(doc, object))
+            };
+            let rotate = inherited_page_attribute(doc, page_id, b"Rotate")
+                .and_then(|object| object.as_i64().ok())
+                .map(normalize_rotation)
+                .unwrap_or(0);
+            let user_unit = doc
+                .get_dictionary(page_id)
+                .ok()
+                .and_then(|dict| dict.get(b"UserUnit").ok())
+                .and_then(|object| pdf_number(doc, object))
+                .filter(|unit| *unit > 0.0)
+                .unwrap_or(1.0);
+            PageGeometry {
+                page,
+                media_box,
+                crop_box: page_box(b"CropBox"),
+                trim_box: page_box(b"TrimBox"),
+                bleed_box: page_box(b"BleedBox"),
+                rotate,
+                user_unit,
+            }
+        })
+        .collect()
+}
+
+impl PageGeometry {
+    pub(crate) fn box_size(&self, rect: [f64; 4]) -> PageDimensions {
+        let width = (rect[2] - rect[0]) * self.user_unit;
+        let height = (rect[3] - rect[1]) * self.user_unit;
+        if self.rotate % 180 == 0 {
+            PageDimensions {
+                width_pt: width,
+                height_pt: height,
+            }
+        } else {
+            PageDimensions {
+                width_pt: height,
+                height_pt: width,
+            }
+        }
+    }
+
+    /// MediaBox size in points as the page is displayed (rotation and UserUnit applied).
+    pub(crate) fn displayed_media_size(&self) -> PageDimensions {
+        self.box_size(self.media_box)
+    }
+
+    /// The region a viewer shows: the CropBox clipped to the MediaBox.
+    pub(crate) fn visible_box(&self) -> [f64; 4] {
+        let media = self.media_box;
+        match self.crop_box {
+            Some(crop) => {
+                let clipped = [
+                    crop[0].max(media[0]),
+                    crop[1].max(media[1]),
+                    crop[2].min(media[2]),
+                    crop[3].min(media[3]),
+                ];
+                if clipped[0] < clipped[2] && clipped[1] < clipped[3] {
+                    clipped
+                } else {
+                    media
+                }
+            }
+            None => media,
+        }
+    }
+
+    /// Matrix taking the visible box to `[0, 0, width, height]` as the page is displayed
+    /// (rotation and UserUnit applied), together with that displayed size.
+    pub(crate) fn upright_matrix(&self) -> (PdfMatrix, PageDimensions) {
+        let rect = self.visible_box();
+        let width = rect[2] - rect[0];
+        let height = rect[3] - rect[1];
+        // /Rotate turns the page clockwise when displayed.
+        let rotation = match self.rotate {
+            90 => [0.0, -1.0, 1.0, 0.0, 0.0, width],
+            180 => [-1.0, 0.0, 0.0, -1.0, width, height],
+            270 => [0.0, 1.0, -1.0, 0.0, height, 0.0],
+            _ => [1.0, 0.0, 0.0, 1.0, 0.0, 0.0],
+        };
+        let unit = self.user_unit;
+        let matrix = concat_matrix(
+            concat_matrix([1.0, 0.0, 0.0, 1.0, -rect[0], -rect[1]], rotation),
+            [unit, 0.0, 0.0, unit, 0.0, 0.0],
+        );
+        (matrix, self.box_size(rect))
+    }
+}
+
+/// Bounding box of `rect` after `matrix`.
+pub(crate) fn transform_rect(matrix: PdfMatrix, rect: [f64; 4]) -> [f64; 4] {
+    let [a, b, c, d, e, f] = matrix;
+    let [x0, y0, x1, y1] = rect;
+    let corners = [(x0, y0), (x1, y0), (x0, y1), (x1, y1)]
+        .map(|(x, y)| (a * x + c * y + e, b * x + d * y + f));
+    let xs = corners.map(|(x, _)| x);
+    let ys = corners.map(|(_, y)| y);
+    [
+        xs.iter().copied().fold(f64::INFINITY, f64::min),
+        ys.iter().copied().fold(f64::INFINITY, f64::min),
+        xs.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+        ys.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+    ]
+}
+
+/// Compose two matrices so that `first` is applied before `then`.
+pub(crate) fn concat_matrix(first: PdfMatrix, then: PdfMatrix) -> PdfMatrix {
+    let [a1, b1, c1, d1, e1, f1] = first;
+    let [a2, b2, c2, d2, e2, f2] = then;
+    [
+        a1 * a2 + b1 * c2,
+        a1 * b2 + b1 * d2,
+        c1 * a2 + d1 * c2,
+        c1 * b2 + d1 * d2,
+        e1 * a2 + f1 * c2 + e2,
+        e1 * b2 + f1 * d2 + f2,
+    ]
+}
+
+/// After a Ghostscript rewrite that moved or scaled the pages, set each output page's
+/// TrimBox and BleedBox to its source page's boxes mapped by the same transform and clipped
+/// to the new MediaBox. `transforms` take each source page's user space to its output page.
+pub(crate) fn carry_page_boxes(
+    output: &Path,
+    geometry: &[PageGeometry],
+    transforms: &[PdfMatrix],
+) -> Result<(), CommandError> {
+    let mut doc = load_pdf_document(output)?;
+    let output_geometry = read_page_geometry(&doc);
+    let page_ids = doc.get_pages();
+    for ((source, matrix), target) in geometry.iter().zip(transforms).zip(&output_geometry) {
+        let media = target.media_box;
+        let mapped = |rect: Option<[f64; 4]>| {
+            let rect = transform_rect(*matrix, rect?);
+            let clipped = [
+                rect[0].max(media[0]),
+                rect[1].max(media[1]),
+                rect[2].min(media[2]),
+                rect[3].min(media[3]),
+            ];
+            Some(clipped).filter(|rect| rect[0] < rect[2] && rect[1] < rect[3])
+        };
+        let page_dict = doc
+            .get_dictionary_mut(page_ids[&target.page])
+            .map_err(|e| CommandError::Io {
+                action: "read_page",
+                error: format!("page {}: {}", target.page, e),
+            })?;
+        for (key, rect) in [
+            ("TrimBox", mapped(source.trim_box)),
+            ("BleedBox", mapped(source.bleed_box)),
+        ] {
+            match rect {
+                Some(rect) => page_dict.set(key, pdf_rect_object(rect)),
+                None => {
+                    page_dict.remove(key.as_bytes());
+                }
+            }
+        }
+    }
+    save_pdf_document(&mut doc, output)
+}
+
+/// Whether `carry_page_boxes` has anything to carry.
+pub(crate) fn has_print_boxes(geometry: &[PageGeometry]) -> bool {
+    geometry
+        .iter()
+        .any(|page| page.trim_box.is_some() || page.bleed_box.is_some())
+}
+
+/// Transform from a source page's user space to a Ghostscript output page on which its
+/// upright MediaBox was scaled by `scale` and moved to `offset`.
+pub(crate) fn placed_media_transform(
+    page: &PageGeometry,
+    scale: f64,
+    offset: (f64, f64),
+) -> PdfMatrix {
+    let media = PageGeometry {
+        crop_box: None,
+        ..page.clone()
+    };
+    let (upright, _) = media.upright_matrix();
+    concat_matrix(upright, [scale, 0.0, 0.0, scale, offset.0, offset.1])
+}
+
+/// Decoded stream data; unfiltered streams are returned as stored.
+pub(crate) fn stream_content(stream: &lopdf::Stream) -> Result<Vec<u8>, CommandError> {
+    if stream.dict.has(b"Filter") {
+        stream.decompressed_content().map_err(|e| CommandError::Io {
+            action: "decode_stream",
+            error: e.to_string(),
+        })
+    } else {
+        Ok(stream.content.clone())
+    }
+}
+
+pub(crate) fn pdf_dict<'a>(
+    doc: &'a lopdf::Document,
+    object: &'a lopdf::Object,
+) -> Option<&'a lopdf::Dictionary> {
+    let (_, object) = doc.dereference(object).ok()?;
+    match object {
+        lopdf::Object::Dictionary(dict) => Some(dict),
+        lopdf::Object::Stream(stream) => Some(&stream.dict),
+        _ => None,
+    }
+}
+
+/// A resource category such as `/XObject` or `/ColorSpace`, if present.
+pub(crate) fn resource_category<'a>(
+    doc: &'a lopdf::Document,
+    resources: Option<&'a lopdf::Dictionary>,
+    key: &[u8],
+) -> Option<&'a lopdf::Dictionary> {
+    pdf_dict(doc, resources?.get(key).ok()?)
+}
+
+/// The page's content followed by every Form XObject it uses, recursively, each paired
+/// with the resources it draws from. Undecodable streams are skipped.
+pub(crate) fn page_content_scopes(
+    doc: &lopdf::Document,
+    page_id: lopdf::ObjectId,
+) -> Vec<ContentScope<'_>> {
+    let resources = inherited_page_attribute(doc, page_id, b"Resources")
+        .and_then(|object| pdf_dict(doc, object));
+    let mut content = Vec::new();
+    for id in doc.get_page_contents(page_id) {
+        if let Some(data) = doc
+            .get_object(id)
+            .and_then(lopdf::Object::as_stream)
+            .ok()
+            .and_then(|stream| stream_content(stream).ok())
+        {
+            content.extend(data);
+            content.push(b'\n');
+        }
+    }
+    let mut scopes = Vec::new();
+    push_content_scope(doc, resources, &content, &mut HashSet::new(), &mut scopes);
+    scopes
+}
+
+pub(crate) fn push_content_scope<'a>(
+    doc: &'a lopdf::Document,
+    resources: Option<&'a lopdf::Dictionary>,
+    content: &[u8],
+    visited: &mut HashSet<lopdf::ObjectId>,
+    scopes: &mut Vec<ContentScope<'a>>,
+) {
+    let operations = lopdf::content::Content::decode(content)
+        .map(|content| content.operations)
+        .unwrap_or_default();
+    scopes.push(ContentScope {
+        resources,
+        operations,
+    });
+
+    let Some(xobjects) = resource_category(doc, resources, b"XObject") else {
+        return;
+    };
+    for (_, value) in xobjects.iter() {
+        // Each form is visited once, which also guards against self-referencing forms.
+        let Ok(id) = value.as_reference() else {
+            continue;
+        };
+        if !visited.insert(id) {
+            continue;
+        }
+        let Ok(stream) = doc.get_object(id).and_then(lopdf::Object::as_stream) else {
+            continue;
+        };
+        if stream
+            .dict
+            .get(b"Subtype")
+            .and_then(lopdf::Object::as_name)
+            .ok()
+            != Some(&b"Form"[..])
+        {
+            continue;
+        }
+        let form_resources = stream
+            .dict
+            .get(b"Resources")
+            .ok()
+            .and_then(|object| pdf_dict(doc, object))
+            .or(resources);
+        if let Ok(data) = stream_content(stream) {
+            push_content_scope(doc, form_resources, &data, visited, scopes);
+        }
+    }
+}
+
+/// Whether a color space (a family name or array) can only produce neutral gray.
+pub(crate) fn is_gray_color_space(doc: &lopdf::Document, space: &lopdf::Object) -> bool {
+    let Ok((_, space)) = doc.dereference(space) else {
+        return false;
+    };
+    match space {
+        lopdf::Object::Name(name) => matches!(
+            name.as_slice(),
+            b"DeviceGray" | b"CalGray" | b"G" | b"Pattern"
+        ),
+        lopdf::Object::Array(items) => {
+            let family = items.first().and_then(|item| item.as_name().ok());
+            match family {
+                Some(b"CalGray") => true,
+                Some(b"ICCBased") => {
+                    items
+                        .get(1)
+                        .and_then(|profile| pdf_dict(doc, profile))
+                        .and_then(|profile| profile.get(b"N").ok())
+                        .and_then(|n| n.as_i64().ok())
+                        == Some(1)
+                }
+                Some(b"Indexed") | Some(b"I") => items
+                    .get(1)
+                    .is_some_and(|base| is_gray_color_space(doc, base)),
+                // An uncolored pattern takes its color from the underlying space.
+                Some(b"Pattern") => items
+                    .get(1)
+                    .map_or(true, |base| is_gray_color_space(doc, base)),
+                _ => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+/// Whether anything in the scope paints with a non-gray color: chromatic `rg`/`k`
+/// operands, non-gray `cs` selections, or color images and shadings in its resources.
+pub(crate) fn scope_has_color(doc: &lopdf::Document, scope: &ContentScope<'_>) -> bool {
+    let color_spaces = resource_category(doc, scope.resources, b"ColorSpace");
+    for op in &scope.operations {
+        let values: Vec<f32> = op
+            .operands
+            .iter()
+            .filter_map(|operand| operand.as_float().ok())
+            .collect();
+        let chromatic = match op.operator.as_str() {
+            "rg" | "RG" => values.windows(2).any(|pair| pair[0] != pair[1]),
+            "k" | "K" => values.iter().take(3).any(|value| *value != 0.0),
+            "cs" | "CS" => match op.operands.first() {
+                Some(lopdf::Object::Name(name)) => {
+                    let named = color_spaces.and_then(|spaces| spaces.get(name).ok());
+                    match named {
+                        Some(space) => !is_gray_color_space(doc, space),
+                        None => !is_gray_color_space(doc, &op.operands[0]),
+                    }
+                }
+                _ => false,
+            },
+            _ => false,
+        };
+        if chromatic {
+            return true;
+        }
+    }
+
+    let colored = |dict: &lopdf::Dictionary| {
+        dict.get(b"ColorSpace")
+            .is_ok_and(|space| !is_gray_color_space(doc, space))
+    };
+    let color_image = resource_category(doc, scope.resources, b"XObject").is_some_and(|xobjects| {
+        xobjects.iter().any(|(_, xobject)| {
+            pdf_dict(doc, xobject).is_some_and(|dict| {
+                dict.get(b"Subtype").and_then(lopdf::Object::as_name).ok() == Some(&b"Image"[..])
+                    && colored(dict)
+            })
+        })
+    });
+    let color_shading =
+        resource_category(doc, scope.resources, b"Shading").is_some_and(|shadings| {
+            shadings
+                .iter()
+                .any(|(_, shading)| pdf_dict(doc, shading).is_some_and(colored))
+        });
+    color_image || color_shading
+}
+
+/// 1-based pages that still paint with color, judged from the content structure.
+pub(crate) fn pages_with_color(doc: &lopdf::Document) -> Vec<u32> {
+    doc.get_pages()
+        .into_iter()
+        .filter(|(_, page_id)| {
+            page_content_scopes(doc, *page_id)
+                .iter()
+                .any(|scope| scope_has_color(doc, scope))
+        })
+        .map(|(page, _)| page)
+        .collect()
+}
+
+/// Every font the pages' resources (and their forms') refer to, in order of first use.
+/// Fonts shared by reference are listed once with all their pages.
+pub(crate) fn document_fonts(doc: &lopdf::Document) -> Vec<FontInfo> {
+    let mut fonts: Vec<FontInfo> = Vec::new();
+    let mut ids: Vec<Option<lopdf::ObjectId>> = Vec::new();
+    for (page, page_id) in doc.get_pages() {
+        for scope in page_content_scopes(doc, page_id) {
+            let Some(resources) = resource_category(doc, scope.resources, b"Font") else {
+                continue;
+            };
+            for (_, object) in resources.iter() {
+                let Some(font) = pdf_dict(doc, object) else {
+                    continue;
+                };
+                let id = object.as_reference().ok();
+                let name = font
+                    .get(b"BaseFont")
+                    .and_then(lopdf::Object::as_name)
+                    .map(|name| String::from_utf8_lossy(name).to_string())
+                    .unwrap_or_else(|_| String::from("(unnamed)"));
+                // Direct font dictionaries have no identity beyond their name.
+                let existing = fonts
+                    .iter()
+                    .zip(&ids)
+                    .position(|(known, known_id)| match id {
+                        Some(_) => *known_id == id,
+                        None => known_id.is_none() && known.name == name,
+                    });
+                if let Some(index) = existing {
+                    if !fonts[index].pages.contains(&page) {
+                        fonts[index].pages.push(page);
+                    }
+                    continue;
+                }
+                fonts.push(font_info(doc, font, name, page));
+                ids.push(id);
+            }
+        }
+    }
+    fonts
+}
+
+/// `name` without the six-letter subset tag of e.g. `ABCDEF+Helvetica`.
+pub(crate) fn without_subset_tag(name: &str) -> &str {
+    let bytes = name.as_bytes();
+    if bytes.len() > 7 && bytes[6] == b'+' && bytes[..6].iter().all(u8::is_ascii_uppercase) {
+        &name[7..]
+    } else {
+        name
+    }
+}
+
+pub(crate) fn font_info(
+    doc: &lopdf::Document,
+    font: &lopdf::Dictionary,
+    name: String,
+    page: u32,
+) -> FontInfo {
+    let subtype = font.get(b"Subtype").and_then(lopdf::Object::as_name).ok();
+    // Composite fonts keep their descriptor on the descendant CIDFont.
+    let descendant = font
+        .get(b"DescendantFonts")
+        .ok()
+        .and_then(|fonts| doc.dereference(fonts).ok())
+        .and_then(|(_, fonts)| fonts.as_array().ok())
+        .and_then(|fonts| fonts.first())
+        .and_then(|font| pdf_dict(doc, font));
+    let font_type = match subtype {
+        Some(b"Type1") => FontType::Type1,
+        Some(b"MMType1") => FontType::Mmtype1,
+        Some(b"TrueType") => FontType::Truetype,
+        Some(b"Type3") => FontType::Type3,
+        Some(b"Type0") => FontType::Cid,
+        _ => FontType::Unknown,
+    };
+    let embedded = font_type == FontType::Type3
+        || descendant
+            .unwrap_or(font)
+            .get(b"FontDescriptor")
+            .ok()
+            .and_then(|descriptor| pdf_dict(doc, descriptor))
+            .is_some_and(|descriptor| {
+                [&b"FontFile"[..], b"FontFile2", b"FontFile3"]
+                    .iter()
+                    .any(|key| descriptor.has(key))
+            });
+    let subset = without_subset_tag(&name).len() != name.len();
+    FontInfo {
+        name,
+        font_type,
+        embedded,
+        subset,
+        pages: vec![page],
+    }
+}
@@ -0,0 +1,370 @@
+//! Settings and presets, kept in memory and saved to the app config dir.
+
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+
+use crate::error::CommandError;
+use crate::ghostscript::{DEFAULT_GHOSTSCRIPT_TIMEOUT_SECS, GHOSTSCRIPT_TIMEOUT_SECS};
+use crate::logging::{apply_log_level, LogLevel};
+use crate::operations::{paper_size, CompressPreset, ContentMode, Operation, PageDimensions};
+use crate::output::{
+    write_file_atomically, ConflictPolicy, DEFAULT_IO_RETRY_ATTEMPTS, DEFAULT_IO_RETRY_BACKOFF_MS,
+    IO_RETRY_ATTEMPTS, IO_RETRY_BACKOFF_MS,
+};
+
+const SETTINGS_FILE: &str = "settings.json";
+
+pub(crate) const DEFAULT_PIPE_THRESHOLD_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Layout version of `Settings`; bump it and migrate older files when fields change meaning.
+const SETTINGS_SCHEMA_VERSION: u32 = 1;
+
+/// User preferences, persisted in the app config dir.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub(crate) struct Settings {
+    pub(crate) schema_version: u32,
+    pub(crate) default_output_dir: Option<String>,
+    /// Name of the preset selected when the app starts.
+    pub(crate) default_preset: Option<String>,
+    /// See `render_output_name`.
+    pub(crate) output_name_template: Option<String>,
+    pub(crate) on_conflict: ConflictPolicy,
+    pub(crate) preserve_mtime: bool,
+    pub(crate) log_level: LogLevel,
+    /// `flatten_pdf` inputs up to this size skip temp files; 0 always uses them.
+    pub(crate) pipe_threshold_bytes: u64,
+    /// Ghostscript runs longer than this are killed; 0 lets them run. Commands can
+    /// override it with `timeout_secs` in their options.
+    pub(crate) ghostscript_timeout_secs: u64,
+    /// How often a file-based command waits for an input another program has locked.
+    pub(crate) io_retry_attempts: u32,
+    /// Wait before the first such retry; each further one waits twice as long.
+    pub(crate) io_retry_backoff_ms: u64,
+    /// Fields written by newer versions, kept so saving here doesn't drop them.
+    #[serde(flatten)]
+    pub(crate) unknown: serde_json::Map<String, serde_json::Value>,
+}
+
+pub(crate) struct SettingsStore {
+    pub(crate) config_dir: Option<PathBuf>,
+    pub(crate) settings: Mutex<Settings>,
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub(crate) struct Preset {
+    pub(crate) name: String,
+    pub(crate) operation: Operation,
+    /// Shipped with the app: can be duplicated but not changed or deleted.
+    #[serde(default)]
+    pub(crate) built_in: bool,
+}
+
+const PRESETS_FILE: &str = "presets.json";
+
+/// The user's presets, persisted in the app config dir; see `built_in_presets` for the rest.
+pub(crate) struct PresetStore {
+    pub(crate) config_dir: Option<PathBuf>,
+    pub(crate) presets: Mutex<Vec<Preset>>,
+}
+
+/// Version of the preset export format; files from newer versions are still read entry by
+/// entry.
+pub(crate) const PRESET_EXPORT_VERSION: u32 = 1;
+
+#[derive(serde::Serialize)]
+pub(crate) struct PresetExport<'a> {
+    pub(crate) version: u32,
+    pub(crate) presets: &'a [Preset],
+}
+
+#[derive(serde::Serialize)]
+struct RejectedPreset {
+    /// Missing if the entry had no readable name.
+    pub(crate) name: Option<String>,
+    pub(crate) reason: String,
+}
+
+#[derive(Default, serde::Serialize)]
+pub(crate) struct PresetImportReport {
+    /// Names the presets were saved under; renamed ones appear under their new name.
+    pub(crate) imported: Vec<String>,
+    pub(crate) skipped: Vec<String>,
+    rejected: Vec<RejectedPreset>,
+}
+
+/// Put the settings that code without a `SettingsStore` reads into effect.
+pub(crate) fn apply_settings(settings: &Settings) {
+    apply_log_level(settings.log_level);
+    GHOSTSCRIPT_TIMEOUT_SECS.store(settings.ghostscript_timeout_secs, Ordering::Relaxed);
+    IO_RETRY_ATTEMPTS.store(settings.io_retry_attempts, Ordering::Relaxed);
+    IO_RETRY_BACKOFF_MS.store(settings.io_retry_backoff_ms, Ordering::Relaxed);
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            schema_version: SETTINGS_SCHEMA_VERSION,
+            default_output_dir: None,
+            default_preset: None,
+            output_name_template: None,
+            on_conflict: ConflictPolicy::default(),
+            preserve_mtime: false,
+            log_level: LogLevel::default(),
+            pipe_threshold_bytes: DEFAULT_PIPE_THRESHOLD_BYTES,
+            ghostscript_timeout_secs: DEFAULT_GHOSTSCRIPT_TIMEOUT_SECS,
+            io_retry_attempts: DEFAULT_IO_RETRY_ATTEMPTS,
+            io_retry_backoff_ms: DEFAULT_IO_RETRY_BACKOFF_MS,
+            unknown: serde_json::Map::new(),
+        }
+    }
+}
+
+impl SettingsStore {
+    /// Load the saved settings; a missing or unreadable file gives the defaults.
+    pub(crate) fn load(config_dir: Option<PathBuf>) -> Self {
+        let settings = config_dir
+            .as_deref()
+            .and_then(|dir| std::fs::read_to_string(dir.join(SETTINGS_FILE)).ok())
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+        Self {
+            config_dir,
+            settings: Mutex::new(settings),
+        }
+    }
+
+    pub(crate) fn get(&self) -> Settings {
+        self.settings
+            .lock()
+            .expect("settings mutex poisoned")
+            .clone()
+    }
+
+    /// Save `settings` and make them current.
+    pub(crate) fn replace(&self, settings: Settings) -> Result<Settings, CommandError> {
+        let mut current = self.settings.lock().expect("settings mutex poisoned");
+        if let Some(config_dir) = self.config_dir.as_deref() {
+            let contents =
+                serde_json::to_string_pretty(&settings).map_err(|e| CommandError::Io {
+                    action: "serialize_settings",
+                    error: e.to_string(),
+                })?;
+            std::fs::create_dir_all(config_dir)
+                .and_then(|_| {
+                    write_file_atomically(&config_dir.join(SETTINGS_FILE), contents.as_bytes())
+                })
+                .map_err(|e| CommandError::Io {
+                    action: "save_settings",
+                    error: e.to_string(),
+                })?;
+        }
+        *current = settings.clone();
+        Ok(settings)
+    }
+}
+
+pub(crate) fn built_in_presets() -> Vec<Preset> {
+    let resize = |name: &str, size: PageDimensions| Preset {
+        name: name.to_string(),
+        operation: Operation::Resize {
+            width_pt: size.width_pt,
+            height_pt: size.height_pt,
+            content_mode: ContentMode::Scale,
+            anchor: None,
+            linearize: false,
+        },
+        built_in: true,
+    };
+    vec![
+        resize(
+            "a4-print",
+            paper_size("a4").expect("a4 is a known paper size"),
+        ),
+        resize(
+            "a5-flyer",
+            paper_size("a5").expect("a5 is a known paper size"),
+        ),
+        Preset {
+            name: String::from("compress-email"),
+            operation: Operation::Compress {
+                preset: CompressPreset::Screen,
+            },
+            built_in: true,
+        },
+        Preset {
+            name: String::from("grayscale"),
+            operation: Operation::ConvertToGrayscale,
+            built_in: true,
+        },
+    ]
+}
+
+impl PresetStore {
+    pub(crate) fn load(config_dir: Option<PathBuf>) -> Self {
+        let presets = config_dir
+            .as_deref()
+            .and_then(|dir| std::fs::read_to_string(dir.join(PRESETS_FILE)).ok())
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+        Self {
+            config_dir,
+            presets: Mutex::new(presets),
+        }
+    }
+
+    /// Built-in presets first, then the user's in the order they were saved.
+    pub(crate) fn list(&self) -> Vec<Preset> {
+        let mut presets = built_in_presets();
+        presets.extend(
+            self.presets
+                .lock()
+                .expect("presets mutex poisoned")
+                .iter()
+                .cloned(),
+        );
+        presets
+    }
+
+    pub(crate) fn find(&self, name: &str) -> Option<Preset> {
+        self.list().into_iter().find(|preset| preset.name == name)
+    }
+
+    /// Apply `change` to the user's presets and save them. Built-in names are reserved.
+    pub(crate) fn update(
+        &self,
+        change: impl FnOnce(&mut Vec<Preset>) -> Result<(), CommandError>,
+    ) -> Result<(), CommandError> {
+        let mut presets = self.presets.lock().expect("presets mutex poisoned");
+        let mut changed = presets.clone();
+        change(&mut changed)?;
+        let built_in = built_in_presets();
+        if let Some(preset) = changed
+            .iter()
+            .find(|preset| built_in.iter().any(|b| b.name == preset.name))
+        {
+            return Err(CommandError::PresetBuiltIn {
+                name: preset.name.clone(),
+            });
+        }
+        if let Some(config_dir) = self.config_dir.as_deref() {
+            let contents =
+                serde_json::to_string_pretty(&changed).map_err(|e| CommandError::Io {
+                    action: "serialize_presets",
+                    error: e.to_string(),
+                })?;
+            std::fs::create_dir_all(config_dir)
+                .and_then(|_| {
+                    write_file_atomically(&config_dir.join(PRESETS_FILE), contents.as_bytes())
+                })
+                .map_err(|e| CommandError::Io {
+                    action: "save_presets",
+                    error: e.to_string(),
+                })?;
+        }
+        *presets = changed;
+        Ok(())
+    }
+}
+
+pub(crate) fn user_preset_index(presets: &[Preset], name: &str) -> Result<usize, CommandError> {
+    presets
+        .iter()
+        .position(|preset| preset.name == name)
+        .ok_or_else(|| CommandError::PresetNotFound {
+            name: name.to_string(),
+        })
+}
+
+/// `name (2)`, `name (3)`, ...: the first not in `taken`.
+fn numbered_preset_name(name: &str, taken: &[String]) -> String {
+    (2..)
+        .map(|n| format!("{} ({})", name, n))
+        .find(|candidate| !taken.contains(candidate))
+        .expect("unbounded range yields a free name")
+}
+
+/// Add the presets in an exported document to `presets`, checking each entry on its own so
+/// one bad or unknown-kind entry doesn't spoil the rest. Names taken by `built_in`
+/// presets are never overwritten.
+pub(crate) fn import_preset_entries(
+    document: &serde_json::Value,
+    presets: &mut Vec<Preset>,
+    built_in: &[Preset],
+    on_conflict: ConflictPolicy,
+) -> Result<PresetImportReport, String> {
+    let entries = document
+        .get("presets")
+        .and_then(serde_json::Value::as_array)
+        .ok_or_else(|| String::from("The file has no list of presets."))?;
+    let mut report = PresetImportReport::default();
+    for entry in entries {
+        let name = entry
+            .get("name")
+            .and_then(serde_json::Value::as_str)
+            .map(String::from);
+        let mut preset: Preset = match serde_json::from_value(entry.clone()) {
+            Ok(preset) => preset,
+            Err(e) => {
+                report.rejected.push(RejectedPreset {
+                    name,
+                    reason: format!("Not a preset this version understands: {}", e),
+                });
+                continue;
+            }
+        };
+        preset.built_in = false;
+        if let Err(error) = validate_preset_name(&preset.name) {
+            report.rejected.push(RejectedPreset {
+                name,
+                reason: error.message(),
+            });
+            continue;
+        }
+        let is_built_in = built_in.iter().any(|b| b.name == preset.name);
+        let existing = presets.iter().position(|p| p.name == preset.name);
+        if !is_built_in && existing.is_none() {
+            report.imported.push(preset.name.clone());
+            presets.push(preset);
+            continue;
+        }
+        match on_conflict {
+            ConflictPolicy::Skip => report.skipped.push(preset.name),
+            ConflictPolicy::Overwrite if !is_built_in => {
+                report.imported.push(preset.name.clone());
+                presets[existing.expect("conflict is with a user preset")] = preset;
+            }
+            ConflictPolicy::Rename => {
+                let taken: Vec<String> = built_in
+                    .iter()
+                    .chain(presets.iter())
+                    .map(|p| p.name.clone())
+                    .collect();
+                preset.name = numbered_preset_name(&preset.name, &taken);
+                report.imported.push(preset.name.clone());
+                presets.push(preset);
+            }
+            ConflictPolicy::Overwrite | ConflictPolicy::Error => {
+                report.rejected.push(RejectedPreset {
+                    reason: if is_built_in {
+                        format!("'{}' is a built-in preset.", preset.name)
+                    } else {
+                        format!("A preset named '{}' already exists.", preset.name)
+                    },
+                    name: Some(preset.name),
+                });
+            }
+        }
+    }
+    Ok(report)
+}
+
+pub(crate) fn validate_preset_name(name: &str) -> Result<(), CommandError> {
+    if name.trim().is_empty() {
+        return Err(CommandError::MissingInput {
+            field: "preset_name",
+        });
+    }
+    Ok(())
+}
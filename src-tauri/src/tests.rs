@@ -0,0 +1,1987 @@
+//! Processing tests. Ghostscript is replaced by shell scripts, so these run without one
+//! installed; the scripts need a Unix shell. The few that need a real Ghostscript are
+//! ignored by default.
+
+use super::*;
+#[cfg(unix)]
+use crate::ghostscript::ResolvedGhostscript;
+use crate::ghostscript::{decode_process_output, diagnose_ghostscript_output, GhostscriptFailure};
+use crate::operations::COMPATIBILITY_LEVELS;
+use crate::output::is_same_file;
+use crate::pdf::{pdf_dict, pdf_rect_object, read_page_geometry};
+
+/// A fresh directory in the temp dir, removed with its contents when dropped.
+struct TestDir(PathBuf);
+
+impl TestDir {
+    fn new(label: &str) -> Self {
+        let path = std::env::temp_dir().join(unique_temp_stem(label));
+        std::fs::create_dir_all(&path).expect("create test dir");
+        Self(path)
+    }
+
+    fn join(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// A runtime that runs `script` as its Ghostscript, already resolved so no installed
+/// Ghostscript is tried after it fails.
+#[cfg(unix)]
+fn fake_ghostscript(dir: &TestDir, script: &str) -> GhostscriptRuntime {
+    let command = write_script(dir, "gs", script);
+    let runtime = GhostscriptRuntime::default();
+    runtime.set_resolved(ResolvedGhostscript {
+        candidate: GhostscriptCandidate {
+            command,
+            gs_root: None,
+            source: GhostscriptSource::Custom,
+        },
+        envs: HashMap::new(),
+    });
+    runtime
+}
+
+#[cfg(unix)]
+fn write_script(dir: &TestDir, name: &str, script: &str) -> PathBuf {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = dir.join(name);
+    std::fs::write(&path, format!("#!/bin/sh\n{}\n", script)).expect("write script");
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))
+        .expect("make script executable");
+    path
+}
+
+/// The error of a Ghostscript run expected to fail.
+#[cfg(unix)]
+fn failed_run(runtime: &GhostscriptRuntime, args: &[&str]) -> CommandError {
+    match run_ghostscript(args, runtime, None, GhostscriptHooks::default()) {
+        Ok(output) => panic!("Ghostscript succeeded: {}", output.stdout),
+        Err(error) => error,
+    }
+}
+
+/// `Ошибка: файл не найден` in CP1251, as Ghostscript passes system error text through
+/// on a Russian Windows.
+const CP1251_STDERR: &[u8] = b"GPL Ghostscript 10.02.1: \xce\xf8\xe8\xe1\xea\xe0: \
+    \xf4\xe0\xe9\xeb \xed\xe5 \xed\xe0\xe9\xe4\xe5\xed\n";
+
+#[test]
+fn decodes_non_utf8_output_without_failing() {
+    let text = decode_process_output(CP1251_STDERR);
+    assert!(text.starts_with("GPL Ghostscript 10.02.1: "));
+    #[cfg(not(windows))]
+    assert!(text.contains(char::REPLACEMENT_CHARACTER));
+}
+
+#[cfg(unix)]
+#[test]
+fn failed_run_keeps_non_utf8_stderr_bytes() {
+    let dir = TestDir::new("cp1251");
+    let stderr_file = dir.join("stderr.bin");
+    std::fs::write(&stderr_file, CP1251_STDERR).unwrap();
+    let runtime = fake_ghostscript(
+        &dir,
+        &format!("cat '{}' >&2\nexit 1", stderr_file.display()),
+    );
+
+    let error = failed_run(&runtime, &["-q"]);
+    match &error {
+        CommandError::GsFailed {
+            exit_code,
+            stderr,
+            stderr_bytes,
+            ..
+        } => {
+            assert_eq!(*exit_code, Some(1));
+            assert!(stderr.starts_with("GPL Ghostscript 10.02.1: "));
+            assert_eq!(stderr_bytes.as_deref(), Some(CP1251_STDERR));
+        }
+        other => panic!("expected GsFailed, got {:?}", other),
+    }
+    let json = serde_json::to_value(&error).unwrap();
+    assert_eq!(json["code"], "gs_failed");
+    assert_eq!(
+        json["details"]["stderr_bytes"],
+        serde_json::json!(CP1251_STDERR)
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn failed_run_with_utf8_stderr_has_no_raw_bytes() {
+    let dir = TestDir::new("utf8-stderr");
+    let runtime = fake_ghostscript(&dir, "echo 'Ошибка: файл не найден' >&2\nexit 1");
+
+    let error = failed_run(&runtime, &["-q"]);
+    match error {
+        CommandError::GsFailed {
+            stderr,
+            stderr_bytes,
+            ..
+        } => {
+            assert_eq!(stderr, "Ошибка: файл не найден");
+            assert_eq!(stderr_bytes, None);
+        }
+        other => panic!("expected GsFailed, got {:?}", other),
+    }
+}
+
+/// A fake Ghostscript that writes a minimal PDF to its `-sOutputFile=` and then runs
+/// `script`.
+#[cfg(unix)]
+fn fake_pdfwrite(dir: &TestDir, script: &str) -> GhostscriptRuntime {
+    fake_ghostscript(
+        dir,
+        &format!(
+            "for arg; do case \"$arg\" in -sOutputFile=*) out=\"${{arg#-sOutputFile=}}\";; esac; done\n\
+             printf '%%PDF-1.4\\n%%%%EOF\\n' > \"$out\"\n{}",
+            script
+        ),
+    )
+}
+
+/// A fake pdfwrite that appends its `-sOutputFile=` path and its input, the last
+/// argument, to `log`, runs `script` and then copies the input to the output.
+#[cfg(unix)]
+fn logging_pdfwrite(dir: &TestDir, log: &Path, script: &str) -> GhostscriptRuntime {
+    fake_ghostscript(
+        dir,
+        &format!(
+            "for arg; do case \"$arg\" in -sOutputFile=*) out=\"${{arg#-sOutputFile=}}\";; esac; \
+             last=\"$arg\"; done\n\
+             echo \"$out\" >> '{log}'\necho \"$last\" >> '{log}'\n{script}\ncat \"$last\" > \"$out\"",
+            log = log.display(),
+            script = script,
+        ),
+    )
+}
+
+#[cfg(unix)]
+#[test]
+fn concurrent_flattens_keep_their_own_files() {
+    const JOBS: usize = 6;
+    let dir = TestDir::new("concurrent");
+    let log = dir.join("paths.log");
+    // Overlap the runs, so a shared temp name would be overwritten while in use.
+    let runtime = logging_pdfwrite(&dir, &log, "sleep 0.3");
+    let options = FlattenOptions::default();
+
+    let outputs: Vec<Vec<u8>> = std::thread::scope(|scope| {
+        let jobs: Vec<_> = (0..JOBS)
+            .map(|job| {
+                let (runtime, options) = (&runtime, &options);
+                scope.spawn(move || {
+                    let pdf = format!("%PDF-1.4\n% job {}\n%%EOF\n", job).into_bytes();
+                    flatten_with_ghostscript(pdf, options, 0, runtime, JobContext::default())
+                })
+            })
+            .collect();
+        jobs.into_iter()
+            .map(|job| job.join().unwrap().unwrap())
+            .collect()
+    });
+
+    for (job, output) in outputs.iter().enumerate() {
+        assert_eq!(
+            String::from_utf8_lossy(output),
+            format!("%PDF-1.4\n% job {}\n%%EOF\n", job)
+        );
+    }
+    let paths: Vec<String> = std::fs::read_to_string(&log)
+        .unwrap()
+        .lines()
+        .map(String::from)
+        .collect();
+    assert_eq!(paths.len(), JOBS * 2);
+    assert_eq!(paths.iter().collect::<HashSet<_>>().len(), paths.len());
+}
+
+/// Whether `path` is gone and no longer registered for deletion at shutdown.
+fn cleaned_up(path: &Path) -> bool {
+    !path.exists()
+        && !LIVE_TEMP_FILES
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|live| live == path)
+}
+
+#[cfg(unix)]
+#[test]
+fn failed_flattens_remove_their_temp_files() {
+    let dir = TestDir::new("failed-flatten");
+    let log = dir.join("paths.log");
+    let runtime = logging_pdfwrite(&dir, &log, "echo 'Error: /syntaxerror' >&2\nexit 1");
+
+    let result = flatten_with_ghostscript(
+        b"%PDF-1.4\n%%EOF\n".to_vec(),
+        &FlattenOptions::default(),
+        0,
+        &runtime,
+        JobContext::default(),
+    );
+
+    assert!(matches!(result, Err(CommandError::GsFailed { .. })));
+    let paths = std::fs::read_to_string(&log).unwrap();
+    assert_eq!(paths.lines().count(), 2);
+    for path in paths.lines() {
+        assert!(cleaned_up(Path::new(path)), "{} was left behind", path);
+    }
+}
+
+#[test]
+fn panicking_writes_remove_their_temp_files() {
+    let dir = TestDir::new("panicked-write");
+    let input = dir.join("in.pdf");
+    std::fs::write(&input, b"%PDF-1.4\n%%EOF\n").unwrap();
+    let temp = Mutex::new(None);
+
+    let result = std::panic::catch_unwind(|| {
+        let _ = write_output(
+            &input,
+            &dir.join("out.pdf"),
+            OutputOptions::default(),
+            |_, output, _| -> Result<PageCountResult, CommandError> {
+                std::fs::write(output, b"%PDF-1.4\n").unwrap();
+                *temp.lock().unwrap() = Some(output.to_path_buf());
+                panic!("processing bug");
+            },
+        );
+    });
+
+    assert!(result.is_err());
+    let temp = temp.into_inner().unwrap().expect("the write started");
+    assert!(cleaned_up(&temp), "{} was left behind", temp.display());
+    assert!(!dir.join("out.pdf").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn written_output_reports_ghostscript_warnings_once_each() {
+    let dir = TestDir::new("warnings");
+    let runtime = fake_pdfwrite(
+        &dir,
+        "echo '   **** Warning: File has unbalanced q/Q operators.' >&2\n\
+         echo '   **** Warning: File has unbalanced q/Q operators.' >&2\n\
+         echo 'Substituting font Helvetica for ArialMT.'",
+    );
+    let input = dir.join("in.pdf");
+    std::fs::write(&input, b"%PDF-1.4\n%%EOF\n").unwrap();
+
+    let written = write_output(
+        &input,
+        &dir.join("out.pdf"),
+        OutputOptions::default(),
+        |input, output, warnings| {
+            run_pdfwrite(
+                &[input],
+                output,
+                &[],
+                None,
+                &runtime,
+                JobContext::default().collecting(warnings),
+            )?;
+            Ok::<_, CommandError>(PageCountResult {
+                output_path: output.to_string_lossy().to_string(),
+                page_count: 1,
+            })
+        },
+    )
+    .unwrap();
+
+    let warnings: Vec<(&str, u32)> = written
+        .warnings
+        .iter()
+        .map(|warning| (warning.code.as_str(), warning.count))
+        .collect();
+    assert_eq!(warnings, [("gs_warning", 2), ("gs_font_substituted", 1)]);
+}
+
+#[cfg(unix)]
+#[test]
+fn warnings_of_a_failed_attempt_are_not_reported_later() {
+    let dir = TestDir::new("failed-warnings");
+    let input = dir.join("in.pdf");
+    std::fs::write(&input, b"%PDF-1.4\n%%EOF\n").unwrap();
+    let warning_runtime = fake_pdfwrite(&dir, "echo '**** Warning: damaged object' >&2");
+    let failed = write_output(
+        &input,
+        &dir.join("failed.pdf"),
+        OutputOptions::default(),
+        |input, output, warnings| {
+            run_pdfwrite(
+                &[input],
+                output,
+                &[],
+                None,
+                &warning_runtime,
+                JobContext::default().collecting(warnings),
+            )?;
+            Err::<PageCountResult, _>(CommandError::Cancelled)
+        },
+    );
+    assert!(failed.is_err());
+
+    let clean_dir = TestDir::new("clean-warnings");
+    let clean_runtime = fake_pdfwrite(&clean_dir, "");
+    let written = write_output(
+        &input,
+        &dir.join("clean.pdf"),
+        OutputOptions::default(),
+        |input, output, warnings| {
+            run_pdfwrite(
+                &[input],
+                output,
+                &[],
+                None,
+                &clean_runtime,
+                JobContext::default().collecting(warnings),
+            )?;
+            Ok::<_, CommandError>(PageCountResult {
+                output_path: output.to_string_lossy().to_string(),
+                page_count: 1,
+            })
+        },
+    )
+    .unwrap();
+    assert!(written.warnings.is_empty());
+}
+
+#[test]
+fn outputs_taken_after_their_claim_get_the_policy_again() {
+    let dir = TestDir::new("late-conflict");
+    let requested = dir.join("out.pdf");
+    let commit = |policy| {
+        let claimed = claim_output_path(&requested, policy, &[])
+            .unwrap()
+            .expect("claimed");
+        // Another job finishes first with the same name.
+        std::fs::write(&claimed, b"%PDF-1.4\nother job\n").unwrap();
+        let temp = sibling_temp_path(&claimed);
+        std::fs::write(&temp, b"%PDF-1.4\nthis job\n").unwrap();
+        let committed = commit_output(&temp, &requested, &claimed, policy);
+        let _ = std::fs::remove_file(&temp);
+        committed
+    };
+
+    assert_eq!(
+        commit(ConflictPolicy::Rename).unwrap(),
+        Some(dir.join("out (2).pdf"))
+    );
+    assert_eq!(
+        std::fs::read(dir.join("out (2).pdf")).unwrap(),
+        b"%PDF-1.4\nthis job\n"
+    );
+    for policy in [ConflictPolicy::Skip, ConflictPolicy::Error] {
+        std::fs::remove_file(&requested).unwrap();
+        match commit(policy) {
+            Ok(None) => assert!(policy == ConflictPolicy::Skip),
+            Err(_) => assert!(policy == ConflictPolicy::Error),
+            Ok(Some(path)) => panic!("{} was written", path.display()),
+        }
+        assert_eq!(std::fs::read(&requested).unwrap(), b"%PDF-1.4\nother job\n");
+    }
+}
+
+#[test]
+fn preserved_outputs_keep_the_input_times() {
+    let dir = TestDir::new("times");
+    let input = dir.join("in.pdf");
+    std::fs::write(&input, b"%PDF-1.4\n%%EOF\n").unwrap();
+    let modified = filetime::FileTime::from_unix_time(1_500_000_000, 0);
+    filetime::set_file_mtime(&input, modified).unwrap();
+    let options = OutputOptions {
+        preserve_mtime: true,
+        ..OutputOptions::default()
+    };
+
+    let written = write_output(&input, &dir.join("out.pdf"), options, |input, output, _| {
+        std::fs::copy(input, output).map_err(|e| CommandError::Io {
+            action: "copy",
+            error: e.to_string(),
+        })?;
+        Ok(PageCountResult {
+            output_path: output.to_string_lossy().to_string(),
+            page_count: 1,
+        })
+    })
+    .unwrap();
+
+    let metadata = std::fs::metadata(dir.join("out.pdf")).unwrap();
+    assert_eq!(
+        filetime::FileTime::from_last_modification_time(&metadata),
+        modified
+    );
+    assert!(written.output_warnings.is_empty());
+}
+
+#[test]
+fn zip_archives_read_back() {
+    use std::io::Read;
+
+    let report = br#"{"os":"linux"}"#.repeat(100);
+    let entries: [(&str, &[u8]); 3] = [
+        ("report.json", &report),
+        ("settings.json", b"{}"),
+        ("log.txt", b""),
+    ];
+    let archive = zip_archive(&entries).unwrap();
+
+    let mut read = zip::ZipArchive::new(std::io::Cursor::new(archive)).unwrap();
+    assert_eq!(read.len(), entries.len());
+    for (index, (name, contents)) in entries.iter().enumerate() {
+        let mut file = read.by_index(index).unwrap();
+        assert_eq!(file.name(), *name);
+        let mut read_back = Vec::new();
+        file.read_to_end(&mut read_back).unwrap();
+        assert_eq!(read_back, *contents, "{}", name);
+    }
+}
+
+#[test]
+fn metadata_outside_ascii_round_trips_as_utf16() {
+    let dir = TestDir::new("metadata");
+    let input = dir.join("in.pdf");
+    std::fs::write(&input, SELF_TEST_PDF).unwrap();
+    let title = "Годовой отчёт 2024 🎉";
+    let author = "Zoë 👩\u{200d}💻";
+    let patch = MetadataPatch {
+        title: Some(title.to_string()),
+        author: Some(author.to_string()),
+        subject: None,
+        keywords: Some(String::from("plain ascii")),
+        creator: None,
+        producer: None,
+        creation_date: None,
+        modification_date: None,
+    };
+
+    let output = dir.join("out.pdf");
+    let metadata = write_pdf_metadata(&input, &output, &patch).unwrap();
+    assert_eq!(metadata.title.as_deref(), Some(title));
+    assert_eq!(metadata.author.as_deref(), Some(author));
+    assert_eq!(metadata.keywords.as_deref(), Some("plain ascii"));
+
+    let doc = lopdf::Document::load(&output).unwrap();
+    let info = doc
+        .trailer
+        .get(b"Info")
+        .and_then(lopdf::Object::as_reference)
+        .and_then(|id| doc.get_dictionary(id))
+        .unwrap();
+    let raw = |key: &[u8]| info.get(key).unwrap().as_str().unwrap().to_vec();
+    let mut utf16 = vec![0xFE, 0xFF];
+    utf16.extend(title.encode_utf16().flat_map(u16::to_be_bytes));
+    assert_eq!(raw(b"Title"), utf16);
+    assert_eq!(raw(b"Keywords"), b"plain ascii");
+    assert_eq!(decode_pdf_text(&raw(b"Author")), author);
+}
+
+/// Writes a PDF to `path` with a page for each of `pages`, which hold the page's own
+/// entries such as its boxes; a Parent, a small content stream and resources are added.
+fn write_test_pdf(path: &Path, pages: Vec<lopdf::Dictionary>) {
+    let name = |name: &str| lopdf::Object::Name(name.as_bytes().to_vec());
+    let mut doc = lopdf::Document::with_version("1.7");
+    let pages_id = doc.new_object_id();
+    let mut kids = Vec::new();
+    for mut page in pages {
+        let content = lopdf::Stream::new(lopdf::Dictionary::new(), b"0 0 m 10 10 l S".to_vec());
+        page.set("Type", name("Page"));
+        page.set("Parent", pages_id);
+        page.set("Contents", doc.add_object(content));
+        page.set("Resources", lopdf::Dictionary::new());
+        kids.push(lopdf::Object::Reference(doc.add_object(page)));
+    }
+    let mut tree = lopdf::Dictionary::new();
+    tree.set("Type", name("Pages"));
+    tree.set("Count", kids.len() as i64);
+    tree.set("Kids", kids);
+    doc.objects
+        .insert(pages_id, lopdf::Object::Dictionary(tree));
+    let mut catalog = lopdf::Dictionary::new();
+    catalog.set("Type", name("Catalog"));
+    catalog.set("Pages", pages_id);
+    let catalog_id = doc.add_object(catalog);
+    doc.trailer.set("Root", catalog_id);
+    doc.save(path).unwrap();
+}
+
+/// A page dictionary with the given boxes.
+fn page_with_boxes(boxes: &[(&str, [f64; 4])]) -> lopdf::Dictionary {
+    let mut page = lopdf::Dictionary::new();
+    for (key, rect) in boxes {
+        page.set(*key, pdf_rect_object(*rect));
+    }
+    page
+}
+
+#[test]
+fn printer_marks_set_the_sheet_boxes() {
+    let dir = TestDir::new("printer-marks");
+    let input = dir.join("in.pdf");
+    // A 200 x 300 pt page, bare and with the same trim inside a larger MediaBox.
+    write_test_pdf(
+        &input,
+        vec![
+            page_with_boxes(&[("MediaBox", [0.0, 0.0, 200.0, 300.0])]),
+            page_with_boxes(&[
+                ("MediaBox", [0.0, 0.0, 220.0, 320.0]),
+                ("TrimBox", [10.0, 10.0, 210.0, 310.0]),
+            ]),
+        ],
+    );
+    let marks = PrinterMarks {
+        crop: true,
+        registration: true,
+        color_bars: false,
+    };
+
+    let output = dir.join("out.pdf");
+    let result = printer_marks_pdf_pages(&input, &output, 9.0, 12.0, 3.0, marks).unwrap();
+
+    // Bleed, offset and mark length add 24 pt on every side.
+    let media_box = [0.0, 0.0, 248.0, 348.0];
+    let trim_box = [24.0, 24.0, 224.0, 324.0];
+    let bleed_box = [15.0, 15.0, 233.0, 333.0];
+    let doc = lopdf::Document::load(&output).unwrap();
+    let geometry = read_page_geometry(&doc);
+    assert_eq!(geometry.len(), 2);
+    for (page, marked) in geometry.iter().zip(&result.pages) {
+        assert_eq!(page.media_box, media_box);
+        assert_eq!(page.trim_box, Some(trim_box));
+        assert_eq!(page.bleed_box, Some(bleed_box));
+        assert_eq!(
+            (marked.media_box, marked.trim_box, marked.bleed_box),
+            (media_box, trim_box, bleed_box)
+        );
+    }
+}
+
+/// A fake pdfwrite that appends its arguments to `args.log`, one per line, and the
+/// PostScript programs it is given to `programs.ps`.
+#[cfg(unix)]
+fn recording_pdfwrite(dir: &TestDir) -> GhostscriptRuntime {
+    fake_pdfwrite(
+        dir,
+        &format!(
+            "for arg; do echo \"$arg\" >> '{args}'; \
+             case \"$arg\" in *.ps) cat \"$arg\" >> '{programs}';; esac; done",
+            args = dir.join("args.log").display(),
+            programs = dir.join("programs.ps").display(),
+        ),
+    )
+}
+
+#[cfg(unix)]
+fn recorded_args(dir: &TestDir) -> Vec<String> {
+    std::fs::read_to_string(dir.join("args.log"))
+        .unwrap()
+        .lines()
+        .map(String::from)
+        .collect()
+}
+
+#[cfg(unix)]
+#[test]
+fn nup_places_each_page_in_its_own_cell() {
+    let dir = TestDir::new("nup");
+    let input = dir.join("in.pdf");
+    let portrait = || page_with_boxes(&[("MediaBox", [0.0, 0.0, 595.0, 842.0])]);
+    // Four A4 pages, one landscape A5 among them, and a last page alone on its sheet.
+    write_test_pdf(
+        &input,
+        vec![
+            portrait(),
+            page_with_boxes(&[("MediaBox", [0.0, 0.0, 595.0, 420.0])]),
+            portrait(),
+            portrait(),
+            portrait(),
+        ],
+    );
+    let runtime = recording_pdfwrite(&dir);
+    let layout = NupLayout {
+        cols: 2,
+        rows: 2,
+        sheet_size: "A4",
+        gap_pt: 10.0,
+        border: true,
+    };
+
+    let output = dir.join("out.pdf");
+    let result =
+        nup_with_ghostscript(&input, &output, layout, &runtime, JobContext::default()).unwrap();
+
+    assert_eq!((result.sheet_count, result.pages_per_sheet), (2, 4));
+    let args = recorded_args(&dir);
+    assert!(args.contains(&String::from("-dFIXEDMEDIA")));
+    // Portrait pages fill 2 x 2 cells best on a portrait sheet.
+    let sheet = paper_size("A4").unwrap();
+    assert_eq!(result.sheet.width_pt, sheet.width_pt);
+    assert!(args.contains(&format!("-dDEVICEWIDTHPOINTS={:.3}", sheet.width_pt)));
+    // The program runs ahead of the input.
+    let input_arg = args.iter().position(|arg| Path::new(arg) == input).unwrap();
+    assert!(args[input_arg - 1].ends_with(".ps"));
+
+    let program = std::fs::read_to_string(dir.join("programs.ps")).unwrap();
+    assert!(program.contains("rectstroke"));
+    let frames: Vec<(Vec<f64>, bool)> = program
+        .lines()
+        .find(|line| line.contains("/nup_frames"))
+        .unwrap()
+        .split('[')
+        .skip(2)
+        .map(|frame| {
+            let words: Vec<&str> = frame.split([' ', ']']).filter(|w| !w.is_empty()).collect();
+            let rect = words[..4].iter().map(|w| w.parse().unwrap()).collect();
+            (rect, words[4] == "true")
+        })
+        .collect();
+    let ends_sheet: Vec<bool> = frames.iter().map(|(_, ends)| *ends).collect();
+    assert_eq!(ends_sheet, [false, false, false, true, true]);
+    let near = |a: f64, b: f64| (a - b).abs() < 0.01;
+    let center = |rect: &[f64]| (rect[0] + rect[2] / 2.0, rect[1] + rect[3] / 2.0);
+    // Each page is centered in its cell, the landscape one at the same width as the rest.
+    let (cell_width, cell_height) = (
+        (sheet.width_pt - 30.0) / 2.0,
+        (sheet.height_pt - 30.0) / 2.0,
+    );
+    let (x, y) = center(&frames[1].0);
+    assert!(near(x, 20.0 + cell_width * 1.5) && near(y, 20.0 + cell_height * 1.5));
+    assert!(near(frames[1].0[2], frames[0].0[2]));
+    assert!(frames[1].0[3] < frames[0].0[3]);
+    // The page alone on the last sheet is centered on it.
+    let (x, y) = center(&frames[4].0);
+    assert!(near(x, sheet.width_pt / 2.0) && near(y, sheet.height_pt / 2.0));
+}
+
+/// A page's content, decompressed, and the XObjects its resources name.
+fn page_drawing(doc: &lopdf::Document, page: u32) -> (String, lopdf::Dictionary) {
+    let page_id = doc.get_pages()[&page];
+    let content = String::from_utf8_lossy(&doc.get_page_content(page_id).unwrap()).to_string();
+    let xobjects = doc
+        .get_dictionary(page_id)
+        .and_then(|page| page.get(b"Resources"))
+        .ok()
+        .and_then(|resources| pdf_dict(doc, resources))
+        .and_then(|resources| resources.get(b"XObject").ok())
+        .and_then(|xobjects| pdf_dict(doc, xobjects))
+        .cloned()
+        .unwrap_or_default();
+    (content, xobjects)
+}
+
+#[test]
+fn booklet_sides_fold_into_reading_order() {
+    let dir = TestDir::new("booklet");
+    let input = dir.join("in.pdf");
+    // Five A5 pages, padded with three blanks to two sheets.
+    write_test_pdf(
+        &input,
+        (0..5)
+            .map(|_| page_with_boxes(&[("MediaBox", [0.0, 0.0, 420.0, 595.0])]))
+            .collect(),
+    );
+
+    let output = dir.join("out.pdf");
+    let result = booklet_pdf_pages(&input, &output, "A4", 2.0).unwrap();
+
+    assert_eq!((result.sheet_count, result.blank_pages), (2, 3));
+    let sheet = paper_size("A4").unwrap().oriented(true);
+    let doc = lopdf::Document::load(&output).unwrap();
+    let near = |a: f64, b: f64| (a - b).abs() < 0.01;
+    for page in read_page_geometry(&doc) {
+        let [x, y, width, height] = page.media_box;
+        assert!(near(x, 0.0) && near(y, 0.0));
+        assert!(near(width, sheet.width_pt) && near(height, sheet.height_pt));
+    }
+    // The pages each side draws, with the x offset they are drawn at.
+    let sides: Vec<Vec<(String, f64)>> = (1..=4)
+        .map(|page| {
+            page_drawing(&doc, page)
+                .0
+                .lines()
+                .filter_map(|line| {
+                    let words: Vec<&str> = line.split_whitespace().collect();
+                    let name = words.iter().position(|word| *word == "Do")?;
+                    Some((words[name - 1][1..].to_string(), words[5].parse().unwrap()))
+                })
+                .collect()
+        })
+        .collect();
+    let names: Vec<Vec<&str>> = sides
+        .iter()
+        .map(|side| side.iter().map(|(name, _)| name.as_str()).collect())
+        .collect();
+    assert_eq!(
+        names,
+        [vec!["Pg1"], vec!["Pg2"], vec!["Pg3"], vec!["Pg4", "Pg5"]]
+    );
+    let half = sheet.width_pt / 2.0;
+    assert!(sides[3][0].1 < half && sides[3][1].1 >= half);
+    // The inner sheet's pages sit one creep further out.
+    assert!(near(sides[2][0].1 - sides[0][0].1, 2.0));
+    assert!(near(sides[3][0].1 - sides[1][0].1, -2.0));
+}
+
+#[cfg(unix)]
+#[test]
+fn encryption_passes_the_passwords_and_permissions() {
+    let dir = TestDir::new("encrypt");
+    let input = dir.join("in.pdf");
+    write_test_pdf(
+        &input,
+        vec![page_with_boxes(&[("MediaBox", [0.0, 0.0, 612.0, 792.0])])],
+    );
+    let runtime = recording_pdfwrite(&dir);
+    let output = dir.join("out.pdf");
+    let encrypt = |user: &str, owner: &str| {
+        encrypt_with_ghostscript(
+            &input,
+            &output,
+            user,
+            owner,
+            true,
+            false,
+            &runtime,
+            JobContext::default(),
+        )
+    };
+
+    let error = encrypt("secret", "secret").map(|_| ()).unwrap_err();
+    assert!(matches!(error, CommandError::PasswordsMustDiffer));
+    let error = encrypt("secret", "").map(|_| ()).unwrap_err();
+    assert!(matches!(
+        error,
+        CommandError::MissingInput {
+            field: "owner_password"
+        }
+    ));
+    assert!(!dir.join("args.log").exists());
+
+    let result = encrypt("user", "owner").unwrap();
+    assert_eq!((result.key_length_bits, result.revision), (128, 3));
+    let args = recorded_args(&dir);
+    let flags = permission_flags(true, false);
+    for expected in [
+        String::from("-sOwnerPassword=owner"),
+        String::from("-sUserPassword=user"),
+        String::from("-dEncryptionR=3"),
+        String::from("-dKeyLength=128"),
+        format!("-dPermissions={}", flags),
+    ] {
+        assert!(args.contains(&expected), "{}", expected);
+    }
+    // Printing is allowed, copying is not.
+    assert_ne!(flags & (1 << 2), 0);
+    assert_eq!(flags & (1 << 4), 0);
+}
+
+#[cfg(unix)]
+#[test]
+fn eps_export_takes_exactly_one_page() {
+    let dir = TestDir::new("eps");
+    let input = dir.join("in.pdf");
+    let page = || page_with_boxes(&[("MediaBox", [0.0, 0.0, 612.0, 792.0])]);
+    write_test_pdf(&input, vec![page(), page()]);
+    let runtime = recording_pdfwrite(&dir);
+    // As if `gs -h` had listed it.
+    *runtime.devices.lock().unwrap() = Some(vec![String::from("eps2write")]);
+    let output = dir.join("out.eps");
+    let export = |pages: Option<Vec<u32>>| {
+        postscript_with_ghostscript(
+            &input,
+            &output,
+            PostScriptDevice::Eps2write,
+            pages,
+            Some(2),
+            &runtime,
+            JobContext::default(),
+        )
+    };
+
+    let error = export(None).map(|_| ()).unwrap_err();
+    assert!(matches!(
+        error,
+        CommandError::EpsNeedsOnePage { selected: 2 }
+    ));
+    assert!(!dir.join("args.log").exists());
+
+    let result = export(Some(vec![2])).unwrap();
+    assert!(result.transparent_pages.is_empty());
+    let args = recorded_args(&dir);
+    for expected in ["-sDEVICE=eps2write", "-sPageList=2", "-dLanguageLevel=2"] {
+        assert!(args.iter().any(|arg| arg == expected), "{}", expected);
+    }
+}
+
+#[test]
+fn pdf_stamps_go_under_the_selected_pages() {
+    let dir = TestDir::new("stamp");
+    let input = dir.join("in.pdf");
+    let page = || page_with_boxes(&[("MediaBox", [0.0, 0.0, 200.0, 300.0])]);
+    write_test_pdf(&input, vec![page(), page()]);
+    let stamp = dir.join("stamp.pdf");
+    write_test_pdf(
+        &stamp,
+        vec![page_with_boxes(&[("MediaBox", [0.0, 0.0, 100.0, 50.0])])],
+    );
+    let placement = StampPlacement {
+        layer: StampLayer::Under,
+        pages: Some(vec![2]),
+        ..StampPlacement::default()
+    };
+
+    let output = dir.join("out.pdf");
+    let result = stamp_pdf_pages(&input, &output, &stamp, &placement).unwrap();
+
+    assert_eq!(result.pages_stamped, 1);
+    let doc = lopdf::Document::load(&output).unwrap();
+    let (content, xobjects) = page_drawing(&doc, 1);
+    assert_eq!(content, "0 0 m 10 10 l S");
+    assert!(xobjects.is_empty());
+    // Centered at its own size, ahead of the page's content.
+    let (content, xobjects) = page_drawing(&doc, 2);
+    assert!(content.starts_with("q 1.0000 0.0000 0.0000 1.0000 50.0000 125.0000 cm /Stamp0 Do Q\n"));
+    assert!(content.ends_with("0 0 m 10 10 l S"));
+    let form = doc
+        .get_object(xobjects.get(b"Stamp0").unwrap().as_reference().unwrap())
+        .and_then(lopdf::Object::as_stream)
+        .unwrap();
+    assert_eq!(
+        form.dict.get(b"Subtype").unwrap().as_name().unwrap(),
+        b"Form"
+    );
+    assert_eq!(
+        pdf_rect(&doc, form.dict.get(b"BBox").unwrap()),
+        Some([0.0, 0.0, 100.0, 50.0])
+    );
+}
+
+#[test]
+fn png_stamps_become_image_xobjects() {
+    let dir = TestDir::new("stamp-png");
+    let input = dir.join("in.pdf");
+    write_test_pdf(
+        &input,
+        vec![page_with_boxes(&[("MediaBox", [0.0, 0.0, 200.0, 300.0])])],
+    );
+    let stamp = dir.join("stamp.png");
+    // Two RGB pixels, red and blue; 2 x 1 pt at the default 72 dpi.
+    std::fs::write(
+        &stamp,
+        encode_png(2, 1, 8, 2, &[], &[255, 0, 0, 0, 0, 255]).unwrap(),
+    )
+    .unwrap();
+    let placement = StampPlacement {
+        scale: StampScale::Fit,
+        ..StampPlacement::default()
+    };
+
+    let output = dir.join("out.pdf");
+    let result = stamp_pdf_pages(&input, &output, &stamp, &placement).unwrap();
+
+    assert_eq!(result.pages_stamped, 1);
+    let doc = lopdf::Document::load(&output).unwrap();
+    let (content, xobjects) = page_drawing(&doc, 1);
+    // Scaled to the page width and centered vertically, over the page's content.
+    assert!(
+        content.ends_with("Q\nq 200.0000 0.0000 0.0000 100.0000 0.0000 100.0000 cm /Stamp0 Do Q\n")
+    );
+    let image = doc
+        .get_object(xobjects.get(b"Stamp0").unwrap().as_reference().unwrap())
+        .and_then(lopdf::Object::as_stream)
+        .unwrap();
+    let entry = |key: &[u8]| image.dict.get(key).unwrap();
+    assert_eq!(entry(b"Subtype").as_name().unwrap(), b"Image");
+    assert_eq!(entry(b"Width").as_i64().unwrap(), 2);
+    assert_eq!(entry(b"Height").as_i64().unwrap(), 1);
+    assert_eq!(entry(b"ColorSpace").as_name().unwrap(), b"DeviceRGB");
+
+    std::fs::write(&stamp, b"not a png").unwrap();
+    let error = stamp_pdf_pages(&input, &dir.join("other.pdf"), &stamp, &placement)
+        .map(|_| ())
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        CommandError::InvalidImage {
+            reason: "not_png",
+            ..
+        }
+    ));
+}
+
+#[test]
+fn overlays_follow_the_repeat_mode() {
+    let dir = TestDir::new("overlay");
+    let base = dir.join("base.pdf");
+    let page = || page_with_boxes(&[("MediaBox", [0.0, 0.0, 200.0, 300.0])]);
+    write_test_pdf(&base, vec![page(), page(), page()]);
+    let overlay = dir.join("overlay.pdf");
+    write_test_pdf(
+        &overlay,
+        vec![
+            page(),
+            page_with_boxes(&[("MediaBox", [0.0, 0.0, 100.0, 100.0])]),
+        ],
+    );
+
+    // The object each base page shows as its overlay, if any.
+    let overlaid = |repeat: OverlayRepeat| {
+        let output = dir.join("out.pdf");
+        let _ = std::fs::remove_file(&output);
+        let result =
+            overlay_pdf_documents(&base, &overlay, &output, StampLayer::Over, repeat, false)
+                .unwrap();
+        let doc = lopdf::Document::load(&output).unwrap();
+        let forms: Vec<Option<lopdf::ObjectId>> = (1..=3)
+            .map(|page| {
+                let (content, xobjects) = page_drawing(&doc, page);
+                let form = xobjects.get(b"Overlay0").ok()?.as_reference().ok()?;
+                assert!(content.ends_with("/Overlay0 Do Q\n"));
+                Some(form)
+            })
+            .collect();
+        (result.pages_overlaid, forms)
+    };
+
+    let (count, forms) = overlaid(OverlayRepeat::Once);
+    assert_eq!(count, 2);
+    assert!(forms[0].is_some() && forms[1].is_some() && forms[0] != forms[1]);
+    assert_eq!(forms[2], None);
+    let (count, forms) = overlaid(OverlayRepeat::Cycle);
+    assert_eq!(count, 3);
+    assert_eq!(forms[2], forms[0]);
+    let (count, forms) = overlaid(OverlayRepeat::StretchLast);
+    assert_eq!(count, 3);
+    assert_eq!(forms[2], forms[1]);
+}
+
+#[test]
+fn extracted_images_are_written_or_skipped_with_a_reason() {
+    let dir = TestDir::new("extract-images");
+    let input = dir.join("in.pdf");
+    let page = || page_with_boxes(&[("MediaBox", [0.0, 0.0, 200.0, 300.0])]);
+    write_test_pdf(&input, vec![page(), page()]);
+    let mut doc = lopdf::Document::load(&input).unwrap();
+    let name = |name: &str| lopdf::Object::Name(name.as_bytes().to_vec());
+    let mut image = |entries: Vec<(&str, lopdf::Object)>, data: &[u8]| {
+        let mut dict = lopdf::Dictionary::new();
+        dict.set("Type", name("XObject"));
+        dict.set("Subtype", name("Image"));
+        for (key, value) in entries {
+            dict.set(key, value);
+        }
+        doc.add_object(lopdf::Stream::new(dict, data.to_vec()))
+    };
+    let jpeg = b"\xFF\xD8 not really a JPEG \xFF\xD9";
+    let rgb = image(
+        vec![
+            ("Width", 2.into()),
+            ("Height", 2.into()),
+            ("ColorSpace", name("DeviceRGB")),
+            ("BitsPerComponent", 8.into()),
+        ],
+        &[255, 0, 0, 0, 255, 0, 0, 0, 255, 255, 255, 255],
+    );
+    let dct = image(
+        vec![
+            ("Width", 4.into()),
+            ("Height", 4.into()),
+            ("ColorSpace", name("DeviceRGB")),
+            ("BitsPerComponent", 8.into()),
+            ("Filter", name("DCTDecode")),
+        ],
+        jpeg,
+    );
+    let stencil = image(
+        vec![
+            ("Width", 8.into()),
+            ("Height", 2.into()),
+            ("ImageMask", true.into()),
+        ],
+        &[0xF0, 0x0F],
+    );
+    let tiny = image(
+        vec![
+            ("Width", 1.into()),
+            ("Height", 1.into()),
+            ("ColorSpace", name("DeviceGray")),
+            ("BitsPerComponent", 8.into()),
+        ],
+        &[0],
+    );
+    let pages = doc.get_pages();
+    // The RGB image is on both pages and listed once.
+    for (page, images) in [(1, vec![rgb, dct, tiny]), (2, vec![rgb, stencil])] {
+        let mut xobjects = lopdf::Dictionary::new();
+        for (index, id) in images.into_iter().enumerate() {
+            xobjects.set(format!("Im{}", index), id);
+        }
+        let mut resources = lopdf::Dictionary::new();
+        resources.set("XObject", xobjects);
+        doc.get_dictionary_mut(pages[&page])
+            .unwrap()
+            .set("Resources", resources);
+    }
+    doc.save(&input).unwrap();
+
+    let output_dir = dir.join("images");
+    let mut images = extract_pdf_images(&input, &output_dir, 4, ConflictPolicy::Skip).unwrap();
+    images.sort_by_key(|image| image.object);
+
+    let objects: Vec<u32> = images.iter().map(|image| image.object).collect();
+    assert_eq!(objects, [rgb.0, dct.0, stencil.0]);
+    let png = images[0].path.as_deref().unwrap();
+    assert!(png.ends_with(&format!("in_p1_obj{}.png", rgb.0)));
+    let png = read_png(Path::new(png)).unwrap();
+    assert_eq!((png.width, png.height, png.color_type), (2, 2, 2));
+    let stored = std::fs::read(images[1].path.as_deref().unwrap()).unwrap();
+    assert_eq!(stored, jpeg);
+    assert_eq!(images[2].path, None);
+    assert!(matches!(images[2].skipped, Some(ImageSkip::StencilMask)));
+
+    // A second run leaves the files alone and says why.
+    let images = extract_pdf_images(&input, &output_dir, 4, ConflictPolicy::Skip).unwrap();
+    assert!(images
+        .iter()
+        .filter(|image| image.object != stencil.0)
+        .all(|image| matches!(image.skipped, Some(ImageSkip::OutputExists { .. }))));
+}
+
+#[test]
+#[ignore = "needs an installed Ghostscript"]
+fn mirroring_twice_keeps_the_page_geometry() {
+    let runtime = GhostscriptRuntime::default();
+    let dir = TestDir::new("mirror");
+    let input = dir.join("in.pdf");
+    let mut rotated = page_with_boxes(&[("MediaBox", [0.0, 0.0, 200.0, 300.0])]);
+    rotated.set("Rotate", 90);
+    write_test_pdf(
+        &input,
+        vec![
+            page_with_boxes(&[("MediaBox", [0.0, 0.0, 612.0, 792.0])]),
+            rotated,
+        ],
+    );
+    let displayed_sizes = |path: &Path| -> Vec<(i64, i64)> {
+        let doc = lopdf::Document::load(path).unwrap();
+        read_page_geometry(&doc)
+            .iter()
+            .map(|page| {
+                let (_, size) = page.upright_matrix();
+                (size.width_pt.round() as i64, size.height_pt.round() as i64)
+            })
+            .collect()
+    };
+
+    for axis in [MirrorAxis::Horizontal, MirrorAxis::Vertical] {
+        let once = dir.join("once.pdf");
+        let twice = dir.join("twice.pdf");
+        for (from, to) in [(&input, &once), (&once, &twice)] {
+            let _ = std::fs::remove_file(to);
+            let result =
+                mirror_with_ghostscript(from, to, axis, None, &runtime, JobContext::default())
+                    .unwrap();
+            assert_eq!(result.mirrored_pages, 2);
+        }
+        assert_eq!(displayed_sizes(&once), displayed_sizes(&input));
+        assert_eq!(displayed_sizes(&twice), displayed_sizes(&input));
+    }
+}
+
+#[test]
+fn filled_forms_are_drawn_into_the_page() {
+    let dir = TestDir::new("forms");
+    let input = dir.join("in.pdf");
+    write_test_pdf(
+        &input,
+        vec![page_with_boxes(&[("MediaBox", [0.0, 0.0, 612.0, 792.0])])],
+    );
+    let mut doc = lopdf::Document::load(&input).unwrap();
+    let name = |name: &str| lopdf::Object::Name(name.as_bytes().to_vec());
+    let text = |text: &str| lopdf::Object::string_literal(text);
+    let mut appearance = |content: &str| {
+        let mut dict = lopdf::Dictionary::new();
+        dict.set("BBox", pdf_rect_object([0.0, 0.0, 20.0, 20.0]));
+        doc.add_object(lopdf::Stream::new(dict, content.as_bytes().to_vec()))
+    };
+    let text_ap = appearance("% text: Jane Doe");
+    let (checked_ap, unchecked_ap) = (appearance("% checked"), appearance("% unchecked"));
+    let (radio_on_ap, radio_off_ap) = (appearance("% radio a"), appearance("% radio off"));
+    let hidden_ap = appearance("% hidden");
+    let states = |entries: &[(&str, lopdf::ObjectId)]| {
+        let mut states = lopdf::Dictionary::new();
+        for (state, id) in entries {
+            states.set(*state, *id);
+        }
+        let mut appearances = lopdf::Dictionary::new();
+        appearances.set("N", states);
+        appearances
+    };
+    let widget = |entries: Vec<(&str, lopdf::Object)>| {
+        let mut widget = lopdf::Dictionary::new();
+        widget.set("Type", name("Annot"));
+        widget.set("Subtype", name("Widget"));
+        widget.set("Rect", pdf_rect_object([50.0, 50.0, 70.0, 70.0]));
+        for (key, value) in entries {
+            widget.set(key, value);
+        }
+        widget
+    };
+    let direct = |id: lopdf::ObjectId| {
+        let mut appearances = lopdf::Dictionary::new();
+        appearances.set("N", id);
+        appearances
+    };
+    let widgets = [
+        widget(vec![
+            ("FT", name("Tx")),
+            ("T", text("name")),
+            ("V", text("Jane Doe")),
+            ("AP", direct(text_ap).into()),
+        ]),
+        widget(vec![
+            ("FT", name("Btn")),
+            ("T", text("agree")),
+            ("V", name("Yes")),
+            ("AS", name("Yes")),
+            (
+                "AP",
+                states(&[("Yes", checked_ap), ("Off", unchecked_ap)]).into(),
+            ),
+        ]),
+        // The two buttons of one radio group, the first one selected.
+        widget(vec![
+            ("AS", name("A")),
+            (
+                "AP",
+                states(&[("A", radio_on_ap), ("Off", radio_off_ap)]).into(),
+            ),
+        ]),
+        widget(vec![
+            ("AS", name("Off")),
+            (
+                "AP",
+                states(&[("B", radio_on_ap), ("Off", radio_off_ap)]).into(),
+            ),
+        ]),
+        widget(vec![("F", 2.into()), ("AP", direct(hidden_ap).into())]),
+    ];
+    let mut link = lopdf::Dictionary::new();
+    link.set("Type", name("Annot"));
+    link.set("Subtype", name("Link"));
+    link.set("Rect", pdf_rect_object([0.0, 0.0, 10.0, 10.0]));
+    let annots: Vec<lopdf::Object> = widgets
+        .into_iter()
+        .chain([link])
+        .map(|annotation| doc.add_object(annotation).into())
+        .collect();
+    let page_id = doc.get_pages()[&1];
+    let fields = annots[..4].to_vec();
+    doc.get_dictionary_mut(page_id)
+        .unwrap()
+        .set("Annots", annots);
+    let mut form = lopdf::Dictionary::new();
+    form.set("Fields", fields);
+    let catalog_id = doc.trailer.get(b"Root").unwrap().as_reference().unwrap();
+    doc.get_dictionary_mut(catalog_id)
+        .unwrap()
+        .set("AcroForm", form);
+    doc.save(&input).unwrap();
+
+    let output = dir.join("out.pdf");
+    let result = flatten_pdf_forms(&input, &output).unwrap();
+    assert_eq!(result.fields_flattened, 4);
+    assert!(result.missing_appearances.is_empty());
+
+    let doc = lopdf::Document::load(&output).unwrap();
+    let catalog = doc.catalog().unwrap();
+    assert!(catalog.get(b"AcroForm").is_err());
+    let page_id = doc.get_pages()[&1];
+    let annots = page_annotations(&doc, page_id);
+    assert_eq!(annots.len(), 1);
+    let kept = pdf_dict(&doc, &annots[0]).unwrap();
+    assert_eq!(kept.get(b"Subtype").unwrap().as_name().unwrap(), b"Link");
+    let resources = doc
+        .get_dictionary(page_id)
+        .and_then(|page| page.get(b"Resources"))
+        .map(|resources| pdf_dict(&doc, resources).unwrap())
+        .unwrap();
+    let mut drawn: Vec<String> = pdf_dict(&doc, resources.get(b"XObject").unwrap())
+        .unwrap()
+        .iter()
+        .map(|(_, xobject)| {
+            let id = xobject.as_reference().unwrap();
+            let stream = doc
+                .get_object(id)
+                .and_then(lopdf::Object::as_stream)
+                .unwrap();
+            String::from_utf8_lossy(&stream.content).to_string()
+        })
+        .collect();
+    drawn.sort();
+    assert_eq!(
+        drawn,
+        ["% checked", "% radio a", "% radio off", "% text: Jane Doe"]
+    );
+}
+
+fn opened(files: &[&str]) -> ResolvedOpenPaths {
+    ResolvedOpenPaths {
+        files: files.iter().map(|file| file.to_string()).collect(),
+        ..ResolvedOpenPaths::default()
+    }
+}
+
+#[test]
+fn opened_files_wait_for_the_frontend() {
+    let mut events: Vec<(String, serde_json::Value)> = Vec::new();
+    let mut pending = PendingOpen::default();
+    let mut resolved = opened(&["/a.pdf", "/b.pdf"]);
+    resolved.unsupported.push("/notes.txt".to_string());
+    pending.queue(
+        resolved,
+        vec![PresetRequest {
+            paths: vec!["/b.pdf".to_string()],
+            preset: "print".to_string(),
+        }],
+    );
+    // Opening a queued file again doesn't queue it twice.
+    pending.queue(opened(&["/a.pdf"]), Vec::new());
+    pending.emit_if_ready(|event, payload| events.push((event.to_string(), payload)));
+    assert!(events.is_empty());
+
+    pending.set_frontend_ready(|event, payload| events.push((event.to_string(), payload)));
+    let names: Vec<&str> = events.iter().map(|(event, _)| event.as_str()).collect();
+    assert_eq!(
+        names,
+        [
+            "unsupported-files-ignored",
+            "external-files-opened",
+            "external-preset-requested"
+        ]
+    );
+    assert_eq!(events[1].1, serde_json::json!(["/a.pdf", "/b.pdf"]));
+    assert_eq!(events[2].1["preset"], "print");
+
+    // Once ready, what is opened is emitted straight away, even a file emitted before.
+    events.clear();
+    pending.queue(opened(&["/a.pdf"]), Vec::new());
+    pending.emit_if_ready(|event, payload| events.push((event.to_string(), payload)));
+    assert_eq!(
+        events,
+        [(
+            "external-files-opened".to_string(),
+            serde_json::json!(["/a.pdf"])
+        )]
+    );
+    assert!(pending.paths.is_empty() && pending.preset_requests.is_empty());
+}
+
+#[test]
+fn files_opened_during_the_handshake_are_emitted_once() {
+    let state = Arc::new(PendingOpenPaths::default());
+    let emitted = Arc::new(Mutex::new(Vec::new()));
+    let collect = |emitted: &Arc<Mutex<Vec<String>>>| {
+        let emitted = Arc::clone(emitted);
+        move |event: &str, payload: serde_json::Value| {
+            assert_eq!(event, "external-files-opened");
+            let paths = payload.as_array().unwrap().iter();
+            let mut emitted = emitted.lock().unwrap();
+            emitted.extend(paths.map(|path| path.as_str().unwrap().to_string()));
+        }
+    };
+    // Half the files are opened before the frontend is ready, the rest while it gets so.
+    let halfway = Arc::new(std::sync::Barrier::new(2));
+    let opener = {
+        let state = Arc::clone(&state);
+        let halfway = Arc::clone(&halfway);
+        let emit = collect(&emitted);
+        std::thread::spawn(move || {
+            for n in 0..200 {
+                if n == 100 {
+                    halfway.wait();
+                }
+                let mut pending = state.0.lock().unwrap();
+                pending.queue(opened(&[&format!("/{}.pdf", n)]), Vec::new());
+                pending.emit_if_ready(emit.clone());
+            }
+        })
+    };
+    halfway.wait();
+    state
+        .0
+        .lock()
+        .unwrap()
+        .set_frontend_ready(collect(&emitted));
+    opener.join().unwrap();
+
+    let expected: Vec<String> = (0..200).map(|n| format!("/{}.pdf", n)).collect();
+    assert_eq!(*emitted.lock().unwrap(), expected);
+    assert!(state.0.lock().unwrap().paths.is_empty());
+}
+
+/// Set for the child process `open_paths_name_the_same_file` starts, which runs in the
+/// directory holding `foo.pdf`.
+const OPEN_PATHS_CHILD_ENV: &str = "PDF_RESIZER_TEST_OPEN_PATHS";
+
+/// Run in a child process only, so the relative paths resolve against a known directory.
+#[test]
+#[ignore]
+fn open_paths_child() {
+    if std::env::var_os(OPEN_PATHS_CHILD_ENV).is_none() {
+        return;
+    }
+    let absolute = std::env::current_dir().unwrap().join("foo.pdf");
+    let canonical = absolute
+        .canonicalize()
+        .unwrap()
+        .to_string_lossy()
+        .to_string();
+    for relative in ["./foo.pdf", "foo.pdf", "sub/../foo.pdf"] {
+        assert_eq!(canonical_open_path(relative), canonical, "{}", relative);
+        assert!(is_same_file(Path::new(relative), &absolute), "{}", relative);
+    }
+    assert!(!is_same_file(Path::new("./other.pdf"), &absolute));
+}
+
+#[test]
+fn open_paths_name_the_same_file() {
+    let dir = TestDir::new("open-paths");
+    let file = dir.join("foo.pdf");
+    std::fs::write(&file, b"%PDF-1.4\n%%EOF\n").unwrap();
+    std::fs::write(dir.join("other.pdf"), b"%PDF-1.4\n%%EOF\n").unwrap();
+    std::fs::create_dir(dir.join("sub")).unwrap();
+    let canonical = file.canonicalize().unwrap().to_string_lossy().to_string();
+
+    let dotted = dir.join("sub").join("..").join(".").join("foo.pdf");
+    assert_eq!(canonical_open_path(&file.to_string_lossy()), canonical);
+    assert_eq!(canonical_open_path(&dotted.to_string_lossy()), canonical);
+    assert!(is_same_file(&dotted, &file));
+    #[cfg(unix)]
+    {
+        let link = dir.join("link.pdf");
+        std::os::unix::fs::symlink(&file, &link).unwrap();
+        assert_eq!(canonical_open_path(&link.to_string_lossy()), canonical);
+        assert!(is_same_file(&link, &file));
+    }
+    // Paths that don't exist are kept as given and match nothing, not even themselves.
+    let missing = dir.join("missing.pdf");
+    assert_eq!(
+        canonical_open_path(&missing.to_string_lossy()),
+        missing.to_string_lossy()
+    );
+    assert!(!is_same_file(&missing, &missing));
+    assert!(!is_same_file(&dir.join("other.pdf"), &file));
+
+    run_child_test(
+        child_test("open_paths_child")
+            .env(OPEN_PATHS_CHILD_ENV, "1")
+            .current_dir(&dir.0),
+    );
+}
+
+/// Set for the child process `ghostscript_env_var_wins_over_path` starts.
+#[cfg(unix)]
+const ENV_OVERRIDE_CHILD_ENV: &str = "PDF_RESIZER_TEST_ENV_OVERRIDE";
+
+/// Run in a child process only, with a `gs` on PATH and another in `PDFRESIZER_GS`.
+#[cfg(unix)]
+#[test]
+#[ignore]
+fn ghostscript_env_var_child() {
+    if std::env::var_os(ENV_OVERRIDE_CHILD_ENV).is_none() {
+        return;
+    }
+    let runtime = GhostscriptRuntime::default();
+    let output = run_ghostscript(&["-h"], &runtime, None, GhostscriptHooks::default()).unwrap();
+    assert_eq!(output.stdout.trim(), "from PDFRESIZER_GS");
+    let resolved = runtime.resolved().expect("resolved");
+    assert!(matches!(
+        resolved.candidate.source,
+        GhostscriptSource::Environment("PDFRESIZER_GS")
+    ));
+}
+
+#[cfg(unix)]
+#[test]
+fn ghostscript_env_var_wins_over_path() {
+    let dir = TestDir::new("env-override");
+    std::fs::create_dir(dir.join("bin")).unwrap();
+    write_script(&dir, "bin/gs", "echo 'from PATH'");
+    let env_gs = write_script(&dir, "env-gs", "echo 'from PDFRESIZER_GS'");
+    let path = format!("{}:/usr/bin:/bin", dir.join("bin").display());
+
+    run_child_test(
+        child_test("ghostscript_env_var_child")
+            .env(ENV_OVERRIDE_CHILD_ENV, "1")
+            .env("PATH", path)
+            .env("PDFRESIZER_GS", env_gs)
+            .env_remove("GS_EXECUTABLE"),
+    );
+}
+
+/// A command running only the ignored test `name` of this binary, for tests that need a
+/// process of their own: one to kill, or one whose environment or globals they change.
+fn child_test(name: &str) -> std::process::Command {
+    let mut command = std::process::Command::new(std::env::current_exe().unwrap());
+    command.args(["--exact", &format!("tests::{}", name), "--ignored"]);
+    command
+}
+
+/// Runs a child from `child_test` to the end and fails with its output if it failed.
+fn run_child_test(command: &mut std::process::Command) {
+    let output = command.output().expect("start the child test");
+    assert!(
+        output.status.success(),
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+/// Set for the child process `shutdown_stops_every_run` starts.
+#[cfg(unix)]
+const SHUTDOWN_CHILD_ENV: &str = "PDF_RESIZER_TEST_SHUTDOWN";
+
+/// Run in a child process only, since it sets `SHUTTING_DOWN` and kills every child.
+#[cfg(unix)]
+#[test]
+#[ignore]
+fn shutdown_child() {
+    const JOBS: usize = 8;
+    if std::env::var_os(SHUTDOWN_CHILD_ENV).is_none() {
+        return;
+    }
+    let dir = TestDir::new("shutdown");
+    let log = dir.join("paths.log");
+    // Runs that ignore SIGTERM and run until killed; `exec` leaves no orphan holding
+    // the output pipes open.
+    let runtime = logging_pdfwrite(&dir, &log, "trap '' TERM\nexec sleep 60");
+    let options = FlattenOptions::default();
+
+    let started = std::time::Instant::now();
+    let results: Vec<Result<Vec<u8>, CommandError>> = std::thread::scope(|scope| {
+        let jobs: Vec<_> = (0..JOBS)
+            .map(|_| {
+                let (runtime, options) = (&runtime, &options);
+                scope.spawn(move || {
+                    let pdf = b"%PDF-1.4\n%%EOF\n".to_vec();
+                    flatten_with_ghostscript(pdf, options, 0, runtime, JobContext::default())
+                })
+            })
+            .collect();
+        let logged = || std::fs::read_to_string(&log).map_or(0, |log| log.lines().count());
+        while RUNNING_CHILDREN.lock().unwrap().len() < JOBS || logged() < JOBS * 2 {
+            assert!(started.elapsed().as_secs() < 30, "the runs never started");
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+        SHUTTING_DOWN.store(true, Ordering::SeqCst);
+        stop_ghostscript_work();
+        jobs.into_iter().map(|job| job.join().unwrap()).collect()
+    });
+
+    assert!(started.elapsed().as_secs() < 30);
+    assert!(results.iter().all(Result::is_err));
+    assert!(RUNNING_CHILDREN.lock().unwrap().is_empty());
+    assert!(LIVE_TEMP_FILES.lock().unwrap().is_empty());
+    for path in std::fs::read_to_string(&log).unwrap().lines() {
+        assert!(!Path::new(path).exists(), "{} was left behind", path);
+    }
+    // Nothing starts once the app is closing.
+    let result = flatten_with_ghostscript(
+        b"%PDF-1.4\n%%EOF\n".to_vec(),
+        &options,
+        0,
+        &runtime,
+        JobContext::default(),
+    );
+    assert!(matches!(result, Err(CommandError::Cancelled)));
+}
+
+#[cfg(unix)]
+#[test]
+fn shutdown_stops_every_run() {
+    run_child_test(child_test("shutdown_child").env(SHUTDOWN_CHILD_ENV, "1"));
+}
+
+/// Set for the child process `killed_writes_leave_the_destination_alone` starts: the
+/// directory to write in and the destination's name.
+const KILLED_WRITE_ENV: &str = "PDF_RESIZER_TEST_KILLED_WRITE";
+
+/// Run in a child process only: starts writing the output, then waits to be killed.
+#[test]
+#[ignore]
+fn killed_write_child() {
+    let Some(spec) = std::env::var_os(KILLED_WRITE_ENV) else {
+        return;
+    };
+    let spec = PathBuf::from(spec);
+    let (dir, output) = (spec.parent().unwrap(), spec.as_path());
+    let input = dir.join("in.pdf");
+    let options = OutputOptions {
+        on_conflict: ConflictPolicy::Overwrite,
+        ..OutputOptions::default()
+    };
+    let _ = write_output(&input, output, options, |_, temp, _| {
+        std::fs::write(temp, b"%PDF-1.4\n1 0 obj\n<< /Type /Cat").unwrap();
+        std::fs::write(dir.join("writing"), b"").unwrap();
+        std::thread::sleep(std::time::Duration::from_secs(60));
+        Err::<PageCountResult, _>(CommandError::Cancelled)
+    });
+}
+
+#[test]
+fn killed_writes_leave_the_destination_alone() {
+    let dir = TestDir::new("killed-write");
+    std::fs::write(dir.join("in.pdf"), b"%PDF-1.4\n%%EOF\n").unwrap();
+    let old = b"%PDF-1.4\nthe old output\n%%EOF\n";
+    std::fs::write(dir.join("existing.pdf"), old).unwrap();
+
+    for name in ["existing.pdf", "new.pdf"] {
+        let _ = std::fs::remove_file(dir.join("writing"));
+        let mut child = child_test("killed_write_child")
+            .env(KILLED_WRITE_ENV, dir.join(name))
+            .stdout(std::process::Stdio::null())
+            .spawn()
+            .expect("start the child test");
+        let started = std::time::Instant::now();
+        while !dir.join("writing").exists() {
+            assert!(
+                started.elapsed().as_secs() < 30,
+                "the child never started writing"
+            );
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+        child.kill().unwrap();
+        child.wait().unwrap();
+
+        match name {
+            "existing.pdf" => assert_eq!(std::fs::read(dir.join(name)).unwrap(), old),
+            _ => assert!(!dir.join(name).exists()),
+        }
+        // What was written went to a hidden temp sibling instead.
+        let partial = std::fs::read_dir(&dir.0)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .find(|name| name.starts_with(".pdfresizer_output_") && name.ends_with(".tmp"))
+            .expect("a temp sibling");
+        std::fs::remove_file(dir.join(&partial)).unwrap();
+    }
+}
+
+/// One error of each `CommandError` variant.
+fn one_error_per_variant() -> Vec<CommandError> {
+    let path = || String::from("/tmp/in.pdf");
+    vec![
+        CommandError::GsNotFound(String::from("gs: not found")),
+        CommandError::GsFailed {
+            failure: None,
+            exit_code: Some(1),
+            stderr: String::from("Error: /undefined"),
+            stderr_bytes: None,
+        },
+        CommandError::GsMissingDevice {
+            device: String::from("tiffsep"),
+        },
+        CommandError::GsTooOld {
+            found: String::from("9.05"),
+            required: String::from("9.11"),
+        },
+        CommandError::InvalidInput {
+            field: "compatibility_level",
+            value: String::from("2.5"),
+            expected: COMPATIBILITY_LEVELS.join(", "),
+        },
+        CommandError::PasswordRequired,
+        CommandError::WrongPassword,
+        CommandError::DiskFull {
+            volume: String::from("/tmp"),
+            needed_bytes: 2_000_000,
+            available_bytes: 1_000_000,
+        },
+        CommandError::Cancelled,
+        CommandError::OutputSkipped { path: path() },
+        CommandError::Timeout { after_secs: 600 },
+        CommandError::Io {
+            action: "write_pdf",
+            error: String::from("Permission denied"),
+        },
+        CommandError::NotFound { path: path() },
+        CommandError::NotAFile { path: path() },
+        CommandError::NotAFolder { path: path() },
+        CommandError::NotWritable {
+            path: path(),
+            error: String::from("Read-only file system"),
+        },
+        CommandError::SameInputAndOutput { path: path() },
+        CommandError::InvalidPdf {
+            path: path(),
+            error: String::from("invalid file header"),
+        },
+        CommandError::PageOutOfRange {
+            page: 4,
+            page_count: 3,
+        },
+        CommandError::OutOfRange {
+            field: "jpeg_quality",
+            value: String::from("101"),
+            expected: String::from("between 1 and 100"),
+        },
+        CommandError::MissingInput { field: "operation" },
+        CommandError::InvalidTemplate {
+            template: String::from("{nmae}"),
+            reason: String::from("'{nmae}' is not a placeholder."),
+        },
+        CommandError::PresetNotFound {
+            name: String::from("Print"),
+        },
+        CommandError::PresetExists {
+            name: String::from("Print"),
+        },
+        CommandError::PresetBuiltIn {
+            name: String::from("Print"),
+        },
+        CommandError::NotAPresetFile {
+            path: path(),
+            error: String::from("expected value at line 1 column 1"),
+        },
+        CommandError::OpenFailed { path: path() },
+        CommandError::JobAlreadyRunning {
+            job_id: String::from("job-1"),
+        },
+        CommandError::InvalidSettings {
+            error: String::from("unknown variant `loud`"),
+        },
+        CommandError::OutputIsWatchedFolder { path: path() },
+        CommandError::NoPages { path: path() },
+        CommandError::PasswordsMustDiffer,
+        CommandError::FontsNotEmbedded {
+            fonts: vec![String::from("Helvetica"), String::from("Frutiger")],
+        },
+        CommandError::InvalidImage {
+            path: String::from("/tmp/logo.png"),
+            reason: "interlaced",
+        },
+        CommandError::NoVisibleArea {
+            path: path(),
+            page: 1,
+        },
+        CommandError::UnsupportedFont {
+            path: String::from("/tmp/font.otf"),
+        },
+        CommandError::MissingGlyphs {
+            path: String::from("/tmp/font.ttf"),
+            characters: String::from("ж"),
+        },
+        CommandError::NoOverlayFont,
+        CommandError::AllPagesSkipped {
+            skipped: 2,
+            page_count: 2,
+        },
+        CommandError::EpsNeedsOnePage { selected: 3 },
+        CommandError::InkCoverageIncomplete {
+            reported: 2,
+            page_count: 3,
+        },
+        CommandError::AttachmentNotFound {
+            path: path(),
+            name: String::from("invoice.xml"),
+        },
+        CommandError::MissingBundledFile {
+            name: String::from("srgb.icc"),
+        },
+        CommandError::BoxExceedsPage {
+            field: "crop",
+            page: 2,
+            width_pt: 595.3,
+            height_pt: 841.9,
+        },
+        CommandError::BleedMissesTrim { page: 1 },
+        CommandError::NoPagesLeft,
+        CommandError::InvalidIccProfile {
+            path: String::from("/tmp/profile.icc"),
+            found: Some(String::from("RGB")),
+            expected: String::from("CMYK"),
+        },
+        CommandError::PageCountUnknown { path: path() },
+        CommandError::OutputExists { path: path() },
+        CommandError::OutputInvalid {
+            path: path(),
+            reason: "empty",
+        },
+        CommandError::FileLocked {
+            path: path(),
+            error: String::from("sharing violation"),
+        },
+        CommandError::NotAPdf {
+            path: path(),
+            reason: "not_pdf",
+        },
+        CommandError::TaskFailed {
+            error: String::from("task panicked"),
+        },
+    ]
+}
+
+/// Position of the variant in `one_error_per_variant`. The match stops compiling when a
+/// variant is added, as a reminder to give it a code and list it there.
+fn variant_index(error: &CommandError) -> usize {
+    match error {
+        CommandError::GsNotFound(_) => 0,
+        CommandError::GsFailed { .. } => 1,
+        CommandError::GsMissingDevice { .. } => 2,
+        CommandError::GsTooOld { .. } => 3,
+        CommandError::InvalidInput { .. } => 4,
+        CommandError::PasswordRequired => 5,
+        CommandError::WrongPassword => 6,
+        CommandError::DiskFull { .. } => 7,
+        CommandError::Cancelled => 8,
+        CommandError::OutputSkipped { .. } => 9,
+        CommandError::Timeout { .. } => 10,
+        CommandError::Io { .. } => 11,
+        CommandError::NotFound { .. } => 12,
+        CommandError::NotAFile { .. } => 13,
+        CommandError::NotAFolder { .. } => 14,
+        CommandError::NotWritable { .. } => 15,
+        CommandError::SameInputAndOutput { .. } => 16,
+        CommandError::InvalidPdf { .. } => 17,
+        CommandError::PageOutOfRange { .. } => 18,
+        CommandError::OutOfRange { .. } => 19,
+        CommandError::MissingInput { .. } => 20,
+        CommandError::InvalidTemplate { .. } => 21,
+        CommandError::PresetNotFound { .. } => 22,
+        CommandError::PresetExists { .. } => 23,
+        CommandError::PresetBuiltIn { .. } => 24,
+        CommandError::NotAPresetFile { .. } => 25,
+        CommandError::OpenFailed { .. } => 26,
+        CommandError::JobAlreadyRunning { .. } => 27,
+        CommandError::InvalidSettings { .. } => 28,
+        CommandError::OutputIsWatchedFolder { .. } => 29,
+        CommandError::NoPages { .. } => 30,
+        CommandError::PasswordsMustDiffer => 31,
+        CommandError::FontsNotEmbedded { .. } => 32,
+        CommandError::InvalidImage { .. } => 33,
+        CommandError::NoVisibleArea { .. } => 34,
+        CommandError::UnsupportedFont { .. } => 35,
+        CommandError::MissingGlyphs { .. } => 36,
+        CommandError::NoOverlayFont => 37,
+        CommandError::AllPagesSkipped { .. } => 38,
+        CommandError::EpsNeedsOnePage { .. } => 39,
+        CommandError::InkCoverageIncomplete { .. } => 40,
+        CommandError::AttachmentNotFound { .. } => 41,
+        CommandError::MissingBundledFile { .. } => 42,
+        CommandError::BoxExceedsPage { .. } => 43,
+        CommandError::BleedMissesTrim { .. } => 44,
+        CommandError::NoPagesLeft => 45,
+        CommandError::InvalidIccProfile { .. } => 46,
+        CommandError::PageCountUnknown { .. } => 47,
+        CommandError::OutputExists { .. } => 48,
+        CommandError::OutputInvalid { .. } => 49,
+        CommandError::FileLocked { .. } => 50,
+        CommandError::NotAPdf { .. } => 51,
+        CommandError::TaskFailed { .. } => 52,
+    }
+}
+
+#[test]
+fn every_error_variant_has_its_own_code() {
+    let errors = one_error_per_variant();
+    let indices: Vec<usize> = errors.iter().map(variant_index).collect();
+    assert_eq!(indices, (0..errors.len()).collect::<Vec<_>>());
+
+    let mut codes = HashSet::new();
+    for error in &errors {
+        let code = error.code();
+        assert!(
+            code.bytes().all(|b| b.is_ascii_lowercase() || b == b'_'),
+            "{} is not snake_case",
+            code
+        );
+        assert!(codes.insert(code), "{} is used twice", code);
+        assert_ne!(code, "other", "errors are classified, not catch-all");
+        assert!(!error.message().is_empty(), "{} has no message", code);
+
+        let json = serde_json::to_value(error).unwrap();
+        assert_eq!(json["code"], code);
+        assert_eq!(json["message"], error.message());
+    }
+}
+
+#[test]
+fn ghostscript_failures_have_their_own_codes() {
+    let failures = [
+        GhostscriptFailure::PasswordRequired,
+        GhostscriptFailure::DamagedFile,
+        GhostscriptFailure::UnknownDevice,
+        GhostscriptFailure::OutOfMemory,
+        GhostscriptFailure::InvalidFont,
+        GhostscriptFailure::FileNotFound,
+        GhostscriptFailure::FileAccessDenied,
+    ];
+    let variant_codes: HashSet<&str> = one_error_per_variant()
+        .iter()
+        .map(CommandError::code)
+        .filter(|code| *code != "password_required")
+        .collect();
+    let mut codes = HashSet::new();
+    for failure in failures {
+        let error = CommandError::GsFailed {
+            failure: Some(failure),
+            exit_code: Some(1),
+            stderr: String::new(),
+            stderr_bytes: None,
+        };
+        assert!(codes.insert(error.code()), "{} is used twice", error.code());
+        assert!(!variant_codes.contains(error.code()), "{}", error.code());
+        assert!(!error.message().is_empty());
+    }
+}
+
+/// Ghostscript's output for each failure it can be diagnosed with, as captured from
+/// 9.5x and 10.x runs, with the banner and the stacks trimmed.
+const GHOSTSCRIPT_FAILURE_SAMPLES: &[(&str, GhostscriptFailure)] = &[
+    (
+        "   **** This file requires a password for access.\n\
+         Error: /invalidfileaccess in pdf_process_Encrypt\n\
+         Operand stack:\n   --dict:10/10(L)--   Encrypt   false\n\
+         GPL Ghostscript 9.55.0: Unrecoverable error, exit code 1\n",
+        GhostscriptFailure::PasswordRequired,
+    ),
+    (
+        "   **** Error:  Cannot find a 'startxref' anywhere in the file.\n\
+         \x20              Output may be incorrect.\n\
+         \x20  **** Error:  An error occurred while reading an XREF table.\n\
+         \x20  **** The file has been damaged.  This may have been caused\n\
+         \x20  **** by a problem while converting or transfering the file.\n\
+         \x20  **** Ghostscript will attempt to recover the data.\n\
+         No pages will be processed (FirstPage > LastPage).\n",
+        GhostscriptFailure::DamagedFile,
+    ),
+    (
+        "Error: /syntaxerror in pdfopen\n\
+         Operand stack:\n   --nostringval--\n\
+         GPL Ghostscript 9.55.0: Unrecoverable error, exit code 1\n",
+        GhostscriptFailure::DamagedFile,
+    ),
+    (
+        "Unknown device: pdfwrite\n\
+         Unrecoverable error: undefined in .uninstallpagedevice\n\
+         Operand stack:\n    defaultdevice\n",
+        GhostscriptFailure::UnknownDevice,
+    ),
+    (
+        "Error: /VMerror in --image--\n\
+         Operand stack:\n   --dict:15/15(L)--\n\
+         Current allocation mode is local\n\
+         GPL Ghostscript 10.02.1: Unrecoverable error, exit code 1\n",
+        GhostscriptFailure::OutOfMemory,
+    ),
+    (
+        "Error: /invalidfont in definefont\n\
+         Operand stack:\n   --nostringval--   --nostringval--\n\
+         GPL Ghostscript 9.55.0: Unrecoverable error, exit code 1\n",
+        GhostscriptFailure::InvalidFont,
+    ),
+    (
+        "Error: /undefinedfilename in (/tmp/missing.pdf)\n\
+         Operand stack:\n\n\
+         Execution stack:\n   %interp_exit   .runexec2   --nostringval--\n\
+         Current allocation mode is local\n\
+         Last OS error: No such file or directory\n\
+         GPL Ghostscript 10.02.1: Unrecoverable error, exit code 1\n",
+        GhostscriptFailure::FileNotFound,
+    ),
+    (
+        "Error: /invalidfileaccess in --file--\n\
+         Operand stack:\n   (/etc/passwd)   (r)\n\
+         Current allocation mode is local\n\
+         GPL Ghostscript 10.02.1: Unrecoverable error, exit code 1\n",
+        GhostscriptFailure::FileAccessDenied,
+    ),
+];
+
+#[test]
+fn captured_ghostscript_output_is_diagnosed() {
+    for &(output, failure) in GHOSTSCRIPT_FAILURE_SAMPLES {
+        assert_eq!(
+            diagnose_ghostscript_output(output),
+            Some(failure),
+            "{}",
+            output
+        );
+    }
+    // Repaired files and plain errors carry no diagnosis.
+    let repaired = "   **** The file was produced by:\n\
+        \x20  **** >>>> Microsoft Word 2016 <<<<\n\
+        \x20  **** This file had errors that were repaired or ignored.\n";
+    assert_eq!(diagnose_ghostscript_output(repaired), None);
+    let undefined = "Error: /undefined in --run--\nOperand stack:\n   foo\n";
+    assert_eq!(diagnose_ghostscript_output(undefined), None);
+}
+
+#[cfg(unix)]
+#[test]
+fn failed_runs_carry_the_diagnosis_of_their_stderr() {
+    let dir = TestDir::new("diagnosis");
+    let (sample, failure) = GHOSTSCRIPT_FAILURE_SAMPLES[6];
+    std::fs::write(dir.join("stderr.txt"), sample).unwrap();
+    let script = format!("cat '{}' >&2\nexit 1", dir.join("stderr.txt").display());
+    let runtime = fake_ghostscript(&dir, &script);
+    match failed_run(&runtime, &["-q"]) {
+        CommandError::GsFailed {
+            failure: Some(found),
+            exit_code: Some(1),
+            ..
+        } => assert_eq!(found, failure),
+        other => panic!("unexpected error: {:?}", other),
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn hung_ghostscript_is_killed_at_the_timeout() {
+    let dir = TestDir::new("timeout");
+    let pid_file = dir.join("pid");
+    // Ignores SIGTERM, so only the SIGKILL after the grace period stops it.
+    let script = format!(
+        "echo $$ > '{}'\ntrap '' TERM\nexec sleep 30",
+        pid_file.display()
+    );
+    let runtime = fake_ghostscript(&dir, &script);
+    let control = JobControl {
+        timeout_secs: Some(1),
+        ..JobControl::default()
+    };
+    let hooks = JobContext {
+        control: Some(&control),
+        ..JobContext::default()
+    }
+    .hooks();
+
+    let started = std::time::Instant::now();
+    let result = run_ghostscript(&["-q"], &runtime, None, hooks);
+    assert!(
+        matches!(result, Err(CommandError::Timeout { after_secs: 1 })),
+        "{:?}",
+        result.map(|output| output.stdout)
+    );
+    assert!(started.elapsed() < std::time::Duration::from_secs(10));
+    let pid = std::fs::read_to_string(&pid_file).unwrap();
+    let alive = std::process::Command::new("kill")
+        .args(["-0", pid.trim()])
+        .stderr(std::process::Stdio::null())
+        .status()
+        .unwrap()
+        .success();
+    assert!(!alive, "Ghostscript {} is still running", pid.trim());
+    assert!(control.child.lock().unwrap().is_none());
+}
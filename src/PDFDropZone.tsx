@@ -345,8 +345,8 @@ function PDFDropZone() {
       try {
         const isTauriEnv = typeof window !== 'undefined' && Boolean((window as any).__TAURI_IPC__);
         if (isTauriEnv) {
-          const version: string = await invoke('check_ghostscript');
-          setGhostscriptAvailable(Boolean(version));
+          const info: { found: boolean } = await invoke('ghostscript_info');
+          setGhostscriptAvailable(info.found);
         }
       } catch {
         setGhostscriptAvailable(false);
@@ -1179,18 +1179,6 @@ function PDFDropZone() {
     let unlistenFileDropHover: (() => void) | null = null;
     let unlistenFileDropCancelled: (() => void) | null = null;
     (async () => {
-      try {
-        const pendingPaths = await invoke<string[]>('take_pending_open_paths');
-        for (const absPath of pendingPaths || []) {
-          try {
-            await importFileByPath(absPath);
-          } catch (e) {
-            console.error('Failed to import pending path', absPath, e);
-          }
-        }
-      } catch (e) {
-        console.error('Failed to fetch pending open paths', e);
-      }
       try {
         unlisten = await listen<string[]>('external-files-opened', async (event) => {
           const paths = Array.isArray(event.payload) ? event.payload : [];
@@ -1205,6 +1193,12 @@ function PDFDropZone() {
       } catch (e) {
         console.error('Failed to subscribe to external-files-opened', e);
       }
+      try {
+        // Files opened before this point are queued in the backend until now.
+        await invoke('frontend_ready');
+      } catch (e) {
+        console.error('Failed to signal frontend readiness', e);
+      }
       try {
         unlistenFileDrop = await listen<string[]>('tauri://file-drop', async (event) => {
           setDragActive(false);
@@ -1466,10 +1460,11 @@ function PDFDropZone() {
           try {
             const flattenedBytes: number[] = await invoke('flatten_pdf', {
               pdfBytes: Array.from(new Uint8Array(processedPdfBuffer)),
+              options: {},
             });
             processedPdfBuffer = new Uint8Array(flattenedBytes).buffer;
           } catch (e: any) {
-            throw new Error(`Flatten failed: ${e}`);
+            throw new Error(`Flatten failed: ${e?.message ?? e}`);
           }
         }
       } else if (sourceKind === 'image') {